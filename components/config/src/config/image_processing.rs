@@ -0,0 +1,553 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde_derive::{Deserialize, Serialize};
+
+use errors::{bail, Result};
+
+/// The current naming scheme: the hash of the resize operation and the extension
+pub const DEFAULT_FILENAME_TEMPLATE: &str = "{hash}.{ext}";
+
+/// The directory of `static` the processed images are written to
+pub const DEFAULT_PROCESSED_DIR: &str = "processed_images";
+
+/// Sidecar files are named after the image they describe, eg `beach.jpg.meta.toml`
+pub const DEFAULT_SIDECAR_SUFFIX: &str = ".meta.toml";
+
+/// All the placeholders that can be used in `filename_template`
+pub const FILENAME_PLACEHOLDERS: &[&str] = &["{hash}", "{stem}", "{width}", "{height}", "{ext}"];
+
+/// The most `fetch_retries`, the wait doubling before each of them
+pub const MAX_FETCH_RETRIES: u32 = 10;
+
+/// The extensions of all the formats the image decoders of Zola can read. HEIC needs Zola to be
+/// built with the `heic` feature, they are allowed anyway to get a helpful error otherwise
+pub const DEFAULT_SOURCE_EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "png", "gif", "webp", "bmp", "ico", "tif", "tiff", "tga", "dds", "hdr", "pbm",
+    "pgm", "ppm", "pam", "ff", "heic", "heif",
+];
+
+/// What to do with the processed images when the encoders of Zola changed since they were made
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EncoderChangePolicy {
+    /// Process all the images again
+    Regenerate,
+    /// Keep the existing images and print a warning
+    Warn,
+}
+
+/// How the cache headers of the processed images are written
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheHeadersFormat {
+    /// The `_headers` file of Netlify and Cloudflare Pages, only the block listing the
+    /// processed images is replaced so other rules can be kept in the same file
+    Headers,
+    /// A JSON array of paths and their headers, for other hosts to convert
+    Json,
+}
+
+/// How the dimension computed to keep the aspect ratio of an image is rounded to whole pixels
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResizeRounding {
+    /// To the nearest pixel
+    Round,
+    /// Down, like the `image` crate does
+    Floor,
+    /// Up
+    Ceil,
+    /// To the nearest even number of pixels, eg to center the image on a pixel grid
+    Even,
+}
+
+impl ResizeRounding {
+    /// Rounds that computed dimension, which is never less than a pixel, or 2 for `Even`
+    pub fn apply(self, dimension: f64) -> u32 {
+        let rounded = match self {
+            ResizeRounding::Round => dimension.round(),
+            ResizeRounding::Floor => dimension.floor(),
+            ResizeRounding::Ceil => dimension.ceil(),
+            ResizeRounding::Even => ((dimension / 2.0).round() * 2.0).max(2.0),
+        };
+        rounded.max(1.0) as u32
+    }
+}
+
+/// What `resize_image` does when asked to make an image bigger than its source
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ForbidUpscale {
+    /// Upscale it
+    Off,
+    /// Fail the build
+    Error,
+    /// Make it as big as the source allows, keeping the requested aspect ratio, with a warning
+    Clamp,
+}
+
+/// The case of the alt texts made out of filenames
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AltCase {
+    /// As in the filename
+    Keep,
+    /// `beach sunset`
+    Lower,
+    /// `Beach sunset`
+    Sentence,
+    /// `Beach Sunset`
+    Title,
+}
+
+/// How `get_alt` makes an alt text out of the filename of images that don't have one
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AltFallback {
+    /// The characters of the filename replaced by spaces. Defaults to `-` and `_`
+    pub separators: String,
+    /// Defaults to keeping the case of the filename
+    pub case: AltCase,
+}
+
+impl Default for AltFallback {
+    fn default() -> Self {
+        AltFallback { separators: "-_".to_string(), case: AltCase::Keep }
+    }
+}
+
+impl AltFallback {
+    /// The alt text for the image at that path, eg `beach sunset` for `gallery/beach-sunset.jpg`
+    pub fn alt_from_filename(&self, path: &str) -> String {
+        let stem = Path::new(path).file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+        let words: Vec<_> = stem
+            .split(|c: char| c.is_whitespace() || self.separators.contains(c))
+            .filter(|w| !w.is_empty())
+            .collect();
+        let capitalize = |word: &str| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect::<String>(),
+                None => String::new(),
+            }
+        };
+
+        match self.case {
+            AltCase::Keep => words.join(" "),
+            AltCase::Lower => words.join(" ").to_lowercase(),
+            AltCase::Sentence => capitalize(&words.join(" ").to_lowercase()),
+            AltCase::Title => {
+                words.iter().map(|w| capitalize(&w.to_lowercase())).collect::<Vec<_>>().join(" ")
+            }
+        }
+    }
+}
+
+/// How the images of Markdown content are processed, `[image_processing.markdown_images]`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MarkdownImages {
+    /// The widths, in CSS pixels, of the images of the `srcset`. Images are never upscaled,
+    /// the widths bigger than the source are replaced by the width of the source
+    pub widths: Vec<u32>,
+    /// The format of the processed images, as given to `resize_image`. Defaults to `auto`
+    pub format: String,
+    /// Defaults to the quality `resize_image` uses when none is given
+    pub quality: Option<u8>,
+}
+
+impl Default for MarkdownImages {
+    fn default() -> Self {
+        MarkdownImages { widths: vec![640, 1280], format: "auto".to_string(), quality: None }
+    }
+}
+
+/// One of the crops of an art direction rule set
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArtDirectionVariant {
+    /// The media query of the `<source>`. The variant without one is the fallback `<img>`
+    pub media: Option<String>,
+    /// The aspect ratio of the crop, eg `16:9`
+    pub ratio: String,
+    /// The width of the crop in pixels, its height is computed from the ratio
+    pub width: u32,
+}
+
+impl ArtDirectionVariant {
+    /// The width and height of the crop, errors if the ratio isn't in the `W:H` format
+    pub fn dimensions(&self) -> Result<(u32, u32)> {
+        let parts: Vec<_> = self.ratio.split(':').map(|p| p.trim().parse::<u32>()).collect();
+        match parts.as_slice() {
+            [Ok(w), Ok(h)] if *w > 0 && *h > 0 && self.width > 0 => {
+                let height = (u64::from(self.width) * u64::from(*h) + u64::from(*w) / 2) / u64::from(*w);
+                Ok((self.width, height.max(1) as u32))
+            }
+            _ => bail!(
+                "Invalid art direction variant `{}@{}`: the ratio needs to look like `16:9` and the width be positive",
+                self.ratio,
+                self.width
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImageProcessing {
+    /// The directory, relative to `static`, the processed images are written to.
+    /// Defaults to `processed_images`
+    pub processed_dir: String,
+    /// The path of the URLs of the processed images, eg `img` for `https://example.com/img/...`.
+    /// The images are moved there in the output directory. Defaults to `processed_dir`
+    pub url_prefix: Option<String>,
+    /// The pattern used to name the processed images, eg `{stem}-{width}x{height}.{ext}`.
+    /// Defaults to `{hash}.{ext}`
+    pub filename_template: String,
+    /// Whether asking for an output format that this build of Zola can't encode (eg AVIF)
+    /// falls back to the `auto` format with a warning instead of erroring. Defaults to false
+    pub fallback_unsupported_formats: bool,
+    /// Whether `resize_image` prints a warning and returns the original image, copied next to
+    /// the processed ones, instead of erroring when an image can't be processed. Defaults to false
+    pub fallback_to_original: bool,
+    /// Whether `resize_image` and `get_image_metadata` print a warning and return `null`
+    /// instead of erroring when a source can't be decoded, eg a truncated JPEG. Defaults to false
+    pub skip_corrupt_images: bool,
+    /// Whether JPEGs are resized from the thumbnail embedded in their EXIF data, which is
+    /// much faster to decode, when it's at least as big as the output and of good enough quality.
+    /// Defaults to false
+    pub use_embedded_thumbnails: bool,
+    /// Whether sources with the same content, eg copies of an image in several sections, share
+    /// their processed images instead of each getting their own. Each source is then read once
+    /// to be hashed. Defaults to false
+    pub dedup_identical_sources: bool,
+    /// Whether processed images leave out the metadata of their source, eg the GPS coordinates
+    /// in the EXIF data of photos. `resize_image` can override it. Defaults to true
+    pub strip_metadata: bool,
+    /// A TOML or JSON file, relative to the site root, mapping image paths to their alt text.
+    /// Used by `get_alt`
+    pub alt_text_file: Option<String>,
+    /// If set, `get_alt` makes an alt text out of the filename of images without one
+    /// instead of returning an empty string
+    pub alt_fallback: Option<AltFallback>,
+    /// Whether the images left without alt text by `get_alt` and `markdown_images` fail the build,
+    /// all of them being listed at its end, instead of only printing a warning. Defaults to false
+    pub strict_alt: bool,
+    /// What is appended to the filename of an image to get the path of its sidecar file read by
+    /// `get_image_sidecar`, ending with `.toml` or `.json`. Defaults to `.meta.toml`
+    pub sidecar_suffix: String,
+    /// The command `image_ocr` runs to read the text of an image, as the program followed by its
+    /// arguments. `{path}` is replaced by the path of the image, which is appended when no
+    /// argument contains it. The text is read from its output. Defaults to no OCR
+    pub ocr_command: Option<Vec<String>>,
+    /// If set, a JSON log of every image processed during the build is written to that path,
+    /// relative to the site root
+    pub processing_log: Option<String>,
+    /// If set, the processed images are listed in that file, relative to the site root, with
+    /// headers marking them as immutable for hosts and CDNs
+    pub cache_headers_file: Option<String>,
+    /// Whether a `manifest.json` listing the URL of every processed image is written to the
+    /// processed images directory at the end of the build. Defaults to false
+    pub write_manifest: bool,
+    /// The format of `cache_headers_file`. Defaults to the `_headers` format
+    pub cache_headers_format: CacheHeadersFormat,
+    /// Named sizes in pixels that can be used as `width` and `height` of `resize_image`
+    /// by prefixing them with `@`, eg `width="@content-width"`
+    pub sizes: HashMap<String, u32>,
+    /// How many colors the gradients of `image_gradient` are made of. Defaults to 4
+    pub gradient_colors: u32,
+    /// When `resize_image` isn't given a quality, caps the quality of JPEGs made from
+    /// JPEGs near the estimated quality of the source. Defaults to false
+    pub clamp_quality_to_source: bool,
+    /// Named sets of crops that `resize_image` generates all at once when given `art_direction`
+    pub art_direction: HashMap<String, Vec<ArtDirectionVariant>>,
+    /// Whether the responsive sets, ie `resize_image` with `art_direction` and the images of
+    /// `markdown_images`, include a tiny blurred preview to show while they load, as a `data:` URI.
+    /// Can be changed per call with `preview`. Defaults to false
+    pub responsive_previews: bool,
+    /// Whether the crop and orientation recorded in the EXIF data of images, eg by the RAW editor
+    /// they were exported from, are applied before resizing them, even with `respect_exif=false`.
+    /// The orientation alone is applied otherwise. Defaults to false
+    pub apply_exif_transforms: bool,
+    /// Whether to print how long decoding, resizing and encoding each image took at the end
+    /// of the processing, and add it to the processing log. Defaults to false
+    pub report_timings: bool,
+    /// Directories, relative to the site root, that every processed image is also copied to.
+    /// URLs still point to the images in `static/<processed_dir>`
+    pub extra_output_dirs: Vec<String>,
+    /// What to do when the processed images were made by another version of the encoders.
+    /// Defaults to regenerating them
+    pub on_encoder_change: EncoderChangePolicy,
+    /// How many times bigger than the requested `width` and `height` the processed images are,
+    /// for high density screens. Defaults to 1
+    pub pixel_ratio: u32,
+    /// How `fit_width`, `fit_height` and `fit` round the dimension they compute to keep the
+    /// aspect ratio. Defaults to rounding down, which is what the integer division used before
+    /// it existed did, so existing images keep their dimensions and filenames
+    pub resize_rounding: ResizeRounding,
+    /// The extensions, without the dot, of the images `resize_image` accepts as source.
+    /// Defaults to all the formats Zola can decode
+    pub allowed_source_extensions: Vec<String>,
+    /// The maximum width and height of the images `resize_image` makes, by output format
+    /// extension, eg `png`. Bigger images are downscaled with a warning. Defaults to no limit
+    pub max_dimensions: HashMap<String, u32>,
+    /// Whether `resize_image` can make images bigger than their source, which blurs them.
+    /// Defaults to allowing it
+    pub forbid_upscale: ForbidUpscale,
+    /// Processes the images one after the other in a fixed order, always regenerates them when
+    /// the encoders changed and leaves the timings out of the processing log, so builds
+    /// from the same sources give byte-identical outputs. Defaults to false
+    pub reproducible: bool,
+    /// How many images are processed at the same time at the end of the build.
+    /// Defaults to the number of threads of the rest of the build, one per CPU
+    pub threads: Option<usize>,
+    /// The most pixels an image can have, as declared by its header, to be processed or read by
+    /// `get_image_metadata`, so huge images are refused before being decoded.
+    /// Defaults to 16384x16384
+    pub max_pixels: u64,
+    /// The biggest width and height `resize_image` can make an image, so huge dimensions fail
+    /// the build with an error instead of running out of memory. Defaults to 20000
+    pub max_image_dimension: u32,
+    /// Whether `resize_image` and `get_image_metadata` accept `http://` and `https://` URLs as
+    /// `path`, downloading each image once into `.remote-images`. Defaults to false
+    pub allow_remote_images: bool,
+    /// How many remote images are downloaded at the same time, whatever the number of pages
+    /// rendered in parallel. Defaults to 4
+    pub max_concurrent_fetches: usize,
+    /// How many seconds downloading a remote image can take before it's retried or fails the
+    /// build. Defaults to 30
+    pub fetch_timeout: u64,
+    /// How many more times a download failing with a timeout, a connection error or a 5xx
+    /// status is tried, waiting twice as long before each new try. Defaults to 2
+    pub fetch_retries: u32,
+    /// How many bytes a remote image can be at most, bigger ones failing the build without being
+    /// downloaded entirely. Defaults to 20 MB
+    pub max_remote_image_bytes: u64,
+    /// Directories of the site, relative to its root, where `resize_image` and
+    /// `get_image_metadata` look for the images they can't find in `content` and `static`,
+    /// in that order. Defaults to none
+    pub asset_dirs: Vec<String>,
+    /// Whether processed images that are byte for byte identical, eg made from copies of the
+    /// same source, are hardlinks to one file instead of separate copies. The copies are kept on
+    /// file systems without hardlinks. Defaults to false
+    pub hardlink_identical: bool,
+    /// If set, the colocated images of Markdown content are processed and rendered as
+    /// responsive `<img>`. Defaults to leaving them untouched
+    pub markdown_images: Option<MarkdownImages>,
+}
+
+impl ImageProcessing {
+    /// Makes sure the filename template only uses known placeholders and will
+    /// not generate the same filename for different images
+    pub fn validate(&self) -> Result<()> {
+        let template = &self.filename_template;
+
+        let mut rest = template.as_str();
+        while let Some(start) = rest.find('{') {
+            let end = match rest[start..].find('}') {
+                Some(e) => start + e + 1,
+                None => bail!("Unclosed placeholder in `filename_template`: {}", template),
+            };
+            if !FILENAME_PLACEHOLDERS.contains(&&rest[start..end]) {
+                bail!(
+                    "Unknown placeholder {} in `filename_template`, valid ones are: {}",
+                    &rest[start..end],
+                    FILENAME_PLACEHOLDERS.join(", ")
+                );
+            }
+            rest = &rest[end..];
+        }
+
+        if template.contains('/') || template.contains('\\') {
+            bail!("`filename_template` cannot contain a path separator: {}", template);
+        }
+        if !template.contains("{ext}") {
+            bail!("`filename_template` needs to contain `{{ext}}`: {}", template);
+        }
+        if !template.contains("{hash}")
+            && !(template.contains("{width}") && template.contains("{height}"))
+        {
+            bail!(
+                "`filename_template` needs to contain either `{{hash}}` or both `{{width}}` and `{{height}}` to generate unique filenames: {}",
+                template
+            );
+        }
+
+        let asset_dirs = self.asset_dirs.iter().map(|dir| ("asset_dirs", dir.as_str()));
+        for (name, dir) in
+            [("processed_dir", self.processed_dir.as_str()), ("url_prefix", self.url_prefix())]
+                .iter()
+                .copied()
+                .chain(asset_dirs)
+        {
+            let dir = dir.trim_matches('/');
+            if dir.is_empty()
+                || dir.split('/').any(|part| part.is_empty() || part == "." || part == "..")
+            {
+                bail!("`{}` needs to be a relative path without `.` or `..`, got `{}`", name, dir);
+            }
+        }
+
+        let processed_dir = self.processed_dir.trim_matches('/');
+        let url_prefix = self.url_prefix();
+        if processed_dir != url_prefix
+            && (url_prefix.starts_with(&format!("{}/", processed_dir))
+                || processed_dir.starts_with(&format!("{}/", url_prefix)))
+        {
+            bail!("`url_prefix` and `processed_dir` cannot be inside one another");
+        }
+
+        if !self.sidecar_suffix.ends_with(".toml") && !self.sidecar_suffix.ends_with(".json") {
+            bail!("`sidecar_suffix` needs to end with `.toml` or `.json`: {}", self.sidecar_suffix);
+        }
+
+        if matches!(self.ocr_command, Some(ref command) if command.is_empty()) {
+            bail!("`ocr_command` needs to contain at least the program to run");
+        }
+
+        if let Some((format, _)) = self.max_dimensions.iter().find(|(_, max)| **max == 0) {
+            bail!("The max dimension of {} images needs to be at least 1", format);
+        }
+
+        if let Some(ref markdown_images) = self.markdown_images {
+            if markdown_images.widths.is_empty() || markdown_images.widths.contains(&0) {
+                bail!("`markdown_images.widths` needs at least one width, all of them positive");
+            }
+        }
+
+        if self.pixel_ratio == 0 {
+            bail!("`pixel_ratio` needs to be at least 1");
+        }
+        if self.threads == Some(0) {
+            bail!("`threads` needs to be at least 1");
+        }
+        if self.max_pixels == 0 {
+            bail!("`max_pixels` needs to be at least 1");
+        }
+        if self.max_image_dimension == 0 {
+            bail!("`max_image_dimension` needs to be at least 1");
+        }
+        if self.max_concurrent_fetches == 0 {
+            bail!("`max_concurrent_fetches` needs to be at least 1");
+        }
+        if self.fetch_timeout == 0 {
+            bail!("`fetch_timeout` needs to be at least 1 second");
+        }
+        if self.max_remote_image_bytes == 0 {
+            bail!("`max_remote_image_bytes` needs to be at least 1");
+        }
+        if self.fetch_retries > MAX_FETCH_RETRIES {
+            bail!(
+                "`fetch_retries` can't be more than {}, got {}",
+                MAX_FETCH_RETRIES,
+                self.fetch_retries
+            );
+        }
+        if self.gradient_colors < 2 {
+            bail!("`gradient_colors` needs to be at least 2, got {}", self.gradient_colors);
+        }
+
+        for (name, variants) in &self.art_direction {
+            for variant in variants {
+                variant.dimensions()?;
+            }
+            if variants.iter().filter(|v| v.media.is_none()).count() != 1 {
+                bail!(
+                    "The art direction rule set `{}` needs exactly one variant without `media` to use as fallback",
+                    name
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The path of the URLs of the processed images, without leading and trailing slashes
+    pub fn url_prefix(&self) -> &str {
+        match self.url_prefix {
+            Some(ref prefix) => prefix.trim_matches('/'),
+            None => self.processed_dir.trim_matches('/'),
+        }
+    }
+
+    /// Whether the extension of that source image is in `allowed_source_extensions`, ignoring case
+    pub fn is_allowed_source<P: AsRef<Path>>(&self, source: P) -> bool {
+        let ext = match source.as_ref().extension() {
+            Some(ext) => ext.to_string_lossy().to_lowercase(),
+            None => return false,
+        };
+        self.allowed_source_extensions
+            .iter()
+            .any(|allowed| allowed.trim_start_matches('.').to_lowercase() == ext)
+    }
+
+    /// The variants of the art direction rule set with that name
+    pub fn art_direction_rule(&self, name: &str) -> Result<&[ArtDirectionVariant]> {
+        match self.art_direction.get(name) {
+            Some(variants) => Ok(variants),
+            None => {
+                let mut available: Vec<_> = self.art_direction.keys().map(|k| k.as_str()).collect();
+                available.sort_unstable();
+                bail!(
+                    "Unknown art direction rule set `{}`, available ones are: {}",
+                    name,
+                    available.join(", ")
+                )
+            }
+        }
+    }
+}
+
+impl Default for ImageProcessing {
+    fn default() -> Self {
+        ImageProcessing {
+            processed_dir: DEFAULT_PROCESSED_DIR.to_string(),
+            url_prefix: None,
+            filename_template: DEFAULT_FILENAME_TEMPLATE.to_string(),
+            fallback_unsupported_formats: false,
+            fallback_to_original: false,
+            skip_corrupt_images: false,
+            use_embedded_thumbnails: false,
+            dedup_identical_sources: false,
+            strip_metadata: true,
+            alt_text_file: None,
+            alt_fallback: None,
+            strict_alt: false,
+            sidecar_suffix: DEFAULT_SIDECAR_SUFFIX.to_string(),
+            ocr_command: None,
+            processing_log: None,
+            cache_headers_file: None,
+            write_manifest: false,
+            cache_headers_format: CacheHeadersFormat::Headers,
+            sizes: HashMap::new(),
+            gradient_colors: 4,
+            clamp_quality_to_source: false,
+            art_direction: HashMap::new(),
+            responsive_previews: false,
+            apply_exif_transforms: false,
+            report_timings: false,
+            extra_output_dirs: Vec::new(),
+            on_encoder_change: EncoderChangePolicy::Regenerate,
+            pixel_ratio: 1,
+            resize_rounding: ResizeRounding::Floor,
+            allowed_source_extensions: DEFAULT_SOURCE_EXTENSIONS
+                .iter()
+                .map(|ext| ext.to_string())
+                .collect(),
+            reproducible: false,
+            threads: None,
+            max_pixels: 16384 * 16384,
+            max_image_dimension: 20000,
+            allow_remote_images: false,
+            max_concurrent_fetches: 4,
+            fetch_timeout: 30,
+            fetch_retries: 2,
+            max_remote_image_bytes: 20_000_000,
+            asset_dirs: Vec::new(),
+            hardlink_identical: false,
+            max_dimensions: HashMap::new(),
+            forbid_upscale: ForbidUpscale::Off,
+            markdown_images: None,
+        }
+    }
+}
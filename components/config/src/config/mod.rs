@@ -1,3 +1,4 @@
+pub mod image_processing;
 pub mod languages;
 pub mod link_checker;
 pub mod markup;
@@ -107,6 +108,9 @@ pub struct Config {
     /// The config for the Markdown rendering: syntax highlighting and everything
     pub markdown: markup::Markdown,
 
+    /// The config for processed images: naming and everything
+    pub image_processing: image_processing::ImageProcessing,
+
     /// All user params set in [extra] in the config
     pub extra: HashMap<String, Toml>,
 }
@@ -150,6 +154,8 @@ impl Config {
                 Some(glob_set_builder.build().expect("Bad ignored_content in config file."));
         }
 
+        config.image_processing.validate()?;
+
         for taxonomy in config.taxonomies.iter_mut() {
             if taxonomy.lang.is_empty() {
                 taxonomy.lang = config.default_language.clone();
@@ -383,6 +389,7 @@ impl Default for Config {
             slugify: slugify::Slugify::default(),
             search: search::Search::default(),
             markdown: markup::Markdown::default(),
+            image_processing: image_processing::ImageProcessing::default(),
             extra: HashMap::new(),
         }
     }
@@ -724,4 +731,162 @@ output_dir = "docs"
         let config = Config::parse(config).unwrap();
         assert_eq!(config.output_dir, "docs".to_string());
     }
+
+    #[test]
+    fn default_image_filename_template() {
+        let config = r#"
+base_url = "https://replace-this-with-your-url.com"
+        "#;
+
+        let config = Config::parse(config).unwrap();
+        assert_eq!(config.image_processing.filename_template, "{hash}.{ext}");
+    }
+
+    #[test]
+    fn can_set_image_filename_template() {
+        let config = r#"
+base_url = "https://replace-this-with-your-url.com"
+
+[image_processing]
+filename_template = "{stem}-{width}x{height}.{ext}"
+        "#;
+
+        let config = Config::parse(config).unwrap();
+        assert_eq!(config.image_processing.filename_template, "{stem}-{width}x{height}.{ext}");
+    }
+
+    #[test]
+    fn error_on_non_unique_image_filename_template() {
+        let config = r#"
+base_url = "https://replace-this-with-your-url.com"
+
+[image_processing]
+filename_template = "{stem}-{width}.{ext}"
+        "#;
+
+        assert!(Config::parse(config).is_err());
+    }
+
+    #[test]
+    fn error_on_unknown_image_filename_placeholder() {
+        let config = r#"
+base_url = "https://replace-this-with-your-url.com"
+
+[image_processing]
+filename_template = "{hash}-{quality}.{ext}"
+        "#;
+
+        assert!(Config::parse(config).is_err());
+    }
+
+    #[test]
+    fn can_set_art_direction_rules() {
+        let config = r#"
+base_url = "https://replace-this-with-your-url.com"
+
+[image_processing.art_direction]
+hero = [
+    { media = "(max-width: 600px)", ratio = "1:1", width = 360 },
+    { ratio = "16:9", width = 1200 },
+]
+        "#;
+
+        let config = Config::parse(config).unwrap();
+        let variants = config.image_processing.art_direction_rule("hero").unwrap();
+        assert_eq!(variants[0].dimensions().unwrap(), (360, 360));
+        assert_eq!(variants[1].dimensions().unwrap(), (1200, 675));
+        assert!(config.image_processing.art_direction_rule("banner").is_err());
+    }
+
+    #[test]
+    fn error_on_art_direction_rule_without_fallback() {
+        let config = r#"
+base_url = "https://replace-this-with-your-url.com"
+
+[image_processing.art_direction]
+hero = [{ media = "(max-width: 600px)", ratio = "1:1", width = 360 }]
+        "#;
+
+        assert!(Config::parse(config).is_err());
+    }
+
+    #[test]
+    fn can_restrict_source_extensions() {
+        let config = r#"
+base_url = "https://replace-this-with-your-url.com"
+
+[image_processing]
+allowed_source_extensions = ["JPG", "png"]
+        "#;
+
+        let config = Config::parse(config).unwrap();
+        assert!(config.image_processing.is_allowed_source("photos/cat.jpg"));
+        assert!(config.image_processing.is_allowed_source("photos/logo.PNG"));
+        assert!(!config.image_processing.is_allowed_source("photos/scan.tiff"));
+        assert!(!config.image_processing.is_allowed_source("photos/README"));
+        assert!(Config::default().image_processing.is_allowed_source("photos/scan.tiff"));
+    }
+
+    #[test]
+    fn can_make_alt_text_from_filenames() {
+        use image_processing::{AltCase, AltFallback};
+
+        let config = r#"
+base_url = "https://replace-this-with-your-url.com"
+
+[image_processing.alt_fallback]
+case = "sentence"
+        "#;
+
+        let config = Config::parse(config).unwrap();
+        let fallback = config.image_processing.alt_fallback.unwrap();
+        assert_eq!(fallback.alt_from_filename("gallery/beach-SUNSET_2.jpg"), "Beach sunset 2");
+        let fallback = AltFallback { case: AltCase::Title, ..AltFallback::default() };
+        assert_eq!(fallback.alt_from_filename("beach-sunset.jpg"), "Beach Sunset");
+        assert_eq!(AltFallback::default().alt_from_filename("beach-sunset.jpg"), "beach sunset");
+    }
+
+    #[test]
+    fn errors_on_markdown_images_without_widths() {
+        let config = r#"
+base_url = "https://replace-this-with-your-url.com"
+
+[image_processing.markdown_images]
+widths = []
+        "#;
+
+        let config = Config::parse(config);
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn can_serve_processed_images_from_another_url() {
+        let config = r#"
+base_url = "https://replace-this-with-your-url.com"
+
+[image_processing]
+url_prefix = "/img/"
+        "#;
+
+        let config = Config::parse(config).unwrap();
+        assert_eq!(config.image_processing.processed_dir, "processed_images");
+        assert_eq!(config.image_processing.url_prefix(), "img");
+        assert_eq!(Config::default().image_processing.url_prefix(), "processed_images");
+
+        let config = r#"
+base_url = "https://replace-this-with-your-url.com"
+
+[image_processing]
+processed_dir = "img"
+url_prefix = "img/small"
+        "#;
+        assert!(Config::parse(config).is_err());
+        let config = r#"
+base_url = "https://replace-this-with-your-url.com"
+
+[image_processing]
+url_prefix = "../img"
+        "#;
+        assert!(Config::parse(config).is_err());
+    }
 }
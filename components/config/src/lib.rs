@@ -2,7 +2,15 @@ mod config;
 pub mod highlighting;
 mod theme;
 pub use crate::config::{
-    languages::Language, link_checker::LinkChecker, slugify::Slugify, taxonomies::Taxonomy, Config,
+    image_processing::{
+        AltCase, AltFallback, ArtDirectionVariant, CacheHeadersFormat, EncoderChangePolicy,
+        ForbidUpscale, ImageProcessing, MarkdownImages, ResizeRounding,
+    },
+    languages::Language,
+    link_checker::LinkChecker,
+    slugify::Slugify,
+    taxonomies::Taxonomy,
+    Config,
 };
 
 use std::path::Path;
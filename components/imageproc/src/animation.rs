@@ -0,0 +1,177 @@
+//! Reads the frames of animated GIFs, PNGs and WebPs from their container, without decoding them.
+
+use std::fs;
+use std::path::Path;
+
+use serde_derive::Serialize;
+
+use errors::Result;
+
+/// How an animated image plays
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Animation {
+    /// The number of frames
+    pub frames: u32,
+    /// How long each frame is displayed, in milliseconds
+    pub delays: Vec<u32>,
+    /// How long a single loop of the animation lasts, in milliseconds
+    pub duration_ms: u64,
+}
+
+impl Animation {
+    fn from_delays(delays: Vec<u32>) -> Option<Animation> {
+        if delays.len() < 2 {
+            return None;
+        }
+        Some(Animation {
+            frames: delays.len() as u32,
+            duration_ms: delays.iter().map(|d| u64::from(*d)).sum(),
+            delays,
+        })
+    }
+}
+
+/// Reads the frames of the image at that path.
+/// Returns `None` for images that are not animated, including animations of a single frame,
+/// and for formats that can't be animated.
+pub fn read_animation<P: AsRef<Path>>(path: P) -> Result<Option<Animation>> {
+    let data = fs::read(path)?;
+    let delays = if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        gif_delays(&data)
+    } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        apng_delays(&data)
+    } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(&b"WEBP"[..]) {
+        webp_delays(&data)
+    } else {
+        None
+    };
+    Ok(delays.and_then(Animation::from_delays))
+}
+
+/// Goes through the blocks of a GIF, the delay of a frame being in the graphic control
+/// extension preceding it, in hundredths of a second
+fn gif_delays(data: &[u8]) -> Option<Vec<u32>> {
+    let mut delays = Vec::new();
+    let mut pos = 13 + color_table_size(*data.get(10)?);
+    let mut delay = 0;
+    loop {
+        match *data.get(pos)? {
+            // Extension
+            0x21 => {
+                if *data.get(pos + 1)? == 0xF9 {
+                    delay =
+                        u32::from(u16::from_le_bytes([*data.get(pos + 4)?, *data.get(pos + 5)?]));
+                }
+                pos = skip_sub_blocks(data, pos + 2)?;
+            }
+            // Image descriptor, followed by the LZW code size and the image data
+            0x2C => {
+                delays.push(delay * 10);
+                delay = 0;
+                pos += 10 + color_table_size(*data.get(pos + 9)?);
+                pos = skip_sub_blocks(data, pos + 1)?;
+            }
+            // Trailer
+            0x3B => return Some(delays),
+            _ => return None,
+        }
+    }
+}
+
+/// Copies the NETSCAPE2.0 application extension of the GIF `source`, which makes it loop,
+/// into the GIF `target` right after its header, as the encoder doesn't write one.
+/// `target` is left as is if `source` doesn't loop
+pub fn copy_gif_repeat(source: &[u8], target: &mut Vec<u8>) {
+    let extension = match netscape_extension(source) {
+        Some(extension) => extension,
+        None => return,
+    };
+    let pos = match target.get(10) {
+        Some(packed) => 13 + color_table_size(*packed),
+        None => return,
+    };
+    if pos <= target.len() {
+        target.splice(pos..pos, extension.iter().copied());
+    }
+}
+
+/// The NETSCAPE2.0 extension of a GIF, which comes before its first frame
+fn netscape_extension(data: &[u8]) -> Option<&[u8]> {
+    let mut pos = 13 + color_table_size(*data.get(10)?);
+    while *data.get(pos)? == 0x21 {
+        let end = skip_sub_blocks(data, pos + 2)?;
+        if *data.get(pos + 1)? == 0xFF && data.get(pos + 3..pos + 14) == Some(&b"NETSCAPE2.0"[..]) {
+            return data.get(pos..end);
+        }
+        pos = end;
+    }
+    None
+}
+
+/// The size of the color table announced by the packed field of a GIF header or descriptor
+fn color_table_size(packed: u8) -> usize {
+    if packed & 0x80 == 0 {
+        0
+    } else {
+        3 << ((packed & 0x07) + 1)
+    }
+}
+
+/// Returns the position after the sub-blocks starting at `pos`
+fn skip_sub_blocks(data: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *data.get(pos)? as usize;
+        pos += 1 + len;
+        if len == 0 {
+            return Some(pos);
+        }
+    }
+}
+
+/// Collects the delays of the frame control chunks of an APNG, which are fractions of a second
+fn apng_delays(data: &[u8]) -> Option<Vec<u32>> {
+    let mut delays = Vec::new();
+    let mut animated = false;
+    let mut pos = 8;
+    while let Some(header) = data.get(pos..pos + 8) {
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let chunk = data.get(pos + 8..pos + 8 + len)?;
+        match &header[4..] {
+            b"acTL" => animated = true,
+            b"fcTL" => {
+                let num = u32::from(u16::from_be_bytes([*chunk.get(20)?, *chunk.get(21)?]));
+                // A denominator of 0 means hundredths of a second
+                let den = match u16::from_be_bytes([*chunk.get(22)?, *chunk.get(23)?]) {
+                    0 => 100,
+                    den => u32::from(den),
+                };
+                delays.push(num * 1000 / den);
+            }
+            b"IEND" => break,
+            _ => (),
+        }
+        // Length, type, data and CRC
+        pos += 12 + len;
+    }
+    if animated {
+        Some(delays)
+    } else {
+        None
+    }
+}
+
+/// Collects the durations of the frames of an animated WebP, in milliseconds
+fn webp_delays(data: &[u8]) -> Option<Vec<u32>> {
+    let mut delays = Vec::new();
+    let mut pos = 12;
+    while let Some(header) = data.get(pos..pos + 8) {
+        let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        if &header[..4] == b"ANMF" {
+            let duration = data.get(pos + 20..pos + 23)?;
+            delays.push(u32::from_le_bytes([duration[0], duration[1], duration[2], 0]));
+        }
+        // Chunks are padded to an even size
+        pos += 8 + len + (len & 1);
+    }
+    Some(delays)
+}
@@ -0,0 +1,71 @@
+//! The average color and the palette of images, read by `get_image_metadata` with `compute_color`.
+
+use std::collections::HashMap;
+
+use image::{DynamicImage, GenericImageView};
+use serde_derive::Serialize;
+
+/// The size of the square the image is downscaled to fit in before its colors are read,
+/// which is plenty for an average and a few colors
+const COLOR_SAMPLE_SIZE: u32 = 64;
+/// The number of colors of the palette, the palettes asked for being its first colors
+pub const MAX_PALETTE_SIZE: usize = 16;
+
+/// The colors of an image, as `#rrggbb` hex strings
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ImageColors {
+    /// The average of all the pixels of the image, weighted by their opacity
+    pub average: String,
+    /// The most common colors of the image, most common first
+    pub palette: Vec<String>,
+}
+
+/// Reads the colors of a decoded image out of a downscaled copy of it
+pub fn image_colors(img: &DynamicImage) -> ImageColors {
+    let sample = if img.width().max(img.height()) > COLOR_SAMPLE_SIZE {
+        img.thumbnail(COLOR_SAMPLE_SIZE, COLOR_SAMPLE_SIZE).to_rgba8()
+    } else {
+        img.to_rgba8()
+    };
+
+    // Fully transparent images are averaged as if they weren't
+    let opaque = sample.pixels().any(|p| p.0[3] > 0);
+    let mut totals = [0u64; 3];
+    let mut weights = 0u64;
+    // Colors with 4 bits per channel, with the total of their channels and their pixel count
+    let mut buckets: HashMap<u16, ([u64; 3], u64)> = HashMap::new();
+    for pixel in sample.pixels() {
+        let [r, g, b, a] = pixel.0;
+        let weight = if opaque { u64::from(a) } else { 1 };
+        if weight == 0 {
+            continue;
+        }
+        for (total, channel) in totals.iter_mut().zip(&[r, g, b]) {
+            *total += u64::from(*channel) * weight;
+        }
+        weights += weight;
+
+        let key = (u16::from(r >> 4) << 8) | (u16::from(g >> 4) << 4) | u16::from(b >> 4);
+        let bucket = buckets.entry(key).or_insert(([0; 3], 0));
+        for (total, channel) in bucket.0.iter_mut().zip(&[r, g, b]) {
+            *total += u64::from(*channel);
+        }
+        bucket.1 += 1;
+    }
+
+    let hex = |totals: [u64; 3], count: u64| {
+        let channel = |total: u64| (total + count / 2) / count.max(1);
+        format!("#{:02x}{:02x}{:02x}", channel(totals[0]), channel(totals[1]), channel(totals[2]))
+    };
+    let mut buckets: Vec<_> = buckets.into_iter().collect();
+    // Ties go to the smallest key so the palette is the same on every build
+    buckets.sort_by(|(key_a, (_, a)), (key_b, (_, b))| b.cmp(a).then(key_a.cmp(key_b)));
+    ImageColors {
+        average: hex(totals, weights),
+        palette: buckets
+            .into_iter()
+            .take(MAX_PALETTE_SIZE)
+            .map(|(_, (totals, count))| hex(totals, count))
+            .collect(),
+    }
+}
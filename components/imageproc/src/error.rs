@@ -0,0 +1,147 @@
+//! Why the arguments given to `resize_image` can't make an `ImageOp`.
+
+use std::error::Error as StdError;
+use std::fmt;
+
+use errors::Error;
+
+use crate::{RESIZE_OPS, SUPPORTED_FORMATS};
+
+/// The error of `ImageOp::from_args`, the variants being what callers can react to
+#[derive(Debug)]
+pub enum ImageOpError {
+    /// The source is not one of the `allowed_source_extensions`
+    NotAllowedSource { source: String, allowed: Vec<String> },
+    /// The source is not an image, or not one Zola can read
+    UnsupportedSource(String),
+    /// The `op` is not one of the resize operations
+    UnknownOp(String),
+    /// The `format` is not one of the `SUPPORTED_FORMATS`
+    UnknownFormat(String),
+    /// The `format` exists but its encoder is not compiled into this build of Zola
+    UnavailableFormat(String),
+    /// The `quality` is not within 1-100
+    InvalidQuality(u8),
+    /// The `op` needs arguments that were not given, `missing` being eg "a `width`"
+    MissingDimensions { op: String, missing: &'static str },
+    /// Arguments that can't be used together, or with that `op`
+    IncompatibleArguments(String),
+    /// An argument with an invalid value, eg an unknown named size or a crop going beyond
+    /// the source
+    InvalidArgument(Error),
+    /// The source is animated and the output format can't be, unless `first_frame_only` is set
+    AnimatedSource { source: String, format: &'static str },
+    /// The source is animated and `format="webp"`, which this build of Zola can't encode, let
+    /// alone animated
+    AnimatedWebp(String),
+    /// The source is an animation that can't be resized as one, only GIFs can
+    UnsupportedAnimation(String),
+    /// The output would be bigger than the source and `forbid_upscale` is set to "error"
+    Upscaled { source: String, from: (u32, u32), to: (u32, u32) },
+    /// A dimension of the output, `dimension` being "width" or "height", is above
+    /// `max_image_dimension`
+    TooLarge { source: String, dimension: &'static str, value: u32, max: u32 },
+    /// The source couldn't be read
+    Source(Error),
+}
+
+impl fmt::Display for ImageOpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ImageOpError::*;
+
+        match self {
+            NotAllowedSource { source, allowed } => write!(
+                f,
+                "`{}` is not an allowed source image, `allowed_source_extensions` only allows: {}",
+                source,
+                allowed.join(", ")
+            ),
+            UnsupportedSource(source) => write!(f, "Unsupported image file: {}", source),
+            UnknownOp(op) => write!(
+                f,
+                "Invalid image resize operation: {}, it needs to be one of: {}",
+                op,
+                RESIZE_OPS.join(", ")
+            ),
+            UnknownFormat(format) => write!(
+                f,
+                "Invalid image format: {}, supported formats are: {}",
+                format,
+                SUPPORTED_FORMATS.join(", ")
+            ),
+            UnavailableFormat(format) => {
+                let encoder = match format.as_str() {
+                    "webp" => "WebP",
+                    "webp-lossless" => "lossless WebP",
+                    _ => "AVIF",
+                };
+                write!(
+                    f,
+                    "format=\"{}\" needs the {} encoder, which is not compiled into this Zola build",
+                    format, encoder
+                )
+            }
+            InvalidQuality(quality) => {
+                write!(f, "Invalid quality: {}, it needs to be within 1-100", quality)
+            }
+            MissingDimensions { op, missing } => {
+                write!(f, "op=\"{}\" requires {} argument", op, missing)
+            }
+            IncompatibleArguments(msg) => write!(f, "{}", msg),
+            InvalidArgument(e) | Source(e) => write!(f, "{}", e),
+            AnimatedSource { source, format } => write!(
+                f,
+                "{} is animated and {} images can't be, use format=\"gif\" to keep the animation or `first_frame_only=true` to only keep its first frame",
+                source,
+                format.to_uppercase()
+            ),
+            AnimatedWebp(source) => write!(
+                f,
+                "{} is animated and this build of Zola can't encode animated WebP, use format=\"gif\" to keep the animation or `first_frame_only=true` to only keep its first frame",
+                source
+            ),
+            UnsupportedAnimation(source) => write!(
+                f,
+                "{} is animated but only the animations of GIFs can be resized, use `first_frame_only=true` to only keep its first frame",
+                source
+            ),
+            Upscaled { source, from, to } => write!(
+                f,
+                "{} would be upscaled from {}x{} to {}x{} and `forbid_upscale` is set to \"error\"",
+                source, from.0, from.1, to.0, to.1
+            ),
+            TooLarge { source, dimension, value, max } => write!(
+                f,
+                "The {} of the image made of {} would be {}px, above the {}px of `max_image_dimension`",
+                dimension, source, value, max
+            ),
+        }
+    }
+}
+
+impl StdError for ImageOpError {
+    // The message of the inner errors is already the one of the variant, only their causes
+    // are left
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            ImageOpError::InvalidArgument(e) | ImageOpError::Source(e) => e.source(),
+            _ => None,
+        }
+    }
+}
+
+impl From<Error> for ImageOpError {
+    fn from(e: Error) -> Self {
+        ImageOpError::Source(e)
+    }
+}
+
+impl From<ImageOpError> for Error {
+    fn from(e: ImageOpError) -> Self {
+        match e {
+            // Passed through so their causes are kept
+            ImageOpError::InvalidArgument(e) | ImageOpError::Source(e) => e,
+            e => Error::msg(e),
+        }
+    }
+}
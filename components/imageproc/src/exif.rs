@@ -0,0 +1,686 @@
+//! A small reader for the metadata tags embedded in images: the EXIF data of JPEGs and PNGs,
+//! the text chunks of PNGs and the pixel density of both.
+//! Only the first IFD of the EXIF data is read, it's where the tags about the main image live.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use image::DynamicImage;
+
+use errors::Result;
+
+pub const TAG_SOFTWARE: u16 = 0x0131;
+pub const TAG_ARTIST: u16 = 0x013B;
+pub const TAG_DATE_TIME: u16 = 0x0132;
+pub const TAG_PHOTOMETRIC_INTERPRETATION: u16 = 0x0106;
+pub const TAG_SAMPLES_PER_PIXEL: u16 = 0x0115;
+pub const TAG_DATE_TIME_ORIGINAL: u16 = 0x9003;
+pub const TAG_ORIENTATION: u16 = 0x0112;
+pub const TAG_MAKE: u16 = 0x010F;
+pub const TAG_USER_COMMENT: u16 = 0x9286;
+pub const TAG_X_RESOLUTION: u16 = 0x011A;
+pub const TAG_Y_RESOLUTION: u16 = 0x011B;
+/// The crop of DNG files, which RAW editors also write in the JPEGs they export
+pub const TAG_DEFAULT_CROP_ORIGIN: u16 = 0xC61F;
+pub const TAG_DEFAULT_CROP_SIZE: u16 = 0xC620;
+const TAG_EXIF_IFD: u16 = 0x8769;
+/// Where the JPEG thumbnail of the second IFD starts and how long it is
+const TAG_THUMBNAIL_OFFSET: u16 = 0x0201;
+const TAG_THUMBNAIL_LENGTH: u16 = 0x0202;
+
+pub(crate) const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// The value of an EXIF field, only the types we make use of are kept
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExifValue {
+    Ascii(String),
+    Short(Vec<u16>),
+    Long(Vec<u32>),
+    /// Numerators and denominators
+    Rational(Vec<(u32, u32)>),
+    /// Bytes whose meaning depends on the tag
+    Undefined(Vec<u8>),
+}
+
+/// The metadata tags found in an image
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImageTags {
+    /// EXIF fields by tag number
+    pub exif: HashMap<u16, ExifValue>,
+    /// PNG text chunks by keyword
+    pub text: HashMap<String, String>,
+    /// Whether the image is a TIFF, in which case `exif` holds the tags of the TIFF itself
+    pub is_tiff: bool,
+    /// The horizontal and vertical pixel density of the JFIF header of a JPEG or of the
+    /// pHYs chunk of a PNG
+    pub density: Option<(u32, u32)>,
+}
+
+impl ImageTags {
+    pub fn exif_ascii(&self, tag: u16) -> Option<&str> {
+        match self.exif.get(&tag) {
+            Some(ExifValue::Ascii(s)) if !s.is_empty() => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn exif_short(&self, tag: u16) -> Option<u16> {
+        match self.exif.get(&tag) {
+            Some(ExifValue::Short(v)) => v.first().copied(),
+            _ => None,
+        }
+    }
+
+    /// The value at that index of a numeric field, whatever its type
+    pub fn exif_number(&self, tag: u16, index: usize) -> Option<f64> {
+        match self.exif.get(&tag)? {
+            ExifValue::Short(v) => v.get(index).map(|n| f64::from(*n)),
+            ExifValue::Long(v) => v.get(index).map(|n| f64::from(*n)),
+            ExifValue::Rational(v) => match v.get(index)? {
+                (_, 0) => None,
+                (num, den) => Some(f64::from(*num) / f64::from(*den)),
+            },
+            ExifValue::Ascii(_) | ExifValue::Undefined(_) => None,
+        }
+    }
+
+    /// The crop and orientation the EXIF data of an image of that size asks for.
+    /// A crop that doesn't fit in the image is ignored
+    pub fn exif_transform(&self, (width, height): (u32, u32)) -> ExifTransform {
+        let orientation = self.orientation();
+        let number = |tag, index| self.exif_number(tag, index).map(|n| n.round() as u32);
+        let crop = match (
+            number(TAG_DEFAULT_CROP_ORIGIN, 0),
+            number(TAG_DEFAULT_CROP_ORIGIN, 1),
+            number(TAG_DEFAULT_CROP_SIZE, 0),
+            number(TAG_DEFAULT_CROP_SIZE, 1),
+        ) {
+            (Some(x), Some(y), Some(w), Some(h))
+                if w > 0
+                    && h > 0
+                    && u64::from(x) + u64::from(w) <= u64::from(width)
+                    && u64::from(y) + u64::from(h) <= u64::from(height) =>
+            {
+                Some((x, y, w, h))
+            }
+            _ => None,
+        };
+        ExifTransform { crop, orientation }
+    }
+
+    /// The EXIF orientation, from 1 (as stored) to 8. Missing and invalid ones are 1
+    pub fn orientation(&self) -> u16 {
+        match self.exif_short(TAG_ORIENTATION) {
+            Some(o) if (1..=8).contains(&o) => o,
+            _ => 1,
+        }
+    }
+
+    /// The number of channels of a TIFF
+    pub fn samples_per_pixel(&self) -> Option<u16> {
+        if !self.is_tiff {
+            return None;
+        }
+        self.exif_short(TAG_SAMPLES_PER_PIXEL)
+    }
+
+    /// What the channels of a TIFF represent, eg `rgb` or `min-is-black` for grayscale images
+    pub fn photometric_interpretation(&self) -> Option<String> {
+        if !self.is_tiff {
+            return None;
+        }
+        let name = match self.exif_short(TAG_PHOTOMETRIC_INTERPRETATION)? {
+            0 => "min-is-white",
+            1 => "min-is-black",
+            2 => "rgb",
+            3 => "palette",
+            4 => "mask",
+            5 => "separated",
+            6 => "ycbcr",
+            8 => "cielab",
+            9 => "icclab",
+            10 => "itulab",
+            32844 => "logl",
+            32845 => "logluv",
+            34892 => "linear-raw",
+            other => return Some(other.to_string()),
+        };
+        Some(name.to_string())
+    }
+
+    /// The tool the image was produced with
+    pub fn software(&self) -> Option<String> {
+        self.exif_ascii(TAG_SOFTWARE)
+            .or_else(|| self.text.get("Software").map(|s| s.as_str()))
+            .map(|s| s.to_string())
+    }
+
+    /// When the picture was taken, or else when the file was last changed according to its EXIF
+    /// data, in the `YYYY-MM-DDTHH:MM:SS` format
+    pub fn date(&self) -> Option<String> {
+        let date =
+            self.exif_ascii(TAG_DATE_TIME_ORIGINAL).or_else(|| self.exif_ascii(TAG_DATE_TIME))?;
+        // EXIF dates look like `2021:03:14 15:09:26`, with zeros when unknown
+        if date.len() < 19 || !date.is_char_boundary(19) || date.starts_with("0000") {
+            return None;
+        }
+        let (day, time) = (&date[..10], &date[11..19]);
+        if !day.chars().chain(time.chars()).all(|c| c.is_ascii_digit() || c == ':') {
+            return None;
+        }
+        Some(format!("{}T{}", day.replace(':', "-"), time))
+    }
+
+    /// The EXIF user comment, if it is ASCII or of an undefined charset. macOS writes `Screenshot`
+    /// in the ones of its screenshots
+    pub fn user_comment(&self) -> Option<String> {
+        let bytes = match self.exif.get(&TAG_USER_COMMENT) {
+            Some(ExifValue::Undefined(b)) if b.len() > 8 => b,
+            _ => return None,
+        };
+        // The comment starts with 8 bytes naming its charset
+        let (charset, comment) = bytes.split_at(8);
+        if charset != b"ASCII\0\0\0" && charset.iter().any(|b| *b != 0) {
+            return None;
+        }
+        let comment =
+            String::from_utf8_lossy(comment).trim_matches(|c| c == '\0' || c == ' ').to_string();
+        if comment.is_empty() {
+            None
+        } else {
+            Some(comment)
+        }
+    }
+
+    /// The person or organisation that created the image
+    pub fn creator(&self) -> Option<String> {
+        self.exif_ascii(TAG_ARTIST)
+            .or_else(|| self.text.get("Author").map(|s| s.as_str()))
+            .map(|s| s.to_string())
+    }
+
+    /// How many times wider than they are tall the pixels are meant to be displayed, from the
+    /// density of the container or else the EXIF resolution. 1 when neither is set.
+    /// Anamorphic video stills are stored with fewer columns this way
+    pub fn pixel_aspect_ratio(&self) -> f64 {
+        let density = match self.density {
+            Some((x, y)) if x > 0 && y > 0 => Some((f64::from(x), f64::from(y))),
+            _ => {
+                match (self.exif_number(TAG_X_RESOLUTION, 0), self.exif_number(TAG_Y_RESOLUTION, 0))
+                {
+                    (Some(x), Some(y)) if x > 0.0 && y > 0.0 => Some((x, y)),
+                    _ => None,
+                }
+            }
+        };
+        // Fewer pixels per unit horizontally means wider pixels
+        density.map_or(1.0, |(x, y)| y / x)
+    }
+}
+
+/// Which parts of the EXIF transform of a source are applied before it's processed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ExifTransforms {
+    /// The image is processed as stored
+    None,
+    /// Only the orientation, so the pixels are turned the way browsers display the source
+    Orientation,
+    /// The crop and the orientation, with `apply_exif_transforms`
+    All,
+}
+
+impl ExifTransforms {
+    /// The transforms applied to the source at that path: all of them if `all` is set, else the
+    /// orientation if `orientation` is set and the source has one other than as stored
+    pub fn for_source<P: AsRef<Path>>(path: P, all: bool, orientation: bool) -> Result<Self> {
+        if all {
+            return Ok(ExifTransforms::All);
+        }
+        if orientation && read_image_tags(path)?.orientation() != 1 {
+            return Ok(ExifTransforms::Orientation);
+        }
+        Ok(ExifTransforms::None)
+    }
+
+    /// The part of the EXIF transform of the image at that path, which has those dimensions,
+    /// that is applied
+    pub fn read<P: AsRef<Path>>(self, path: P, dimensions: (u32, u32)) -> Result<ExifTransform> {
+        match self {
+            ExifTransforms::None => Ok(ExifTransform { crop: None, orientation: 1 }),
+            ExifTransforms::Orientation => {
+                Ok(ExifTransform { crop: None, orientation: read_image_tags(path)?.orientation() })
+            }
+            ExifTransforms::All => ExifTransform::read(path, dimensions),
+        }
+    }
+}
+
+/// How the EXIF data of an image says it should be displayed. The crop is applied first as it's
+/// in the coordinates of the stored pixels, then the orientation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExifTransform {
+    /// The x, y, width and height of the crop, in pixels of the stored image
+    pub crop: Option<(u32, u32, u32, u32)>,
+    /// The EXIF orientation, from 1 (as stored) to 8
+    pub orientation: u16,
+}
+
+impl ExifTransform {
+    /// Reads the transform of the image at that path, which has those dimensions
+    pub fn read<P: AsRef<Path>>(path: P, dimensions: (u32, u32)) -> Result<ExifTransform> {
+        Ok(read_image_tags(path)?.exif_transform(dimensions))
+    }
+
+    /// The dimensions of an image of that size once transformed
+    pub fn dimensions(self, (width, height): (u32, u32)) -> (u32, u32) {
+        let (width, height) = self.crop.map_or((width, height), |(_, _, w, h)| (w, h));
+        // Orientations 5 to 8 are rotated by a quarter turn
+        if self.orientation >= 5 {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+
+    pub fn apply(self, mut img: DynamicImage) -> DynamicImage {
+        if let Some((x, y, w, h)) = self.crop {
+            img = img.crop(x, y, w, h);
+        }
+        match self.orientation {
+            2 => img.fliph(),
+            3 => img.rotate180(),
+            4 => img.flipv(),
+            5 => img.rotate90().fliph(),
+            6 => img.rotate90(),
+            7 => img.rotate270().fliph(),
+            8 => img.rotate270(),
+            _ => img,
+        }
+    }
+}
+
+/// Reads the metadata tags of the image at that path.
+/// Formats other than JPEG, PNG and TIFF, as well as malformed metadata, give empty tags.
+pub fn read_image_tags<P: AsRef<Path>>(path: P) -> Result<ImageTags> {
+    let data = fs::read(path)?;
+    let mut tags = ImageTags::default();
+    if data.starts_with(&[0xFF, 0xD8]) {
+        let exif = jpeg_segments(&data)
+            .into_iter()
+            .find(|(marker, segment)| *marker == 0xE1 && segment.starts_with(b"Exif\0\0"));
+        if let Some((_, segment)) = exif {
+            tags.exif = parse_tiff(&segment[6..]).unwrap_or_default();
+        }
+        // The identifier and version, the unit and then the densities
+        let jfif = jpeg_segments(&data)
+            .into_iter()
+            .find(|(marker, segment)| *marker == 0xE0 && segment.starts_with(b"JFIF\0"));
+        if let Some((_, segment)) = jfif.filter(|(_, segment)| segment.len() >= 12) {
+            tags.density = Some((
+                u32::from(u16::from_be_bytes([segment[8], segment[9]])),
+                u32::from(u16::from_be_bytes([segment[10], segment[11]])),
+            ));
+        }
+    } else if data.starts_with(PNG_SIGNATURE) {
+        read_png_chunks(&data, &mut tags);
+    } else if data.starts_with(b"II\x2a\x00") || data.starts_with(b"MM\x00\x2a") {
+        tags.exif = parse_tiff(&data).unwrap_or_default();
+        tags.is_tiff = true;
+    }
+    Ok(tags)
+}
+
+/// Reads the JPEG thumbnail cameras embed in the EXIF data of their photos, `None` if the image
+/// isn't a JPEG or doesn't have one
+pub fn read_embedded_thumbnail<P: AsRef<Path>>(path: P) -> Result<Option<Vec<u8>>> {
+    let data = fs::read(path)?;
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return Ok(None);
+    }
+    let exif = jpeg_segments(&data)
+        .into_iter()
+        .find(|(marker, segment)| *marker == 0xE1 && segment.starts_with(b"Exif\0\0"));
+    Ok(exif.and_then(|(_, segment)| embedded_thumbnail(&segment[6..])).map(|t| t.to_vec()))
+}
+
+/// The EXIF data of a JPEG or PNG as is, ie TIFF-structured, `None` if it doesn't have any
+pub fn read_exif_data<P: AsRef<Path>>(path: P) -> Result<Option<Vec<u8>>> {
+    let data = fs::read(path)?;
+    if data.starts_with(&[0xFF, 0xD8]) {
+        let exif = jpeg_segments(&data)
+            .into_iter()
+            .find(|(marker, segment)| *marker == 0xE1 && segment.starts_with(b"Exif\0\0"));
+        return Ok(exif.map(|(_, segment)| segment[6..].to_vec()));
+    }
+    if data.starts_with(PNG_SIGNATURE) {
+        return Ok(png_chunk(&data, b"eXIf").map(|chunk| chunk.to_vec()));
+    }
+    Ok(None)
+}
+
+/// Sets the orientation in the first IFD of TIFF-structured EXIF data to 1, ie as stored,
+/// for the EXIF data of a source whose orientation was already applied to its pixels
+pub(crate) fn reset_orientation(tiff: &mut [u8]) {
+    let order = match tiff_byte_order(tiff) {
+        Some(order) => order,
+        None => return,
+    };
+    let ifd0 = match order.u32(tiff, 4) {
+        Some(ifd0) => ifd0 as usize,
+        None => return,
+    };
+    let count = order.u16(tiff, ifd0).unwrap_or(0) as usize;
+    for i in 0..count {
+        let entry = ifd0 + 2 + i * 12;
+        if order.u16(tiff, entry) == Some(TAG_ORIENTATION) && order.u16(tiff, entry + 2) == Some(3)
+        {
+            let one = match order {
+                ByteOrder::Little => 1u16.to_le_bytes(),
+                ByteOrder::Big => 1u16.to_be_bytes(),
+            };
+            // The entry can be cut off after its type in malformed data
+            if let Some(value) = tiff.get_mut(entry + 8..entry + 10) {
+                value.copy_from_slice(&one);
+            }
+        }
+    }
+}
+
+/// Adds that EXIF data to the JPEG or PNG at that path, which must not have any.
+/// Returns false if it can't hold it, ie for other formats or if it doesn't fit in a JPEG segment
+pub(crate) fn write_exif_data<P: AsRef<Path>>(path: P, tiff: &[u8]) -> Result<bool> {
+    let path = path.as_ref();
+    let data = fs::read(path)?;
+    let (pos, added) = if data.starts_with(&[0xFF, 0xD8]) {
+        // The length counts itself and the identifier
+        if tiff.len() + 8 > usize::from(u16::MAX) {
+            return Ok(false);
+        }
+        let mut segment = vec![0xFF, 0xE1];
+        segment.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+        segment.extend_from_slice(b"Exif\0\0");
+        segment.extend_from_slice(tiff);
+        // After the JFIF segment, which has to come first
+        let pos = match jpeg_segments(&data).first() {
+            Some((0xE0, jfif)) => 2 + 4 + jfif.len(),
+            _ => 2,
+        };
+        (pos, segment)
+    } else if data.starts_with(PNG_SIGNATURE) {
+        let mut chunk = (tiff.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(b"eXIf");
+        chunk.extend_from_slice(tiff);
+        let crc = crc32(&chunk[4..]);
+        chunk.extend_from_slice(&crc.to_be_bytes());
+        // After IHDR, which is always the first chunk and 13 bytes long, so it comes before IDAT
+        (PNG_SIGNATURE.len() + 12 + 13, chunk)
+    } else {
+        return Ok(false);
+    };
+    let mut with_exif = data[..pos].to_vec();
+    with_exif.extend(added);
+    with_exif.extend_from_slice(&data[pos..]);
+    fs::write(path, with_exif)?;
+    Ok(true)
+}
+
+/// The CRC of PNG chunks, computed over their type and data
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// The data of the first chunk of a PNG with that type
+pub(crate) fn png_chunk<'a>(data: &'a [u8], kind: &[u8]) -> Option<&'a [u8]> {
+    let mut pos = PNG_SIGNATURE.len();
+    while let Some(header) = data.get(pos..pos + 8) {
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        if &header[4..] == kind {
+            return data.get(pos + 8..pos + 8 + len);
+        }
+        if &header[4..] == b"IEND" {
+            return None;
+        }
+        // Length, type, data and CRC
+        pos += 12 + len;
+    }
+    None
+}
+
+/// The thumbnail is described by the second IFD, which follows the entries of the first one
+fn embedded_thumbnail(tiff: &[u8]) -> Option<&[u8]> {
+    let order = tiff_byte_order(tiff)?;
+    let ifd0 = order.u32(tiff, 4)? as usize;
+    let count = order.u16(tiff, ifd0)? as usize;
+    let ifd1 = order.u32(tiff, ifd0 + 2 + count * 12)? as usize;
+    if ifd1 == 0 {
+        return None;
+    }
+    let mut fields = HashMap::new();
+    parse_ifd(tiff, order, ifd1, &mut fields)?;
+    let number = |tag| match fields.get(&tag)? {
+        ExifValue::Long(v) => v.first().map(|v| *v as usize),
+        ExifValue::Short(v) => v.first().map(|v| usize::from(*v)),
+        _ => None,
+    };
+    let offset = number(TAG_THUMBNAIL_OFFSET)?;
+    let thumbnail = tiff.get(offset..offset + number(TAG_THUMBNAIL_LENGTH)?)?;
+    if thumbnail.starts_with(&[0xFF, 0xD8]) {
+        Some(thumbnail)
+    } else {
+        None
+    }
+}
+
+/// Lists the marker and content of the segments of a JPEG found before the image data,
+/// which is where all the metadata lives
+pub(crate) fn jpeg_segments(data: &[u8]) -> Vec<(u8, &[u8])> {
+    let mut segments = Vec::new();
+    let mut pos = 2;
+    loop {
+        let marker = match (data.get(pos), data.get(pos + 1)) {
+            (Some(0xFF), Some(marker)) => *marker,
+            _ => return segments,
+        };
+        match marker {
+            // Padding
+            0xFF => {
+                pos += 1;
+                continue;
+            }
+            // Markers without a length
+            0x01 | 0xD0..=0xD8 => {
+                pos += 2;
+                continue;
+            }
+            // Start of the image data or end of the image: no more metadata after that
+            0xDA | 0xD9 => return segments,
+            _ => (),
+        }
+        let len = match data.get(pos + 2..pos + 4) {
+            Some(len) => u16::from_be_bytes([len[0], len[1]]) as usize,
+            None => return segments,
+        };
+        match data.get(pos + 4..pos + 2 + len) {
+            Some(segment) => segments.push((marker, segment)),
+            None => return segments,
+        }
+        pos += 2 + len;
+    }
+}
+
+/// Goes through the chunks of a PNG, collecting the EXIF data and the uncompressed text chunks
+fn read_png_chunks(data: &[u8], tags: &mut ImageTags) {
+    let mut pos = PNG_SIGNATURE.len();
+    while let Some(header) = data.get(pos..pos + 8) {
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let chunk = match data.get(pos + 8..pos + 8 + len) {
+            Some(c) => c,
+            None => return,
+        };
+        match &header[4..] {
+            b"eXIf" => tags.exif = parse_tiff(chunk).unwrap_or_default(),
+            // The pixels per unit on each axis, followed by the unit
+            b"pHYs" if chunk.len() >= 8 => {
+                tags.density = Some((
+                    u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]),
+                    u32::from_be_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]),
+                ));
+            }
+            b"tEXt" => {
+                if let Some(nul) = chunk.iter().position(|b| *b == 0) {
+                    // tEXt is latin-1, which maps 1:1 to the first unicode code points
+                    let keyword = chunk[..nul].iter().map(|b| *b as char).collect();
+                    let text = chunk[nul + 1..].iter().map(|b| *b as char).collect();
+                    tags.text.insert(keyword, text);
+                }
+            }
+            b"iTXt" => {
+                if let Some((keyword, text)) = parse_itxt(chunk) {
+                    tags.text.insert(keyword, text);
+                }
+            }
+            b"IEND" => return,
+            _ => (),
+        }
+        // Length, type, data and CRC
+        pos += 12 + len;
+    }
+}
+
+/// Parses an uncompressed iTXt chunk, compressed ones are ignored
+fn parse_itxt(chunk: &[u8]) -> Option<(String, String)> {
+    let nul = chunk.iter().position(|b| *b == 0)?;
+    let keyword = String::from_utf8_lossy(&chunk[..nul]).into_owned();
+    let rest = chunk.get(nul + 1..)?;
+    if *rest.first()? != 0 {
+        return None;
+    }
+    // Skip the compression flag and method, then the language tag and translated keyword
+    let mut rest = rest.get(2..)?;
+    for _ in 0..2 {
+        let nul = rest.iter().position(|b| *b == 0)?;
+        rest = &rest[nul + 1..];
+    }
+    Some((keyword, String::from_utf8_lossy(rest).into_owned()))
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ByteOrder {
+    Little,
+    Big,
+}
+
+impl ByteOrder {
+    fn u16(self, data: &[u8], at: usize) -> Option<u16> {
+        let b = data.get(at..at + 2)?;
+        Some(match self {
+            ByteOrder::Little => u16::from_le_bytes([b[0], b[1]]),
+            ByteOrder::Big => u16::from_be_bytes([b[0], b[1]]),
+        })
+    }
+
+    fn u32(self, data: &[u8], at: usize) -> Option<u32> {
+        let b = data.get(at..at + 4)?;
+        Some(match self {
+            ByteOrder::Little => u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            ByteOrder::Big => u32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+        })
+    }
+}
+
+/// Parses the first IFD of TIFF-structured EXIF data, along with the EXIF IFD it points to
+/// which holds the tags about how the picture was taken
+fn parse_tiff(data: &[u8]) -> Option<HashMap<u16, ExifValue>> {
+    let order = tiff_byte_order(data)?;
+    let mut fields = HashMap::new();
+    parse_ifd(data, order, order.u32(data, 4)? as usize, &mut fields)?;
+    if let Some(ExifValue::Long(offset)) = fields.get(&TAG_EXIF_IFD) {
+        let offset = *offset.first()? as usize;
+        // The EXIF IFD being malformed doesn't make the first one invalid
+        parse_ifd(data, order, offset, &mut fields);
+    }
+    Some(fields)
+}
+
+/// The byte order of TIFF-structured data, `None` if it doesn't have a valid header
+fn tiff_byte_order(data: &[u8]) -> Option<ByteOrder> {
+    let order = match data.get(0..2)? {
+        b"II" => ByteOrder::Little,
+        b"MM" => ByteOrder::Big,
+        _ => return None,
+    };
+    if order.u16(data, 2)? != 42 {
+        return None;
+    }
+    Some(order)
+}
+
+fn parse_ifd(
+    data: &[u8],
+    order: ByteOrder,
+    ifd: usize,
+    fields: &mut HashMap<u16, ExifValue>,
+) -> Option<()> {
+    let count = order.u16(data, ifd)? as usize;
+    for i in 0..count {
+        let entry = ifd + 2 + i * 12;
+        let tag = order.u16(data, entry)?;
+        let kind = order.u16(data, entry + 2)?;
+        let n = order.u32(data, entry + 4)? as usize;
+        let size = match kind {
+            2 | 7 => 1,
+            3 => 2,
+            4 => 4,
+            5 => 8,
+            // A type we don't read
+            _ => continue,
+        };
+        // Values that fit in 4 bytes are stored in the entry itself
+        let start = if n * size <= 4 { entry + 8 } else { order.u32(data, entry + 8)? as usize };
+        let bytes = match data.get(start..start + n * size) {
+            Some(b) => b,
+            None => continue,
+        };
+        let value = match kind {
+            2 => {
+                let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+                ExifValue::Ascii(String::from_utf8_lossy(&bytes[..end]).trim().to_string())
+            }
+            3 => ExifValue::Short((0..n).filter_map(|j| order.u16(bytes, j * 2)).collect()),
+            4 => ExifValue::Long((0..n).filter_map(|j| order.u32(bytes, j * 4)).collect()),
+            7 => ExifValue::Undefined(bytes.to_vec()),
+            _ => ExifValue::Rational(
+                (0..n)
+                    .filter_map(|j| Some((order.u32(bytes, j * 8)?, order.u32(bytes, j * 8 + 4)?)))
+                    .collect(),
+            ),
+        };
+        fields.insert(tag, value);
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_reset_the_orientation_of_truncated_exif_data() {
+        // An IFD0 of two orientation entries, the second being cut off after its type
+        let mut tiff = b"II\x2a\x00\x08\x00\x00\x00\x02\x00\
+            \x12\x01\x03\x00\x01\x00\x00\x00\x06\x00\x00\x00\
+            \x12\x01\x03\x00"
+            .to_vec();
+        reset_orientation(&mut tiff);
+        assert_eq!(tiff[18..20], [1, 0]);
+        assert_eq!(tiff.len(), 26);
+    }
+}
@@ -0,0 +1,139 @@
+//! Picks where to crop images resized with `op="fill"`.
+
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView};
+
+use errors::Result;
+
+/// The size of the grid the contrast of the image is measured on
+const CONTRAST_GRID_SIZE: u32 = 24;
+/// How much more contrast the busiest intersection needs compared to the calmest one
+/// for the crop to move away from the center
+const MIN_CONTRAST_DIFFERENCE: f64 = 0.05;
+
+/// The anchors `resize_image` accepts, with their horizontal and vertical position:
+/// 0 for the left or top, 1 for the middle and 2 for the right or bottom
+const ANCHORS: &[(&str, (u8, u8))] = &[
+    ("top_left", (0, 0)),
+    ("top", (1, 0)),
+    ("top_right", (2, 0)),
+    ("left", (0, 1)),
+    ("center", (1, 1)),
+    ("right", (2, 1)),
+    ("bottom_left", (0, 2)),
+    ("bottom", (1, 2)),
+    ("bottom_right", (2, 2)),
+];
+
+/// What part of the image is kept when `op="fill"` needs to crop it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Focus {
+    /// The center of the image
+    Center,
+    /// The rule of thirds intersection around which the image has the most contrast
+    Thirds,
+    /// An edge or a corner of the image, by horizontal and vertical position as in `ANCHORS`.
+    /// The center is always `Center`
+    Anchor(u8, u8),
+}
+
+impl Focus {
+    pub fn from_args(focus: &str) -> Result<Focus> {
+        match focus {
+            "center" => Ok(Focus::Center),
+            "thirds" => Ok(Focus::Thirds),
+            _ => {
+                Err(format!("Invalid focus: {}, it needs to be `center` or `thirds`", focus).into())
+            }
+        }
+    }
+
+    /// The focus keeping that edge or corner of the image, eg `top` or `bottom_right`
+    pub fn from_anchor(anchor: &str) -> Result<Focus> {
+        match ANCHORS.iter().find(|(name, _)| *name == anchor) {
+            Some((_, (1, 1))) => Ok(Focus::Center),
+            Some((_, (x, y))) => Ok(Focus::Anchor(*x, *y)),
+            None => {
+                let names: Vec<_> = ANCHORS.iter().map(|(name, _)| *name).collect();
+                Err(format!(
+                    "Invalid anchor: {}, it needs to be one of: {}",
+                    anchor,
+                    names.join(", ")
+                )
+                .into())
+            }
+        }
+    }
+
+    /// The offset of a `crop_w` x `crop_h` crop of the image
+    pub fn crop_offset(self, img: &DynamicImage, (crop_w, crop_h): (u32, u32)) -> (u32, u32) {
+        let (img_w, img_h) = (img.width(), img.height());
+        let center = ((img_w - crop_w) / 2, (img_h - crop_h) / 2);
+        match self {
+            Focus::Center => return center,
+            Focus::Anchor(x, y) => {
+                // The middle is the same as for the center
+                let offset = |extra: u32, position: u8| match position {
+                    0 => 0,
+                    1 => extra / 2,
+                    _ => extra,
+                };
+                return (offset(img_w - crop_w, x), offset(img_h - crop_h, y));
+            }
+            Focus::Thirds => (),
+        }
+
+        let (tx, ty) = match busiest_intersection(img) {
+            Some(intersection) => intersection,
+            None => return center,
+        };
+        // The intersection of the source lands on the same intersection of the crop,
+        // as far as the crop can go without leaving the image
+        let offset = |img_len: u32, crop_len: u32, third: u32| {
+            let point = f64::from(img_len) * f64::from(third) / 3.0;
+            let offset = point - f64::from(crop_len) * f64::from(third) / 3.0;
+            offset.round().max(0.0).min(f64::from(img_len - crop_len)) as u32
+        };
+        (offset(img_w, crop_w, tx), offset(img_h, crop_h, ty))
+    }
+}
+
+/// Which of the 4 rule of thirds intersections, as `(1 or 2, 1 or 2)` thirds of the width
+/// and height, has the most contrast around it. `None` if none stands out
+fn busiest_intersection(img: &DynamicImage) -> Option<(u32, u32)> {
+    let grid =
+        img.resize_exact(CONTRAST_GRID_SIZE, CONTRAST_GRID_SIZE, FilterType::Triangle).to_luma8();
+    let luma = |x: u32, y: u32| f64::from(grid.get_pixel(x, y).0[0]);
+
+    // Each cell counts towards the intersection closest to it
+    let mut contrast = [[0.0; 2]; 2];
+    for y in 0..CONTRAST_GRID_SIZE - 1 {
+        for x in 0..CONTRAST_GRID_SIZE - 1 {
+            let local = (luma(x, y) - luma(x + 1, y)).abs() + (luma(x, y) - luma(x, y + 1)).abs();
+            let column = (2 * x >= CONTRAST_GRID_SIZE) as usize;
+            let row = (2 * y >= CONTRAST_GRID_SIZE) as usize;
+            contrast[row][column] += local;
+        }
+    }
+
+    let cells = [(0, 0), (1, 0), (0, 1), (1, 1)];
+    let value = |(column, row): (usize, usize)| contrast[row][column];
+    // Ties go to the first intersection so the crop is the same on every build
+    let busiest =
+        cells.iter().copied().fold(
+            (0, 0),
+            |best, cell| {
+                if value(cell) > value(best) {
+                    cell
+                } else {
+                    best
+                }
+            },
+        );
+    let calmest = cells.iter().copied().map(value).fold(f64::INFINITY, f64::min);
+    if value(busiest) == 0.0 || value(busiest) - calmest < value(busiest) * MIN_CONTRAST_DIFFERENCE
+    {
+        return None;
+    }
+    Some((busiest.0 as u32 + 1, busiest.1 as u32 + 1))
+}
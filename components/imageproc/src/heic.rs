@@ -0,0 +1,107 @@
+//! Reads HEIC and HEIF images, the format of the photos taken with iPhones, which the `image`
+//! crate can't decode. It's done with libheif, which is only compiled in with the `heic` feature.
+
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use image::DynamicImage;
+
+use errors::{Error, Result};
+
+/// Whether the image at that path is a HEIC or HEIF, by its extension
+pub fn is_heic(path: &Path) -> bool {
+    let extension = path.extension().and_then(OsStr::to_str).map(str::to_lowercase);
+    matches!(extension.as_deref(), Some("heic") | Some("heif"))
+}
+
+/// The MIME type of the file at that path if its content is a HEIC or HEIF, from the brand
+/// of its `ftyp` box
+pub fn heic_mime_type(path: &Path) -> Result<Option<&'static str>> {
+    let mut header = Vec::with_capacity(12);
+    File::open(path)?.take(12).read_to_end(&mut header)?;
+    if header.len() < 12 || &header[4..8] != b"ftyp" {
+        return Ok(None);
+    }
+    Ok(match &header[8..12] {
+        b"heic" | b"heix" | b"heim" | b"heis" | b"hevc" | b"hevx" => Some("image/heic"),
+        b"mif1" | b"msf1" => Some("image/heif"),
+        _ => None,
+    })
+}
+
+/// The dimensions of the primary image of that HEIC, as displayed
+#[cfg(feature = "heic")]
+pub fn heic_dimensions(path: &Path) -> Result<(u32, u32)> {
+    let context = read_context(path)?;
+    let handle = context.primary_image_handle().map_err(|e| heic_error(path, e))?;
+    Ok((handle.width(), handle.height()))
+}
+
+/// Decodes the primary image of that HEIC, turned as it is displayed
+#[cfg(feature = "heic")]
+pub fn open_heic(path: &Path) -> Result<DynamicImage> {
+    use image::{RgbImage, RgbaImage};
+    use libheif_rs::{ColorSpace, RgbChroma};
+
+    let context = read_context(path)?;
+    let handle = context.primary_image_handle().map_err(|e| heic_error(path, e))?;
+    let has_alpha = handle.has_alpha_channel();
+    let chroma = if has_alpha { RgbChroma::Rgba } else { RgbChroma::Rgb };
+    let decoded = handle.decode(ColorSpace::Rgb(chroma), false).map_err(|e| heic_error(path, e))?;
+    let plane = match decoded.planes().interleaved {
+        Some(plane) => plane,
+        None => {
+            return Err(format!("Failed to read HEIC image {}: no RGB plane", path.display()).into())
+        }
+    };
+
+    // Rows can be padded, only their pixels are kept
+    let row_len = plane.width as usize * if has_alpha { 4 } else { 3 };
+    let pixels: Vec<u8> = plane
+        .data
+        .chunks(plane.stride)
+        .take(plane.height as usize)
+        .flat_map(|row| &row[..row_len])
+        .copied()
+        .collect();
+    let img = if has_alpha {
+        RgbaImage::from_raw(plane.width, plane.height, pixels).map(DynamicImage::ImageRgba8)
+    } else {
+        RgbImage::from_raw(plane.width, plane.height, pixels).map(DynamicImage::ImageRgb8)
+    };
+    img.ok_or_else(|| {
+        format!("Failed to read HEIC image {}: truncated pixels", path.display()).into()
+    })
+}
+
+#[cfg(feature = "heic")]
+fn read_context(path: &Path) -> Result<libheif_rs::HeifContext> {
+    libheif_rs::HeifContext::read_from_file(&path.to_string_lossy())
+        .map_err(|e| heic_error(path, e))
+}
+
+#[cfg(feature = "heic")]
+fn heic_error(path: &Path, e: libheif_rs::HeifError) -> Error {
+    Error::msg(format!("Failed to read HEIC image {}: {}", path.display(), e))
+}
+
+#[cfg(not(feature = "heic"))]
+pub fn heic_dimensions(path: &Path) -> Result<(u32, u32)> {
+    Err(heic_unsupported(path))
+}
+
+#[cfg(not(feature = "heic"))]
+pub fn open_heic(path: &Path) -> Result<DynamicImage> {
+    Err(heic_unsupported(path))
+}
+
+/// Rather than the generic error of the `image` crate not knowing the format
+#[cfg(not(feature = "heic"))]
+fn heic_unsupported(path: &Path) -> Error {
+    Error::msg(format!(
+        "{} is a HEIC image and HEIC support is not compiled into this Zola build, it needs to be built with `cargo build --features imageproc/heic`",
+        path.display()
+    ))
+}
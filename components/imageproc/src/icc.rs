@@ -0,0 +1,313 @@
+//! Keeps the ICC color profiles of sources with `color_profile`, eg the Display P3 one of the
+//! photos of recent phones, which would otherwise be displayed as if they were sRGB once
+//! processed. Profiles are either copied as is into JPEG and PNG outputs or applied to the pixels,
+//! converting them to sRGB, which only works for the matrix/TRC profiles of RGB displays.
+
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use image::{DynamicImage, GenericImageView, Rgb, RgbImage, Rgba, RgbaImage};
+
+use errors::{Error, Result};
+
+use crate::exif::{crc32, jpeg_segments, png_chunk, PNG_SIGNATURE};
+
+/// What the APP2 segments holding a profile in JPEGs start with, before the index of the segment
+/// and how many there are
+const JPEG_ICC_MARKER: &[u8] = b"ICC_PROFILE\0";
+/// The most profile bytes a JPEG segment can hold, along with its length, marker and index
+const MAX_JPEG_ICC_CHUNK: usize = 65535 - 2 - JPEG_ICC_MARKER.len() - 2;
+
+/// What `resize_image` does with the ICC profile of the source
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorProfile {
+    /// Leave it out, the output being displayed as sRGB
+    Strip,
+    /// Copy it into the output, only JPEG and PNG can hold one
+    Preserve,
+    /// Convert the pixels to sRGB, which the output is displayed as without a profile
+    Srgb,
+}
+
+impl ColorProfile {
+    pub fn from_args(color_profile: &str) -> Result<ColorProfile> {
+        match color_profile {
+            "strip" => Ok(ColorProfile::Strip),
+            "preserve" => Ok(ColorProfile::Preserve),
+            "srgb" => Ok(ColorProfile::Srgb),
+            _ => Err(format!(
+                "Invalid `color_profile`: {}, it needs to be `preserve`, `srgb` or `strip`",
+                color_profile
+            )
+            .into()),
+        }
+    }
+}
+
+/// The ICC profile of the JPEG or PNG at that path, if it has one
+pub fn read_icc_profile<P: AsRef<Path>>(path: P) -> Result<Option<Vec<u8>>> {
+    let data = fs::read(path)?;
+    if data.starts_with(&[0xFF, 0xD8]) {
+        // The profile can be split over several segments, each knowing its index
+        let mut chunks: Vec<(u8, &[u8])> = jpeg_segments(&data)
+            .into_iter()
+            .filter(|(marker, segment)| *marker == 0xE2 && segment.starts_with(JPEG_ICC_MARKER))
+            .filter_map(|(_, segment)| {
+                let index = *segment.get(JPEG_ICC_MARKER.len())?;
+                Some((index, segment.get(JPEG_ICC_MARKER.len() + 2..)?))
+            })
+            .collect();
+        if chunks.is_empty() {
+            return Ok(None);
+        }
+        chunks.sort_by_key(|(index, _)| *index);
+        return Ok(Some(chunks.into_iter().flat_map(|(_, chunk)| chunk.iter().copied()).collect()));
+    }
+    if data.starts_with(PNG_SIGNATURE) {
+        let chunk = match png_chunk(&data, b"iCCP") {
+            Some(chunk) => chunk,
+            None => return Ok(None),
+        };
+        // A keyword, its null terminator and the compression method, always zlib
+        let compressed = match chunk.iter().position(|b| *b == 0) {
+            Some(end) => chunk.get(end + 2..).unwrap_or(&[]),
+            None => return Ok(None),
+        };
+        let mut profile = Vec::new();
+        ZlibDecoder::new(compressed)
+            .read_to_end(&mut profile)
+            .map_err(|e| Error::chain("Failed to decompress the ICC profile of a PNG", e))?;
+        return Ok(Some(profile));
+    }
+    Ok(None)
+}
+
+/// The color space of the images the profile is for, eg `RGB`, `GRAY` or `CMYK`, from its
+/// header. `None` if it's too short to have one
+pub fn color_space(profile: &[u8]) -> Option<&str> {
+    std::str::from_utf8(profile.get(16..20)?).ok().map(str::trim_end)
+}
+
+/// Adds that ICC profile to the JPEG or PNG at that path, which must not have one.
+/// Returns false if it can't hold it, ie for other formats
+pub fn write_icc_profile<P: AsRef<Path>>(path: P, profile: &[u8]) -> Result<bool> {
+    let path = path.as_ref();
+    let data = fs::read(path)?;
+    let (pos, added) = if data.starts_with(&[0xFF, 0xD8]) {
+        let chunks: Vec<_> = profile.chunks(MAX_JPEG_ICC_CHUNK).collect();
+        if chunks.len() > usize::from(u8::MAX) {
+            return Ok(false);
+        }
+        let mut segments = Vec::new();
+        for (i, chunk) in chunks.iter().enumerate() {
+            segments.extend_from_slice(&[0xFF, 0xE2]);
+            let len = 2 + JPEG_ICC_MARKER.len() + 2 + chunk.len();
+            segments.extend_from_slice(&(len as u16).to_be_bytes());
+            segments.extend_from_slice(JPEG_ICC_MARKER);
+            segments.extend_from_slice(&[i as u8 + 1, chunks.len() as u8]);
+            segments.extend_from_slice(chunk);
+        }
+        // After the JFIF segment, which has to come first
+        let pos = match jpeg_segments(&data).first() {
+            Some((0xE0, jfif)) => 2 + 4 + jfif.len(),
+            _ => 2,
+        };
+        (pos, segments)
+    } else if data.starts_with(PNG_SIGNATURE) {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(profile)?;
+        let compressed = encoder.finish()?;
+        let mut body = b"iCCP".to_vec();
+        body.extend_from_slice(b"ICC Profile\0\0");
+        body.extend_from_slice(&compressed);
+        let mut chunk = ((body.len() - 4) as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(&body);
+        chunk.extend_from_slice(&crc32(&body).to_be_bytes());
+        // After IHDR, which is always the first chunk and 13 bytes long, as it has to come
+        // before PLTE and IDAT
+        (PNG_SIGNATURE.len() + 12 + 13, chunk)
+    } else {
+        return Ok(false);
+    };
+    let mut with_profile = data[..pos].to_vec();
+    with_profile.extend(added);
+    with_profile.extend_from_slice(&data[pos..]);
+    fs::write(path, with_profile)?;
+    Ok(true)
+}
+
+/// The tone response curve of a channel of a profile, from encoded values to linear ones
+#[derive(Debug, Clone, PartialEq)]
+enum Curve {
+    /// `curv` with a single gamma value, or with no entry for a gamma of 1
+    Gamma(f64),
+    /// `curv` with evenly spaced samples
+    Table(Vec<f64>),
+    /// `para`: a parametric function and its parameters g, a, b, c, d, e and f
+    Parametric(u16, [f64; 7]),
+}
+
+impl Curve {
+    fn linearize(&self, x: f64) -> f64 {
+        match self {
+            Curve::Gamma(g) => x.powf(*g),
+            Curve::Table(samples) => {
+                let pos = x * (samples.len() - 1) as f64;
+                let i = (pos.floor() as usize).min(samples.len() - 2);
+                let t = pos - i as f64;
+                samples[i] * (1.0 - t) + samples[i + 1] * t
+            }
+            Curve::Parametric(kind, [g, a, b, c, d, e, f]) => match *kind {
+                0 => x.powf(*g),
+                1 if x >= -b / a => (a * x + b).powf(*g),
+                1 => 0.0,
+                2 if x >= -b / a => (a * x + b).powf(*g) + c,
+                2 => *c,
+                3 if x >= *d => (a * x + b).powf(*g),
+                3 => c * x,
+                _ if x >= *d => (a * x + b).powf(*g) + e,
+                _ => c * x + f,
+            },
+        }
+    }
+}
+
+/// A matrix/TRC RGB profile: the curves making the channels linear and the matrix from linear
+/// RGB to the XYZ of the D50 profile connection space
+#[derive(Debug, Clone, PartialEq)]
+pub struct RgbProfile {
+    curves: [Curve; 3],
+    to_xyz: [[f64; 3]; 3],
+}
+
+/// From the D50 XYZ of profiles to linear sRGB, with the Bradford adaptation from D50 to D65
+const XYZ_D50_TO_SRGB: [[f64; 3]; 3] = [
+    [3.133_856_1, -1.616_866_7, -0.490_614_6],
+    [-0.978_768_4, 1.916_141_5, 0.033_454_0],
+    [0.071_945_3, -0.228_991_4, 1.405_242_7],
+];
+
+impl RgbProfile {
+    /// `None` for the profiles that aren't made of a matrix and curves, eg the ones with
+    /// lookup tables of printers, which can't be applied this way
+    pub fn parse(profile: &[u8]) -> Option<RgbProfile> {
+        let u16_at =
+            |pos: usize| profile.get(pos..pos + 2).map(|b| u16::from_be_bytes([b[0], b[1]]));
+        let u32_at = |pos: usize| {
+            profile.get(pos..pos + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        };
+        let s15_fixed16_at = |pos: usize| u32_at(pos).map(|v| f64::from(v as i32) / 65536.0);
+        if profile.get(16..20)? != b"RGB " {
+            return None;
+        }
+        // Where the data of the tag with that signature starts
+        let tag = |signature: &[u8]| -> Option<usize> {
+            let count = u32_at(128)? as usize;
+            let entry = (0..count)
+                .map(|i| 132 + i * 12)
+                .find(|e| profile.get(*e..*e + 4) == Some(signature))?;
+            let (offset, size) = (u32_at(entry + 4)? as usize, u32_at(entry + 8)? as usize);
+            if offset.checked_add(size)? > profile.len() {
+                return None;
+            }
+            Some(offset)
+        };
+        let xyz = |signature: &[u8]| -> Option<[f64; 3]> {
+            let at = tag(signature)?;
+            if profile.get(at..at + 4)? != b"XYZ " {
+                return None;
+            }
+            Some([s15_fixed16_at(at + 8)?, s15_fixed16_at(at + 12)?, s15_fixed16_at(at + 16)?])
+        };
+        let curve = |signature: &[u8]| -> Option<Curve> {
+            let at = tag(signature)?;
+            match profile.get(at..at + 4)? {
+                b"curv" => match u32_at(at + 8)? {
+                    0 => Some(Curve::Gamma(1.0)),
+                    1 => Some(Curve::Gamma(f64::from(u16_at(at + 12)?) / 256.0)),
+                    count => (0..count as usize)
+                        .map(|i| u16_at(at + 12 + i * 2).map(|v| f64::from(v) / 65535.0))
+                        .collect::<Option<Vec<_>>>()
+                        .map(Curve::Table),
+                },
+                b"para" => {
+                    let kind = u16_at(at + 8)?;
+                    let count = match kind {
+                        0 => 1,
+                        1 => 3,
+                        2 => 4,
+                        3 => 5,
+                        4 => 7,
+                        _ => return None,
+                    };
+                    let mut params = [0.0; 7];
+                    for (i, param) in params.iter_mut().enumerate().take(count) {
+                        *param = s15_fixed16_at(at + 12 + i * 4)?;
+                    }
+                    Some(Curve::Parametric(kind, params))
+                }
+                _ => None,
+            }
+        };
+
+        let (r, g, b) = (xyz(b"rXYZ")?, xyz(b"gXYZ")?, xyz(b"bXYZ")?);
+        Some(RgbProfile {
+            curves: [curve(b"rTRC")?, curve(b"gTRC")?, curve(b"bTRC")?],
+            // The columns are the XYZ of each primary
+            to_xyz: [[r[0], g[0], b[0]], [r[1], g[1], b[1]], [r[2], g[2], b[2]]],
+        })
+    }
+
+    /// Converts the pixels of an image in that profile to sRGB
+    pub fn convert_to_srgb(&self, img: DynamicImage) -> DynamicImage {
+        let mut matrix = [[0.0; 3]; 3];
+        for (i, row) in matrix.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..3).map(|k| XYZ_D50_TO_SRGB[i][k] * self.to_xyz[k][j]).sum();
+            }
+        }
+        // 8 bits per channel, so every value can be made linear once up front
+        let luts: Vec<Vec<f64>> = self
+            .curves
+            .iter()
+            .map(|curve| (0..256).map(|v| curve.linearize(f64::from(v) / 255.0)).collect())
+            .collect();
+        let convert = |r: u8, g: u8, b: u8| -> [u8; 3] {
+            let linear = [luts[0][r as usize], luts[1][g as usize], luts[2][b as usize]];
+            let mut srgb = [0; 3];
+            for (channel, row) in srgb.iter_mut().zip(&matrix) {
+                let value = row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2];
+                *channel = encode_srgb(value);
+            }
+            srgb
+        };
+
+        let (width, height) = img.dimensions();
+        if img.color().has_alpha() {
+            let rgba = img.to_rgba8();
+            DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, y| {
+                let [r, g, b, a] = rgba.get_pixel(x, y).0;
+                let [r, g, b] = convert(r, g, b);
+                Rgba([r, g, b, a])
+            }))
+        } else {
+            let rgb = img.to_rgb8();
+            DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+                let [r, g, b] = rgb.get_pixel(x, y).0;
+                Rgb(convert(r, g, b))
+            }))
+        }
+    }
+}
+
+/// Applies the sRGB transfer function to a linear value, clipping the colors sRGB can't show
+fn encode_srgb(linear: f64) -> u8 {
+    let linear = linear.max(0.0).min(1.0);
+    let encoded =
+        if linear <= 0.003_130_8 { linear * 12.92 } else { 1.055 * linear.powf(1.0 / 2.4) - 0.055 };
+    (encoded * 255.0).round() as u8
+}
@@ -1,26 +1,161 @@
+use std::cell::RefCell;
 use std::collections::hash_map::DefaultHasher;
 use std::collections::hash_map::Entry as HEntry;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs::{self, File};
 use std::hash::{Hash, Hasher};
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
 use image::imageops::FilterType;
-use image::{GenericImageView, ImageOutputFormat};
-use lazy_static::lazy_static;
+use image::{ColorType, DynamicImage, GenericImageView, ImageOutputFormat, Luma, LumaA};
 use rayon::prelude::*;
 use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
 
-use errors::{Error, Result};
+use config::{
+    CacheHeadersFormat, Config, EncoderChangePolicy, ForbidUpscale, ImageProcessing, ResizeRounding,
+};
+use errors::{bail, Error, Result};
 use utils::fs as ufs;
+use utils::slugs::{slugify_paths, SlugifyStrategy};
 
-static RESIZED_SUBDIR: &str = "processed_images";
+mod animation;
+mod colors;
+mod error;
+mod exif;
+mod focus;
+mod heic;
+mod icc;
+mod meta;
+mod placeholder;
 
-lazy_static! {
-    pub static ref RESIZED_FILENAME: Regex =
-        Regex::new(r#"([0-9a-f]{16})([0-9a-f]{2})[.](jpg|png)"#).unwrap();
+pub use self::animation::{read_animation, Animation};
+pub use self::colors::{ImageColors, MAX_PALETTE_SIZE};
+pub use self::error::ImageOpError;
+pub use self::exif::{ExifTransform, ExifTransforms};
+pub use self::focus::Focus;
+pub use self::icc::ColorProfile;
+pub use self::meta::{
+    detect_mime_type, estimate_jpeg_quality, format_mime_type, is_likely_screenshot,
+    read_image_colors, read_image_date, read_image_metadata, read_image_metadata_with_colors,
+    ImageMeta,
+};
+pub use self::placeholder::{
+    gradient_css, placeholder_data_uri, preview_data_uri, svg_blur_placeholder,
+};
+
+/// Where the sources that couldn't be processed are copied with `fallback_to_original`,
+/// inside the `processed_dir`
+static ORIGINALS_SUBDIR: &str = "originals";
+
+/// Where a source is copied inside of `originals`. The sources outside of `content`, those of
+/// `asset_dirs` and the remote images, go in `_root` so that `../assets/a.jpg` can't overwrite
+/// the copy of `content/assets/a.jpg`
+fn original_path(source: &str) -> String {
+    let source = Path::new(source);
+    let parts = source
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/");
+    if source.components().any(|c| c == Component::ParentDir) {
+        format!("_root/{}", parts)
+    } else {
+        parts
+    }
+}
+
+/// Identifies the encoders the processed images are made with. Bump it when upgrading the
+/// `image` crate, or when changing how an image is encoded without changing its filename, so
+/// images encoded by the previous version can be regenerated
+pub const ENCODER_VERSION: &str = "image-0.23-2";
+/// The file in the processed images directory recording the `ENCODER_VERSION` they were made with
+const ENCODER_VERSION_FILE: &str = ".encoder-version";
+/// The file in the processed images directory listing them with `write_manifest`
+const MANIFEST_FILE: &str = "manifest.json";
+/// The file in the processed images directory listing the filenames written there since the
+/// last `clean_processed_images`, which only deletes those and the ones the current
+/// `filename_template` makes
+const PROCESSED_FILES_FILE: &str = ".processed-files";
+/// The subdirectory of each `output_dir` its images are written to. Zola owns it and prunes
+/// it, so the files of `static` next to it are never touched
+const OUTPUT_DIR_SUBDIR: &str = "processed_images";
+/// The file in the processed images directory listing the `output_dir` of the last build, so
+/// the ones that aren't used anymore, eg of a deleted page, can be pruned
+const OUTPUT_DIRS_FILE: &str = ".output-dirs";
+/// The file in the processed images directory recording which operation, by hash, and source
+/// each filename was given to in the last build, so the same one keeps it whatever the order
+/// the templates are rendered in, and a file made by another operation is never kept as is
+const FILENAME_OWNERS_FILE: &str = ".filename-owners";
+
+/// The processed images never change once made as their names depend on what they are made of
+const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+/// The lines around the processed images in a `_headers` file, everything else is kept as is
+const HEADERS_BLOCK_START: &str = "# Processed images, generated by Zola";
+const HEADERS_BLOCK_END: &str = "# End of the processed images";
+
+/// How many characters of the hash are appended to the stem of an image whose filename is
+/// already used by another one, tried in order
+const STEM_HASH_LENGTHS: &[usize] = &[8, 18];
+
+/// The JPEG quality used when `resize_image` isn't given one
+pub const DEFAULT_QUALITY: u8 = 75;
+/// How far above the estimated quality of the source JPEG the output quality
+/// can go when `clamp_quality_to_source` is enabled
+const SOURCE_QUALITY_MARGIN: u8 = 5;
+/// The estimated quality the embedded thumbnail of a JPEG needs to be used with
+/// `use_embedded_thumbnails`, the ones of most cameras being good enough
+const MIN_THUMBNAIL_QUALITY: u8 = 80;
+
+/// A dimension given to `resize_image`: either a number of pixels or the name of one of
+/// the `sizes` of the `[image_processing]` config, prefixed by `@`, eg `@content-width`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Dimension {
+    Pixels(u32),
+    Named(String),
+}
+
+impl Dimension {
+    pub fn resolve(&self, settings: &ImageProcessing) -> Result<u32> {
+        match self {
+            Dimension::Pixels(px) => Ok(*px),
+            Dimension::Named(name) => {
+                if !name.starts_with('@') {
+                    return Err(format!(
+                        "Invalid dimension `{}`, named sizes need to start with `@`",
+                        name
+                    )
+                    .into());
+                }
+                match settings.sizes.get(&name[1..]) {
+                    Some(px) => Ok(*px),
+                    None => {
+                        let mut available: Vec<_> =
+                            settings.sizes.keys().map(|k| format!("@{}", k)).collect();
+                        available.sort();
+                        Err(format!(
+                            "Unknown size `{}`, available sizes are: {}",
+                            name,
+                            available.join(", ")
+                        )
+                        .into())
+                    }
+                }
+            }
+        }
+    }
 }
 
+/// The operations `resize_image` accepts as `op`
+pub const RESIZE_OPS: &[&str] =
+    &["fill", "fit", "fit_width", "fit_height", "scale", "crop", "convert"];
+
 /// Describes the precise kind of a resize operation
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ResizeOp {
@@ -41,35 +176,33 @@ pub enum ResizeOp {
     /// The part of the image that doesn't fit in the thumbnail due to differing
     /// aspect ratio will be cropped away, if any.
     Fill(u32, u32),
+    /// Keeps the rectangle of the image at that x and y offset with that width and height,
+    /// in pixels. Nothing is resized.
+    Crop(u32, u32, u32, u32),
 }
 
 impl ResizeOp {
-    pub fn from_args(op: &str, width: Option<u32>, height: Option<u32>) -> Result<ResizeOp> {
+    pub fn from_args(
+        op: &str,
+        width: Option<u32>,
+        height: Option<u32>,
+    ) -> std::result::Result<ResizeOp, ImageOpError> {
         use ResizeOp::*;
 
         // Validate args:
-        match op {
-            "fit_width" => {
-                if width.is_none() {
-                    return Err("op=\"fit_width\" requires a `width` argument".to_string().into());
-                }
-            }
-            "fit_height" => {
-                if height.is_none() {
-                    return Err("op=\"fit_height\" requires a `height` argument"
-                        .to_string()
-                        .into());
-                }
+        let missing = match op {
+            "fit_width" if width.is_none() => Some("a `width`"),
+            "fit_height" if height.is_none() => Some("a `height`"),
+            "scale" | "fit" | "fill" if width.is_none() || height.is_none() => {
+                Some("a `width` and `height`")
             }
-            "scale" | "fit" | "fill" => {
-                if width.is_none() || height.is_none() {
-                    return Err(
-                        format!("op={} requires a `width` and `height` argument", op).into()
-                    );
-                }
-            }
-            _ => return Err(format!("Invalid image resize operation: {}", op).into()),
+            "fit_width" | "fit_height" | "scale" | "fit" | "fill" => None,
+            "crop" => Some("a `crop`"),
+            _ => return Err(ImageOpError::UnknownOp(op.to_string())),
         };
+        if let Some(missing) = missing {
+            return Err(ImageOpError::MissingDimensions { op: op.to_string(), missing });
+        }
 
         Ok(match op {
             "scale" => Scale(width.unwrap(), height.unwrap()),
@@ -90,6 +223,91 @@ impl ResizeOp {
             FitHeight(_) => None,
             Fit(w, _) => Some(w),
             Fill(w, _) => Some(w),
+            Crop(_, _, w, _) => Some(w),
+        }
+    }
+
+    /// The name of the operation, as given to `resize_image`
+    pub fn name(self) -> &'static str {
+        use ResizeOp::*;
+
+        match self {
+            Scale(_, _) => "scale",
+            FitWidth(_) => "fit_width",
+            FitHeight(_) => "fit_height",
+            Fit(_, _) => "fit",
+            Fill(_, _) => "fill",
+            Crop(_, _, _, _) => "crop",
+        }
+    }
+
+    /// The dimensions of the image this operation makes out of a source of that size, the
+    /// dimension computed to keep the aspect ratio being rounded with `rounding`
+    pub fn output_dimensions(
+        self,
+        (img_w, img_h): (u32, u32),
+        rounding: ResizeRounding,
+    ) -> (u32, u32) {
+        use ResizeOp::*;
+
+        match self {
+            Scale(w, h) | Fill(w, h) | Crop(_, _, w, h) => (w, h),
+            FitWidth(w) => fit_dimensions((img_w, img_h), (w, u32::max_value()), rounding),
+            FitHeight(h) => fit_dimensions((img_w, img_h), (u32::max_value(), h), rounding),
+            Fit(w, h) => {
+                if img_w > w || img_h > h {
+                    fit_dimensions((img_w, img_h), (w, h), rounding)
+                } else {
+                    (img_w, img_h)
+                }
+            }
+        }
+    }
+
+    /// The same operation making an image that fits in a `max` x `max` square, `None` if
+    /// the image it makes already does. Crops are kept as they are since they don't resize
+    pub fn capped(
+        self,
+        source_dimensions: (u32, u32),
+        max: u32,
+        rounding: ResizeRounding,
+    ) -> Option<ResizeOp> {
+        self.fitted(source_dimensions, (max, max), rounding)
+    }
+
+    /// The same operation making an image no bigger than its source, with the same aspect
+    /// ratio. `None` if it already isn't, which is always the case of `fit` and `crop`
+    pub fn clamped_to_source(
+        self,
+        source_dimensions: (u32, u32),
+        rounding: ResizeRounding,
+    ) -> Option<ResizeOp> {
+        self.fitted(source_dimensions, source_dimensions, rounding)
+    }
+
+    /// The same operation making an image that fits in the box, `None` if the image it makes
+    /// already does
+    fn fitted(
+        self,
+        source_dimensions: (u32, u32),
+        box_dimensions: (u32, u32),
+        rounding: ResizeRounding,
+    ) -> Option<ResizeOp> {
+        use ResizeOp::*;
+
+        let (w, h) = self.output_dimensions(source_dimensions, rounding);
+        if w <= box_dimensions.0 && h <= box_dimensions.1 {
+            return None;
+        }
+        // Rounded down so the fitted image is never bigger than the box
+        let (w, h) = fit_dimensions((w, h), box_dimensions, ResizeRounding::Floor);
+        match self {
+            Scale(_, _) => Some(Scale(w, h)),
+            FitWidth(_) => Some(FitWidth(w)),
+            FitHeight(_) => Some(FitHeight(h)),
+            Fit(_, _) => Some(Fit(w, h)),
+            Fill(_, _) => Some(Fill(w, h)),
+            Crop(..) => None,
         }
     }
 
@@ -102,10 +320,32 @@ impl ResizeOp {
             FitHeight(h) => Some(h),
             Fit(_, h) => Some(h),
             Fill(_, h) => Some(h),
+            Crop(_, _, _, h) => Some(h),
         }
     }
 }
 
+/// The largest dimensions fitting in the box that keep the aspect ratio of the image.
+/// The computed dimension is rounded with `rounding`, `Floor` rounding down the same way the
+/// `image` crate does. `Even` can make it go a pixel past an odd box
+fn fit_dimensions(
+    (img_w, img_h): (u32, u32),
+    (box_w, box_h): (u32, u32),
+    rounding: ResizeRounding,
+) -> (u32, u32) {
+    let (img_w, img_h) = (u64::from(img_w), u64::from(img_h));
+    // Integer division is exact, unlike going through floats
+    let scaled = |len: u64, num: u64, den: u64| match rounding {
+        ResizeRounding::Floor => (len * num / den).max(1) as u32,
+        _ => rounding.apply(len as f64 * num as f64 / den as f64),
+    };
+    if u64::from(box_w) * img_h <= img_w * u64::from(box_h) {
+        (box_w, scaled(img_h, u64::from(box_w), img_w))
+    } else {
+        (scaled(img_w, u64::from(box_h), img_h), box_h)
+    }
+}
+
 impl From<ResizeOp> for u8 {
     fn from(op: ResizeOp) -> u8 {
         use ResizeOp::*;
@@ -116,6 +356,7 @@ impl From<ResizeOp> for u8 {
             FitHeight(_) => 3,
             Fit(_, _) => 4,
             Fill(_, _) => 5,
+            Crop(_, _, _, _) => 6,
         }
     }
 }
@@ -130,6 +371,209 @@ impl Hash for ResizeOp {
         if let Some(h) = self.height() {
             hasher.write_u32(h);
         }
+        if let ResizeOp::Crop(x, y, _, _) = *self {
+            hasher.write_u32(x);
+            hasher.write_u32(y);
+        }
+    }
+}
+
+/// The unit of the values of a `crop` argument
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CropUnit {
+    Pixels,
+    /// Percentages of the dimensions of the source, so the same crop works on sources of any size
+    Percent,
+}
+
+impl CropUnit {
+    pub fn from_args(unit: &str) -> Result<CropUnit> {
+        match unit {
+            "px" => Ok(CropUnit::Pixels),
+            "percent" => Ok(CropUnit::Percent),
+            _ => {
+                Err(format!("Invalid crop unit: {}, it needs to be `px` or `percent`", unit).into())
+            }
+        }
+    }
+}
+
+/// The rectangle to keep with `op="crop"`: x and y offsets, width and height
+#[derive(Debug, Clone, PartialEq)]
+pub struct CropArea {
+    pub unit: CropUnit,
+    pub rect: [f64; 4],
+}
+
+impl CropArea {
+    /// Converts the rectangle to pixels of a source of that size, making sure it's within the source
+    pub fn to_pixels(&self, (img_w, img_h): (u32, u32)) -> Result<ResizeOp> {
+        let [x, y, w, h] = self.rect;
+        if self.rect.iter().any(|v| !v.is_finite() || *v < 0.0) {
+            return Err(format!(
+                "Invalid crop rectangle {:?}: values can't be negative",
+                self.rect
+            )
+            .into());
+        }
+        if w <= 0.0 || h <= 0.0 {
+            return Err(format!(
+                "Invalid crop rectangle {:?}: it needs a positive area",
+                self.rect
+            )
+            .into());
+        }
+
+        let (x, y, w, h) = match self.unit {
+            CropUnit::Pixels => {
+                let (x, y) = (x.round() as u32, y.round() as u32);
+                // Rounding can't make the rectangle empty
+                (x, y, (w.round() as u32).max(1), (h.round() as u32).max(1))
+            }
+            CropUnit::Percent => {
+                if self.rect.iter().any(|v| *v > 100.0) || x + w > 100.0 || y + h > 100.0 {
+                    return Err(format!(
+                        "Invalid crop rectangle {:?}: percentages need to stay within 0-100",
+                        self.rect
+                    )
+                    .into());
+                }
+                // The pixels partly covered by the rectangle are kept, the edges being rounded
+                // outwards rather than the width so the crop never ends past the source
+                let edges = |start: f64, length: f64, size: u32| {
+                    let size_f = f64::from(size);
+                    let start_px = ((start * size_f / 100.0).floor() as u32).min(size - 1);
+                    let end_px = (((start + length) * size_f / 100.0).ceil() as u32).min(size);
+                    (start_px, (end_px - start_px).max(1))
+                };
+                let (x, w) = edges(x, w, img_w);
+                let (y, h) = edges(y, h, img_h);
+                (x, y, w, h)
+            }
+        };
+        if u64::from(x) + u64::from(w) > u64::from(img_w)
+            || u64::from(y) + u64::from(h) > u64::from(img_h)
+        {
+            return Err(format!(
+                "Invalid crop rectangle {:?}: it goes beyond the {}x{} source",
+                self.rect, img_w, img_h
+            )
+            .into());
+        }
+        Ok(ResizeOp::Crop(x, y, w, h))
+    }
+}
+
+/// The arguments of `resize_image` describing an operation
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResizeArgs {
+    pub op: String,
+    pub width: Option<Dimension>,
+    pub height: Option<Dimension>,
+    pub format: String,
+    pub quality: u8,
+    /// Only used with `op="crop"`
+    pub crop: Option<CropArea>,
+    /// Rounds the corners of the image with that radius in pixels
+    pub corner_radius: Option<u32>,
+    /// Makes the image a circle, its diameter being the smallest of its dimensions
+    pub circle: bool,
+    /// Where to crop with `op="fill"`, `center` by default
+    pub focus: Option<String>,
+    /// The edge or corner `op="fill"` keeps, eg `top`. Ignored by the other operations
+    pub anchor: Option<String>,
+    /// Whether the source is turned according to its EXIF orientation first. Always the case
+    /// with `apply_exif_transforms`
+    pub respect_exif: bool,
+    /// The quality of the alpha channel, when it's not the same as `quality`. Only AVIF has one
+    pub alpha_quality: Option<u8>,
+    /// Whether the metadata of the source is left out of the output, `strip_metadata` of the
+    /// config when not set
+    pub strip_metadata: Option<bool>,
+    /// Scales both dimensions of the source to that percentage, instead of `width` and
+    /// `height`. Only used with `op="scale"`
+    pub percent: Option<u32>,
+    /// The directory of `static` the image is written to instead of `processed_dir`
+    pub output_dir: Option<String>,
+    /// Whether only the first frame of animated sources is kept, instead of resizing all of
+    /// them into an animated GIF
+    pub first_frame_only: bool,
+    /// What is done with the ICC profile of the source: `preserve`, `srgb` or `strip`, which
+    /// is what `None` does
+    pub color_profile: Option<String>,
+}
+
+/// An alpha mask applied to the image after it's resized
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mask {
+    None,
+    /// Rounded corners with that radius in pixels
+    RoundedCorners(u32),
+    /// A circle centered on the image
+    Circle,
+}
+
+impl Mask {
+    pub fn from_args(corner_radius: Option<u32>, circle: bool) -> Result<Mask> {
+        match (corner_radius, circle) {
+            (Some(_), true) => {
+                Err("`corner_radius` and `circle` cannot be used at the same time".into())
+            }
+            (Some(radius), false) if radius > 0 => Ok(Mask::RoundedCorners(radius)),
+            (_, true) => Ok(Mask::Circle),
+            _ => Ok(Mask::None),
+        }
+    }
+
+    /// Makes the pixels outside of the mask transparent, antialiasing its edges
+    fn apply(self, img: DynamicImage) -> DynamicImage {
+        if self == Mask::None {
+            return img;
+        }
+
+        let mut rgba = img.to_rgba8();
+        let (w, h) = rgba.dimensions();
+        let (w, h) = (f64::from(w), f64::from(h));
+        // Pixels are kept if they are within `radius` of the inner rectangle, which is
+        // only the center of the image for circles
+        let (radius, (x_min, x_max), (y_min, y_max)) = match self {
+            Mask::Circle => (w.min(h) / 2.0, (w / 2.0, w / 2.0), (h / 2.0, h / 2.0)),
+            Mask::RoundedCorners(r) => {
+                let r = f64::from(r).min(w / 2.0).min(h / 2.0);
+                (r, (r, w - r), (r, h - r))
+            }
+            Mask::None => unreachable!(),
+        };
+        for (x, y, pixel) in rgba.enumerate_pixels_mut() {
+            let (px, py) = (f64::from(x) + 0.5, f64::from(y) + 0.5);
+            let (cx, cy) = (px.max(x_min).min(x_max), py.max(y_min).min(y_max));
+            let distance = ((px - cx).powi(2) + (py - cy).powi(2)).sqrt();
+            let coverage = (radius - distance + 0.5).max(0.0).min(1.0);
+            if coverage < 1.0 {
+                pixel.0[3] = (f64::from(pixel.0[3]) * coverage).round() as u8;
+            }
+        }
+        DynamicImage::ImageRgba8(rgba)
+    }
+}
+
+/// How much effort the PNG encoder puts into making small files, PNGs being lossless
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PngCompression {
+    Fast,
+    Default,
+    Best,
+}
+
+impl PngCompression {
+    /// The `quality` given for a PNG: below 40 is fast, 80 and above is the best compression.
+    /// The default quality of `resize_image` gives the default compression
+    pub fn from_quality(quality: u8) -> PngCompression {
+        match quality {
+            0..=39 => PngCompression::Fast,
+            40..=79 => PngCompression::Default,
+            _ => PngCompression::Best,
+        }
     }
 }
 
@@ -138,25 +582,67 @@ impl Hash for ResizeOp {
 pub enum Format {
     /// JPEG, The `u8` argument is JPEG quality (in percent).
     Jpeg(u8),
-    /// PNG
-    Png,
+    /// PNG, compressed with that effort
+    Png(PngCompression),
+    /// AVIF, The arguments are the quality (in percent) of the colors and, if it's different,
+    /// of the alpha channel.
+    #[cfg(feature = "avif")]
+    Avif(u8, Option<u8>),
+    /// GIF, the only output format that keeps the frames of an animation
+    Gif,
 }
 
+/// The output formats this build of Zola can encode
+#[cfg(not(feature = "avif"))]
+pub const SUPPORTED_FORMATS: &[&str] = &["auto", "jpg", "jpeg", "png", "gif"];
+#[cfg(feature = "avif")]
+pub const SUPPORTED_FORMATS: &[&str] = &["auto", "jpg", "jpeg", "png", "gif", "avif"];
+
+/// Output formats that exist but whose encoder is not compiled into this build of Zola
+#[cfg(not(feature = "avif"))]
+const UNAVAILABLE_FORMATS: &[&str] = &["webp", "webp-lossless", "avif"];
+#[cfg(feature = "avif")]
+const UNAVAILABLE_FORMATS: &[&str] = &["webp", "webp-lossless"];
+
+/// The speed of the AVIF encoder, from 1 (slowest, smallest files) to 10
+#[cfg(feature = "avif")]
+const AVIF_SPEED: u8 = 6;
+
 impl Format {
-    pub fn from_args(source: &str, format: &str, quality: u8) -> Result<Format> {
+    /// If `fallback` is set, asking for a format whose encoder is not available will
+    /// print a warning and use the `auto` format instead of erroring
+    pub fn from_args(
+        source: &str,
+        format: &str,
+        quality: u8,
+        fallback: bool,
+    ) -> std::result::Result<Format, ImageOpError> {
         use Format::*;
 
-        assert!(quality > 0 && quality <= 100, "Jpeg quality must be within the range [1; 100]");
+        if !(1..=100).contains(&quality) {
+            return Err(ImageOpError::InvalidQuality(quality));
+        }
 
         match format {
             "auto" => match Self::is_lossy(source) {
                 Some(true) => Ok(Jpeg(quality)),
-                Some(false) => Ok(Png),
-                None => Err(format!("Unsupported image file: {}", source).into()),
+                Some(false) => Ok(Png(PngCompression::from_quality(quality))),
+                None => Err(ImageOpError::UnsupportedSource(source.to_string())),
             },
             "jpeg" | "jpg" => Ok(Jpeg(quality)),
-            "png" => Ok(Png),
-            _ => Err(format!("Invalid image format: {}", format).into()),
+            "png" => Ok(Png(PngCompression::from_quality(quality))),
+            "gif" => Ok(Gif),
+            #[cfg(feature = "avif")]
+            "avif" => Ok(Avif(quality, None)),
+            _ if UNAVAILABLE_FORMATS.contains(&format) => {
+                let error = ImageOpError::UnavailableFormat(format.to_string());
+                if !fallback {
+                    return Err(error);
+                }
+                println!("Warning: {}, using format=\"auto\" for {}", error, source);
+                Self::from_args(source, "auto", quality, fallback)
+            }
+            _ => Err(ImageOpError::UnknownFormat(format.to_string())),
         }
     }
 
@@ -166,7 +652,7 @@ impl Format {
             .extension()
             .and_then(std::ffi::OsStr::to_str)
             .map(|ext| match ext.to_lowercase().as_str() {
-                "jpg" | "jpeg" => Some(true),
+                "jpg" | "jpeg" | "heic" | "heif" => Some(true),
                 "png" => Some(false),
                 "gif" => Some(false),
                 "bmp" => Some(false),
@@ -175,13 +661,60 @@ impl Format {
             .unwrap_or(None)
     }
 
-    fn extension(&self) -> &str {
-        // Kept in sync with RESIZED_FILENAME and op_filename
+    fn extension(&self) -> &'static str {
+        // Kept in sync with `filename_regex`
         use Format::*;
 
         match *self {
-            Png => "png",
+            Png(_) => "png",
             Jpeg(_) => "jpg",
+            #[cfg(feature = "avif")]
+            Avif(..) => "avif",
+            Gif => "gif",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match *self {
+            Format::Png(_) => "image/png",
+            Format::Jpeg(_) => "image/jpeg",
+            #[cfg(feature = "avif")]
+            Format::Avif(..) => "image/avif",
+            Format::Gif => "image/gif",
+        }
+    }
+
+    /// Drops the channels a lossless output doesn't use: the alpha of fully opaque images and
+    /// the colors of grayscale ones. Flat graphics like UI assets and screenshots are often
+    /// saved as RGBA while being neither, this can make them several times smaller.
+    /// Only done for the PNGs picked by `format="auto"`, an explicit `format="png"` keeping the
+    /// channels of the source. PNG is the only lossless encoder of this build so `auto` can't
+    /// also try lossless WebP, and indexed PNGs are out of scope as the PNG encoder of `image`
+    /// can't write a palette.
+    /// With `keep_rgb`, grayscale images stay RGB so the RGB ICC profile kept with them applies
+    fn narrow_color_type(img: DynamicImage, keep_rgb: bool) -> DynamicImage {
+        // 16 bit images would lose their depth
+        if !matches!(img.color(), ColorType::Rgba8 | ColorType::Rgb8 | ColorType::La8) {
+            return img;
+        }
+        let opaque = meta::is_opaque(&img);
+        let rgba = img.to_rgba8();
+        let gray = !keep_rgb && rgba.pixels().all(|p| p.0[0] == p.0[1] && p.0[1] == p.0[2]);
+        let (width, height) = rgba.dimensions();
+        match (gray, opaque) {
+            (true, true) => {
+                DynamicImage::ImageLuma8(image::ImageBuffer::from_fn(width, height, |x, y| {
+                    Luma([rgba.get_pixel(x, y).0[0]])
+                }))
+            }
+            (true, false) => {
+                DynamicImage::ImageLumaA8(image::ImageBuffer::from_fn(width, height, |x, y| {
+                    let p = rgba.get_pixel(x, y).0;
+                    LumaA([p[0], p[3]])
+                }))
+            }
+            (false, true) => DynamicImage::ImageRgb8(img.to_rgb8()),
+            (false, false) => img,
         }
     }
 }
@@ -192,8 +725,30 @@ impl Hash for Format {
         use Format::*;
 
         let q = match *self {
-            Png => 0,
+            // Only hashed when not the default so existing PNGs keep their filenames
+            Png(compression) => {
+                match compression {
+                    PngCompression::Fast => hasher.write(b"png-fast"),
+                    PngCompression::Default => (),
+                    PngCompression::Best => hasher.write(b"png-best"),
+                }
+                0
+            }
             Jpeg(q) => q,
+            #[cfg(feature = "avif")]
+            Avif(q, alpha_quality) => {
+                // Sets it apart from a JPEG of the same quality
+                hasher.write(b"avif");
+                if let Some(alpha_quality) = alpha_quality {
+                    hasher.write(b"alpha");
+                    hasher.write_u8(alpha_quality);
+                }
+                q
+            }
+            Gif => {
+                hasher.write(b"gif");
+                0
+            }
         };
 
         hasher.write_u8(q);
@@ -201,11 +756,40 @@ impl Hash for Format {
 }
 
 /// Holds all data needed to perform a resize operation
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ImageOp {
     source: String,
     op: ResizeOp,
     format: Format,
+    mask: Mask,
+    focus: Focus,
+    /// What of the crop and orientation of the EXIF data of the source is applied before the op
+    exif_transforms: ExifTransforms,
+    /// How the dimension computed to keep the aspect ratio is rounded
+    rounding: ResizeRounding,
+    /// Whether the EXIF thumbnail of the source is resized instead of the source when it's big
+    /// enough, with `use_embedded_thumbnails`
+    embedded_thumbnail: bool,
+    /// Whether the EXIF data of the source is left out of the output. Only JPEG and PNG
+    /// outputs can keep it
+    strip_metadata: bool,
+    /// What is done with the ICC profile of the source, always `Strip` for sources without one
+    color_profile: ColorProfile,
+    /// The ICC profile of the source, read once when the operation is made, unless
+    /// `color_profile` is `Strip`
+    icc_profile: Option<Arc<Vec<u8>>>,
+    /// The percentage of the source `op="scale"` was given instead of dimensions
+    percent: Option<u32>,
+    /// The directory of `static` the output goes in instead of `processed_dir`, with `/` slashes
+    output_dir: Option<String>,
+    /// Whether all the frames of the source, an animated GIF, are resized into the output
+    animated: bool,
+    /// Whether the output, a PNG picked by `format="auto"`, is written with only the channels
+    /// the image uses
+    narrow_color_type: bool,
+    /// The hash of the content of the source, hashed instead of its path with
+    /// `dedup_identical_sources` so copies of the same image make the same files
+    content_hash: Option<u64>,
     /// Hash of the above parameters
     hash: u64,
     /// If there is a hash collision with another ImageOp, this contains a sequential ID > 1
@@ -217,163 +801,1459 @@ pub struct ImageOp {
 
 impl ImageOp {
     pub fn new(source: String, op: ResizeOp, format: Format) -> ImageOp {
-        let mut hasher = DefaultHasher::new();
-        hasher.write(source.as_ref());
-        op.hash(&mut hasher);
-        format.hash(&mut hasher);
-        let hash = hasher.finish();
-
-        ImageOp { source, op, format, hash, collision_id: 0 }
+        let mut img_op = ImageOp {
+            source,
+            op,
+            format,
+            mask: Mask::None,
+            focus: Focus::Center,
+            exif_transforms: ExifTransforms::None,
+            rounding: ResizeRounding::Floor,
+            embedded_thumbnail: false,
+            strip_metadata: true,
+            color_profile: ColorProfile::Strip,
+            icc_profile: None,
+            percent: None,
+            output_dir: None,
+            animated: false,
+            narrow_color_type: false,
+            content_hash: None,
+            hash: 0,
+            collision_id: 0,
+        };
+        img_op.hash = img_op.compute_hash();
+        img_op
     }
 
-    pub fn from_args(
-        source: String,
-        op: &str,
-        width: Option<u32>,
-        height: Option<u32>,
-        format: &str,
-        quality: u8,
-    ) -> Result<ImageOp> {
-        let op = ResizeOp::from_args(op, width, height)?;
-        let format = Format::from_args(&source, format, quality)?;
-        Ok(Self::new(source, op, format))
+    pub fn format(&self) -> Format {
+        self.format
     }
 
-    fn perform(&self, content_path: &Path, target_path: &Path) -> Result<()> {
-        use ResizeOp::*;
-
-        let src_path = content_path.join(&self.source);
-        if !ufs::file_stale(&src_path, target_path) {
-            return Ok(());
-        }
+    pub fn with_mask(mut self, mask: Mask) -> ImageOp {
+        self.mask = mask;
+        self.hash = self.compute_hash();
+        self
+    }
 
-        let mut img = image::open(&src_path)?;
-        let (img_w, img_h) = img.dimensions();
+    pub fn with_focus(mut self, focus: Focus) -> ImageOp {
+        self.focus = focus;
+        self.hash = self.compute_hash();
+        self
+    }
 
-        const RESIZE_FILTER: FilterType = FilterType::Lanczos3;
-        const RATIO_EPSILLION: f32 = 0.1;
+    pub fn with_exif_transforms(mut self, exif_transforms: ExifTransforms) -> ImageOp {
+        self.exif_transforms = exif_transforms;
+        self.hash = self.compute_hash();
+        self
+    }
 
-        let img = match self.op {
-            Scale(w, h) => img.resize_exact(w, h, RESIZE_FILTER),
-            FitWidth(w) => img.resize(w, u32::max_value(), RESIZE_FILTER),
-            FitHeight(h) => img.resize(u32::max_value(), h, RESIZE_FILTER),
-            Fit(w, h) => {
-                if img_w > w || img_h > h {
-                    img.resize(w, h, RESIZE_FILTER)
-                } else {
-                    img
-                }
-            }
-            Fill(w, h) => {
-                let factor_w = img_w as f32 / w as f32;
-                let factor_h = img_h as f32 / h as f32;
+    pub fn with_rounding(mut self, rounding: ResizeRounding) -> ImageOp {
+        self.rounding = rounding;
+        self.hash = self.compute_hash();
+        self
+    }
 
-                if (factor_w - factor_h).abs() <= RATIO_EPSILLION {
-                    // If the horizontal and vertical factor is very similar,
-                    // that means the aspect is similar enough that there's not much point
-                    // in cropping, so just perform a simple scale in this case.
-                    img.resize_exact(w, h, RESIZE_FILTER)
-                } else {
-                    // We perform the fill such that a crop is performed first
-                    // and then resize_exact can be used, which should be cheaper than
-                    // resizing and then cropping (smaller number of pixels to resize).
-                    let (crop_w, crop_h) = if factor_w < factor_h {
-                        (img_w, (factor_w * h as f32).round() as u32)
-                    } else {
-                        ((factor_h * w as f32).round() as u32, img_h)
-                    };
+    pub fn with_embedded_thumbnail(mut self, embedded_thumbnail: bool) -> ImageOp {
+        self.embedded_thumbnail = embedded_thumbnail;
+        self.hash = self.compute_hash();
+        self
+    }
 
-                    let (offset_w, offset_h) = if factor_w < factor_h {
-                        (0, (img_h - crop_h) / 2)
-                    } else {
-                        ((img_w - crop_w) / 2, 0)
-                    };
+    pub fn with_strip_metadata(mut self, strip_metadata: bool) -> ImageOp {
+        self.strip_metadata = strip_metadata;
+        self.hash = self.compute_hash();
+        self
+    }
 
-                    img.crop(offset_w, offset_h, crop_w, crop_h).resize_exact(w, h, RESIZE_FILTER)
-                }
-            }
+    /// The profile is only needed for `Preserve` and `Srgb`. It isn't hashed, a source with
+    /// another profile being a changed source
+    pub fn with_color_profile(
+        mut self,
+        color_profile: ColorProfile,
+        icc_profile: Option<Vec<u8>>,
+    ) -> ImageOp {
+        self.color_profile = color_profile;
+        self.icc_profile = match color_profile {
+            ColorProfile::Strip => None,
+            _ => icc_profile.map(Arc::new),
         };
-
-        let mut f = File::create(target_path)?;
-
-        match self.format {
-            Format::Png => {
-                img.write_to(&mut f, ImageOutputFormat::Png)?;
-            }
-            Format::Jpeg(q) => {
-                img.write_to(&mut f, ImageOutputFormat::Jpeg(q))?;
-            }
-        }
-
-        Ok(())
+        self.hash = self.compute_hash();
+        self
     }
-}
 
-/// A strcture into which image operations can be enqueued and then performed.
-/// All output is written in a subdirectory in `static_path`,
-/// taking care of file stale status based on timestamps and possible hash collisions.
-#[derive(Debug)]
-pub struct Processor {
-    content_path: PathBuf,
-    resized_path: PathBuf,
-    resized_url: String,
-    /// A map of a ImageOps by their stored hash.
-    /// Note that this cannot be a HashSet, because hashset handles collisions and we don't want that,
-    /// we need to be aware of and handle collisions ourselves.
-    img_ops: HashMap<u64, ImageOp>,
-    /// Hash collisions go here:
-    img_ops_collisions: Vec<ImageOp>,
-}
+    pub fn with_percent(mut self, percent: Option<u32>) -> ImageOp {
+        self.percent = percent;
+        self.hash = self.compute_hash();
+        self
+    }
 
-impl Processor {
-    pub fn new(content_path: PathBuf, static_path: &Path, base_url: &str) -> Processor {
-        Processor {
-            content_path,
-            resized_path: static_path.join(RESIZED_SUBDIR),
-            resized_url: Self::resized_url(base_url),
-            img_ops: HashMap::new(),
-            img_ops_collisions: Vec::new(),
-        }
+    pub fn with_output_dir(mut self, output_dir: Option<String>) -> ImageOp {
+        self.output_dir = output_dir;
+        self.hash = self.compute_hash();
+        self
     }
 
-    fn resized_url(base_url: &str) -> String {
-        if base_url.ends_with('/') {
-            format!("{}{}", base_url, RESIZED_SUBDIR)
-        } else {
-            format!("{}/{}", base_url, RESIZED_SUBDIR)
-        }
+    pub fn with_animated(mut self, animated: bool) -> ImageOp {
+        self.animated = animated;
+        self.hash = self.compute_hash();
+        self
     }
 
-    pub fn set_base_url(&mut self, base_url: &str) {
-        self.resized_url = Self::resized_url(base_url);
+    pub fn with_narrow_color_type(mut self, narrow_color_type: bool) -> ImageOp {
+        self.narrow_color_type = narrow_color_type;
+        self.hash = self.compute_hash();
+        self
     }
 
-    pub fn source_exists(&self, source: &str) -> bool {
-        self.content_path.join(source).exists()
+    /// The same operation on `source`, a copy of the source with that content hash
+    fn with_content_hash(mut self, source: String, content_hash: u64) -> ImageOp {
+        self.source = source;
+        self.content_hash = Some(content_hash);
+        self.hash = self.compute_hash();
+        self
     }
 
-    pub fn num_img_ops(&self) -> usize {
-        self.img_ops.len() + self.img_ops_collisions.len()
+    /// The dimensions of the image this operation makes out of a source of that size
+    fn output_dimensions(&self, source_dimensions: (u32, u32)) -> (u32, u32) {
+        self.op.output_dimensions(source_dimensions, self.rounding)
     }
 
-    fn insert_with_collisions(&mut self, mut img_op: ImageOp) -> u32 {
-        match self.img_ops.entry(img_op.hash) {
-            HEntry::Occupied(entry) => {
-                if *entry.get() == img_op {
-                    return 0;
-                }
-            }
-            HEntry::Vacant(entry) => {
-                entry.insert(img_op);
-                return 0;
+    fn compute_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        match self.content_hash {
+            Some(content_hash) => {
+                hasher.write(b"content");
+                hasher.write_u64(content_hash);
             }
+            None => hasher.write(self.source.as_ref()),
         }
+        self.op.hash(&mut hasher);
+        self.format.hash(&mut hasher);
+        // Only hashed when set so existing images keep their filenames
+        if self.mask != Mask::None {
+            self.mask.hash(&mut hasher);
+        }
+        if self.focus != Focus::Center {
+            self.focus.hash(&mut hasher);
+        }
+        match self.exif_transforms {
+            ExifTransforms::None => (),
+            // Hashed like the `true` it was when it could only be all or nothing
+            ExifTransforms::All => hasher.write_u8(1),
+            ExifTransforms::Orientation => hasher.write_u8(2),
+        }
+        if self.rounding != ResizeRounding::Floor {
+            self.rounding.hash(&mut hasher);
+        }
+        // The thumbnail doesn't look exactly like the source once resized
+        if self.embedded_thumbnail {
+            hasher.write(b"thumbnail");
+        }
+        if !self.strip_metadata {
+            hasher.write(b"metadata");
+        }
+        match self.color_profile {
+            ColorProfile::Strip => (),
+            ColorProfile::Preserve => hasher.write(b"icc"),
+            ColorProfile::Srgb => hasher.write(b"srgb"),
+        }
+        if let Some(percent) = self.percent {
+            hasher.write(b"percent");
+            hasher.write_u32(percent);
+        }
+        // The same operation written to two directories needs two entries
+        if let Some(ref output_dir) = self.output_dir {
+            hasher.write(b"dir");
+            hasher.write(output_dir.as_bytes());
+        }
+        // A GIF of the first frame and one of all of them are different images
+        if self.animated {
+            hasher.write(b"animated");
+        }
+        // Not the same file as the PNG of an explicit `format="png"`
+        if self.narrow_color_type {
+            hasher.write(b"narrow");
+        }
+        hasher.finish()
+    }
+
+    /// `source_path` is where the source is, its dimensions are needed to convert percentages
+    /// of crops to pixels
+    pub fn from_args(
+        source: String,
+        source_path: &Path,
+        args: &ResizeArgs,
+        settings: &ImageProcessing,
+    ) -> std::result::Result<ImageOp, ImageOpError> {
+        use ImageOpError::{IncompatibleArguments, InvalidArgument};
+
+        // Checked before anything reads the file
+        if !settings.is_allowed_source(&source) {
+            return Err(ImageOpError::NotAllowedSource {
+                source,
+                allowed: settings.allowed_source_extensions.clone(),
+            });
+        }
+        // Sources without an orientation are processed as stored whatever `respect_exif` is,
+        // so their filenames don't depend on it
+        let exif_transforms = ExifTransforms::for_source(
+            source_path,
+            settings.apply_exif_transforms,
+            args.respect_exif,
+        )?;
+        let output_dir = args
+            .output_dir
+            .as_deref()
+            .map(normalize_output_dir)
+            .transpose()
+            .map_err(InvalidArgument)?;
+        if args.percent.is_some() && args.op != "scale" {
+            return Err(IncompatibleArguments(
+                "The `percent` argument can only be used with op=\"scale\"".to_string(),
+            ));
+        }
+        let op = match (args.op.as_str(), &args.crop) {
+            // The crop is relative to the image as displayed, after its EXIF transform
+            ("crop", Some(area)) => area
+                .to_pixels(source_dimensions(source_path, exif_transforms)?)
+                .map_err(InvalidArgument)?,
+            (_, Some(_)) => {
+                return Err(IncompatibleArguments(
+                    "The `crop` argument can only be used with op=\"crop\"".to_string(),
+                ))
+            }
+            // Only the format changes, the pixels are the ones of the source
+            ("convert", None) => {
+                if args.width.is_some() || args.height.is_some() {
+                    return Err(IncompatibleArguments(
+                        "op=\"convert\" keeps the size of the source, it cannot be combined with `width` or `height`"
+                            .to_string(),
+                    ));
+                }
+                let (w, h) = source_dimensions(source_path, exif_transforms)?;
+                ResizeOp::Scale(w, h)
+            }
+            (op, None) => match args.percent {
+                Some(_) if args.width.is_some() || args.height.is_some() => {
+                    return Err(IncompatibleArguments(
+                        "The `percent` argument cannot be combined with `width` or `height`"
+                            .to_string(),
+                    ));
+                }
+                Some(percent) => {
+                    // A percentage of the pixels of the source, which `pixel_ratio` doesn't change
+                    let (w, h) = source_dimensions(source_path, exif_transforms)?;
+                    let scaled = |d: u32| {
+                        settings.resize_rounding.apply(f64::from(d) * f64::from(percent) / 100.0)
+                    };
+                    ResizeOp::Scale(scaled(w), scaled(h))
+                }
+                None => {
+                    // Sizes are given in CSS pixels, the file is made `pixel_ratio` times bigger
+                    let scaled = |d: &Dimension| {
+                        d.resolve(settings).map(|d| d.saturating_mul(settings.pixel_ratio))
+                    };
+                    let width =
+                        args.width.as_ref().map(scaled).transpose().map_err(InvalidArgument)?;
+                    let height =
+                        args.height.as_ref().map(scaled).transpose().map_err(InvalidArgument)?;
+                    ResizeOp::from_args(op, width, height)?
+                }
+            },
+        };
+        // Animations are resized into GIFs rather than silently losing all but their first frame
+        let animated = !args.first_frame_only && is_animated(source_path)?;
+        // Checked before the format so `fallback_unsupported_formats` doesn't turn it into the
+        // error of a format that wasn't asked for
+        if animated && args.format == "webp" {
+            return Err(ImageOpError::AnimatedWebp(source));
+        }
+        let format_arg = match args.format.as_str() {
+            "auto" if animated => "gif",
+            format => format,
+        };
+        let format = Format::from_args(
+            &source,
+            format_arg,
+            args.quality,
+            settings.fallback_unsupported_formats,
+        )?;
+        if animated && format != Format::Gif {
+            return Err(ImageOpError::AnimatedSource { source, format: format.extension() });
+        }
+        if animated
+            && image::ImageFormat::from_path(source_path).ok() != Some(image::ImageFormat::Gif)
+        {
+            return Err(ImageOpError::UnsupportedAnimation(source));
+        }
+        let format = match (format, args.alpha_quality) {
+            #[cfg(feature = "avif")]
+            (Format::Avif(q, _), Some(alpha_quality)) => Format::Avif(q, Some(alpha_quality)),
+            (format, Some(_)) => {
+                println!(
+                    "Warning: `alpha_quality` only applies to AVIF, ignoring it for {} processed to {}",
+                    source,
+                    format.extension().to_uppercase()
+                );
+                format
+            }
+            (format, None) => format,
+        };
+        let strip_metadata = match args.strip_metadata.unwrap_or(settings.strip_metadata) {
+            false if !matches!(format, Format::Jpeg(_) | Format::Png(_)) => {
+                println!(
+                    "Warning: only JPEG and PNG can keep the metadata of the source, leaving it out of {} processed to {}",
+                    source,
+                    format.extension().to_uppercase()
+                );
+                true
+            }
+            strip_metadata => strip_metadata,
+        };
+        let color_profile = match args.color_profile {
+            Some(ref color_profile) => {
+                ColorProfile::from_args(color_profile).map_err(InvalidArgument)?
+            }
+            None => ColorProfile::Strip,
+        };
+        let icc_profile = match color_profile {
+            ColorProfile::Strip => None,
+            _ => icc::read_icc_profile(source_path)?,
+        };
+        // Sources without a profile are processed the same whatever `color_profile` is, so
+        // their filenames don't depend on it
+        let color_profile = match icc_profile {
+            None => ColorProfile::Strip,
+            // The decoder gives RGB pixels for CMYK sources, their profile can't describe them
+            Some(ref profile)
+                if !matches!(icc::color_space(profile), Some("RGB") | Some("GRAY")) =>
+            {
+                println!(
+                    "Warning: the ICC profile of {} is for {} images and can't be kept or converted, leaving it out",
+                    source,
+                    icc::color_space(profile).unwrap_or("unknown")
+                );
+                ColorProfile::Strip
+            }
+            Some(ref profile) => match color_profile {
+                ColorProfile::Srgb if icc::RgbProfile::parse(profile).is_none() => {
+                    println!(
+                        "Warning: the ICC profile of {} can't be converted to sRGB, keeping it instead",
+                        source
+                    );
+                    ColorProfile::Preserve
+                }
+                color_profile => color_profile,
+            },
+        };
+        let color_profile = match color_profile {
+            ColorProfile::Preserve if !matches!(format, Format::Jpeg(_) | Format::Png(_)) => {
+                println!(
+                    "Warning: only JPEG and PNG can keep the ICC profile of the source, leaving it out of {} processed to {}",
+                    source,
+                    format.extension().to_uppercase()
+                );
+                ColorProfile::Strip
+            }
+            color_profile => color_profile,
+        };
+        let op = match settings.forbid_upscale {
+            ForbidUpscale::Off => op,
+            policy => {
+                let source_dimensions = source_dimensions(source_path, exif_transforms)?;
+                match op.clamped_to_source(source_dimensions, settings.resize_rounding) {
+                    Some(clamped) => {
+                        let upscaled =
+                            op.output_dimensions(source_dimensions, settings.resize_rounding);
+                        if policy == ForbidUpscale::Error {
+                            return Err(ImageOpError::Upscaled {
+                                source,
+                                from: source_dimensions,
+                                to: upscaled,
+                            });
+                        }
+                        let (w, h) =
+                            clamped.output_dimensions(source_dimensions, settings.resize_rounding);
+                        println!(
+                            "Warning: {} would be upscaled from {}x{} to {}x{}, making it {}x{} instead",
+                            source,
+                            source_dimensions.0,
+                            source_dimensions.1,
+                            upscaled.0,
+                            upscaled.1,
+                            w,
+                            h
+                        );
+                        clamped
+                    }
+                    None => op,
+                }
+            }
+        };
+        let op = match settings.max_dimensions.get(format.extension()) {
+            Some(max) => {
+                let source_dimensions = source_dimensions(source_path, exif_transforms)?;
+                match op.capped(source_dimensions, *max, settings.resize_rounding) {
+                    Some(capped) => {
+                        let (w, h) =
+                            op.output_dimensions(source_dimensions, settings.resize_rounding);
+                        println!(
+                            "Warning: {} would be {}x{}, downscaling it to fit the {}px max dimension of {}",
+                            source,
+                            w,
+                            h,
+                            max,
+                            format.extension().to_uppercase()
+                        );
+                        capped
+                    }
+                    None => op,
+                }
+            }
+            None => op,
+        };
+        // Rejected before anything allocates the output, a typo like `width=60000` would
+        // otherwise take all the memory of the build
+        let (width, height) = match op {
+            ResizeOp::FitWidth(_) | ResizeOp::FitHeight(_) => op.output_dimensions(
+                source_dimensions(source_path, exif_transforms)?,
+                settings.resize_rounding,
+            ),
+            _ => (op.width().unwrap_or(0), op.height().unwrap_or(0)),
+        };
+        for (dimension, value) in &[("width", width), ("height", height)] {
+            if *value > settings.max_image_dimension {
+                return Err(ImageOpError::TooLarge {
+                    source,
+                    dimension: *dimension,
+                    value: *value,
+                    max: settings.max_image_dimension,
+                });
+            }
+        }
+        let mask = Mask::from_args(args.corner_radius, args.circle).map_err(InvalidArgument)?;
+        if mask != Mask::None && !matches!(format, Format::Png(_)) {
+            return Err(IncompatibleArguments(
+                "`corner_radius` and `circle` require format=\"png\"".to_string(),
+            ));
+        }
+        let focus = match args.focus {
+            Some(ref focus) if !matches!(op, ResizeOp::Fill(..)) => {
+                return Err(IncompatibleArguments(format!(
+                    "`focus` can only be used with op=\"fill\", got focus=\"{}\"",
+                    focus
+                )))
+            }
+            Some(ref focus) => Focus::from_args(focus).map_err(InvalidArgument)?,
+            None => Focus::Center,
+        };
+        let focus = match args.anchor {
+            Some(_) if args.focus.is_some() => {
+                return Err(IncompatibleArguments(
+                    "`anchor` and `focus` cannot be combined".to_string(),
+                ))
+            }
+            // Checked even when it's ignored so typos aren't silently accepted
+            Some(ref anchor) => {
+                let anchor = Focus::from_anchor(anchor).map_err(InvalidArgument)?;
+                if matches!(op, ResizeOp::Fill(..)) {
+                    anchor
+                } else {
+                    focus
+                }
+            }
+            None => focus,
+        };
+        Ok(Self::new(source, op, format)
+            .with_mask(mask)
+            .with_focus(focus)
+            .with_exif_transforms(exif_transforms)
+            .with_rounding(settings.resize_rounding)
+            .with_embedded_thumbnail(settings.use_embedded_thumbnails)
+            .with_strip_metadata(strip_metadata)
+            .with_color_profile(color_profile, icc_profile)
+            .with_percent(args.percent)
+            .with_output_dir(output_dir)
+            .with_animated(animated)
+            .with_narrow_color_type(args.format == "auto" && matches!(format, Format::Png(_))))
+    }
+
+    /// The EXIF thumbnail of the source, already transformed, along with the dimensions of the
+    /// transformed source, if this operation can be made out of it instead of the source.
+    /// It can if it has the aspect ratio of the source, is at least as big as the output and
+    /// looks good enough. Crops are always made out of the source since they don't resize it
+    fn embedded_thumbnail(&self, src_path: &Path) -> Result<Option<(DynamicImage, (u32, u32))>> {
+        if !self.embedded_thumbnail || matches!(self.op, ResizeOp::Crop(..)) {
+            return Ok(None);
+        }
+        let data = match exif::read_embedded_thumbnail(src_path)? {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+        if !matches!(meta::jpeg_quality(&data), Some(q) if q >= MIN_THUMBNAIL_QUALITY) {
+            return Ok(None);
+        }
+        let (source_w, source_h) = image_dimensions(src_path)?;
+        let transform = self.exif_transforms.read(src_path, (source_w, source_h))?;
+        // The EXIF crop is in pixels of the source
+        if transform.crop.is_some() {
+            return Ok(None);
+        }
+        let thumbnail = match image::load_from_memory_with_format(&data, image::ImageFormat::Jpeg) {
+            Ok(thumbnail) => thumbnail,
+            Err(_) => return Ok(None),
+        };
+        // Within a pixel of the thumbnail, as its dimensions are rounded
+        let (thumb_w, thumb_h) = thumbnail.dimensions();
+        let (thumb_cross, source_cross) =
+            (u64::from(thumb_w) * u64::from(source_h), u64::from(thumb_h) * u64::from(source_w));
+        let ratio_difference = thumb_cross.max(source_cross) - thumb_cross.min(source_cross);
+        if ratio_difference > u64::from(source_w.max(source_h)) {
+            return Ok(None);
+        }
+        let thumbnail = transform.apply(thumbnail);
+        let source_dimensions = transform.dimensions((source_w, source_h));
+        let (w, h) = self.output_dimensions(source_dimensions);
+        if thumbnail.width() < w || thumbnail.height() < h {
+            return Ok(None);
+        }
+        Ok(Some((thumbnail, source_dimensions)))
+    }
+
+    /// Adds the EXIF data of the source, if any, to the output. Its orientation is reset if it
+    /// was applied to the pixels so it isn't turned twice
+    fn copy_metadata(&self, src_path: &Path, target_path: &Path) -> Result<()> {
+        let mut exif_data = match exif::read_exif_data(src_path)? {
+            Some(exif_data) => exif_data,
+            None => return Ok(()),
+        };
+        if self.exif_transforms != ExifTransforms::None {
+            exif::reset_orientation(&mut exif_data);
+        }
+        if !exif::write_exif_data(target_path, &exif_data)? {
+            println!(
+                "Warning: the EXIF data of {} is too big to be kept in {}",
+                self.source,
+                target_path.display()
+            );
+        }
+        Ok(())
+    }
+
+    /// Whether the image at `target_path` is the one this operation makes of the source as it
+    /// is now, so it doesn't need to be processed again. `owner` is the hash of the operation
+    /// the last build recorded for that file, if any
+    fn is_up_to_date(&self, src_path: &Path, target_path: &Path, owner: Option<u64>) -> bool {
+        !ufs::file_stale(src_path, target_path)
+            && !self.target_mismatch(src_path, target_path, owner)
+    }
+
+    /// Whether the existing target was made by another operation with the same filename in a
+    /// previous build, eg of another image with the same stem or with another quality.
+    /// Files made before the owners were recorded are only compared by their dimensions
+    fn target_mismatch(&self, src_path: &Path, target_path: &Path, owner: Option<u64>) -> bool {
+        if let Some(owner) = owner {
+            return owner != self.hash;
+        }
+        match (
+            source_dimensions(src_path, self.exif_transforms),
+            image::image_dimensions(target_path),
+        ) {
+            (Ok(source), Ok(target)) => self.output_dimensions(source) != target,
+            _ => false,
+        }
+    }
+
+    /// Applies the resize operation and the mask to the decoded source.
+    /// The offset of the crop of `op="fill"` is computed on the first image and stored in
+    /// `fill_offset`, so all the frames of an animation are cropped the same way
+    fn transform(
+        &self,
+        mut img: DynamicImage,
+        source_dimensions: (u32, u32),
+        fill_offset: &mut Option<(u32, u32)>,
+    ) -> DynamicImage {
+        use ResizeOp::*;
+
+        let (img_w, img_h) = img.dimensions();
+
+        const RESIZE_FILTER: FilterType = FilterType::Lanczos3;
+        const RATIO_EPSILLION: f32 = 0.1;
+
+        let img = match self.op {
+            Scale(w, h) => img.resize_exact(w, h, RESIZE_FILTER),
+            FitWidth(_) | FitHeight(_) | Fit(_, _) => {
+                // Computed the same way as the dimensions returned by `resize_image`, from the
+                // source even when resizing its thumbnail
+                let (w, h) = self.output_dimensions(source_dimensions);
+                if (w, h) == (img_w, img_h) {
+                    img
+                } else {
+                    img.resize_exact(w, h, RESIZE_FILTER)
+                }
+            }
+            Fill(w, h) => {
+                let factor_w = img_w as f32 / w as f32;
+                let factor_h = img_h as f32 / h as f32;
+
+                if (factor_w - factor_h).abs() <= RATIO_EPSILLION {
+                    // If the horizontal and vertical factor is very similar,
+                    // that means the aspect is similar enough that there's not much point
+                    // in cropping, so just perform a simple scale in this case.
+                    img.resize_exact(w, h, RESIZE_FILTER)
+                } else {
+                    // We perform the fill such that a crop is performed first
+                    // and then resize_exact can be used, which should be cheaper than
+                    // resizing and then cropping (smaller number of pixels to resize).
+                    let (crop_w, crop_h) = if factor_w < factor_h {
+                        (img_w, (factor_w * h as f32).round() as u32)
+                    } else {
+                        ((factor_h * w as f32).round() as u32, img_h)
+                    };
+
+                    let (offset_w, offset_h) = *fill_offset
+                        .get_or_insert_with(|| self.focus.crop_offset(&img, (crop_w, crop_h)));
+
+                    img.crop(offset_w, offset_h, crop_w, crop_h).resize_exact(w, h, RESIZE_FILTER)
+                }
+            }
+            Crop(x, y, w, h) => img.crop(x, y, w, h),
+        };
+        self.mask.apply(img)
+    }
+
+    /// Resizes all the frames of an animated GIF into another one, keeping how long each frame
+    /// is displayed and how many times the animation loops
+    fn perform_animation(
+        &self,
+        src_path: &Path,
+        target_path: &Path,
+        start: Instant,
+    ) -> Result<ImageOpTimings> {
+        use image::codecs::gif::{GifDecoder, GifEncoder};
+        use image::{AnimationDecoder, Frame};
+
+        let source = fs::read(src_path)?;
+        // The frames are composited on the whole canvas of the GIF
+        let frames = GifDecoder::new(&source[..])?.into_frames().collect_frames()?;
+        let source_dimensions = image::image_dimensions(src_path)?;
+        let decoded = Instant::now();
+
+        let mut fill_offset = None;
+        let frames: Vec<_> = frames
+            .into_iter()
+            .map(|frame| {
+                let delay = frame.delay();
+                let img = DynamicImage::ImageRgba8(frame.into_buffer());
+                let img = self.transform(img, source_dimensions, &mut fill_offset);
+                Frame::from_parts(img.to_rgba8(), 0, 0, delay)
+            })
+            .collect();
+        let resized = Instant::now();
+
+        let mut gif = Vec::new();
+        GifEncoder::new(&mut gif).encode_frames(frames)?;
+        animation::copy_gif_repeat(&source, &mut gif);
+        // With `hardlink_identical` the target can be shared with other images
+        if target_path.exists() {
+            fs::remove_file(target_path)?;
+        }
+        fs::write(target_path, gif)?;
+
+        Ok(ImageOpTimings {
+            decode_ms: millis(decoded - start),
+            resize_ms: millis(resized - decoded),
+            encode_ms: millis(resized.elapsed()),
+        })
+    }
+
+    /// Returns how long each step took if the image had to be processed,
+    /// `None` if it was already up to date. With `force`, it is processed even if up to date.
+    /// `owner` is the hash of the operation that made the existing target, if known.
+    /// Sources of more than `max_pixels` pixels are refused before being decoded
+    fn perform(
+        &self,
+        content_path: &Path,
+        target_path: &Path,
+        force: bool,
+        owner: Option<u64>,
+        max_pixels: u64,
+    ) -> Result<Option<ImageOpTimings>> {
+        let src_path = content_path.join(&self.source);
+        if !force && self.is_up_to_date(&src_path, target_path, owner) {
+            return Ok(None);
+        }
+        // The source can have changed since the operation was inserted
+        check_pixel_limit(&src_path, max_pixels)?;
+
+        let start = Instant::now();
+        if self.animated {
+            return self.perform_animation(&src_path, target_path, start).map(Some);
+        }
+        let (img, source_dimensions) = match self.embedded_thumbnail(&src_path)? {
+            Some(thumbnail) => thumbnail,
+            None => {
+                let mut img = open_image(&src_path)?;
+                if self.exif_transforms != ExifTransforms::None {
+                    img = self.exif_transforms.read(&src_path, img.dimensions())?.apply(img);
+                }
+                let dimensions = img.dimensions();
+                (img, dimensions)
+            }
+        };
+        let decoded = Instant::now();
+        let mut img = self.transform(img, source_dimensions, &mut None);
+        // Converted once resized, as there are fewer pixels to convert
+        if self.color_profile == ColorProfile::Srgb {
+            if let Some(profile) =
+                self.icc_profile.as_deref().and_then(|p| icc::RgbProfile::parse(p))
+            {
+                img = profile.convert_to_srgb(img);
+            }
+        }
+        let preserved_profile = match self.icc_profile {
+            Some(ref profile) if self.color_profile == ColorProfile::Preserve => Some(profile),
+            _ => None,
+        };
+        let img = if self.narrow_color_type {
+            let rgb_profile =
+                matches!(preserved_profile.map(|p| icc::color_space(p)), Some(Some("RGB")));
+            Format::narrow_color_type(img, rgb_profile)
+        } else {
+            img
+        };
+
+        let resized = Instant::now();
+        // With `hardlink_identical` the target can be shared with other images, writing it in
+        // place would change them as well
+        if target_path.exists() {
+            fs::remove_file(target_path)?;
+        }
+        let mut f = File::create(target_path)?;
+
+        match self.format {
+            Format::Png(compression) => {
+                use image::codecs::png::{CompressionType, FilterType as PngFilter, PngEncoder};
+
+                let compression = match compression {
+                    PngCompression::Fast => CompressionType::Fast,
+                    PngCompression::Default => CompressionType::Default,
+                    PngCompression::Best => CompressionType::Best,
+                };
+                PngEncoder::new_with_quality(&mut f, compression, PngFilter::Sub).encode(
+                    img.as_bytes(),
+                    img.width(),
+                    img.height(),
+                    img.color(),
+                )?;
+            }
+            Format::Jpeg(q) => {
+                img.write_to(&mut f, ImageOutputFormat::Jpeg(q))?;
+            }
+            Format::Gif => {
+                img.write_to(&mut f, ImageOutputFormat::Gif)?;
+            }
+            #[cfg(feature = "avif")]
+            Format::Avif(q, Some(alpha_quality)) if img.color().has_alpha() => {
+                write_avif_with_alpha_quality(&mut f, &img.to_rgba8(), q, alpha_quality)?;
+            }
+            #[cfg(feature = "avif")]
+            Format::Avif(q, _) => {
+                use image::codecs::avif::AvifEncoder;
+
+                // The encoder only takes 8 bit RGB(A)
+                let encoder = AvifEncoder::new_with_speed_quality(&mut f, AVIF_SPEED, q);
+                if img.color().has_alpha() {
+                    let rgba = img.to_rgba8();
+                    encoder.write_image(&rgba, rgba.width(), rgba.height(), ColorType::Rgba8)?;
+                } else {
+                    let rgb = img.to_rgb8();
+                    encoder.write_image(&rgb, rgb.width(), rgb.height(), ColorType::Rgb8)?;
+                }
+            }
+        }
+        drop(f);
+        if !self.strip_metadata {
+            self.copy_metadata(&src_path, target_path)?;
+        }
+        if let Some(profile) = preserved_profile {
+            // The profile has to describe the channels of the output, eg a grayscale one
+            // only applies to a grayscale output
+            let output_space = if matches!(
+                img.color(),
+                ColorType::L8 | ColorType::La8 | ColorType::L16 | ColorType::La16
+            ) {
+                "GRAY"
+            } else {
+                "RGB"
+            };
+            if icc::color_space(profile) == Some(output_space) {
+                icc::write_icc_profile(target_path, profile)?;
+            } else {
+                println!(
+                    "Warning: the ICC profile of {} doesn't match the colors of {}, leaving it out",
+                    self.source,
+                    target_path.display()
+                );
+            }
+        }
+
+        Ok(Some(ImageOpTimings {
+            decode_ms: millis(decoded - start),
+            resize_ms: millis(resized - decoded),
+            encode_ms: millis(resized.elapsed()),
+        }))
+    }
+}
+
+/// Checks that the `output_dir` of `resize_image` stays in `static`, returning it with `/`
+/// slashes and without leading or trailing ones, a leading one being the root of `static`
+fn normalize_output_dir(dir: &str) -> Result<String> {
+    let normalized = dir.replace('\\', "/").trim_matches('/').to_string();
+    let stays_inside =
+        Path::new(&normalized).components().all(|c| matches!(c, std::path::Component::Normal(_)));
+    if normalized.is_empty() || !stays_inside {
+        return Err(format!(
+            "Invalid `output_dir`: {}, it needs to be a directory of `static`, eg `blog/my-post`",
+            dir
+        )
+        .into());
+    }
+    Ok(normalized)
+}
+
+/// Errors if the header of the image at that path declares more than `max_pixels` pixels, so
+/// decompression bombs are refused before their pixels are allocated. Images whose dimensions
+/// can't be read are left for the decoder to fail on
+pub fn check_pixel_limit<P: AsRef<Path>>(path: P, max_pixels: u64) -> Result<()> {
+    let path = path.as_ref();
+    if let Ok((width, height)) = image_dimensions(path) {
+        let pixels = u64::from(width) * u64::from(height);
+        if pixels > max_pixels {
+            return Err(format!(
+                "{}: image exceeds safe pixel limit, it is {}x{}, ie {} pixels, and `max_pixels` is {}",
+                path.display(),
+                width,
+                height,
+                pixels,
+                max_pixels
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Whether the image at that path has more than one frame, only looking at the formats that
+/// can be animated
+fn is_animated(path: &Path) -> Result<bool> {
+    let extension = path.extension().and_then(std::ffi::OsStr::to_str).map(str::to_lowercase);
+    if !matches!(extension.as_deref(), Some("gif") | Some("png") | Some("apng") | Some("webp")) {
+        return Ok(false);
+    }
+    Ok(read_animation(path)?.is_some())
+}
+
+/// The dimensions of the image at that path as stored, read with libheif for HEIC images
+pub fn image_dimensions<P: AsRef<Path>>(path: P) -> Result<(u32, u32)> {
+    let path = path.as_ref();
+    if heic::is_heic(path) {
+        return heic::heic_dimensions(path);
+    }
+    Ok(image::image_dimensions(path)?)
+}
+
+/// Decodes the image at that path, with libheif for HEIC images
+pub fn open_image<P: AsRef<Path>>(path: P) -> Result<DynamicImage> {
+    let path = path.as_ref();
+    if heic::is_heic(path) {
+        return heic::open_heic(path);
+    }
+    Ok(image::open(path)?)
+}
+
+/// The dimensions of the source image as it is processed, ie after the parts of the EXIF
+/// transform in `exif_transforms`
+pub fn source_dimensions<P: AsRef<Path>>(
+    path: P,
+    exif_transforms: ExifTransforms,
+) -> Result<(u32, u32)> {
+    let path = path.as_ref();
+    let dimensions = image_dimensions(path)?;
+    if exif_transforms == ExifTransforms::None {
+        return Ok(dimensions);
+    }
+    Ok(exif_transforms.read(path, dimensions)?.dimensions(dimensions))
+}
+
+/// What the last `do_process` did, summed up at the end of the build
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProcessingStats {
+    /// The images encoded by this build, including the ones processed right away while rendering
+    pub processed: usize,
+    /// The images already up to date from a previous build
+    pub cache_hits: usize,
+    /// The images that couldn't be processed and were replaced by a copy of their source with
+    /// `fallback_to_original`
+    pub skipped: usize,
+    /// The total size of the images encoded by this build
+    pub bytes_written: u64,
+    /// How long processing the images took, including the ones processed while rendering
+    pub duration: Duration,
+}
+
+impl ProcessingStats {
+    /// The percentage of the images that could be processed that were already up to date,
+    /// rounded down. The skipped ones are left out. 0 if there were no images
+    pub fn hit_percentage(&self) -> usize {
+        match self.cache_hits + self.processed {
+            0 => 0,
+            total => self.cache_hits * 100 / total,
+        }
+    }
+}
+
+/// An entry of the `manifest.json` written with `write_manifest`: a processed image and what
+/// it was made of
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ManifestEntry {
+    pub source: String,
+    pub op: &'static str,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub format: &'static str,
+    pub url: String,
+}
+
+/// An image a build would process, as planned by a dry run
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PlannedImage {
+    pub source: String,
+    /// The URL `resize_image` returned for it, the same as in a real build
+    pub url: String,
+    pub output_path: PathBuf,
+    pub op: &'static str,
+    pub format: &'static str,
+    pub width: u32,
+    pub height: u32,
+    /// The size of the source, which needs to be decoded unless the image is up to date
+    pub input_bytes: u64,
+    /// Whether the image was already processed by a previous build and wouldn't be encoded again
+    pub up_to_date: bool,
+}
+
+/// What `resize_image` returns about an image
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ResizeImageResponse {
+    /// The final URL of the image
+    pub url: String,
+    /// The path of the image, relative to the site root
+    pub static_path: String,
+    /// The dimensions of the image file
+    pub intrinsic_width: u32,
+    pub intrinsic_height: u32,
+    /// The dimensions to display the image at, ie the intrinsic ones divided by the `pixel_ratio`,
+    /// to use in the `width` and `height` attributes of `<img>`
+    pub display_width: u32,
+    pub display_height: u32,
+    /// The same as `display_width` and `display_height`, so templates can use `image.width`
+    /// and `image.height` like for `<img>`
+    pub width: u32,
+    pub height: u32,
+    /// The extension of the format the image is written in, eg the one `format="auto"` picked
+    pub format: String,
+    /// The size in bytes of the image file, only known for the images processed right away, or
+    /// found up to date, by `process_now`. `None` for the ones written at the end of the build
+    pub file_size: Option<u64>,
+}
+
+/// The difference between the images a build was expected to process and the ones it did
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProducedUrlsDiff {
+    /// The URLs that were expected but not processed, sorted
+    pub missing: Vec<String>,
+    /// The URLs that were processed but not expected, sorted
+    pub extra: Vec<String>,
+}
+
+/// How long each step of processing an image took, in milliseconds
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ImageOpTimings {
+    pub decode_ms: f64,
+    pub resize_ms: f64,
+    pub encode_ms: f64,
+}
+
+impl ImageOpTimings {
+    pub fn total_ms(&self) -> f64 {
+        self.decode_ms + self.resize_ms + self.encode_ms
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Encodes an image to AVIF with a quality of its own for the alpha channel, which the AVIF
+/// encoder of `image` doesn't allow, by using the encoder it wraps directly
+#[cfg(feature = "avif")]
+fn write_avif_with_alpha_quality(
+    f: &mut File,
+    img: &image::RgbaImage,
+    quality: u8,
+    alpha_quality: u8,
+) -> Result<()> {
+    use std::io::Write;
+
+    let pixels: Vec<_> = img.pixels().map(|p| ravif::RGBA8::new(p[0], p[1], p[2], p[3])).collect();
+    let config = ravif::Config {
+        quality: f32::from(quality),
+        alpha_quality: f32::from(alpha_quality),
+        speed: AVIF_SPEED,
+        premultiplied_alpha: false,
+        color_space: ravif::ColorSpace::YCbCr,
+        threads: 0,
+    };
+    let buffer = ravif::Img::new(&pixels[..], img.width() as usize, img.height() as usize);
+    let (data, _, _) = ravif::encode_rgba(buffer, &config)
+        .map_err(|e| Error::msg(format!("Failed to encode the AVIF image: {}", e)))?;
+    f.write_all(&data)?;
+    Ok(())
+}
+
+/// The owners of the filenames `Processor::record_filename_owners` wrote, none if it was
+/// never written or can't be read
+fn read_filename_owners(path: &Path) -> HashMap<String, (u64, String)> {
+    fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(3, '\t');
+            let hash = u64::from_str_radix(parts.next()?, 16).ok()?;
+            let path = parts.next()?.to_string();
+            Some((path, (hash, parts.next()?.to_string())))
+        })
+        .collect()
+}
+
+/// The size in bytes of that file, 0 if it doesn't exist
+fn file_size(path: &Path) -> u64 {
+    fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+/// Replaces the files that are byte for byte identical to one before them in `paths` by a
+/// hardlink to it. Files are left as they are if the file system doesn't support hardlinks.
+/// Returns how many links were made, those already there from previous builds aren't counted
+fn hardlink_identical(paths: &[&Path]) -> Result<usize> {
+    // Only files of the same size need to be read
+    let mut by_size: HashMap<u64, Vec<&Path>> = HashMap::new();
+    for path in paths {
+        by_size.entry(file_size(path)).or_default().push(*path);
+    }
+
+    let mut links = 0;
+    for group in by_size.values().filter(|g| g.len() > 1) {
+        let mut originals: Vec<(&Path, Vec<u8>)> = Vec::new();
+        for path in group {
+            let data = fs::read(path)?;
+            let original = match originals.iter().find(|(_, d)| *d == data) {
+                Some((original, _)) => *original,
+                None => {
+                    originals.push((*path, data));
+                    continue;
+                }
+            };
+            if is_same_file(original, path) {
+                continue;
+            }
+            // Linked next to it first so the copy is only replaced if the link can be made
+            let link = path.with_extension("hardlink");
+            if fs::hard_link(original, &link).is_err() {
+                continue;
+            }
+            fs::rename(&link, path)?;
+            links += 1;
+        }
+    }
+    Ok(links)
+}
+
+#[cfg(unix)]
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_same_file(_: &Path, _: &Path) -> bool {
+    false
+}
+
+/// An entry of the processing log, describing what happened to an ImageOp during a build
+#[derive(Debug, Serialize)]
+pub struct ImageOpLog {
+    pub source: String,
+    pub source_path: PathBuf,
+    pub op: &'static str,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub format: &'static str,
+    pub quality: Option<u8>,
+    pub output_path: PathBuf,
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    /// Whether the output was already up to date and didn't need to be processed again
+    pub cache_hit: bool,
+    /// Only collected when `report_timings` is enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timings: Option<ImageOpTimings>,
+}
+
+/// What `ProcessNow::perform` did
+#[derive(Debug)]
+struct ProcessedNow {
+    /// `None` if the output was already up to date
+    timings: Option<ImageOpTimings>,
+    duration: Duration,
+}
+
+/// An operation `Processor::prepare_now` inserted, to be processed right away without the
+/// processor locked meanwhile
+#[derive(Debug)]
+pub struct ProcessNow {
+    img_op: ImageOp,
+    collision_id: u32,
+    content_path: PathBuf,
+    target: PathBuf,
+    force: bool,
+    owner: Option<u64>,
+    max_pixels: u64,
+    /// Shared by the calls asking for the same operation, the first one processes it and the
+    /// others wait for it
+    done: Arc<Mutex<Option<ProcessedNow>>>,
+}
+
+impl ProcessNow {
+    pub fn perform(&self) -> Result<()> {
+        let mut done = self.done.lock().unwrap();
+        if done.is_some() {
+            return Ok(());
+        }
+        let started = Instant::now();
+        let timings = self
+            .img_op
+            .perform(&self.content_path, &self.target, self.force, self.owner, self.max_pixels)
+            .map_err(|e| {
+                Error::chain(format!("Failed to process image: {}", self.img_op.source), e)
+            })?;
+        *done = Some(ProcessedNow { timings, duration: started.elapsed() });
+        Ok(())
+    }
+}
+
+thread_local! {
+    /// The page or section rendered on this thread, the images processed meanwhile count for it
+    static CURRENT_PAGE: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// Runs `render`, counting the images processed on this thread meanwhile as variants of `page`,
+/// the relative path of a page or section. See `Processor::page_variants`
+pub fn render_for_page<T>(page: &str, render: impl FnOnce() -> T) -> T {
+    let previous = CURRENT_PAGE.with(|current| current.replace(Some(page.to_string())));
+    let res = render();
+    CURRENT_PAGE.with(|current| *current.borrow_mut() = previous);
+    res
+}
+
+/// The relative path of the page or section being rendered on this thread, if any
+pub fn current_page() -> Option<String> {
+    CURRENT_PAGE.with(|current| current.borrow().clone())
+}
+
+/// A strcture into which image operations can be enqueued and then performed.
+/// All output is written in a subdirectory in `static_path`,
+/// taking care of file stale status based on timestamps and possible hash collisions.
+#[derive(Debug)]
+pub struct Processor {
+    content_path: PathBuf,
+    /// The `static` directory, which the `output_dir` of operations are in
+    static_path: PathBuf,
+    resized_path: PathBuf,
+    /// The directory of `static` the images are written to, with `/` slashes
+    resized_dir: String,
+    /// The URL of the processed images, which can be different from `resized_dir`
+    resized_url: String,
+    /// The base URL of the site, which the URLs of the images with an `output_dir` start with
+    base_url: String,
+    /// The `[image_processing]` section of the config
+    settings: ImageProcessing,
+    /// How to slugify the stem of the source image when it is part of the filename
+    slugify: SlugifyStrategy,
+    /// Where to write the JSON processing log, if enabled
+    log_path: Option<PathBuf>,
+    /// Where to list the processed images with their cache headers, if enabled
+    cache_headers_path: Option<PathBuf>,
+    /// Where to write the manifest of the processed images, if enabled
+    manifest_path: Option<PathBuf>,
+    /// Other directories the processed images are copied to
+    extra_output_paths: Vec<PathBuf>,
+    /// Whether the existing processed images were made by a different `ENCODER_VERSION`
+    encoder_changed: bool,
+    /// A map of a ImageOps by their stored hash.
+    /// Note that this cannot be a HashSet, because hashset handles collisions and we don't want that,
+    /// we need to be aware of and handle collisions ourselves.
+    img_ops: HashMap<u64, ImageOp>,
+    /// Hash collisions go here:
+    img_ops_collisions: Vec<ImageOp>,
+    /// The sources copied as is with `fallback_to_original`
+    originals: HashSet<String>,
+    /// The URLs of the images the last `do_process` wrote or found up to date
+    written_urls: BTreeSet<String>,
+    /// The hash of the content of the sources with `dedup_identical_sources`, along with their
+    /// modification time when they were hashed
+    content_hashes: HashMap<String, (Option<SystemTime>, u64)>,
+    /// The first source used with each content hash, which is processed for all its copies
+    deduplicated_sources: HashMap<u64, String>,
+    /// Which operation, by hash and collision ID, and source each filename was given to,
+    /// keyed by the filename prefixed by its directory of `static`
+    filenames: HashMap<String, ((u64, u32), String)>,
+    /// How many characters of their hash are appended to the stem of the operations
+    /// whose filename was already given to another one
+    lengthened_stems: HashMap<(u64, u32), usize>,
+    /// The hash of the operation and the source each filename was given to in the last
+    /// build, keyed like `filenames`, read from `FILENAME_OWNERS_FILE`
+    previous_owners: HashMap<String, (u64, String)>,
+    /// The images left without alt text with `strict_alt`, along with where they were used
+    missing_alts: BTreeSet<(String, String)>,
+    /// The operations, by hash and collision ID, asked for while rendering each page or section
+    page_variants: HashMap<String, HashSet<(u64, u32)>>,
+    /// The operations, by hash and collision ID, `prepare_now` was asked for since the last
+    /// `do_process`, which skips the ones already processed
+    processed_now: HashMap<(u64, u32), Arc<Mutex<Option<ProcessedNow>>>>,
+    /// What the last `do_process` did
+    processing_stats: ProcessingStats,
+    /// Whether images are only planned, `do_process` and the pruning leaving the processed
+    /// images directory as is
+    dry_run: bool,
+}
+
+impl Processor {
+    pub fn new(base_path: &Path, config: &Config) -> Processor {
+        let resized_dir = config.image_processing.processed_dir.trim_matches('/').to_string();
+        let resized_path = base_path.join("static").join(&resized_dir);
+        // Images processed before the version was recorded are kept as is
+        let encoder_changed = fs::read_to_string(resized_path.join(ENCODER_VERSION_FILE))
+            .map(|v| v.trim() != ENCODER_VERSION)
+            .unwrap_or(false);
+        let previous_owners = read_filename_owners(&resized_path.join(FILENAME_OWNERS_FILE));
+        let manifest_path = if config.image_processing.write_manifest {
+            Some(resized_path.join(MANIFEST_FILE))
+        } else {
+            None
+        };
+
+        Processor {
+            content_path: base_path.join("content"),
+            resized_path,
+            resized_dir,
+            encoder_changed,
+            static_path: base_path.join("static"),
+            resized_url: Self::resized_url(&config.base_url, config.image_processing.url_prefix()),
+            base_url: config.base_url.clone(),
+            settings: config.image_processing.clone(),
+            log_path: config.image_processing.processing_log.as_ref().map(|p| base_path.join(p)),
+            cache_headers_path: config
+                .image_processing
+                .cache_headers_file
+                .as_ref()
+                .map(|p| base_path.join(p)),
+            manifest_path,
+            extra_output_paths: config
+                .image_processing
+                .extra_output_dirs
+                .iter()
+                .map(|d| base_path.join(d))
+                .collect(),
+            slugify: config.slugify.paths,
+            img_ops: HashMap::new(),
+            img_ops_collisions: Vec::new(),
+            originals: HashSet::new(),
+            written_urls: BTreeSet::new(),
+            content_hashes: HashMap::new(),
+            deduplicated_sources: HashMap::new(),
+            filenames: HashMap::new(),
+            lengthened_stems: HashMap::new(),
+            previous_owners,
+            missing_alts: BTreeSet::new(),
+            page_variants: HashMap::new(),
+            processed_now: HashMap::new(),
+            processing_stats: ProcessingStats::default(),
+            dry_run: false,
+        }
+    }
+
+    fn resized_url(base_url: &str, url_prefix: &str) -> String {
+        if base_url.ends_with('/') {
+            format!("{}{}", base_url, url_prefix)
+        } else {
+            format!("{}/{}", base_url, url_prefix)
+        }
+    }
+
+    pub fn set_base_url(&mut self, base_url: &str) {
+        self.resized_url = Self::resized_url(base_url, self.settings.url_prefix());
+        self.base_url = base_url.to_string();
+    }
+
+    pub fn enable_strict_alt(&mut self) {
+        self.settings.strict_alt = true;
+    }
+
+    /// Only plans the images: `insert` gives out the same URLs as usual but nothing is written
+    /// or removed by `do_process`, `prune` and `clean_processed_images`, see `planned_images`.
+    /// The images of `process_now` are still processed since their bytes are needed right away
+    pub fn enable_dry_run(&mut self) {
+        self.dry_run = true;
+    }
+
+    /// Records that the image `source` ended up without alt text in `location`, eg the page
+    /// it is in. It is only a warning unless `strict_alt` is enabled, in which case
+    /// `check_missing_alts` errors with all of them
+    pub fn report_missing_alt(&mut self, source: &str, location: &str) {
+        if self.settings.strict_alt {
+            self.missing_alts.insert((source.to_string(), location.to_string()));
+        } else {
+            println!("Warning: no alt text for `{}` in {}", source, location);
+        }
+    }
+
+    /// Errors with the images reported by `report_missing_alt` since the last call, if any
+    pub fn check_missing_alts(&mut self) -> Result<()> {
+        let missing_alts = std::mem::take(&mut self.missing_alts);
+        if missing_alts.is_empty() {
+            return Ok(());
+        }
+        let list: Vec<_> = missing_alts
+            .iter()
+            .map(|(source, location)| format!("`{}` in {}", source, location))
+            .collect();
+        Err(format!(
+            "{} image(s) have no alt text and `strict_alt` is enabled:\n{}",
+            list.len(),
+            list.join("\n")
+        )
+        .into())
+    }
+
+    /// Whether up to date images need to be processed again as they were made by other encoders
+    fn regenerate_all(&self) -> bool {
+        self.encoder_changed
+            && (self.settings.reproducible
+                || self.settings.on_encoder_change == EncoderChangePolicy::Regenerate)
+    }
+
+    pub fn settings(&self) -> &ImageProcessing {
+        &self.settings
+    }
+
+    /// The directory the sources are relative to
+    pub fn content_path(&self) -> &Path {
+        &self.content_path
+    }
+
+    /// The quality to use when none is given explicitly.
+    /// With `clamp_quality_to_source`, it is capped near the quality the source JPEG
+    /// was encoded with as re-encoding it at a higher quality only makes it bigger.
+    pub fn default_quality(&self, source: &str) -> u8 {
+        if !self.settings.clamp_quality_to_source || Format::is_lossy(source) != Some(true) {
+            return DEFAULT_QUALITY;
+        }
+        match estimate_jpeg_quality(self.content_path.join(source)) {
+            Ok(Some(q)) => DEFAULT_QUALITY.min(q.saturating_add(SOURCE_QUALITY_MARGIN)),
+            _ => DEFAULT_QUALITY,
+        }
+    }
+
+    pub fn source_exists(&self, source: &str) -> bool {
+        self.content_path.join(source).exists()
+    }
+
+    /// The source to use for that `path` argument: itself if it's in `content`, otherwise the
+    /// first of the `asset_dirs` containing it, relative to `content`. The paths going up with
+    /// `..` and absolute ones are only looked up in `content`
+    pub fn find_source(&self, path: &str) -> Option<String> {
+        if self.source_exists(path) {
+            return Some(path.to_string());
+        }
+        if Path::new(path).is_absolute()
+            || Path::new(path).components().any(|c| c == Component::ParentDir)
+        {
+            return None;
+        }
+        let root = self.content_path.parent().unwrap_or(&self.content_path);
+        self.settings
+            .asset_dirs
+            .iter()
+            .map(|dir| dir.trim_matches('/'))
+            .find(|dir| root.join(dir).join(path).exists())
+            .map(|dir| format!("../{}/{}", dir, path))
+    }
+
+    pub fn source_path(&self, source: &str) -> PathBuf {
+        self.content_path.join(source)
+    }
+
+    pub fn processing_stats(&self) -> ProcessingStats {
+        self.processing_stats
+    }
+
+    pub fn num_img_ops(&self) -> usize {
+        self.img_ops.len() + self.img_ops_collisions.len()
+    }
+
+    /// The URLs of all the images this build wrote, including the originals copied with
+    /// `fallback_to_original`. Only known once `do_process` ran, an image that failed to be
+    /// processed isn't in it even though its URL was given out
+    pub fn produced_urls(&self) -> BTreeSet<String> {
+        let originals = self
+            .originals
+            .iter()
+            .map(|source| format!("{}/{}/{}", self.resized_url, ORIGINALS_SUBDIR, source));
+        self.written_urls.iter().cloned().chain(originals).collect()
+    }
+
+    /// Compares the URLs of the images written by this build to the ones expected
+    pub fn diff_produced_urls(&self, expected: &[String]) -> ProducedUrlsDiff {
+        let produced = self.produced_urls();
+        let expected: BTreeSet<_> = expected.iter().cloned().collect();
+        ProducedUrlsDiff {
+            missing: expected.difference(&produced).cloned().collect(),
+            extra: produced.difference(&expected).cloned().collect(),
+        }
+    }
+
+    fn insert_with_collisions(&mut self, mut img_op: ImageOp) -> u32 {
+        match self.img_ops.entry(img_op.hash) {
+            HEntry::Occupied(entry) => {
+                if *entry.get() == img_op {
+                    return 0;
+                }
+            }
+            HEntry::Vacant(entry) => {
+                entry.insert(img_op);
+                return 0;
+            }
+        }
+
+        // If we get here, that means a hash collision.
+        // This is detected when there is an ImageOp with the same hash in the `img_ops`
+        // map but which is not equal to this one.
+        // To deal with this, all collisions get a (random) sequential ID number.
 
-        // If we get here, that means a hash collision.
-        // This is detected when there is an ImageOp with the same hash in the `img_ops`
-        // map but which is not equal to this one.
-        // To deal with this, all collisions get a (random) sequential ID number.
-
         // First try to look up this ImageOp in `img_ops_collisions`, maybe we've
         // already seen the same ImageOp.
         // At the same time, count IDs to figure out the next free one.
@@ -402,62 +2282,858 @@ impl Processor {
         collision_id
     }
 
-    fn op_filename(hash: u64, collision_id: u32, format: Format) -> String {
-        // Please keep this in sync with RESIZED_FILENAME
+    fn op_filename(&self, img_op: &ImageOp, collision_id: u32) -> String {
+        // Please keep this in sync with `filename_regex`
         assert!(collision_id < 256, "Unexpectedly large number of collisions: {}", collision_id);
-        format!("{:016x}{:02x}.{}", hash, collision_id, format.extension())
+        let source = Path::new(&img_op.source);
+        let stem = source
+            .file_stem()
+            .map(|s| slugify_paths(&s.to_string_lossy(), self.slugify))
+            .unwrap_or_default();
+        // Without `{hash}`, the sources with the same stem in different directories, eg the
+        // `cover.jpg` of every page, would get the same filename. Their stem is followed by a
+        // hash of their directory instead, which is the same on every build
+        let stem = match source.parent() {
+            Some(dir)
+                if dir != Path::new("") && !self.settings.filename_template.contains("{hash}") =>
+            {
+                let mut hasher = DefaultHasher::new();
+                hasher.write(dir.to_string_lossy().replace('\\', "/").as_bytes());
+                format!("{}-{:08x}", stem, hasher.finish() as u32)
+            }
+            _ => stem,
+        };
+        let dimension = |d: Option<u32>| d.map_or_else(|| "auto".to_string(), |d| d.to_string());
+        let hash = format!("{:016x}{:02x}", img_op.hash, collision_id);
+        let stem = match self.lengthened_stems.get(&(img_op.hash, collision_id)) {
+            Some(len) => format!("{}-{}", stem, &hash[..*len]),
+            None => stem,
+        };
+
+        // The stem is replaced last as it's the only user-controlled value
+        self.settings
+            .filename_template
+            .replace("{hash}", &hash)
+            .replace("{width}", &dimension(img_op.op.width()))
+            .replace("{height}", &dimension(img_op.op.height()))
+            .replace("{ext}", img_op.format.extension())
+            .replace("{stem}", &stem)
+    }
+
+    /// The directory of `static` the output of that operation goes in, with `/` slashes
+    fn op_dir(&self, img_op: &ImageOp) -> String {
+        match img_op.output_dir {
+            Some(ref output_dir) => format!("{}/{}", output_dir, OUTPUT_DIR_SUBDIR),
+            None => self.resized_dir.clone(),
+        }
+    }
+
+    /// Where the output of that operation is written
+    fn op_path(&self, img_op: &ImageOp, collision_id: u32) -> PathBuf {
+        self.static_path.join(self.op_dir(img_op)).join(self.op_filename(img_op, collision_id))
     }
 
-    fn op_url(&self, hash: u64, collision_id: u32, format: Format) -> String {
-        format!("{}/{}", &self.resized_url, Self::op_filename(hash, collision_id, format))
+    fn op_url(&self, img_op: &ImageOp, collision_id: u32) -> String {
+        let dir_url = match img_op.output_dir {
+            Some(_) => Self::resized_url(&self.base_url, &self.op_dir(img_op)),
+            None => self.resized_url.clone(),
+        };
+        format!("{}/{}", dir_url, self.op_filename(img_op, collision_id))
+    }
+
+    /// The URL of an image written at `output_path`, in `resized_path` or in an `output_dir`
+    fn output_url(&self, output_path: &Path) -> String {
+        match output_path.parent() {
+            Some(dir) if dir != self.resized_path => {
+                let relative = output_path.strip_prefix(&self.static_path).unwrap();
+                let relative = relative.to_string_lossy().replace('\\', "/");
+                Self::resized_url(&self.base_url, &relative)
+            }
+            _ => {
+                let filename = output_path.file_name().unwrap().to_string_lossy();
+                format!("{}/{}", self.resized_url, filename)
+            }
+        }
+    }
+
+    /// A regex matching every filename `op_filename` can generate with the current template,
+    /// so we don't touch files that we didn't create when pruning
+    fn filename_regex(&self) -> Regex {
+        let pattern = regex::escape(&self.settings.filename_template)
+            .replace(r"\{hash\}", "[0-9a-f]{18}")
+            .replace(r"\{width\}", "(?:[0-9]+|auto)")
+            .replace(r"\{height\}", "(?:[0-9]+|auto)")
+            .replace(r"\{ext\}", "(?:jpg|png|avif|gif)")
+            .replace(r"\{stem\}", ".*");
+        Regex::new(&format!("^{}$", pattern)).unwrap()
+    }
+
+    fn op_response(&self, img_op: &ImageOp, collision_id: u32) -> Result<ResizeImageResponse> {
+        let filename = self.op_filename(img_op, collision_id);
+        let source_dimensions =
+            source_dimensions(self.content_path.join(&img_op.source), img_op.exif_transforms)?;
+        let (intrinsic_width, intrinsic_height) = img_op.output_dimensions(source_dimensions);
+        let display =
+            |d: u32| ((f64::from(d) / f64::from(self.settings.pixel_ratio)).round() as u32).max(1);
+        // Not looked up for the others, checking that an existing file is up to date for every
+        // image asked for would slow down the rendering
+        let file_size = match self.was_processed_now(img_op.hash, collision_id) {
+            Some(_) => fs::metadata(self.op_path(img_op, collision_id)).ok().map(|m| m.len()),
+            None => None,
+        };
+
+        Ok(ResizeImageResponse {
+            url: self.op_url(img_op, collision_id),
+            static_path: format!("static/{}/{}", self.op_dir(img_op), filename),
+            intrinsic_width,
+            intrinsic_height,
+            display_width: display(intrinsic_width),
+            display_height: display(intrinsic_height),
+            width: display(intrinsic_width),
+            height: display(intrinsic_height),
+            format: img_op.format.extension().to_string(),
+            file_size,
+        })
+    }
+
+    pub fn insert(&mut self, img_op: ImageOp) -> Result<ResizeImageResponse> {
+        // Checked before adding the operation so an unreadable source isn't processed later
+        image_dimensions(self.source_path(&img_op.source))?;
+        check_pixel_limit(self.source_path(&img_op.source), self.settings.max_pixels)?;
+        let img_op = self.deduplicate(img_op)?;
+        let collision_id = self.insert_with_collisions(img_op.clone());
+        self.claim_filename(&img_op, collision_id)?;
+        self.record_page_variant(&img_op, collision_id);
+        self.op_response(&img_op, collision_id)
+    }
+
+    /// Counts that operation for the page being rendered on this thread, if any
+    fn record_page_variant(&mut self, img_op: &ImageOp, collision_id: u32) {
+        if let Some(page) = current_page() {
+            self.page_variants.entry(page).or_default().insert((img_op.hash, collision_id));
+        }
+    }
+
+    /// How many distinct processed images were asked for while rendering that page or
+    /// section so far, by relative path
+    pub fn page_variants(&self, page: &str) -> usize {
+        self.page_variants.get(page).map_or(0, HashSet::len)
+    }
+
+    /// With `dedup_identical_sources`, the same operation on the first source used that has
+    /// the same content, so all the copies of an image share its processed files
+    fn deduplicate(&mut self, img_op: ImageOp) -> Result<ImageOp> {
+        if !self.settings.dedup_identical_sources {
+            return Ok(img_op);
+        }
+        let path = self.source_path(&img_op.source);
+        let mtime = ufs::get_file_time(&path);
+        let content_hash = match self.content_hashes.get(&img_op.source) {
+            Some((hashed_mtime, hash)) if mtime.is_some() && *hashed_mtime == mtime => *hash,
+            _ => {
+                let mut hasher = DefaultHasher::new();
+                hasher.write(&fs::read(&path)?);
+                let hash = hasher.finish();
+                self.content_hashes.insert(img_op.source.clone(), (mtime, hash));
+                hash
+            }
+        };
+        let source = self
+            .deduplicated_sources
+            .entry(content_hash)
+            .or_insert_with(|| img_op.source.clone())
+            .clone();
+        Ok(img_op.with_content_hash(source, content_hash))
     }
 
-    pub fn insert(&mut self, img_op: ImageOp) -> String {
-        let hash = img_op.hash;
-        let format = img_op.format;
-        let collision_id = self.insert_with_collisions(img_op);
-        self.op_url(hash, collision_id, format)
+    /// Makes sure no two operations write to the same file, which happens when the
+    /// `filename_template` doesn't contain `{hash}`, eg for sources with the same stem
+    /// in different directories. The filename stays with the operation on the source that had
+    /// it in the last build, otherwise with the first one asking for it. The stem of the other
+    /// one is lengthened with its hash until its filename is free
+    fn claim_filename(&mut self, img_op: &ImageOp, collision_id: u32) -> Result<()> {
+        let key = (img_op.hash, collision_id);
+        // Operations with an `output_dir` can use the same filename in another directory
+        let dir = self.op_dir(img_op);
+        let filename = self.op_filename(img_op, collision_id);
+        let path = format!("{}/{}", dir, filename);
+        if let Some((claimed_by, _)) = self.filenames.get(&path) {
+            if *claimed_by == key {
+                return Ok(());
+            }
+        }
+        let other_source = match self.filename_taken_by(&path, img_op) {
+            None => {
+                self.filenames.insert(path, (key, img_op.source.clone()));
+                return Ok(());
+            }
+            Some(source) => source,
+        };
+
+        for len in STEM_HASH_LENGTHS {
+            self.lengthened_stems.insert(key, *len);
+            let lengthened = self.op_filename(img_op, collision_id);
+            let lengthened_path = format!("{}/{}", dir, lengthened);
+            if self.filename_taken_by(&lengthened_path, img_op).is_none() {
+                println!(
+                    "Warning: {} and {} would both be processed to {}, using {} for {}",
+                    other_source, img_op.source, filename, lengthened, img_op.source
+                );
+                self.filenames.insert(lengthened_path, (key, img_op.source.clone()));
+                return Ok(());
+            }
+        }
+        self.lengthened_stems.remove(&key);
+        Err(format!(
+            "{} and {} would both be processed to {}, add `{{stem}}` or `{{hash}}` to `filename_template`",
+            other_source, img_op.source, filename
+        )
+        .into())
+    }
+
+    /// The source of another operation the filename, prefixed by its directory of `static`, was
+    /// given to, in this build or in the last one. A filename the last build gave to another
+    /// operation on the same source is free, eg when its quality changed
+    fn filename_taken_by(&self, path: &str, img_op: &ImageOp) -> Option<String> {
+        if let Some((_, source)) = self.filenames.get(path) {
+            return Some(source.clone());
+        }
+        match self.previous_owners.get(path) {
+            Some((hash, source)) if *hash != img_op.hash && *source != img_op.source => {
+                Some(source.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// The hash of the operation the last build made the file of that operation with, if any
+    fn previous_owner(&self, img_op: &ImageOp, collision_id: u32) -> Option<u64> {
+        let path = format!("{}/{}", self.op_dir(img_op), self.op_filename(img_op, collision_id));
+        self.previous_owners.get(&path).map(|(hash, _)| *hash)
+    }
+
+    /// Used with `fallback_to_original` when the image couldn't be processed: prints a warning
+    /// and copies the source as is next to the processed images.
+    /// Its dimensions are 0 if they can't be read.
+    pub fn fallback_to_original(
+        &mut self,
+        source: &str,
+        reason: &str,
+    ) -> Result<ResizeImageResponse> {
+        println!("Warning: Failed to process image {}, using the original: {}", source, reason);
+        let source_path = self.source_path(source);
+        let source = original_path(&source.replace('\\', "/"));
+        let target = self.resized_path.join(ORIGINALS_SUBDIR).join(&source);
+        ufs::copy_file_if_needed(&source_path, &target, false)?;
+        let (width, height) = image_dimensions(&source_path).unwrap_or((0, 0));
+        let display = |d: u32| (f64::from(d) / f64::from(self.settings.pixel_ratio)).round() as u32;
+
+        let response = ResizeImageResponse {
+            url: format!("{}/{}/{}", &self.resized_url, ORIGINALS_SUBDIR, source),
+            static_path: format!("static/{}/{}/{}", self.resized_dir, ORIGINALS_SUBDIR, source),
+            intrinsic_width: width,
+            intrinsic_height: height,
+            display_width: display(width),
+            display_height: display(height),
+            width: display(width),
+            height: display(height),
+            format: Path::new(&source)
+                .extension()
+                .map(|e| e.to_string_lossy().to_lowercase())
+                .unwrap_or_default(),
+            file_size: fs::metadata(&target).ok().map(|m| m.len()),
+        };
+        self.originals.insert(source);
+        Ok(response)
+    }
+
+    /// Processes that operation right away instead of waiting for `do_process`, for when the
+    /// result is needed while rendering. Also returns the path the output was written to.
+    /// See `prepare_now` to encode it without keeping the processor locked
+    pub fn process_now(&mut self, img_op: ImageOp) -> Result<(ResizeImageResponse, PathBuf)> {
+        let now = self.prepare_now(img_op)?;
+        now.perform()?;
+        self.finish_now(&now)
+    }
+
+    /// Inserts that operation to process it right away with `ProcessNow::perform`, which
+    /// doesn't need the processor, and then `finish_now`. `do_process` doesn't process it again
+    pub fn prepare_now(&mut self, img_op: ImageOp) -> Result<ProcessNow> {
+        check_pixel_limit(self.source_path(&img_op.source), self.settings.max_pixels)?;
+        let img_op = self.deduplicate(img_op)?;
+        let collision_id = self.insert_with_collisions(img_op.clone());
+        self.claim_filename(&img_op, collision_id)?;
+        self.record_page_variant(&img_op, collision_id);
+        let target = self.op_path(&img_op, collision_id);
+        ufs::ensure_directory_exists(target.parent().unwrap())?;
+        let done = self.processed_now.entry((img_op.hash, collision_id)).or_default().clone();
+        Ok(ProcessNow {
+            content_path: self.content_path.clone(),
+            target,
+            force: self.regenerate_all(),
+            owner: self.previous_owner(&img_op, collision_id),
+            max_pixels: self.settings.max_pixels,
+            img_op,
+            collision_id,
+            done,
+        })
+    }
+
+    /// The response of an operation `ProcessNow::perform` processed, and the path the output
+    /// was written to
+    pub fn finish_now(&self, now: &ProcessNow) -> Result<(ResizeImageResponse, PathBuf)> {
+        Ok((self.op_response(&now.img_op, now.collision_id)?, now.target.clone()))
+    }
+
+    /// The timings of that operation if `ProcessNow::perform` processed it since the last
+    /// `do_process`, `None` inside if it was already up to date
+    fn was_processed_now(&self, hash: u64, collision_id: u32) -> Option<Option<ImageOpTimings>> {
+        let now = self.processed_now.get(&(hash, collision_id))?;
+        let done = now.lock().unwrap();
+        done.as_ref().map(|done| done.timings)
     }
 
     pub fn prune(&self) -> Result<()> {
-        // Do not create folders if they don't exist
-        if !self.resized_path.exists() {
+        if self.dry_run {
             return Ok(());
         }
 
-        ufs::ensure_directory_exists(&self.resized_path)?;
-        let entries = fs::read_dir(&self.resized_path)?;
-        for entry in entries {
-            let entry_path = entry?.path();
-            if entry_path.is_file() {
-                let filename = entry_path.file_name().unwrap().to_string_lossy();
-                if let Some(capts) = RESIZED_FILENAME.captures(filename.as_ref()) {
-                    let hash = u64::from_str_radix(capts.get(1).unwrap().as_str(), 16).unwrap();
-                    let collision_id =
-                        u32::from_str_radix(capts.get(2).unwrap().as_str(), 16).unwrap();
-
-                    if collision_id > 0 || !self.img_ops.contains_key(&hash) {
+        let filename_regex = self.filename_regex();
+        // Do not create folders if they don't exist
+        if self.resized_path.exists() {
+            // ImageOps with collisions are always considered out of date
+            let expected: HashSet<String> = self
+                .img_ops
+                .values()
+                .filter(|op| op.collision_id == 0)
+                .map(|op| self.op_filename(op, 0))
+                .collect();
+
+            let entries = fs::read_dir(&self.resized_path)?;
+            for entry in entries {
+                let entry_path = entry?.path();
+                if entry_path.is_file() {
+                    let filename = entry_path.file_name().unwrap().to_string_lossy();
+                    if filename_regex.is_match(&filename) && !expected.contains(filename.as_ref()) {
                         fs::remove_file(&entry_path)?;
                     }
                 }
             }
+
+            let originals_path = self.resized_path.join(ORIGINALS_SUBDIR);
+            if originals_path.exists() {
+                self.prune_originals(&originals_path)?;
+            }
+        }
+        self.prune_output_dirs(&filename_regex)
+    }
+
+    /// Removes the images of the `output_dir` of the last build and of this one that aren't
+    /// used anymore, along with the subdirectories left empty, and records the ones of this build
+    fn prune_output_dirs(&self, filename_regex: &Regex) -> Result<()> {
+        let record = self.resized_path.join(OUTPUT_DIRS_FILE);
+        let mut expected: HashMap<String, HashSet<String>> = HashMap::new();
+        for op in self.img_ops.values().filter(|op| op.collision_id == 0) {
+            if let Some(ref output_dir) = op.output_dir {
+                expected.entry(output_dir.clone()).or_default().insert(self.op_filename(op, 0));
+            }
+        }
+        let previous = fs::read_to_string(&record).unwrap_or_default();
+        // Read back from a file of the site, so checked again to stay in `static`
+        let previous = previous.lines().filter_map(|dir| normalize_output_dir(dir).ok());
+        let dirs: BTreeSet<String> = previous.chain(expected.keys().cloned()).collect();
+        for dir in &dirs {
+            let path = self.static_path.join(dir).join(OUTPUT_DIR_SUBDIR);
+            if !path.is_dir() {
+                continue;
+            }
+            let filenames = expected.get(dir);
+            for entry in fs::read_dir(&path)? {
+                let entry_path = entry?.path();
+                let filename = entry_path.file_name().unwrap().to_string_lossy().to_string();
+                let used = filenames.map(|f| f.contains(&filename)).unwrap_or(false);
+                if entry_path.is_file() && filename_regex.is_match(&filename) && !used {
+                    fs::remove_file(&entry_path)?;
+                }
+            }
+            if fs::read_dir(&path)?.next().is_none() {
+                fs::remove_dir(&path)?;
+            }
+        }
+
+        let current: BTreeSet<&str> = expected.keys().map(String::as_str).collect();
+        if current.is_empty() {
+            if record.exists() {
+                fs::remove_file(&record)?;
+            }
+        } else {
+            ufs::ensure_directory_exists(&self.resized_path)?;
+            ufs::create_file(&record, &current.into_iter().collect::<Vec<_>>().join("\n"))?;
+        }
+        Ok(())
+    }
+
+    /// Removes the images Zola wrote to the processed images directory that aren't the output of
+    /// one of the operations of this build, including the ones `prune` leaves alone because they
+    /// were made with another `filename_template`. The files put there by hand, the
+    /// subdirectories, the images written to an `output_dir` and the rest of `static` are never
+    /// touched.
+    /// Needs to be called after `do_process`, returns how many files were removed
+    pub fn clean_processed_images(&self) -> Result<usize> {
+        if self.dry_run || !self.resized_path.exists() {
+            return Ok(0);
+        }
+        if self.resized_dir.is_empty() {
+            bail!("Refusing to clean the processed images as `processed_dir` is the whole `static` directory");
+        }
+        // Written by `do_process`, so its absence means Zola never processed images there
+        if !self.resized_path.join(ENCODER_VERSION_FILE).exists() {
+            bail!(
+                "Refusing to clean the processed images as `static/{}` has no `{}`, it may not be a directory of processed images",
+                self.resized_dir,
+                ENCODER_VERSION_FILE
+            );
+        }
+
+        let produced: HashSet<PathBuf> = self
+            .img_ops
+            .values()
+            .chain(self.img_ops_collisions.iter())
+            .filter(|op| op.output_dir.is_none())
+            .map(|op| self.op_path(op, op.collision_id))
+            .chain(self.log_path.iter().cloned())
+            .chain(self.cache_headers_path.iter().cloned())
+            .chain(self.manifest_path.iter().cloned())
+            .chain(std::iter::once(self.resized_path.join(ENCODER_VERSION_FILE)))
+            .chain(std::iter::once(self.resized_path.join(OUTPUT_DIRS_FILE)))
+            .chain(std::iter::once(self.resized_path.join(PROCESSED_FILES_FILE)))
+            .chain(std::iter::once(self.resized_path.join(FILENAME_OWNERS_FILE)))
+            .collect();
+        // Only the files Zola wrote, never the ones put there by hand
+        let recorded = self.recorded_processed_files();
+        let filename_regex = self.filename_regex();
+
+        let mut removed = 0;
+        for entry in fs::read_dir(&self.resized_path)? {
+            let entry_path = entry?.path();
+            let filename = entry_path.file_name().unwrap().to_string_lossy().to_string();
+            let processed = recorded.contains(&filename) || filename_regex.is_match(&filename);
+            if entry_path.is_file() && processed && !produced.contains(&entry_path) {
+                fs::remove_file(&entry_path)?;
+                removed += 1;
+            }
+        }
+        self.record_processed_files(&self.produced_filenames())?;
+        Ok(removed)
+    }
+
+    /// Removes the originals copied in previous builds that are not used anymore
+    fn prune_originals(&self, dir: &Path) -> Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry_path = entry?.path();
+            if entry_path.is_dir() {
+                self.prune_originals(&entry_path)?;
+                continue;
+            }
+            let source = entry_path
+                .strip_prefix(self.resized_path.join(ORIGINALS_SUBDIR))
+                .unwrap()
+                .to_string_lossy()
+                .replace('\\', "/");
+            if !self.originals.contains(&source) {
+                fs::remove_file(&entry_path)?;
+            }
         }
         Ok(())
     }
 
     pub fn do_process(&mut self) -> Result<()> {
+        if self.dry_run {
+            return Ok(());
+        }
         if !self.img_ops.is_empty() {
             ufs::ensure_directory_exists(&self.resized_path)?;
         }
+        let started = Instant::now();
+        // The originals copied for the operations that failed, counted apart since they are
+        // found up to date once the original is copied
+        let fallen_back = Mutex::new(Vec::new());
+
+        let regenerate_all = self.regenerate_all();
+        if self.encoder_changed && !regenerate_all {
+            println!(
+                "Warning: The processed images were made by a different version of the image encoders, they will only be regenerated when their source changes"
+            );
+        }
 
+        let process = |op: &ImageOp| -> Result<ImageOpLog> {
+            let filename = self.op_filename(op, op.collision_id);
+            let target = self.op_path(op, op.collision_id);
+            if op.output_dir.is_some() {
+                ufs::ensure_directory_exists(target.parent().unwrap())?;
+            }
+            let performed = match self.was_processed_now(op.hash, op.collision_id) {
+                Some(timings) => Ok(timings),
+                None => op.perform(
+                    &self.content_path,
+                    &target,
+                    regenerate_all,
+                    self.previous_owner(op, op.collision_id),
+                    self.settings.max_pixels,
+                ),
+            };
+            let timings = match performed {
+                Ok(timings) => timings,
+                // Copied in `originals` as when it fails before, but the URL of the processed
+                // image was already given out so it's also linked there
+                Err(e) if self.settings.fallback_to_original => {
+                    let original = original_path(&op.source.replace('\\', "/"));
+                    println!(
+                        "Warning: Failed to process image {}, using the original {}/{}/{}: {}",
+                        op.source, self.resized_url, ORIGINALS_SUBDIR, original, e
+                    );
+                    let original_target = self.resized_path.join(ORIGINALS_SUBDIR).join(&original);
+                    ufs::copy_file_if_needed(
+                        &self.source_path(&op.source),
+                        &original_target,
+                        false,
+                    )?;
+                    if target.exists() {
+                        fs::remove_file(&target)?;
+                    }
+                    if fs::hard_link(&original_target, &target).is_err() {
+                        fs::copy(&original_target, &target)?;
+                    }
+                    fallen_back.lock().unwrap().push(original);
+                    None
+                }
+                Err(e) => {
+                    return Err(Error::chain(format!("Failed to process image: {}", op.source), e))
+                }
+            };
+            for dir in &self.extra_output_paths {
+                // Only a copy, the build can go on without it
+                if let Err(e) = ufs::copy_file_if_needed(&target, &dir.join(&filename), false) {
+                    println!("Warning: Failed to copy {} to {}: {}", filename, dir.display(), e);
+                }
+            }
+            Ok(self.op_log(op, target, timings))
+        };
+
+        let mut ops: Vec<_> = self.img_ops.values().chain(self.img_ops_collisions.iter()).collect();
+        // The same order on every build, whatever the order the templates were rendered in
+        ops.sort_by_key(|op| (op.hash, op.collision_id));
+        let mut logs = if self.settings.reproducible {
+            ops.iter().map(|op| process(op)).collect::<Result<Vec<_>>>()?
+        } else if let Some(threads) = self.settings.threads {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .map_err(|e| Error::chain("Failed to start the image processing threads", e))?;
+            pool.install(|| ops.par_iter().map(|op| process(op)).collect::<Result<Vec<_>>>())?
+        } else {
+            ops.par_iter().map(|op| process(op)).collect::<Result<Vec<_>>>()?
+        };
+        let encoded: Vec<_> = logs.iter().map(|log| !log.cache_hit).collect();
+        let processing_now_time: Duration = self
+            .processed_now
+            .values()
+            .filter_map(|now| now.lock().unwrap().as_ref().map(|done| done.duration))
+            .sum();
+        let misses = encoded.iter().filter(|encoded| **encoded).count();
+        let fallen_back = fallen_back.into_inner().unwrap();
+        let fallbacks = fallen_back.len();
+        self.processing_stats = ProcessingStats {
+            processed: misses,
+            cache_hits: logs.len() - misses - fallbacks,
+            skipped: fallbacks + self.originals.len(),
+            bytes_written: logs
+                .iter()
+                .zip(&encoded)
+                .filter(|(_, encoded)| **encoded)
+                .map(|(log, _)| log.output_bytes)
+                .sum(),
+            duration: processing_now_time + started.elapsed(),
+        };
+        // Kept by `clean_processed_images`
+        self.originals.extend(fallen_back);
+        self.processed_now.clear();
+        // Whichever thread finished first, so the outputs are listed in the same order every time
+        logs.sort_by(|a, b| a.output_path.cmp(&b.output_path));
+        self.written_urls = logs
+            .iter()
+            .filter(|log| log.output_path.exists())
+            .map(|log| self.output_url(&log.output_path))
+            .collect();
+
+        if self.settings.hardlink_identical {
+            let mut outputs: Vec<_> = logs.iter().map(|log| log.output_path.as_path()).collect();
+            // The first of identical images is the one the others link to
+            outputs.sort_unstable();
+            let links = hardlink_identical(&outputs)?;
+            if links > 0 {
+                println!("Hardlinked {} processed image(s) identical to another one", links);
+            }
+        }
+
+        if self.settings.report_timings {
+            report_timings(&logs);
+        }
+
+        if let Some(ref log_path) = self.log_path {
+            if self.settings.reproducible {
+                // Timings are different on every build
+                logs.iter_mut().for_each(|log| log.timings = None);
+            }
+            let json = serde_json::to_string_pretty(&logs)
+                .map_err(|e| Error::chain("Failed to serialize the image processing log", e))?;
+            ufs::create_file(log_path, &json)?;
+        }
+
+        if let Some(ref headers_path) = self.cache_headers_path {
+            if !self.settings.filename_template.contains("{hash}") {
+                println!(
+                    "Warning: `filename_template` doesn't contain `{{hash}}`, the processed images marked as immutable in {} could change without being renamed",
+                    headers_path.display()
+                );
+            }
+            let mut urls: Vec<_> = logs
+                .iter()
+                .map(|log| url_path(&self.output_url(&log.output_path)).to_string())
+                .collect();
+            urls.sort_unstable();
+            write_cache_headers(headers_path, self.settings.cache_headers_format, &urls)?;
+        }
+
+        if let Some(ref manifest_path) = self.manifest_path {
+            // Also rewritten without any image so it doesn't list the ones of a previous build
+            if self.resized_path.exists() {
+                let json = serde_json::to_string_pretty(&self.manifest()).map_err(|e| {
+                    Error::chain("Failed to serialize the processed images manifest", e)
+                })?;
+                ufs::create_file(manifest_path, &json)?;
+            }
+        }
+
+        if self.resized_path.exists() {
+            ufs::create_file(&self.resized_path.join(ENCODER_VERSION_FILE), ENCODER_VERSION)?;
+            self.encoder_changed = false;
+            let mut processed = self.recorded_processed_files();
+            processed.extend(self.produced_filenames());
+            self.record_processed_files(&processed)?;
+            self.record_filename_owners()?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes `FILENAME_OWNERS_FILE`, one filename per line as the hash of its operation, the
+    /// filename prefixed by its directory of `static` and its source, separated by tabs
+    fn record_filename_owners(&mut self) -> Result<()> {
+        self.previous_owners = self
+            .filenames
+            .iter()
+            .map(|(path, ((hash, _), source))| (path.clone(), (*hash, source.clone())))
+            .collect();
+        let owners: BTreeMap<_, _> = self.previous_owners.iter().collect();
+        let lines: Vec<_> = owners
+            .into_iter()
+            .map(|(path, (hash, source))| format!("{:016x}\t{}\t{}", hash, path, source))
+            .collect();
+        ufs::create_file(&self.resized_path.join(FILENAME_OWNERS_FILE), &lines.join("\n"))
+    }
+
+    /// The filenames of the images of this build written to the processed images directory
+    fn produced_filenames(&self) -> BTreeSet<String> {
         self.img_ops
-            .par_iter()
-            .map(|(hash, op)| {
-                let target =
-                    self.resized_path.join(Self::op_filename(*hash, op.collision_id, op.format));
-                op.perform(&self.content_path, &target)
-                    .map_err(|e| Error::chain(format!("Failed to process image: {}", op.source), e))
+            .values()
+            .chain(self.img_ops_collisions.iter())
+            .filter(|op| op.output_dir.is_none())
+            .map(|op| self.op_filename(op, op.collision_id))
+            .collect()
+    }
+
+    /// The filenames `PROCESSED_FILES_FILE` lists
+    fn recorded_processed_files(&self) -> BTreeSet<String> {
+        fs::read_to_string(self.resized_path.join(PROCESSED_FILES_FILE))
+            .unwrap_or_default()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    fn record_processed_files(&self, filenames: &BTreeSet<String>) -> Result<()> {
+        let list: Vec<&str> = filenames.iter().map(String::as_str).collect();
+        ufs::create_file(&self.resized_path.join(PROCESSED_FILES_FILE), &list.join("\n"))
+    }
+
+    /// The images `do_process` would write, sorted by output path. Whether they are up to date
+    /// is checked the same way `do_process` does
+    pub fn planned_images(&self) -> Result<Vec<PlannedImage>> {
+        let regenerate_all = self.regenerate_all();
+        let mut plan = self
+            .img_ops
+            .values()
+            .chain(self.img_ops_collisions.iter())
+            .map(|op| {
+                let source_path = self.content_path.join(&op.source);
+                let output_path = self.op_path(op, op.collision_id);
+                let (width, height) =
+                    op.output_dimensions(source_dimensions(&source_path, op.exif_transforms)?);
+                Ok(PlannedImage {
+                    source: op.source.clone(),
+                    url: self.op_url(op, op.collision_id),
+                    op: op.op.name(),
+                    format: op.format.extension(),
+                    width,
+                    height,
+                    input_bytes: file_size(&source_path),
+                    up_to_date: !regenerate_all
+                        && op.is_up_to_date(
+                            &source_path,
+                            &output_path,
+                            self.previous_owner(op, op.collision_id),
+                        ),
+                    output_path,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        plan.sort_by(|a, b| a.output_path.cmp(&b.output_path));
+        Ok(plan)
+    }
+
+    /// The processed images of this build, sorted by source and then by URL so the list is the
+    /// same whatever the order the templates were rendered in
+    pub fn manifest(&self) -> Vec<ManifestEntry> {
+        let mut entries: Vec<_> = self
+            .img_ops
+            .values()
+            .chain(self.img_ops_collisions.iter())
+            .map(|op| ManifestEntry {
+                source: op.source.clone(),
+                op: op.op.name(),
+                width: op.op.width(),
+                height: op.op.height(),
+                format: op.format.extension(),
+                url: self.op_url(op, op.collision_id),
             })
-            .collect::<Result<()>>()
+            .collect();
+        entries.sort_by(|a, b| (&a.source, &a.url).cmp(&(&b.source, &b.url)));
+        entries
+    }
+
+    /// `planned_images` as JSON
+    pub fn planned_images_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(&self.planned_images()?)
+            .map_err(|e| Error::chain("Failed to serialize the planned images", e))
+    }
+
+    fn op_log(
+        &self,
+        op: &ImageOp,
+        output_path: PathBuf,
+        timings: Option<ImageOpTimings>,
+    ) -> ImageOpLog {
+        let source_path = self.content_path.join(&op.source);
+        ImageOpLog {
+            source: op.source.clone(),
+            input_bytes: file_size(&source_path),
+            source_path,
+            op: op.op.name(),
+            width: op.op.width(),
+            height: op.op.height(),
+            format: op.format.extension(),
+            quality: match op.format {
+                Format::Jpeg(q) => Some(q),
+                #[cfg(feature = "avif")]
+                Format::Avif(q, _) => Some(q),
+                Format::Png(_) | Format::Gif => None,
+            },
+            output_bytes: file_size(&output_path),
+            output_path,
+            cache_hit: timings.is_none(),
+            timings: timings.filter(|_| self.settings.report_timings),
+        }
     }
 }
+
+/// The path of that URL, eg `/blog/processed_images` for `https://example.com/blog/processed_images`
+fn url_path(url: &str) -> &str {
+    let without_scheme = url.find("://").map_or(url, |i| &url[i + 3..]);
+    without_scheme.find('/').map_or("/", |i| &without_scheme[i..])
+}
+
+/// Writes the cache headers of the processed images at these URL paths. With the `_headers`
+/// format, only the block of the processed images is replaced if the file already exists
+fn write_cache_headers(path: &Path, format: CacheHeadersFormat, urls: &[String]) -> Result<()> {
+    let content = match format {
+        CacheHeadersFormat::Headers => {
+            let mut block = format!("{}\n", HEADERS_BLOCK_START);
+            for url in urls {
+                block.push_str(&format!("{}\n  Cache-Control: {}\n", url, IMMUTABLE_CACHE_CONTROL));
+            }
+            block.push_str(HEADERS_BLOCK_END);
+
+            let existing = fs::read_to_string(path).unwrap_or_default();
+            match (existing.find(HEADERS_BLOCK_START), existing.find(HEADERS_BLOCK_END)) {
+                (Some(start), Some(end)) if start < end => format!(
+                    "{}{}{}",
+                    &existing[..start],
+                    block,
+                    &existing[end + HEADERS_BLOCK_END.len()..]
+                ),
+                _ if existing.is_empty() || existing.ends_with('\n') => {
+                    format!("{}{}\n", existing, block)
+                }
+                _ => format!("{}\n{}\n", existing, block),
+            }
+        }
+        CacheHeadersFormat::Json => {
+            let entries: Vec<_> = urls
+                .iter()
+                .map(|url| {
+                    serde_json::json!({
+                        "path": url,
+                        "headers": { "Cache-Control": IMMUTABLE_CACHE_CONTROL },
+                    })
+                })
+                .collect();
+            serde_json::to_string_pretty(&entries)
+                .map_err(|e| Error::chain("Failed to serialize the cache headers", e))?
+        }
+    };
+    ufs::create_file(path, &content)
+}
+
+/// Prints how long each processed image took, slowest first, followed by the percentiles
+/// of the total times to help finding outliers
+fn report_timings(logs: &[ImageOpLog]) {
+    let mut timed: Vec<_> = logs.iter().filter_map(|log| log.timings.map(|t| (log, t))).collect();
+    if timed.is_empty() {
+        return;
+    }
+    timed.sort_by(|(_, a), (_, b)| b.total_ms().partial_cmp(&a.total_ms()).unwrap());
+
+    println!("Image processing timings:");
+    for (log, t) in &timed {
+        println!(
+            "  {} ({}): {:.1}ms decode, {:.1}ms resize, {:.1}ms encode",
+            log.source,
+            log.output_path.display(),
+            t.decode_ms,
+            t.resize_ms,
+            t.encode_ms
+        );
+    }
+
+    // `timed` is sorted slowest first
+    let percentile = |p: usize| timed[timed.len() - 1 - (timed.len() - 1) * p / 100].1.total_ms();
+    println!(
+        "  {} images processed: p50 {:.1}ms, p90 {:.1}ms, p99 {:.1}ms, max {:.1}ms",
+        timed.len(),
+        percentile(50),
+        percentile(90),
+        percentile(99),
+        timed[0].1.total_ms()
+    );
+}
@@ -0,0 +1,514 @@
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use std::time::SystemTime;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use image::io::Reader as ImageReader;
+use image::{DynamicImage, GenericImageView, ImageFormat};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_derive::Serialize;
+
+use crate::animation::{read_animation, Animation};
+use crate::colors::{image_colors, ImageColors};
+use crate::exif::{jpeg_segments, read_image_tags, TAG_MAKE};
+use crate::heic::{heic_mime_type, is_heic};
+use crate::Format;
+use errors::{bail, Error, Result};
+
+/// What `get_image_metadata` returns about an image
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ImageMeta {
+    /// The dimensions of the image as displayed, after its EXIF orientation
+    pub width: u32,
+    pub height: u32,
+    /// The EXIF orientation, from 1 (as stored) to 8. 1 for images without one and SVGs
+    pub orientation: u16,
+    /// Whether every pixel is fully opaque, `None` for SVGs
+    pub is_opaque: Option<bool>,
+    /// The software the image was made with, from its EXIF data or PNG text chunks
+    pub software: Option<String>,
+    /// The creator of the image, from the EXIF artist or the PNG author
+    pub creator: Option<String>,
+    /// The number of channels of a TIFF, `None` for other formats
+    pub samples_per_pixel: Option<u16>,
+    /// What the channels of a TIFF represent, eg `rgb`, `None` for other formats
+    pub photometric_interpretation: Option<String>,
+    /// The frames of animated GIFs, PNGs and WebPs, `None` for still images and SVGs
+    pub animation: Option<Animation>,
+    /// Whether a PNG is interlaced, `None` for other formats
+    pub interlaced: Option<bool>,
+    /// How many times wider than tall the pixels are meant to be displayed, after the EXIF
+    /// orientation. 1 unless the image says otherwise
+    pub pixel_aspect_ratio: f64,
+    /// When the file was last modified and created, as RFC3339 dates in UTC.
+    /// `None` when the filesystem doesn't record them
+    pub mtime: Option<String>,
+    pub ctime: Option<String>,
+    /// The format of the image, detected from its content, eg `jpg`, `png`, `webp` or `svg`
+    pub format: Option<String>,
+    /// Whether the decoded image has an alpha channel, even if it's fully opaque.
+    /// Always true for SVGs, which can be transparent
+    pub has_alpha: bool,
+}
+
+/// A `width` or `height` of the root element of an SVG
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SvgLength {
+    /// In absolute units, converted to CSS pixels
+    Pixels(f64),
+    /// Relative to what the SVG is displayed in, eg a percentage
+    Relative,
+}
+
+impl SvgLength {
+    /// `None` if it's not a positive length
+    fn parse(value: &str) -> Option<SvgLength> {
+        let value = value.trim();
+        let unit_start = value.find(|c: char| c.is_ascii_alphabetic() || c == '%');
+        let (number, unit) = value.split_at(unit_start.unwrap_or(value.len()));
+        let number: f64 = number.trim().parse().ok()?;
+        if !(number.is_finite() && number > 0.0) {
+            return None;
+        }
+        let pixels_per_unit = match unit.to_lowercase().as_str() {
+            "" | "px" => 1.0,
+            "pt" => 96.0 / 72.0,
+            "pc" => 16.0,
+            "in" => 96.0,
+            "cm" => 96.0 / 2.54,
+            "mm" => 96.0 / 25.4,
+            "%" | "em" | "ex" | "rem" | "vw" | "vh" => return Some(SvgLength::Relative),
+            _ => return None,
+        };
+        Some(SvgLength::Pixels(number * pixels_per_unit))
+    }
+}
+
+/// The `width` and `height` attributes of the root element of an SVG, along with the width and
+/// height of its `viewBox`
+#[derive(Debug, Default)]
+struct SvgRoot {
+    width: Option<SvgLength>,
+    height: Option<SvgLength>,
+    view_box: Option<(f64, f64)>,
+}
+
+impl SvgRoot {
+    /// `None` if there is no `<svg>` element
+    fn parse(content: &str) -> Option<SvgRoot> {
+        lazy_static! {
+            static ref ROOT: Regex = Regex::new(r"(?s)<svg\b[^>]*>").unwrap();
+            static ref ATTRIBUTE: Regex =
+                Regex::new(r#"\s(width|height|viewBox)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap();
+        }
+        let root = ROOT.find(content)?.as_str();
+        let mut svg = SvgRoot::default();
+        for captures in ATTRIBUTE.captures_iter(root) {
+            let value = captures.get(2).or_else(|| captures.get(3)).map_or("", |v| v.as_str());
+            match &captures[1] {
+                "width" => svg.width = SvgLength::parse(value),
+                "height" => svg.height = SvgLength::parse(value),
+                _ => {
+                    // min-x, min-y, width and height, separated by spaces and/or commas
+                    let numbers: Vec<f64> = value
+                        .split(|c: char| c.is_whitespace() || c == ',')
+                        .filter(|n| !n.is_empty())
+                        .filter_map(|n| n.parse().ok())
+                        .collect();
+                    svg.view_box = match numbers[..] {
+                        [_, _, w, h] if w > 0.0 && h > 0.0 => Some((w, h)),
+                        _ => None,
+                    };
+                }
+            }
+        }
+        Some(svg)
+    }
+}
+
+/// The dimensions of an SVG in pixels: its `width` and `height` when they are in absolute
+/// units, otherwise the ones of its `viewBox`, with its aspect ratio if one of them is absolute
+fn svg_dimensions<P: AsRef<Path>>(path: P) -> Result<(u32, u32)> {
+    use SvgLength::Pixels;
+
+    let path = path.as_ref();
+    let content = fs::read_to_string(path)
+        .map_err(|e| Error::chain(format!("Failed to process SVG: {}", path.display()), e))?;
+    let svg = match SvgRoot::parse(&content) {
+        Some(svg) => svg,
+        None => bail!("Failed to process SVG: {}: it has no `<svg>` element", path.display()),
+    };
+    let (width, height) = match (svg.width, svg.height, svg.view_box) {
+        (Some(Pixels(w)), Some(Pixels(h)), _) => (w, h),
+        (Some(Pixels(w)), _, Some((view_w, view_h))) => (w, w * view_h / view_w),
+        (_, Some(Pixels(h)), Some((view_w, view_h))) => (h * view_w / view_h, h),
+        (_, _, Some(view_box)) => view_box,
+        _ => {
+            return Err(format!(
+                "Invalid dimensions: {}: the SVG needs a `width` and `height` in absolute units, eg `200px`, or a `viewBox`",
+                path.display()
+            )
+            .into())
+        }
+    };
+    Ok(((width.round() as u32).max(1), (height.round() as u32).max(1)))
+}
+
+/// Reads the metadata of the image at that path, decoding it if it's not an SVG
+pub fn read_image_metadata<P: AsRef<Path>>(path: P) -> Result<ImageMeta> {
+    read_image_metadata_with_colors(path, false).map(|(meta, _)| meta)
+}
+
+/// Reads the metadata of the image at that path along with its colors if `with_colors` is set,
+/// out of the same decoded image. SVGs have no colors as they have no pixels
+pub fn read_image_metadata_with_colors<P: AsRef<Path>>(
+    path: P,
+    with_colors: bool,
+) -> Result<(ImageMeta, Option<ImageColors>)> {
+    let path = path.as_ref();
+    let (mtime, ctime) = file_times(path);
+    if let Some("svg") = path.extension().and_then(OsStr::to_str) {
+        let (width, height) = svg_dimensions(path)?;
+        let meta = ImageMeta {
+            width,
+            height,
+            orientation: 1,
+            is_opaque: None,
+            software: None,
+            creator: None,
+            samples_per_pixel: None,
+            photometric_interpretation: None,
+            animation: None,
+            interlaced: None,
+            pixel_aspect_ratio: 1.0,
+            mtime,
+            ctime,
+            format: Some("svg".to_string()),
+            has_alpha: true,
+        };
+        Ok((meta, None))
+    } else {
+        let img = crate::open_image(&path)
+            .map_err(|e| Error::chain(format!("Failed to process image: {}", path.display()), e))?;
+        let tags = read_image_tags(&path)?;
+        // libheif reads HEICs, not the `image` crate
+        let format = match ImageReader::open(&path)?.with_guessed_format()?.format() {
+            _ if is_heic(path) => Some("heic"),
+            format => format.and_then(format_name),
+        };
+        let orientation = tags.orientation();
+        // Orientations 5 to 8 are rotated by a quarter turn, browsers display them that way
+        let (width, height) = match img.dimensions() {
+            (w, h) if orientation >= 5 => (h, w),
+            dimensions => dimensions,
+        };
+        let meta = ImageMeta {
+            width,
+            height,
+            orientation,
+            is_opaque: Some(is_opaque(&img)),
+            software: tags.software(),
+            creator: tags.creator(),
+            samples_per_pixel: tags.samples_per_pixel(),
+            photometric_interpretation: tags.photometric_interpretation(),
+            animation: read_animation(&path)?,
+            interlaced: is_interlaced_png(&path)?,
+            pixel_aspect_ratio: match tags.pixel_aspect_ratio() {
+                ratio if orientation >= 5 => 1.0 / ratio,
+                ratio => ratio,
+            },
+            mtime,
+            ctime,
+            format: format.map(str::to_string),
+            has_alpha: img.color().has_alpha(),
+        };
+        let colors = if with_colors { Some(image_colors(&img)) } else { None };
+        Ok((meta, colors))
+    }
+}
+
+/// Reads the colors of the image at that path, `None` for SVGs
+pub fn read_image_colors<P: AsRef<Path>>(path: P) -> Result<Option<ImageColors>> {
+    let path = path.as_ref();
+    if let Some("svg") = path.extension().and_then(OsStr::to_str) {
+        return Ok(None);
+    }
+    let img = crate::open_image(path)
+        .map_err(|e| Error::chain(format!("Failed to process image: {}", path.display()), e))?;
+    Ok(Some(image_colors(&img)))
+}
+
+/// The modification and creation times of a file, as RFC3339 dates
+fn file_times(path: &Path) -> (Option<String>, Option<String>) {
+    let metadata = match fs::metadata(path) {
+        Ok(m) => m,
+        Err(_) => return (None, None),
+    };
+    let rfc3339 =
+        |time: SystemTime| DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Secs, true);
+    (metadata.modified().ok().map(rfc3339), metadata.created().ok().map(rfc3339))
+}
+
+/// Reads the interlace method from the header of a PNG, which is always the first chunk.
+/// Returns `None` if the file isn't a PNG
+fn is_interlaced_png(path: &Path) -> Result<Option<bool>> {
+    let mut header = Vec::with_capacity(29);
+    File::open(path)?.take(29).read_to_end(&mut header)?;
+    // The signature, the length and type of the IHDR chunk, then the dimensions, bit depth,
+    // color type, compression and filter methods before the interlace method
+    if header.len() < 29 || !header.starts_with(b"\x89PNG\r\n\x1a\n") || &header[12..16] != b"IHDR"
+    {
+        return Ok(None);
+    }
+    Ok(Some(header[28] == 1))
+}
+
+/// Reads the date the image was taken at from its EXIF data, in the `YYYY-MM-DDTHH:MM:SS` format
+pub fn read_image_date<P: AsRef<Path>>(path: P) -> Result<Option<String>> {
+    Ok(read_image_tags(path)?.date())
+}
+
+/// Whether all the pixels of the image are fully opaque.
+/// Images without an alpha channel are not scanned at all and the scan stops
+/// at the first pixel that isn't opaque.
+pub(crate) fn is_opaque(img: &DynamicImage) -> bool {
+    if !img.color().has_alpha() {
+        return true;
+    }
+
+    match img {
+        DynamicImage::ImageLumaA8(buf) => buf.pixels().all(|p| p.0[1] == u8::MAX),
+        DynamicImage::ImageRgba8(buf) => buf.pixels().all(|p| p.0[3] == u8::MAX),
+        DynamicImage::ImageBgra8(buf) => buf.pixels().all(|p| p.0[3] == u8::MAX),
+        DynamicImage::ImageLumaA16(buf) => buf.pixels().all(|p| p.0[1] == u16::MAX),
+        DynamicImage::ImageRgba16(buf) => buf.pixels().all(|p| p.0[3] == u16::MAX),
+        _ => img.to_rgba8().pixels().all(|p| p.0[3] == u8::MAX),
+    }
+}
+
+/// The luminance quantization table from the JPEG specification, that encoders scale
+/// according to the quality
+const STANDARD_LUMINANCE_TABLE: [u32; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61, 12, 12, 14, 19, 26, 58, 60, 55, 14, 13, 16, 24, 40, 57, 69, 56,
+    14, 17, 22, 29, 51, 87, 80, 62, 18, 22, 37, 56, 68, 109, 103, 77, 24, 35, 55, 64, 81, 104, 113,
+    92, 49, 64, 78, 87, 103, 121, 120, 101, 72, 92, 95, 98, 112, 100, 103, 99,
+];
+
+/// Estimates the quality a JPEG was encoded with by comparing its luminance quantization table
+/// to the standard one, the same way the IJG library scales it.
+/// Returns `None` if the file isn't a JPEG or doesn't have a luminance table.
+pub fn estimate_jpeg_quality<P: AsRef<Path>>(path: P) -> Result<Option<u8>> {
+    Ok(jpeg_quality(&fs::read(path)?))
+}
+
+/// The same as `estimate_jpeg_quality` for a JPEG already read
+pub(crate) fn jpeg_quality(data: &[u8]) -> Option<u8> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+
+    for (_, mut segment) in jpeg_segments(data).into_iter().filter(|(m, _)| *m == 0xDB) {
+        // A DQT segment can hold several tables, each starting with its precision and id
+        while let Some(info) = segment.first() {
+            let size = if info >> 4 == 0 { 64 } else { 128 };
+            let table = match segment.get(1..=size) {
+                Some(t) => t,
+                None => break,
+            };
+            if info & 0x0F == 0 {
+                let sum: u32 = if size == 64 {
+                    table.iter().map(|v| u32::from(*v)).sum()
+                } else {
+                    table.chunks(2).map(|v| u32::from(u16::from_be_bytes([v[0], v[1]]))).sum()
+                };
+                let standard: u32 = STANDARD_LUMINANCE_TABLE.iter().sum();
+                let scale = sum as f64 * 100.0 / standard as f64;
+                let quality = if scale <= 100.0 { (200.0 - scale) / 2.0 } else { 5000.0 / scale };
+                return Some(quality.round().max(1.0).min(100.0) as u8);
+            }
+            segment = &segment[size + 1..];
+        }
+    }
+    None
+}
+
+/// The tools whose name in the metadata of an image gives it away as a screenshot,
+/// lowercased
+const SCREENSHOT_TOOLS: &[&str] = &[
+    "screenshot",
+    "screencapture",
+    "snipping",
+    "greenshot",
+    "sharex",
+    "flameshot",
+    "spectacle",
+    "ksnip",
+    "lightshot",
+    "snagit",
+    "cleanshot",
+];
+
+/// The sizes in pixels of common desktop and phone screens, the longest side first
+const SCREEN_SIZES: &[(u32, u32)] = &[
+    (1280, 720),
+    (1280, 800),
+    (1366, 768),
+    (1440, 900),
+    (1536, 864),
+    (1600, 900),
+    (1680, 1050),
+    (1920, 1080),
+    (1920, 1200),
+    (2560, 1440),
+    (2560, 1600),
+    (2880, 1800),
+    (3024, 1964),
+    (3456, 2234),
+    (3840, 2160),
+    (1334, 750),
+    (1792, 828),
+    (2340, 1080),
+    (2400, 1080),
+    (2436, 1125),
+    (2532, 1170),
+    (2556, 1179),
+    (2688, 1242),
+    (2778, 1284),
+    (2796, 1290),
+];
+
+/// Guesses whether the image at that path is a screenshot. This is only a heuristic:
+/// it is one if its metadata mentions a screenshot tool, eg in the software tag or the EXIF
+/// user comment, or if it is a lossless image without a camera make that is exactly the size
+/// of a common screen. SVGs are never screenshots
+pub fn is_likely_screenshot<P: AsRef<Path>>(path: P) -> Result<bool> {
+    let path = path.as_ref();
+    if let Some("svg") = path.extension().and_then(OsStr::to_str) {
+        return Ok(false);
+    }
+    let tags = read_image_tags(path)?;
+    let mentions_tool = |s: &str| {
+        let s = s.to_lowercase();
+        SCREENSHOT_TOOLS.iter().any(|tool| s.contains(tool))
+    };
+    // macOS also mentions it in the XMP of its PNG screenshots
+    let described = tags
+        .software()
+        .into_iter()
+        .chain(tags.user_comment())
+        .chain(tags.text.get("XML:com.adobe.xmp").cloned());
+    for text in described {
+        if mentions_tool(&text) {
+            return Ok(true);
+        }
+    }
+
+    // Photos get their make from the camera, and resized ones are usually JPEGs
+    if tags.exif_ascii(TAG_MAKE).is_some() || Format::is_lossy(path) != Some(false) {
+        return Ok(false);
+    }
+    let (width, height) = image::image_dimensions(path)?;
+    Ok(SCREEN_SIZES.contains(&(width.max(height), width.min(height))))
+}
+
+/// Returns the MIME type matching an image format, if it's one we know about
+pub fn format_mime_type(format: ImageFormat) -> Option<&'static str> {
+    match format {
+        ImageFormat::Png => Some("image/png"),
+        ImageFormat::Jpeg => Some("image/jpeg"),
+        ImageFormat::Gif => Some("image/gif"),
+        ImageFormat::WebP => Some("image/webp"),
+        ImageFormat::Tiff => Some("image/tiff"),
+        ImageFormat::Bmp => Some("image/bmp"),
+        ImageFormat::Ico => Some("image/x-icon"),
+        ImageFormat::Tga => Some("image/x-tga"),
+        ImageFormat::Pnm => Some("image/x-portable-anymap"),
+        ImageFormat::Hdr => Some("image/vnd.radiance"),
+        _ => None,
+    }
+}
+
+/// The name `get_image_metadata` gives to an image format, its usual extension
+fn format_name(format: ImageFormat) -> Option<&'static str> {
+    match format {
+        ImageFormat::Png => Some("png"),
+        ImageFormat::Jpeg => Some("jpg"),
+        ImageFormat::Gif => Some("gif"),
+        ImageFormat::WebP => Some("webp"),
+        ImageFormat::Tiff => Some("tiff"),
+        ImageFormat::Bmp => Some("bmp"),
+        ImageFormat::Ico => Some("ico"),
+        ImageFormat::Tga => Some("tga"),
+        ImageFormat::Pnm => Some("pnm"),
+        ImageFormat::Hdr => Some("hdr"),
+        _ => None,
+    }
+}
+
+/// Whether the start of that file looks like an SVG document
+fn looks_like_svg(path: &Path) -> Result<bool> {
+    let mut buf = Vec::with_capacity(1024);
+    File::open(path)?.take(1024).read_to_end(&mut buf)?;
+    Ok(String::from_utf8_lossy(&buf).contains("<svg"))
+}
+
+/// Detects the real format of an image from its content rather than from its
+/// extension and returns the corresponding MIME type
+pub fn detect_mime_type<P: AsRef<Path>>(path: P) -> Result<&'static str> {
+    let path = path.as_ref();
+    let reader = ImageReader::open(path)?.with_guessed_format()?;
+
+    match reader.format() {
+        Some(format) => format_mime_type(format)
+            .ok_or_else(|| format!("Unsupported image format for {}", path.display()).into()),
+        None if looks_like_svg(path)? => Ok("image/svg+xml"),
+        None => match heic_mime_type(path)? {
+            Some(mime_type) => Ok(mime_type),
+            None => Err(format!("Could not detect the image format of {}", path.display()).into()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageOutputFormat, RgbImage};
+
+    fn jpeg(quality: u8) -> Vec<u8> {
+        let mut data = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::from_fn(16, 16, |x, y| {
+            image::Rgb([(x * 16) as u8, (y * 16) as u8, 128])
+        }))
+        .write_to(&mut data, ImageOutputFormat::Jpeg(quality))
+        .unwrap();
+        data
+    }
+
+    #[test]
+    fn can_estimate_the_quality_of_jpegs() {
+        for quality in &[30, 50, 75, 90] {
+            let estimated = jpeg_quality(&jpeg(*quality)).unwrap();
+            // The values of the table are rounded once scaled
+            assert!(
+                (i16::from(estimated) - i16::from(*quality)).abs() <= 1,
+                "{} estimated as {}",
+                quality,
+                estimated
+            );
+        }
+    }
+
+    #[test]
+    fn cant_estimate_the_quality_without_a_luminance_table() {
+        // Cut in the middle of its first quantization table
+        let data = jpeg(75);
+        let dqt = data.windows(2).position(|w| w == [0xFF, 0xDB]).unwrap();
+        assert_eq!(jpeg_quality(&data[..dqt + 20]), None);
+        // No table at all
+        assert_eq!(jpeg_quality(&[0xFF, 0xD8, 0xFF, 0xD9]), None);
+        assert_eq!(jpeg_quality(b"\x89PNG\r\n\x1a\n"), None);
+    }
+}
@@ -0,0 +1,141 @@
+use std::ffi::OsStr;
+use std::path::Path;
+
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, ImageOutputFormat};
+
+use errors::{Error, Result};
+
+/// Decodes the image at `path` for one of the placeholders, `None` for SVGs as they are usually
+/// light enough to not need one
+fn open_unless_svg(path: &Path) -> Result<Option<DynamicImage>> {
+    if let Some("svg") = path.extension().and_then(OsStr::to_str) {
+        return Ok(None);
+    }
+    crate::open_image(path)
+        .map(Some)
+        .map_err(|e| Error::chain(format!("Failed to process image: {}", path.display()), e))
+}
+
+/// Encodes the `kind` of placeholder made from the image at `path` as a base64 `data:` URI
+fn data_uri(
+    img: &DynamicImage,
+    format: ImageOutputFormat,
+    mime_type: &str,
+    kind: &str,
+    path: &Path,
+) -> Result<String> {
+    let mut bytes = Vec::new();
+    img.write_to(&mut bytes, format).map_err(|e| {
+        Error::chain(format!("Failed to encode the {} of {}", kind, path.display()), e)
+    })?;
+    Ok(format!("data:{};base64,{}", mime_type, base64::encode(&bytes)))
+}
+
+/// Builds a CSS `linear-gradient` approximating the image with `colors` colors, to be used as a
+/// placeholder while the image loads.
+/// The image is downscaled to a single row (or column for portrait images) of `colors` pixels,
+/// each being the average color of its band of the image.
+/// Returns `None` for SVGs as they are usually light enough to not need a placeholder.
+pub fn gradient_css<P: AsRef<Path>>(path: P, colors: u32) -> Result<Option<String>> {
+    let img = match open_unless_svg(path.as_ref())? {
+        Some(img) => img,
+        None => return Ok(None),
+    };
+    let colors = colors.max(2);
+    let (width, height) = img.dimensions();
+    let (direction, small) = if width >= height {
+        ("to right", img.resize_exact(colors, 1, FilterType::Triangle))
+    } else {
+        ("to bottom", img.resize_exact(1, colors, FilterType::Triangle))
+    };
+
+    let stops: Vec<_> = small
+        .to_rgb8()
+        .pixels()
+        .map(|p| format!("#{:02x}{:02x}{:02x}", p.0[0], p.0[1], p.0[2]))
+        .collect();
+    Ok(Some(format!("linear-gradient({}, {})", direction, stops.join(", "))))
+}
+
+/// The biggest dimension of the previews made by `preview_data_uri`, in pixels
+const PREVIEW_SIZE: u32 = 16;
+/// How much the previews are blurred, relative to their size
+const PREVIEW_BLUR_SIGMA: f32 = 1.0;
+
+/// Builds a tiny blurred PNG of the image as a `data:` URI, to be displayed, stretched, while
+/// the full image loads. It keeps the aspect ratio of the image and is usually a few hundred bytes.
+/// Returns `None` for SVGs as they are usually light enough to not need a placeholder.
+pub fn preview_data_uri<P: AsRef<Path>>(path: P) -> Result<Option<String>> {
+    let path = path.as_ref();
+    let img = match open_unless_svg(path)? {
+        Some(img) => img,
+        None => return Ok(None),
+    };
+    let preview =
+        img.resize(PREVIEW_SIZE, PREVIEW_SIZE, FilterType::Triangle).blur(PREVIEW_BLUR_SIGMA);
+    data_uri(&preview, ImageOutputFormat::Png, "image/png", "preview", path).map(Some)
+}
+
+/// Builds a tiny JPEG of the image, whose biggest dimension is at most `size` pixels, as a `data:`
+/// URI meant to be inlined as the `src` of the image while the full image loads.
+/// If `blur` is given, the JPEG is blurred by a gaussian of that sigma, in pixels of the JPEG.
+/// JPEG having no alpha channel, the transparent parts of the image end up black.
+/// Returns `None` for SVGs as they are usually light enough to not need a placeholder.
+pub fn placeholder_data_uri<P: AsRef<Path>>(
+    path: P,
+    size: u32,
+    blur: Option<f32>,
+    quality: u8,
+) -> Result<Option<String>> {
+    let path = path.as_ref();
+    let img = match open_unless_svg(path)? {
+        Some(img) => img,
+        None => return Ok(None),
+    };
+    let (width, height) = img.dimensions();
+    let mut placeholder =
+        if width.max(height) > size { img.resize(size, size, FilterType::Triangle) } else { img };
+    if let Some(sigma) = blur {
+        placeholder = placeholder.blur(sigma);
+    }
+    data_uri(
+        &DynamicImage::ImageRgb8(placeholder.to_rgb8()),
+        ImageOutputFormat::Jpeg(quality),
+        "image/jpeg",
+        "placeholder",
+        path,
+    )
+    .map(Some)
+}
+
+/// Builds an SVG of `width` x `height` stretching the preview of `preview_data_uri` under a
+/// gaussian blur, for a smooth placeholder that's a single inline element. The alpha is kept
+/// opaque so the edges don't fade out with the blur.
+/// Returns `None` for SVGs as they are usually light enough to not need a placeholder.
+pub fn svg_blur_placeholder<P: AsRef<Path>>(
+    path: P,
+    (width, height): (u32, u32),
+) -> Result<Option<String>> {
+    let preview = match preview_data_uri(path)? {
+        Some(preview) => preview,
+        None => return Ok(None),
+    };
+    // About half a pixel of the preview, at the size of the placeholder
+    let std_deviation = f64::from(width.max(height)) / f64::from(PREVIEW_SIZE * 2);
+    Ok(Some(format!(
+        concat!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+            r#"<filter id="b" color-interpolation-filters="sRGB">"#,
+            r#"<feGaussianBlur stdDeviation="{blur:.1}"/>"#,
+            r#"<feComponentTransfer><feFuncA type="discrete" tableValues="1 1"/></feComponentTransfer>"#,
+            r#"</filter>"#,
+            r#"<image width="100%" height="100%" preserveAspectRatio="none" filter="url(#b)" href="{preview}"/>"#,
+            r#"</svg>"#
+        ),
+        w = width,
+        h = height,
+        blur = std_deviation,
+        preview = preview
+    )))
+}
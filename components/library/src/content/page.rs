@@ -1,6 +1,7 @@
 /// A page, can be a blog post or a basic page
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
 use lazy_static::lazy_static;
 use regex::Regex;
@@ -11,6 +12,7 @@ use crate::library::Library;
 use config::Config;
 use errors::{Error, Result};
 use front_matter::{split_page_content, InsertAnchor, PageFrontMatter};
+use imageproc::Processor;
 use rendering::{render_content, Heading, RenderContext};
 use utils::fs::{find_related_assets, read_file};
 use utils::site::get_reading_analytics;
@@ -237,20 +239,29 @@ impl Page {
     }
 
     /// We need access to all pages url to render links relative to content
-    /// so that can't happen at the same time as parsing
+    /// so that can't happen at the same time as parsing.
+    /// `imageproc` is used to process the colocated images with `markdown_images`
     pub fn render_markdown(
         &mut self,
         permalinks: &HashMap<String, String>,
         tera: &Tera,
         config: &Config,
         anchor_insert: InsertAnchor,
+        imageproc: Option<&Mutex<Processor>>,
     ) -> Result<()> {
         let mut context =
             RenderContext::new(tera, config, &self.permalink, permalinks, anchor_insert);
+        if let Some(imageproc) = imageproc {
+            context.set_imageproc(imageproc, &self.file.relative);
+        }
 
         context.tera_context.insert("page", &SerializingPage::from_page_basic(self, None));
 
-        let res = render_content(&self.raw_content, &context).map_err(|e| {
+        // The images of the content count towards the variants of the page
+        let res = imageproc::render_for_page(&self.file.relative, || {
+            render_content(&self.raw_content, &context)
+        })
+        .map_err(|e| {
             Error::chain(format!("Failed to render content of {}", self.file.path.display()), e)
         })?;
 
@@ -277,7 +288,10 @@ impl Page {
         context.insert("page", &self.to_serialized(library));
         context.insert("lang", &self.lang);
 
-        render_template(&tpl_name, tera, context, &config.theme).map_err(|e| {
+        imageproc::render_for_page(&self.file.relative, || {
+            render_template(&tpl_name, tera, context, &config.theme)
+        })
+        .map_err(|e| {
             Error::chain(format!("Failed to render page '{}'", self.file.path.display()), e)
         })
     }
@@ -350,6 +364,7 @@ Hello world"#;
             &Tera::default(),
             &Config::default(),
             InsertAnchor::None,
+            None,
         )
         .unwrap();
 
@@ -516,8 +531,14 @@ Hello world
         let res = Page::parse(Path::new("hello.md"), &content, &config, &PathBuf::new());
         assert!(res.is_ok());
         let mut page = res.unwrap();
-        page.render_markdown(&HashMap::default(), &Tera::default(), &config, InsertAnchor::None)
-            .unwrap();
+        page.render_markdown(
+            &HashMap::default(),
+            &Tera::default(),
+            &config,
+            InsertAnchor::None,
+            None,
+        )
+        .unwrap();
         assert_eq!(page.summary, Some("<p>Hello world</p>\n".to_string()));
     }
 
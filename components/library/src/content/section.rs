@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
 use slotmap::DefaultKey;
 use tera::{Context as TeraContext, Tera};
@@ -7,6 +8,7 @@ use tera::{Context as TeraContext, Tera};
 use config::Config;
 use errors::{Error, Result};
 use front_matter::{split_section_content, SectionFrontMatter};
+use imageproc::Processor;
 use rendering::{render_content, Heading, RenderContext};
 use utils::fs::{find_related_assets, read_file};
 use utils::site::get_reading_analytics;
@@ -162,12 +164,14 @@ impl Section {
     }
 
     /// We need access to all pages url to render links relative to content
-    /// so that can't happen at the same time as parsing
+    /// so that can't happen at the same time as parsing.
+    /// `imageproc` is used to process the colocated images with `markdown_images`
     pub fn render_markdown(
         &mut self,
         permalinks: &HashMap<String, String>,
         tera: &Tera,
         config: &Config,
+        imageproc: Option<&Mutex<Processor>>,
     ) -> Result<()> {
         let mut context = RenderContext::new(
             tera,
@@ -176,10 +180,17 @@ impl Section {
             permalinks,
             self.meta.insert_anchor_links,
         );
+        if let Some(imageproc) = imageproc {
+            context.set_imageproc(imageproc, &self.file.relative);
+        }
 
         context.tera_context.insert("section", &SerializingSection::from_section_basic(self, None));
 
-        let res = render_content(&self.raw_content, &context).map_err(|e| {
+        // The images of the content count towards the variants of the section
+        let res = imageproc::render_for_page(&self.file.relative, || {
+            render_content(&self.raw_content, &context)
+        })
+        .map_err(|e| {
             Error::chain(format!("Failed to render content of {}", self.file.path.display()), e)
         })?;
         self.content = res.body;
@@ -201,7 +212,10 @@ impl Section {
         context.insert("section", &self.to_serialized(library));
         context.insert("lang", &self.lang);
 
-        render_template(tpl_name, tera, context, &config.theme).map_err(|e| {
+        imageproc::render_for_page(&self.file.relative, || {
+            render_template(tpl_name, tera, context, &config.theme)
+        })
+        .map_err(|e| {
             Error::chain(format!("Failed to render section '{}'", self.file.path.display()), e)
         })
     }
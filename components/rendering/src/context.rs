@@ -1,8 +1,10 @@
 use std::borrow::Cow;
 use std::collections::HashMap;
+use std::sync::Mutex;
 
 use config::Config;
 use front_matter::InsertAnchor;
+use imageproc::Processor;
 use tera::{Context, Tera};
 
 /// All the information from the zola site that is needed to render HTML from markdown
@@ -14,6 +16,10 @@ pub struct RenderContext<'a> {
     pub current_page_permalink: &'a str,
     pub permalinks: Cow<'a, HashMap<String, String>>,
     pub insert_anchor: InsertAnchor,
+    /// Set when the colocated images of the content are processed, see `markdown_images`
+    pub imageproc: Option<&'a Mutex<Processor>>,
+    /// The path of the .md file being rendered, relative to the content directory
+    pub current_page_path: &'a str,
 }
 
 impl<'a> RenderContext<'a> {
@@ -32,10 +38,21 @@ impl<'a> RenderContext<'a> {
             current_page_permalink,
             permalinks: Cow::Borrowed(permalinks),
             insert_anchor,
+            imageproc: None,
+            current_page_path: "",
             config,
         }
     }
 
+    /// Processes the colocated images of the content at that path, relative to the content
+    /// directory, if `markdown_images` is enabled
+    pub fn set_imageproc(&mut self, imageproc: &'a Mutex<Processor>, current_page_path: &'a str) {
+        if self.config.image_processing.markdown_images.is_some() {
+            self.imageproc = Some(imageproc);
+            self.current_page_path = current_page_path;
+        }
+    }
+
     // In use in the markdown filter
     pub fn from_config(config: &'a Config) -> RenderContext<'a> {
         Self {
@@ -44,6 +61,8 @@ impl<'a> RenderContext<'a> {
             current_page_permalink: "",
             permalinks: Cow::Owned(HashMap::new()),
             insert_anchor: InsertAnchor::None,
+            imageproc: None,
+            current_page_path: "",
             config,
         }
     }
@@ -7,6 +7,7 @@ use errors::Result;
 
 pub use context::RenderContext;
 use markdown::markdown_to_html;
+pub use markdown::ProcessedImage;
 pub use shortcode::render_shortcodes;
 pub use table_of_contents::Heading;
 
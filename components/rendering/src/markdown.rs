@@ -16,7 +16,9 @@ use self::cmark::{Event, LinkType, Options, Parser, Tag};
 
 mod codeblock;
 mod fence;
+mod processed_image;
 use self::codeblock::CodeBlock;
+pub use self::processed_image::ProcessedImage;
 
 const CONTINUE_READING: &str = "<span id=\"continue-reading\"></span>";
 const ANCHOR_LINK_TEMPLATE: &str = "anchor-link.html";
@@ -172,6 +174,8 @@ pub fn markdown_to_html(content: &str, context: &RenderContext) -> Result<Render
     let mut error = None;
 
     let mut highlighter: Option<CodeBlock> = None;
+    // Set between the start and the end of an image processed with `markdown_images`
+    let mut image: Option<ProcessedImage> = None;
 
     let mut inserted_anchors: Vec<String> = vec![];
     let mut headings: Vec<Heading> = vec![];
@@ -193,6 +197,22 @@ pub fn markdown_to_html(content: &str, context: &RenderContext) -> Result<Render
     {
         let mut events = Parser::new_ext(content, opts)
             .map(|event| {
+                // The text of a processed image is its alt, written with the `<img>` at its end
+                if image.is_some() {
+                    match event {
+                        Event::End(Tag::Image(..)) => {
+                            let mut processed = image.take().expect("Processed image");
+                            processed.check_alt(context);
+                            return Event::Html(processed.to_html().into());
+                        }
+                        Event::Text(ref text) | Event::Code(ref text) => {
+                            image.as_mut().expect("Processed image").alt.push_str(text);
+                        }
+                        _ => (),
+                    }
+                    return Event::Html("".into());
+                }
+
                 match event {
                     Event::Text(text) => {
                         // if we are in the middle of a highlighted code block
@@ -267,6 +287,17 @@ pub fn markdown_to_html(content: &str, context: &RenderContext) -> Result<Render
                     }
                     Event::Start(Tag::Image(link_type, src, title)) => {
                         if is_colocated_asset_link(&src) {
+                            match ProcessedImage::new(&src, &title, context) {
+                                Ok(Some(processed)) => {
+                                    image = Some(processed);
+                                    return Event::Html("".into());
+                                }
+                                Ok(None) => (),
+                                Err(err) => {
+                                    error = Some(err);
+                                    return Event::Html("".into());
+                                }
+                            }
                             let link = format!("{}{}", context.current_page_permalink, &*src);
                             return Event::Start(Tag::Image(link_type, link.into(), title));
                         }
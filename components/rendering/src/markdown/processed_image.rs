@@ -0,0 +1,251 @@
+use std::path::Path;
+use std::sync::Mutex;
+
+use pulldown_cmark::escape::{escape_href, escape_html};
+
+use crate::context::RenderContext;
+use errors::{Error, Result};
+use imageproc::{Dimension, ImageOp, ImageOpError, Processor, ResizeArgs, ResizeImageResponse};
+
+/// A colocated image of the content processed with the `markdown_images` settings
+#[derive(Debug)]
+pub struct ProcessedImage {
+    /// The path of the image, relative to the `content` directory
+    pub source: String,
+    /// One per width of the `srcset`, from the smallest to the biggest
+    pub responses: Vec<ResizeImageResponse>,
+    pub title: String,
+    /// The blurred preview shown while the image loads, with `responsive_previews`
+    pub preview: Option<String>,
+    /// The text of the image in the Markdown, read until the end of the image
+    pub alt: String,
+}
+
+impl ProcessedImage {
+    /// Processes the colocated image `src` if `markdown_images` is enabled.
+    /// Returns `None` for the images that are left as is: the ones that don't exist
+    /// and the ones that aren't allowed sources, such as SVGs
+    pub fn new(src: &str, title: &str, context: &RenderContext) -> Result<Option<ProcessedImage>> {
+        let imageproc = match context.imageproc {
+            Some(imageproc) => imageproc,
+            None => return Ok(None),
+        };
+        let source = match Path::new(context.current_page_path).parent() {
+            Some(dir) if dir != Path::new("") => format!("{}/{}", dir.to_string_lossy(), src),
+            _ => src.to_string(),
+        };
+        ProcessedImage::from_source(source, title, imageproc)
+    }
+
+    /// Processes the image at `source`, relative to the `content` directory, the same way as
+    /// the colocated images of the Markdown, so `get_image_preload` finds the same URLs
+    pub fn from_source(
+        source: String,
+        title: &str,
+        imageproc: &Mutex<Processor>,
+    ) -> Result<Option<ProcessedImage>> {
+        let mut imageproc = imageproc.lock().unwrap();
+        let markdown_images = match imageproc.settings().markdown_images {
+            Some(ref markdown_images) => markdown_images.clone(),
+            None => return Ok(None),
+        };
+        let source_path = imageproc.source_path(&source);
+        if !imageproc.settings().is_allowed_source(&source) || !source_path.is_file() {
+            return Ok(None);
+        }
+        let fallback = imageproc.settings().fallback_to_original;
+
+        let source_width = match imageproc::ExifTransforms::for_source(
+            &source_path,
+            imageproc.settings().apply_exif_transforms,
+            true,
+        )
+        .and_then(|transforms| imageproc::source_dimensions(&source_path, transforms))
+        {
+            Ok((width, _)) => (width / imageproc.settings().pixel_ratio).max(1),
+            Err(e) if fallback => {
+                let response = imageproc.fallback_to_original(&source, &e.to_string())?;
+                return Ok(Some(ProcessedImage::from_responses(source, vec![response], title)));
+            }
+            Err(e) => {
+                return Err(Error::chain(format!("Failed to read image `{}`", source), e));
+            }
+        };
+        // Never upscaled, so the widths bigger than the source all become the source width
+        let mut widths: Vec<_> =
+            markdown_images.widths.iter().map(|w| (*w).min(source_width)).collect();
+        widths.sort_unstable();
+        widths.dedup();
+
+        let quality = markdown_images.quality.unwrap_or_else(|| imageproc.default_quality(&source));
+        let mut responses = Vec::with_capacity(widths.len());
+        // Animations that can't be kept in that format become their first frame
+        let mut first_frame_only = false;
+        for width in widths {
+            let mut args = ResizeArgs {
+                op: "fit_width".to_string(),
+                width: Some(Dimension::Pixels(width)),
+                height: None,
+                format: markdown_images.format.clone(),
+                quality,
+                crop: None,
+                corner_radius: None,
+                circle: false,
+                focus: None,
+                anchor: None,
+                respect_exif: true,
+                alpha_quality: None,
+                strip_metadata: None,
+                percent: None,
+                output_dir: None,
+                first_frame_only,
+                color_profile: None,
+            };
+            let img_op =
+                match ImageOp::from_args(source.clone(), &source_path, &args, imageproc.settings())
+                {
+                    Err(e @ ImageOpError::AnimatedSource { .. })
+                    | Err(e @ ImageOpError::AnimatedWebp(_))
+                    | Err(e @ ImageOpError::UnsupportedAnimation(_)) => {
+                        println!(
+                            "Warning: Only the first frame of the animation {} is kept: {}",
+                            source, e
+                        );
+                        first_frame_only = true;
+                        args.first_frame_only = true;
+                        ImageOp::from_args(
+                            source.clone(),
+                            &source_path,
+                            &args,
+                            imageproc.settings(),
+                        )
+                    }
+                    img_op => img_op,
+                };
+            let response = img_op.map_err(Error::from).and_then(|img_op| imageproc.insert(img_op));
+            match response {
+                Ok(response) => responses.push(response),
+                Err(e) if fallback => {
+                    let response = imageproc.fallback_to_original(&source, &e.to_string())?;
+                    return Ok(Some(ProcessedImage::from_responses(source, vec![response], title)));
+                }
+                Err(e) => {
+                    return Err(Error::chain(format!("Failed to process image `{}`", source), e))
+                }
+            }
+        }
+
+        let preview = if imageproc.settings().responsive_previews {
+            imageproc::preview_data_uri(&source_path)
+                .map_err(|e| Error::chain(format!("Failed to process image `{}`", source), e))?
+        } else {
+            None
+        };
+        let mut processed = ProcessedImage::from_responses(source, responses, title);
+        processed.preview = preview;
+        Ok(Some(processed))
+    }
+
+    fn from_responses(
+        source: String,
+        responses: Vec<ResizeImageResponse>,
+        title: &str,
+    ) -> ProcessedImage {
+        ProcessedImage {
+            source,
+            responses,
+            title: title.to_string(),
+            preview: None,
+            alt: String::new(),
+        }
+    }
+
+    /// Reports the image if it has no alt text, which fails the build with `strict_alt`.
+    /// With `alt_fallback`, it gets one made from its filename instead, with a warning
+    pub fn check_alt(&mut self, context: &RenderContext) {
+        if !self.alt.trim().is_empty() {
+            return;
+        }
+        if let Some(ref fallback) = context.config.image_processing.alt_fallback {
+            self.alt = fallback.alt_from_filename(&self.source);
+            println!(
+                "Warning: no alt text for `{}` in {}, using `{}` made from its filename",
+                self.source, context.current_page_path, self.alt
+            );
+            return;
+        }
+        if let Some(imageproc) = context.imageproc {
+            imageproc.lock().unwrap().report_missing_alt(&self.source, context.current_page_path);
+        }
+    }
+
+    fn biggest(&self) -> &ResizeImageResponse {
+        self.responses.last().expect("At least one width")
+    }
+
+    /// The escaped URL of the biggest width
+    fn src(&self) -> String {
+        let mut src = String::new();
+        escape_href(&mut src, &self.biggest().url).expect("Could not write to buffer");
+        src
+    }
+
+    /// The `srcset` and `sizes` of the image, `None` if it was processed at a single width
+    fn srcset_and_sizes(&self) -> Option<(String, String)> {
+        if self.responses.len() < 2 {
+            return None;
+        }
+        let srcset: Vec<_> = self
+            .responses
+            .iter()
+            .map(|r| {
+                let mut url = String::new();
+                escape_href(&mut url, &r.url).expect("Could not write to buffer");
+                format!("{} {}w", url, r.intrinsic_width)
+            })
+            .collect();
+        let sizes = format!("(max-width: {w}px) 100vw, {w}px", w = self.biggest().display_width);
+        Some((srcset.join(", "), sizes))
+    }
+
+    /// The `<img>` of the image, with a `srcset` if it was processed at several widths
+    pub fn to_html(&self) -> String {
+        let biggest = self.biggest();
+        let mut html = format!("<img src=\"{}\"", self.src());
+
+        if let Some((srcset, sizes)) = self.srcset_and_sizes() {
+            html.push_str(&format!(" srcset=\"{}\" sizes=\"{}\"", srcset, sizes));
+        }
+        html.push_str(&format!(
+            " width=\"{}\" height=\"{}\" alt=\"",
+            biggest.display_width, biggest.display_height
+        ));
+        escape_html(&mut html, &self.alt).expect("Could not write to buffer");
+        html.push('"');
+        if !self.title.is_empty() {
+            html.push_str(" title=\"");
+            escape_html(&mut html, &self.title).expect("Could not write to buffer");
+            html.push('"');
+        }
+        if let Some(ref preview) = self.preview {
+            // The preview is stretched behind the image until it's loaded
+            html.push_str(&format!(
+                " style=\"background-size: cover; background-image: url({})\"",
+                preview
+            ));
+        }
+        html.push_str(" />");
+        html
+    }
+
+    /// The `<link>` preloading the image, with the same `srcset` and `sizes` as its `<img>`
+    /// so browsers fetch the width they will display
+    pub fn to_preload_html(&self) -> String {
+        let mut html = format!("<link rel=\"preload\" as=\"image\" href=\"{}\"", self.src());
+        if let Some((srcset, sizes)) = self.srcset_and_sizes() {
+            html.push_str(&format!(" imagesrcset=\"{}\" imagesizes=\"{}\"", srcset, sizes));
+        }
+        html.push_str(" />");
+        html
+    }
+}
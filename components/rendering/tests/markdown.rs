@@ -1,10 +1,14 @@
 use std::collections::HashMap;
+use std::fs::create_dir_all;
+use std::sync::Mutex;
 
 use tera::Tera;
 
-use config::Config;
+use config::{AltFallback, Config, MarkdownImages};
 use front_matter::InsertAnchor;
-use rendering::{render_content, RenderContext};
+use image::{Rgb, RgbImage};
+use imageproc::Processor;
+use rendering::{render_content, ProcessedImage, RenderContext};
 use templates::ZOLA_TERA;
 use utils::slugs::SlugifyStrategy;
 
@@ -1156,3 +1160,145 @@ fn can_use_smart_punctuation() {
     let res = render_content(r#"This -- is "it"..."#, &context).unwrap();
     assert_eq!(res.body, "<p>This – is “it”…</p>\n");
 }
+
+#[test]
+fn can_process_colocated_images() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let page_dir = tmp_dir.path().join("content").join("blog").join("post");
+    create_dir_all(&page_dir).unwrap();
+    RgbImage::from_pixel(100, 50, Rgb([255, 0, 0])).save(page_dir.join("red.png")).unwrap();
+
+    let permalinks_ctx = HashMap::new();
+    let mut config = Config::default();
+    config.image_processing.markdown_images =
+        Some(MarkdownImages { widths: vec![40, 80, 200], ..MarkdownImages::default() });
+    let imageproc = Mutex::new(Processor::new(tmp_dir.path(), &config));
+    let mut context = RenderContext::new(
+        &ZOLA_TERA,
+        &config,
+        "https://example.com/blog/post/",
+        &permalinks_ctx,
+        InsertAnchor::None,
+    );
+    context.set_imageproc(&imageproc, "blog/post/index.md");
+
+    let res = render_content(r#"![A *red* square](red.png "Red")"#, &context).unwrap();
+    assert!(res.body.starts_with("<p><img src=\""));
+    assert!(res.body.contains(" 40w, "));
+    assert!(res.body.contains(" 80w, "));
+    // Never upscaled
+    assert!(res.body.contains(" 100w\" sizes=\"(max-width: 100px) 100vw, 100px\""));
+    assert!(res.body.contains(r#" width="100" height="50" alt="A red square" title="Red" />"#));
+    assert_eq!(imageproc.lock().unwrap().num_img_ops(), 3);
+
+    // The preload of `get_image_preload` has the same srcset and sizes
+    let preload = ProcessedImage::from_source("blog/post/red.png".to_string(), "", &imageproc)
+        .unwrap()
+        .unwrap()
+        .to_preload_html();
+    let start = res.body.find(" srcset=").unwrap();
+    let end = res.body.find(" width=").unwrap();
+    assert!(preload.contains(
+        &res.body[start..end]
+            .replace(" srcset=", " imagesrcset=")
+            .replace(" sizes=", " imagesizes=")
+    ));
+    assert_eq!(imageproc.lock().unwrap().num_img_ops(), 3);
+
+    // External and missing images are left as is
+    let res = render_content("![](https://example.com/red.png) ![](blue.png)", &context).unwrap();
+    assert_eq!(
+        res.body,
+        "<p><img src=\"https://example.com/red.png\" alt=\"\" /> <img src=\"https://example.com/blog/post/blue.png\" alt=\"\" /></p>\n"
+    );
+    assert_eq!(imageproc.lock().unwrap().num_img_ops(), 3);
+}
+
+#[test]
+fn keeps_the_first_frame_of_animated_colocated_images() {
+    use image::codecs::gif::GifEncoder;
+    use image::{Delay, Frame, Rgba, RgbaImage};
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let page_dir = tmp_dir.path().join("content").join("blog");
+    create_dir_all(&page_dir).unwrap();
+    let frames = [Rgba([255, 0, 0, 255]), Rgba([0, 0, 255, 255])].iter().map(|color| {
+        Frame::from_parts(
+            RgbaImage::from_pixel(100, 50, *color),
+            0,
+            0,
+            Delay::from_numer_denom_ms(100, 1),
+        )
+    });
+    let mut gif = Vec::new();
+    GifEncoder::new(&mut gif).encode_frames(frames).unwrap();
+    std::fs::write(page_dir.join("blink.gif"), &gif).unwrap();
+
+    let permalinks_ctx = HashMap::new();
+    let mut config = Config::default();
+    config.image_processing.markdown_images = Some(MarkdownImages {
+        widths: vec![40, 80],
+        format: "jpg".to_string(),
+        ..MarkdownImages::default()
+    });
+    let imageproc = Mutex::new(Processor::new(tmp_dir.path(), &config));
+    let mut context = RenderContext::new(
+        &ZOLA_TERA,
+        &config,
+        "https://example.com/blog/post/",
+        &permalinks_ctx,
+        InsertAnchor::None,
+    );
+    context.set_imageproc(&imageproc, "blog/post.md");
+
+    // A JPEG can't be animated, so only the first frame is kept instead of failing the build
+    let res = render_content("![Blinking](blink.gif)", &context).unwrap();
+    assert!(res.body.contains(".jpg 40w, "));
+    assert!(res.body.contains(".jpg 80w\""));
+    assert_eq!(imageproc.lock().unwrap().num_img_ops(), 2);
+}
+
+#[test]
+fn can_report_processed_images_without_alt_text() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let page_dir = tmp_dir.path().join("content").join("blog");
+    create_dir_all(&page_dir).unwrap();
+    RgbImage::from_pixel(100, 50, Rgb([255, 0, 0])).save(page_dir.join("red.png")).unwrap();
+
+    let permalinks_ctx = HashMap::new();
+    let mut config = Config::default();
+    config.image_processing.markdown_images = Some(MarkdownImages::default());
+    config.image_processing.strict_alt = true;
+    let imageproc = Mutex::new(Processor::new(tmp_dir.path(), &config));
+    let mut context = RenderContext::new(
+        &ZOLA_TERA,
+        &config,
+        "https://example.com/blog/post/",
+        &permalinks_ctx,
+        InsertAnchor::None,
+    );
+    context.set_imageproc(&imageproc, "blog/post.md");
+
+    render_content("![A red square](red.png)", &context).unwrap();
+    assert!(imageproc.lock().unwrap().check_missing_alts().is_ok());
+
+    // Still rendered, the build failing once everything is rendered
+    let res = render_content("![](red.png) ![ ](red.png)", &context).unwrap();
+    assert!(res.body.contains(r#" alt="" />"#));
+    let err = imageproc.lock().unwrap().check_missing_alts().unwrap_err().to_string();
+    assert!(err.contains("`blog/red.png` in blog/post.md"));
+
+    // Nothing is missing when the images get an alt text made from their filename
+    config.image_processing.alt_fallback = Some(AltFallback::default());
+    let mut context = RenderContext::new(
+        &ZOLA_TERA,
+        &config,
+        "https://example.com/blog/post/",
+        &permalinks_ctx,
+        InsertAnchor::None,
+    );
+    context.set_imageproc(&imageproc, "blog/post.md");
+    let res = render_content("![](red.png)", &context).unwrap();
+    assert!(res.body.contains(r#" alt="red" />"#));
+    assert!(imageproc.lock().unwrap().check_missing_alts().is_ok());
+}
@@ -63,6 +63,8 @@ pub struct Site {
     pub library: Arc<RwLock<Library>>,
     /// Whether to load draft pages
     include_drafts: bool,
+    /// Whether to remove the files of the processed images directory not produced by the build
+    clean_processed_images: bool,
     build_mode: BuildMode,
 }
 
@@ -84,8 +86,7 @@ impl Site {
 
         let content_path = path.join("content");
         let static_path = path.join("static");
-        let imageproc =
-            imageproc::Processor::new(content_path.clone(), &static_path, &config.base_url);
+        let imageproc = imageproc::Processor::new(path, &config);
         let output_path = path.join(config.output_dir.clone());
 
         let site = Site {
@@ -100,6 +101,7 @@ impl Site {
             taxonomies: Vec::new(),
             permalinks: HashMap::new(),
             include_drafts: false,
+            clean_processed_images: false,
             // We will allocate it properly later on
             library: Arc::new(RwLock::new(Library::new(0, 0, false))),
             build_mode: BuildMode::Disk,
@@ -153,6 +155,27 @@ impl Site {
         self.build()
     }
 
+    /// Makes the images without alt text fail the build, see `strict_alt`
+    pub fn enable_strict_alt(&mut self) {
+        let mut imageproc =
+            self.imageproc.lock().expect("Couldn't lock imageproc (enable_strict_alt)");
+        imageproc.enable_strict_alt();
+        self.config.image_processing.strict_alt = true;
+    }
+
+    /// Only plans the processed images instead of writing them, see `Processor::enable_dry_run`
+    pub fn enable_image_dry_run(&mut self) {
+        let mut imageproc =
+            self.imageproc.lock().expect("Couldn't lock imageproc (enable_image_dry_run)");
+        imageproc.enable_dry_run();
+    }
+
+    /// Removes the files of the processed images directory that the build didn't produce,
+    /// see `Processor::clean_processed_images`
+    pub fn enable_clean_processed_images(&mut self) {
+        self.clean_processed_images = true;
+    }
+
     pub fn set_base_url(&mut self, base_url: String) {
         let mut imageproc = self.imageproc.lock().expect("Couldn't lock imageproc (set_base_url)");
         imageproc.set_base_url(&base_url);
@@ -352,6 +375,7 @@ impl Site {
         let permalinks = &self.permalinks;
         let tera = &self.tera;
         let config = &self.config;
+        let imageproc = Some(&*self.imageproc);
 
         // This is needed in the first place because of silly borrow checker
         let mut pages_insert_anchors = HashMap::new();
@@ -370,7 +394,7 @@ impl Site {
             .par_iter_mut()
             .map(|page| {
                 let insert_anchor = pages_insert_anchors[&page.file.path];
-                page.render_markdown(permalinks, tera, config, insert_anchor)
+                page.render_markdown(permalinks, tera, config, insert_anchor, imageproc)
             })
             .collect::<Result<()>>()?;
 
@@ -379,7 +403,7 @@ impl Site {
             .values_mut()
             .collect::<Vec<_>>()
             .par_iter_mut()
-            .map(|section| section.render_markdown(permalinks, tera, config))
+            .map(|section| section.render_markdown(permalinks, tera, config, imageproc))
             .collect::<Result<()>>()?;
 
         Ok(())
@@ -392,7 +416,13 @@ impl Site {
         if render_md {
             let insert_anchor =
                 self.find_parent_section_insert_anchor(&page.file.parent, &page.lang);
-            page.render_markdown(&self.permalinks, &self.tera, &self.config, insert_anchor)?;
+            page.render_markdown(
+                &self.permalinks,
+                &self.tera,
+                &self.config,
+                insert_anchor,
+                Some(&*self.imageproc),
+            )?;
         }
 
         let mut library = self.library.write().expect("Get lock for add_page");
@@ -419,7 +449,12 @@ impl Site {
     pub fn add_section(&mut self, mut section: Section, render_md: bool) -> Result<()> {
         self.permalinks.insert(section.file.relative.clone(), section.permalink.clone());
         if render_md {
-            section.render_markdown(&self.permalinks, &self.tera, &self.config)?;
+            section.render_markdown(
+                &self.permalinks,
+                &self.tera,
+                &self.config,
+                Some(&*self.imageproc),
+            )?;
         }
         let mut library = self.library.write().expect("Get lock for add_section");
         library.remove_section(&section.file.path);
@@ -505,6 +540,21 @@ impl Site {
             copy_directory(&self.static_path, &self.output_path, self.config.hard_link_static)?;
         }
 
+        // The processed images are served from their `url_prefix` if it's not their directory
+        let settings = &self.config.image_processing;
+        let processed_path = self.output_path.join(settings.processed_dir.trim_matches('/'));
+        if settings.url_prefix() != settings.processed_dir.trim_matches('/')
+            && processed_path.exists()
+        {
+            copy_directory(
+                &processed_path,
+                &self.output_path.join(settings.url_prefix()),
+                self.config.hard_link_static,
+            )?;
+            remove_dir_all(&processed_path)
+                .map_err(|e| Error::chain("Couldn't move the processed images", e))?;
+        }
+
         Ok(())
     }
 
@@ -513,11 +563,41 @@ impl Site {
         imageproc.num_img_ops()
     }
 
+    /// What processing the images did, once the site is built
+    pub fn image_processing_stats(&self) -> imageproc::ProcessingStats {
+        let imageproc =
+            self.imageproc.lock().expect("Couldn't lock imageproc (image_processing_stats)");
+        imageproc.processing_stats()
+    }
+
+    /// The processed images the build would write, with a dry run
+    pub fn planned_images(&self) -> Result<Vec<imageproc::PlannedImage>> {
+        let imageproc = self.imageproc.lock().expect("Couldn't lock imageproc (planned_images)");
+        imageproc.planned_images()
+    }
+
+    /// Compares the URLs of the images the build wrote to the ones expected, once it's built
+    pub fn diff_processed_images(&self, expected: &[String]) -> imageproc::ProducedUrlsDiff {
+        let imageproc =
+            self.imageproc.lock().expect("Couldn't lock imageproc (diff_processed_images)");
+        imageproc.diff_produced_urls(expected)
+    }
+
     pub fn process_images(&self) -> Result<()> {
         let mut imageproc =
             self.imageproc.lock().expect("Couldn't lock imageproc (process_images)");
+        // All the pages and templates have been rendered by now
+        imageproc.check_missing_alts()?;
         imageproc.prune()?;
-        imageproc.do_process()
+        imageproc.do_process()?;
+        // Before the static directory is copied so the removed files don't end up in the output
+        if self.clean_processed_images {
+            let removed = imageproc.clean_processed_images()?;
+            if removed > 0 {
+                println!("Removed {} stale processed images", removed);
+            }
+        }
+        Ok(())
     }
 
     /// Deletes the `public` directory if it exists
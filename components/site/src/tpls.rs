@@ -62,9 +62,61 @@ pub fn register_early_global_fns(site: &mut Site) {
     );
     site.tera
         .register_function("resize_image", global_fns::ResizeImage::new(site.imageproc.clone()));
+    site.tera.register_function(
+        "resize_image_srcset",
+        global_fns::ResizeImageSrcset::new(site.imageproc.clone()),
+    );
+    site.tera.register_function(
+        "resize_gallery",
+        global_fns::ResizeGallery::new(site.content_path.clone(), site.imageproc.clone()),
+    );
+    site.tera
+        .register_function("get_images", global_fns::GetImages::new(site.content_path.clone()));
+    site.tera
+        .register_function("image_set_css", global_fns::ImageSetCss::new(site.imageproc.clone()));
+    site.tera.register_function(
+        "get_image_preload",
+        global_fns::GetImagePreload::new(site.imageproc.clone()),
+    );
+    site.tera.register_function(
+        "count_image_variants",
+        global_fns::CountImageVariants::new(site.imageproc.clone()),
+    );
     site.tera.register_function(
         "get_image_metadata",
-        global_fns::GetImageMeta::new(site.content_path.clone()),
+        global_fns::GetImageMeta::new(site.content_path.clone(), &site.config),
+    );
+    site.tera.register_function(
+        "get_mime_type",
+        global_fns::GetMimeType::new(site.content_path.clone()),
+    );
+    site.tera.register_function(
+        "get_image_sort_key",
+        global_fns::GetImageSortKey::new(site.content_path.clone()),
+    );
+    site.tera.register_function(
+        "get_image_placeholder",
+        global_fns::GetImagePlaceholder::new(site.content_path.clone(), &site.config),
+    );
+    site.tera.register_function(
+        "check_social_card",
+        global_fns::CheckSocialCard::new(site.content_path.clone()),
+    );
+    site.tera.register_function(
+        "image_gradient",
+        global_fns::ImageGradient::new(site.content_path.clone(), &site.config),
+    );
+    site.tera.register_function(
+        "get_alt",
+        global_fns::GetAlt::new(&site.base_path, &site.config, site.imageproc.clone()),
+    );
+    site.tera.register_function(
+        "get_image_sidecar",
+        global_fns::GetImageSidecar::new(site.content_path.clone(), &site.config),
+    );
+    site.tera.register_function(
+        "image_ocr",
+        global_fns::ImageOcr::new(site.content_path.clone(), &site.config),
     );
     site.tera.register_function("load_data", global_fns::LoadData::new(site.base_path.clone()));
     site.tera.register_function("trans", global_fns::Trans::new(site.config.clone()));
@@ -1,16 +1,17 @@
-use std::collections::HashMap;
-use std::ffi::OsStr;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
 use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
 use std::{fs, io, result};
 
+use rayon::prelude::*;
 use sha2::{Digest, Sha256, Sha384, Sha512};
-use svg_metadata as svg;
-use tera::{from_value, to_value, Error, Function as TeraFn, Result, Value};
+use tera::{from_value, to_value, Error, Function as TeraFn, Map, Result, Value};
 
-use config::Config;
-use image::GenericImageView;
+use config::{AltFallback, Config, ImageProcessing};
 use library::{Library, Taxonomy};
+use utils::fs::{get_file_time, read_file};
 use utils::site::resolve_internal_link;
 use utils::slugs::{slugify_paths, SlugifyStrategy};
 
@@ -18,6 +19,7 @@ use utils::slugs::{slugify_paths, SlugifyStrategy};
 mod macros;
 
 mod load_data;
+mod remote_images;
 
 pub use self::load_data::LoadData;
 
@@ -107,6 +109,13 @@ fn compute_file_sha512(mut file: fs::File) -> result::Result<String, io::Error>
     Ok(format!("{:x}", hasher.finalize()))
 }
 
+/// The subresource integrity of a file, eg `sha384-<base64 digest>`
+fn compute_file_integrity(mut file: fs::File) -> result::Result<String, io::Error> {
+    let mut hasher = Sha384::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(format!("sha384-{}", base64::encode(hasher.finalize())))
+}
+
 fn file_not_found_err(search_paths: &[PathBuf], url: &str) -> Result<Value> {
     Err(format!(
         "file `{}` not found; searched in{}",
@@ -212,641 +221,5523 @@ impl TeraFn for GetFileHash {
 #[derive(Debug)]
 pub struct ResizeImage {
     imageproc: Arc<Mutex<imageproc::Processor>>,
+    /// The responses already given, keyed by their arguments, so repeated identical calls,
+    /// like a logo on every page, don't wait for the processor lock.
+    /// The processor keeps every operation it's given so skipping the duplicates is safe.
+    /// The page being rendered is part of the key so the processor counts its variants
+    responses: RwLock<HashMap<String, Value>>,
+    /// The sources already decoded without error with `skip_corrupt_images`, so each is only
+    /// decoded once per build
+    decodable: RwLock<HashSet<String>>,
 }
 impl ResizeImage {
     pub fn new(imageproc: Arc<Mutex<imageproc::Processor>>) -> Self {
-        Self { imageproc }
+        Self {
+            imageproc,
+            responses: RwLock::new(HashMap::new()),
+            decodable: RwLock::new(HashSet::new()),
+        }
+    }
+
+    /// The arguments as a string, in the same order whatever order they were given in,
+    /// along with the page being rendered
+    fn responses_key(args: &HashMap<String, Value>) -> String {
+        let sorted: BTreeMap<_, _> = args.iter().collect();
+        let args = serde_json::to_string(&sorted).expect("Tera values serialize to JSON");
+        format!("{}{}", imageproc::current_page().unwrap_or_default(), args)
     }
 }
 
 static DEFAULT_OP: &str = "fill";
+
+/// Errors naming the dimensions `op` needs when `resize_image` is called without them, instead
+/// of letting `ImageOp::from_args` fail further down
+fn check_resize_dimensions(op: &str, has_width: bool, has_height: bool) -> Result<()> {
+    let needed = match op {
+        "fit_width" if !has_width => "a `width`",
+        "fit_height" if !has_height => "a `height`",
+        "scale" | "fit" | "fill" if !has_width && !has_height => {
+            return Err(format!(
+                "`resize_image`: op=\"{}\" needs a `width` and a `height`, none were given. Use op=\"convert\" to only change the format of the image",
+                op
+            )
+            .into())
+        }
+        "scale" | "fit" | "fill" if !has_width => "a `width` along with the `height`",
+        "scale" | "fit" | "fill" if !has_height => "a `height` along with the `width`",
+        _ => return Ok(()),
+    };
+    Err(format!("`resize_image`: op=\"{}\" needs {}", op, needed).into())
+}
 static DEFAULT_FMT: &str = "auto";
-const DEFAULT_Q: u8 = 75;
+/// The tiny blurred raster in an SVG of `svg_blur_placeholder`
+const SVG_BLUR_PLACEHOLDER: &str = "svg-blur";
 
 impl TeraFn for ResizeImage {
     fn call(&self, args: &HashMap<String, Value>) -> Result<Value> {
+        let key = Self::responses_key(args);
+        if let Some(response) = self.responses.read().unwrap().get(&key) {
+            return Ok(response.clone());
+        }
+        // Errors aren't kept so every call reports them
+        let response = self.resize(args)?;
+        self.responses.write().unwrap().insert(key, response.clone());
+        Ok(response)
+    }
+}
+
+impl ResizeImage {
+    fn resize(&self, args: &HashMap<String, Value>) -> Result<Value> {
         let path = required_arg!(
             String,
             args.get("path"),
             "`resize_image` requires a `path` argument with a string value"
         );
         let width = optional_arg!(
-            u32,
+            imageproc::Dimension,
             args.get("width"),
-            "`resize_image`: `width` must be a non-negative integer"
+            "`resize_image`: `width` must be a non-negative integer or a named size"
         );
         let height = optional_arg!(
-            u32,
+            imageproc::Dimension,
             args.get("height"),
-            "`resize_image`: `height` must be a non-negative integer"
+            "`resize_image`: `height` must be a non-negative integer or a named size"
         );
         let op = optional_arg!(String, args.get("op"), "`resize_image`: `op` must be a string")
             .unwrap_or_else(|| DEFAULT_OP.to_string());
+        // Before anything else so a typo isn't reported as some other mistake
+        if !imageproc::RESIZE_OPS.contains(&op.as_str()) {
+            return Err(format!(
+                "`resize_image`: Invalid op: \"{}\", it needs to be one of: {}",
+                op,
+                imageproc::RESIZE_OPS.join(", ")
+            )
+            .into());
+        }
+        let art_direction = optional_arg!(
+            String,
+            args.get("art_direction"),
+            "`resize_image`: `art_direction` must be a string"
+        );
+        let crop = optional_arg!(
+            Vec<f64>,
+            args.get("crop"),
+            "`resize_image`: `crop` must be an array of 4 numbers: x, y, width and height"
+        );
+        let crop_unit = optional_arg!(
+            String,
+            args.get("crop_unit"),
+            "`resize_image`: `crop_unit` must be a string"
+        )
+        .unwrap_or_else(|| "px".to_string());
+        let corner_radius = optional_arg!(
+            u32,
+            args.get("corner_radius"),
+            "`resize_image`: `corner_radius` must be a non-negative integer"
+        );
+        let circle = optional_arg!(
+            bool,
+            args.get("circle"),
+            "`resize_image`: `circle` must be a boolean (true or false)"
+        )
+        .unwrap_or(false);
+        let focus =
+            optional_arg!(String, args.get("focus"), "`resize_image`: `focus` must be a string");
+        let anchor =
+            optional_arg!(String, args.get("anchor"), "`resize_image`: `anchor` must be a string");
+        let preview = optional_arg!(
+            bool,
+            args.get("preview"),
+            "`resize_image`: `preview` must be a boolean (true or false)"
+        );
+        let compute_weight = optional_arg!(
+            bool,
+            args.get("compute_weight"),
+            "`resize_image`: `compute_weight` must be a boolean (true or false)"
+        )
+        .unwrap_or(false);
+        let integrity = optional_arg!(
+            bool,
+            args.get("integrity"),
+            "`resize_image`: `integrity` must be a boolean (true or false)"
+        )
+        .unwrap_or(false);
+        let placeholder = optional_arg!(
+            String,
+            args.get("placeholder"),
+            "`resize_image`: `placeholder` must be a string"
+        );
+        let respect_exif = optional_arg!(
+            bool,
+            args.get("respect_exif"),
+            "`resize_image`: `respect_exif` must be a boolean (true or false)"
+        )
+        .unwrap_or(true);
+        let alpha_quality = optional_arg!(
+            u8,
+            args.get("alpha_quality"),
+            "`resize_image`: `alpha_quality` must be a number"
+        );
+        if let Some(q) = alpha_quality {
+            if q == 0 || q > 100 {
+                return Err("`resize_image`: `alpha_quality` must be in range 1-100".into());
+            }
+        }
+        let strip_metadata = optional_arg!(
+            bool,
+            args.get("strip_metadata"),
+            "`resize_image`: `strip_metadata` must be a boolean (true or false)"
+        );
+        let percent =
+            optional_arg!(u32, args.get("percent"), "`resize_image`: `percent` must be a number");
+        if percent == Some(0) {
+            return Err("`resize_image`: `percent` must be greater than 0".into());
+        }
+        let output_dir = optional_arg!(
+            String,
+            args.get("output_dir"),
+            "`resize_image`: `output_dir` must be a string"
+        );
+        let first_frame_only = optional_arg!(
+            bool,
+            args.get("first_frame_only"),
+            "`resize_image`: `first_frame_only` must be a boolean (true or false)"
+        )
+        .unwrap_or(false);
+        let color_profile = optional_arg!(
+            String,
+            args.get("color_profile"),
+            "`resize_image`: `color_profile` must be a string"
+        );
+        // `percent` replaces the dimensions of `scale` and `art_direction` and `crop` have theirs
+        if art_direction.is_none() && crop.is_none() && percent.is_none() {
+            check_resize_dimensions(&op, width.is_some(), height.is_some())?;
+        }
+        match placeholder.as_deref() {
+            None | Some(SVG_BLUR_PLACEHOLDER) => (),
+            Some(other) => {
+                return Err(format!(
+                    "`resize_image`: Invalid placeholder: {}, the only one available is `{}`",
+                    other, SVG_BLUR_PLACEHOLDER
+                )
+                .into())
+            }
+        }
 
         let format =
             optional_arg!(String, args.get("format"), "`resize_image`: `format` must be a string")
                 .unwrap_or_else(|| DEFAULT_FMT.to_string());
 
         let quality =
-            optional_arg!(u8, args.get("quality"), "`resize_image`: `quality` must be a number")
-                .unwrap_or(DEFAULT_Q);
-        if quality == 0 || quality > 100 {
-            return Err("`resize_image`: `quality` must be in range 1-100".to_string().into());
+            optional_arg!(u8, args.get("quality"), "`resize_image`: `quality` must be a number");
+        if let Some(q) = quality {
+            if q == 0 || q > 100 {
+                return Err("`resize_image`: `quality` must be in range 1-100".to_string().into());
+            }
         }
 
+        // Downloaded before locking the processor so other renders aren't waiting on it
+        let path = if remote_images::is_remote(&path) {
+            let (content_path, settings) = {
+                let imageproc = self.imageproc.lock().unwrap();
+                (
+                    imageproc.content_path().to_path_buf(),
+                    remote_images::FetchSettings::new(imageproc.settings()),
+                )
+            };
+            remote_images::fetch_remote_image("resize_image", &content_path, &path, &settings)?
+        } else {
+            path
+        };
+
         let mut imageproc = self.imageproc.lock().unwrap();
-        if !imageproc.source_exists(&path) {
-            return Err(format!("`resize_image`: Cannot find path: {}", path).into());
+        let path = match imageproc.find_source(&path) {
+            Some(source) => source,
+            None => return Err(format!("`resize_image`: Cannot find path: {}", path).into()),
+        };
+        let quality = quality.unwrap_or_else(|| imageproc.default_quality(&path));
+        let crop =
+            match crop {
+                Some(rect) if rect.len() == 4 => Some(imageproc::CropArea {
+                    unit: imageproc::CropUnit::from_args(&crop_unit)
+                        .map_err(|e| format!("`resize_image`: {}", e))?,
+                    rect: [rect[0], rect[1], rect[2], rect[3]],
+                }),
+                Some(_) => return Err(
+                    "`resize_image`: `crop` must be an array of 4 numbers: x, y, width and height"
+                        .into(),
+                ),
+                None => None,
+            };
+        let source_path = imageproc.source_path(&path);
+        // Checked first as too big images are refused even with `skip_corrupt_images`
+        imageproc::check_pixel_limit(&source_path, imageproc.settings().max_pixels)
+            .map_err(|e| Error::chain(format!("`resize_image`: {}", path), e))?;
+        // Decoded to know whether it's corrupt, eg a truncated JPEG, which would otherwise only
+        // fail once processed. The processor lock is released meanwhile so other pages can use it
+        if imageproc.settings().skip_corrupt_images
+            && !self.decodable.read().unwrap().contains(&path)
+        {
+            drop(imageproc);
+            if let Err(e) = imageproc::open_image(&source_path) {
+                println!("Warning: `resize_image`: skipping the corrupt image {}: {}", path, e);
+                return Ok(Value::Null);
+            }
+            self.decodable.write().unwrap().insert(path.clone());
+            imageproc = self.imageproc.lock().unwrap();
+        }
+
+        if let Some(rule) = art_direction {
+            if width.is_some()
+                || height.is_some()
+                || args.contains_key("op")
+                || crop.is_some()
+                || percent.is_some()
+            {
+                return Err(
+                    "`resize_image`: `art_direction` cannot be combined with `width`, `height`, `op`, `crop` or `percent`"
+                        .into(),
+                );
+            }
+            if placeholder.is_some() {
+                return Err(
+                    "`resize_image`: `placeholder` cannot be combined with `art_direction`, use `preview` instead"
+                        .into(),
+                );
+            }
+            let options = ResponsiveSetOptions {
+                format,
+                quality: Some(quality),
+                corner_radius,
+                circle,
+                focus,
+                anchor,
+                compute_weight,
+                integrity,
+                preview,
+                respect_exif,
+                alpha_quality,
+                strip_metadata,
+                output_dir,
+                first_frame_only,
+                color_profile,
+            };
+            drop(imageproc);
+            let picture = responsive_set(&self.imageproc, "resize_image", &path, &rule, &options)?;
+            return Ok(Value::Object(picture));
+        }
+        // `preview = false` is accepted everywhere as single images never have a preview
+        if preview == Some(true) {
+            return Err("`resize_image`: `preview` can only be used with `art_direction`".into());
+        }
+        if integrity {
+            return Err("`resize_image`: `integrity` can only be used with `art_direction`".into());
         }
 
-        let imageop = imageproc::ImageOp::from_args(path, &op, width, height, &format, quality)
-            .map_err(|e| format!("`resize_image`: {}", e))?;
-        let url = imageproc.insert(imageop);
+        let resize_args = imageproc::ResizeArgs {
+            op,
+            width,
+            height,
+            format,
+            quality,
+            crop,
+            corner_radius,
+            circle,
+            focus,
+            anchor,
+            respect_exif,
+            alpha_quality,
+            strip_metadata,
+            percent,
+            output_dir,
+            first_frame_only,
+            color_profile,
+        };
+        let fallback = imageproc.settings().fallback_to_original;
+        let response = match imageproc::ImageOp::from_args(
+            path.clone(),
+            &source_path,
+            &resize_args,
+            imageproc.settings(),
+        ) {
+            Ok(imageop) => {
+                // Processed right away so its `file_size` is known, without the processor locked
+                // meanwhile so other pages can use it
+                let inserted = if compute_weight {
+                    match imageproc.prepare_now(imageop) {
+                        Ok(now) => {
+                            drop(imageproc);
+                            let performed = now.perform();
+                            imageproc = self.imageproc.lock().unwrap();
+                            performed
+                                .and_then(|_| imageproc.finish_now(&now))
+                                .map(|(response, _)| response)
+                        }
+                        Err(e) => Err(e),
+                    }
+                } else {
+                    imageproc.insert(imageop)
+                };
+                match inserted {
+                    Ok(response) => response,
+                    Err(e) if fallback => imageproc
+                        .fallback_to_original(&path, &e.to_string())
+                        .map_err(|e| Error::chain(format!("`resize_image`: {}", path), e))?,
+                    Err(e) => return Err(Error::chain(format!("`resize_image`: {}", path), e)),
+                }
+            }
+            Err(e) if fallback => imageproc
+                .fallback_to_original(&path, &e.to_string())
+                .map_err(|e| Error::chain(format!("`resize_image`: {}", path), e))?,
+            Err(e) => return Err(format!("`resize_image`: {}", e).into()),
+        };
+        drop(imageproc);
 
-        to_value(url).map_err(|err| err.into())
+        let mut value = to_value(&response).map_err(Error::from)?;
+        if placeholder.is_some() {
+            let svg = imageproc::svg_blur_placeholder(
+                &source_path,
+                (response.display_width, response.display_height),
+            )
+            .map_err(|e| Error::chain(format!("`resize_image`: {}", path), e))?;
+            if let Value::Object(ref mut map) = value {
+                map.insert("placeholder".to_string(), to_value(svg).unwrap());
+            }
+        }
+        Ok(value)
     }
 }
 
+/// `resize_image` for each of a list of widths, along with the `srcset` listing them
 #[derive(Debug)]
-pub struct GetImageMeta {
-    content_path: PathBuf,
+pub struct ResizeImageSrcset {
+    resize_image: ResizeImage,
 }
-
-impl GetImageMeta {
-    pub fn new(content_path: PathBuf) -> Self {
-        Self { content_path }
+impl ResizeImageSrcset {
+    pub fn new(imageproc: Arc<Mutex<imageproc::Processor>>) -> Self {
+        Self { resize_image: ResizeImage::new(imageproc) }
     }
 }
 
-impl TeraFn for GetImageMeta {
+impl TeraFn for ResizeImageSrcset {
     fn call(&self, args: &HashMap<String, Value>) -> Result<Value> {
-        let path = required_arg!(
-            String,
-            args.get("path"),
-            "`get_image_metadata` requires a `path` argument with a string value"
+        let widths = required_arg!(
+            Vec<imageproc::Dimension>,
+            args.get("widths"),
+            "`resize_image_srcset` requires a `widths` argument with an array of non-negative integers or named sizes"
         );
-        let src_path = self.content_path.join(&path);
-        if !src_path.exists() {
-            return Err(format!("`get_image_metadata`: Cannot find path: {}", path).into());
+        if widths.is_empty() {
+            return Err("`resize_image_srcset`: `widths` needs at least one width".into());
+        }
+        for arg in &["width", "percent", "art_direction"] {
+            if args.contains_key(*arg) {
+                return Err(format!(
+                    "`resize_image_srcset`: `{}` cannot be used, the widths are given by `widths`",
+                    arg
+                )
+                .into());
+            }
         }
-        let (height, width) = image_dimensions(&src_path)?;
-        let mut map = tera::Map::new();
-        map.insert(String::from("height"), Value::Number(tera::Number::from(height)));
-        map.insert(String::from("width"), Value::Number(tera::Number::from(width)));
-        Ok(Value::Object(map))
-    }
-}
 
-// Try to read the image dimensions for a given image
-fn image_dimensions(path: &PathBuf) -> Result<(u32, u32)> {
-    if let Some("svg") = path.extension().and_then(OsStr::to_str) {
-        let img = svg::Metadata::parse_file(&path)
-            .map_err(|e| Error::chain(format!("Failed to process SVG: {}", path.display()), e))?;
-        match (img.height(), img.width(), img.view_box()) {
-            (Some(h), Some(w), _) => Ok((h as u32, w as u32)),
-            (_, _, Some(view_box)) => Ok((view_box.height as u32, view_box.width as u32)),
-            _ => Err("Invalid dimensions: SVG width/height and viewbox not set.".into()),
+        let mut resize_args = args.clone();
+        resize_args.remove("widths");
+        if !args.contains_key("op") {
+            let op = if args.contains_key("height") { DEFAULT_OP } else { "fit_width" };
+            resize_args.insert("op".to_string(), to_value(op).unwrap());
         }
-    } else {
-        let img = image::open(&path)
-            .map_err(|e| Error::chain(format!("Failed to process image: {}", path.display()), e))?;
-        Ok((img.height(), img.width()))
+        let mut images = Vec::with_capacity(widths.len());
+        let mut candidates = Vec::with_capacity(widths.len());
+        for width in widths {
+            resize_args.insert("width".to_string(), to_value(&width).unwrap());
+            let image = self.resize_image.call(&resize_args)?;
+            // Skipped as corrupt
+            if image.is_null() {
+                return Ok(Value::Null);
+            }
+            // Widths can end up making the same file, eg when `forbid_upscale` clamps them
+            let candidate =
+                format!("{} {}w", image["url"].as_str().unwrap(), image["intrinsic_width"]);
+            if !candidates.contains(&candidate) {
+                candidates.push(candidate);
+                images.push(image);
+            }
+        }
+
+        let mut srcset = Map::new();
+        srcset.insert("srcset".to_string(), to_value(candidates.join(", ")).unwrap());
+        srcset.insert("images".to_string(), Value::Array(images));
+        Ok(Value::Object(srcset))
     }
 }
 
 #[derive(Debug)]
-pub struct GetTaxonomyUrl {
-    taxonomies: HashMap<String, HashMap<String, String>>,
-    default_lang: String,
-    slugify: SlugifyStrategy,
+pub struct ResizeGallery {
+    content_path: PathBuf,
+    imageproc: Arc<Mutex<imageproc::Processor>>,
 }
-
-impl GetTaxonomyUrl {
-    pub fn new(default_lang: &str, all_taxonomies: &[Taxonomy], slugify: SlugifyStrategy) -> Self {
-        let mut taxonomies = HashMap::new();
-        for taxo in all_taxonomies {
-            let mut items = HashMap::new();
-            for item in &taxo.items {
-                items.insert(slugify_paths(&item.name.clone(), slugify), item.permalink.clone());
-            }
-            taxonomies.insert(format!("{}-{}", taxo.kind.name, taxo.kind.lang), items);
-        }
-        Self { taxonomies, default_lang: default_lang.to_string(), slugify }
+impl ResizeGallery {
+    pub fn new(content_path: PathBuf, imageproc: Arc<Mutex<imageproc::Processor>>) -> Self {
+        Self { content_path, imageproc }
     }
 }
-impl TeraFn for GetTaxonomyUrl {
+
+impl TeraFn for ResizeGallery {
     fn call(&self, args: &HashMap<String, Value>) -> Result<Value> {
-        let kind = required_arg!(
+        let pattern = required_arg!(
             String,
-            args.get("kind"),
-            "`get_taxonomy_url` requires a `kind` argument with a string value"
+            args.get("glob"),
+            "`resize_gallery` requires a `glob` argument with a string value"
         );
-        let name = required_arg!(
+        let preset = required_arg!(
             String,
-            args.get("name"),
-            "`get_taxonomy_url` requires a `name` argument with a string value"
+            args.get("preset"),
+            "`resize_gallery` requires a `preset` argument with the name of an `art_direction` rule"
         );
-        let lang =
-            optional_arg!(String, args.get("lang"), "`get_taxonomy`: `lang` must be a string")
-                .unwrap_or_else(|| self.default_lang.clone());
-
-        let container = match self.taxonomies.get(&format!("{}-{}", kind, lang)) {
-            Some(c) => c,
-            None => {
-                return Err(format!(
-                    "`get_taxonomy_url` received an unknown taxonomy as kind: {}",
-                    kind
-                )
-                .into());
+        let format = optional_arg!(
+            String,
+            args.get("format"),
+            "`resize_gallery`: `format` must be a string"
+        )
+        .unwrap_or_else(|| DEFAULT_FMT.to_string());
+        let quality =
+            optional_arg!(u8, args.get("quality"), "`resize_gallery`: `quality` must be a number");
+        if let Some(q) = quality {
+            if q == 0 || q > 100 {
+                return Err("`resize_gallery`: `quality` must be in range 1-100"
+                    .to_string()
+                    .into());
             }
-        };
+        }
+        let focus =
+            optional_arg!(String, args.get("focus"), "`resize_gallery`: `focus` must be a string");
+        let anchor = optional_arg!(
+            String,
+            args.get("anchor"),
+            "`resize_gallery`: `anchor` must be a string"
+        );
+        let preview = optional_arg!(
+            bool,
+            args.get("preview"),
+            "`resize_gallery`: `preview` must be a boolean (true or false)"
+        );
 
-        if let Some(permalink) = container.get(&slugify_paths(&name, self.slugify)) {
-            return Ok(to_value(permalink).unwrap());
+        let paths = glob_content_files("resize_gallery", &self.content_path, &pattern)?;
+
+        let options = ResponsiveSetOptions {
+            format,
+            quality,
+            corner_radius: None,
+            circle: false,
+            focus,
+            anchor,
+            compute_weight: false,
+            integrity: false,
+            preview,
+            respect_exif: true,
+            alpha_quality: None,
+            strip_metadata: None,
+            output_dir: None,
+            first_frame_only: false,
+            color_profile: None,
+        };
+        let mut images = Vec::with_capacity(paths.len());
+        let mut skipped = 0;
+        for path in paths {
+            let skip = {
+                let imageproc = self.imageproc.lock().unwrap();
+                !imageproc.settings().is_allowed_source(&path)
+                    || imageproc::image_dimensions(imageproc.source_path(&path)).is_err()
+            };
+            if skip {
+                skipped += 1;
+                continue;
+            }
+            let mut picture =
+                responsive_set(&self.imageproc, "resize_gallery", &path, &preset, &options)?;
+            picture.insert("path".to_string(), to_value(&path).unwrap());
+            images.push(Value::Object(picture));
         }
+        if skipped > 0 {
+            println!(
+                "Warning: `resize_gallery`: skipped {} file(s) matching `{}` that are not images it can process",
+                skipped, pattern
+            );
+        }
+        Ok(Value::Array(images))
+    }
+}
 
-        Err(format!("`get_taxonomy_url`: couldn't find `{}` in `{}` taxonomy", name, kind).into())
+/// The files matching `pattern`, relative to and inside of the content directory, sorted.
+/// `fn_name` is the function errors are about
+fn glob_content_files(fn_name: &str, content_path: &Path, pattern: &str) -> Result<Vec<String>> {
+    let relative = Path::new(pattern);
+    if relative.is_absolute() || relative.components().any(|c| c == Component::ParentDir) {
+        return Err(format!(
+            "`{}`: `glob` needs to be relative to the content directory, got `{}`",
+            fn_name, pattern
+        )
+        .into());
     }
+    let full_pattern = format!(
+        "{}/{}",
+        glob::Pattern::escape(&content_path.to_string_lossy().replace("\\", "/")),
+        pattern
+    );
+    let entries = glob::glob(&full_pattern)
+        .map_err(|e| format!("`{}`: invalid `glob` `{}`: {}", fn_name, pattern, e))?;
+    let mut paths: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|path| path.is_file())
+        .filter_map(|path| {
+            let relative = path.strip_prefix(content_path).ok()?;
+            Some(relative.to_string_lossy().replace("\\", "/"))
+        })
+        .collect();
+    // The order of the file system isn't the same everywhere
+    paths.sort();
+    Ok(paths)
 }
 
 #[derive(Debug)]
-pub struct GetPage {
-    base_path: PathBuf,
-    library: Arc<RwLock<Library>>,
+pub struct GetImages {
+    content_path: PathBuf,
 }
-impl GetPage {
-    pub fn new(base_path: PathBuf, library: Arc<RwLock<Library>>) -> Self {
-        Self { base_path: base_path.join("content"), library }
+impl GetImages {
+    pub fn new(content_path: PathBuf) -> Self {
+        Self { content_path }
     }
 }
-impl TeraFn for GetPage {
+
+impl TeraFn for GetImages {
     fn call(&self, args: &HashMap<String, Value>) -> Result<Value> {
-        let path = required_arg!(
+        let pattern = required_arg!(
             String,
-            args.get("path"),
-            "`get_page` requires a `path` argument with a string value"
+            args.get("glob"),
+            "`get_images` requires a `glob` argument with a string value"
         );
-        let full_path = self.base_path.join(&path);
-        let library = self.library.read().unwrap();
-        match library.get_page(&full_path) {
-            Some(p) => Ok(to_value(p.to_serialized(&library)).unwrap()),
-            None => Err(format!("Page `{}` not found.", path).into()),
+        let paths = glob_content_files("get_images", &self.content_path, &pattern)?;
+        Ok(to_value(paths).unwrap())
+    }
+}
+
+/// The options of the variants of a responsive set, ie of `resize_image` with `art_direction`
+struct ResponsiveSetOptions {
+    format: String,
+    /// The default quality of the source when not given
+    quality: Option<u8>,
+    corner_radius: Option<u32>,
+    circle: bool,
+    focus: Option<String>,
+    anchor: Option<String>,
+    compute_weight: bool,
+    integrity: bool,
+    preview: Option<bool>,
+    respect_exif: bool,
+    alpha_quality: Option<u8>,
+    strip_metadata: Option<bool>,
+    output_dir: Option<String>,
+    first_frame_only: bool,
+    color_profile: Option<String>,
+}
+
+/// Processes the image at `path` into the variants of the art direction `rule`, returning
+/// the `sources` and `fallback` of a `<picture>`. `fn_name` is the function errors are about.
+/// The processor is only locked for the variants, not while the preview is made
+fn responsive_set(
+    processor: &Mutex<imageproc::Processor>,
+    fn_name: &str,
+    path: &str,
+    rule: &str,
+    options: &ResponsiveSetOptions,
+) -> Result<Map<String, Value>> {
+    let mut imageproc = processor.lock().unwrap();
+    let source_path = imageproc.source_path(path);
+    let quality = options.quality.unwrap_or_else(|| imageproc.default_quality(path));
+    let variants = imageproc
+        .settings()
+        .art_direction_rule(rule)
+        .map_err(|e| format!("`{}`: {}", fn_name, e))?
+        .to_vec();
+
+    let mut sources = Vec::new();
+    let mut fallback = Map::new();
+    let mut total_bytes = 0;
+    for variant in variants {
+        let (w, h) = variant.dimensions().map_err(|e| format!("`{}`: {}", fn_name, e))?;
+        let resize_args = imageproc::ResizeArgs {
+            op: "fill".to_string(),
+            width: Some(imageproc::Dimension::Pixels(w)),
+            height: Some(imageproc::Dimension::Pixels(h)),
+            format: options.format.clone(),
+            quality,
+            crop: None,
+            corner_radius: options.corner_radius,
+            circle: options.circle,
+            focus: options.focus.clone(),
+            anchor: options.anchor.clone(),
+            respect_exif: options.respect_exif,
+            alpha_quality: options.alpha_quality,
+            strip_metadata: options.strip_metadata,
+            percent: None,
+            output_dir: options.output_dir.clone(),
+            first_frame_only: options.first_frame_only,
+            color_profile: options.color_profile.clone(),
+        };
+        let imageop = imageproc::ImageOp::from_args(
+            path.to_string(),
+            &source_path,
+            &resize_args,
+            imageproc.settings(),
+        )
+        .map_err(|e| format!("`{}`: {}", fn_name, e))?;
+
+        // The weight and the digest are only known once the variant is written, which is done
+        // without the processor locked
+        let (response, target) = if options.compute_weight || options.integrity {
+            let now = imageproc
+                .prepare_now(imageop)
+                .map_err(|e| Error::chain(format!("`{}`: {}", fn_name, path), e))?;
+            drop(imageproc);
+            let performed = now.perform();
+            imageproc = processor.lock().unwrap();
+            let (response, target) = performed
+                .and_then(|_| imageproc.finish_now(&now))
+                .map_err(|e| Error::chain(format!("`{}`: {}", fn_name, path), e))?;
+            (response, Some(target))
+        } else {
+            let response = imageproc
+                .insert(imageop)
+                .map_err(|e| Error::chain(format!("`{}`: {}", fn_name, path), e))?;
+            (response, None)
+        };
+        let mut image = match to_value(&response).unwrap() {
+            Value::Object(map) => map,
+            _ => unreachable!(),
+        };
+        if let Some(ref target) = target {
+            if options.compute_weight {
+                let bytes = fs::metadata(target).map(|m| m.len()).unwrap_or(0);
+                total_bytes += bytes;
+                image.insert("bytes".to_string(), to_value(bytes).unwrap());
+            }
+            if options.integrity {
+                let digest =
+                    fs::File::open(target).and_then(compute_file_integrity).map_err(|e| {
+                        format!("`{}`: Failed to hash {}: {}", fn_name, response.url, e)
+                    })?;
+                image.insert("integrity".to_string(), to_value(digest).unwrap());
+            }
+        }
+        match variant.media {
+            Some(media) => {
+                image.insert("media".to_string(), to_value(media).unwrap());
+                sources.push(Value::Object(image));
+            }
+            None => fallback = image,
         }
     }
+
+    let mut picture = Map::new();
+    picture.insert("sources".to_string(), Value::Array(sources));
+    picture.insert("fallback".to_string(), Value::Object(fallback));
+    if options.compute_weight {
+        picture.insert("total_bytes".to_string(), to_value(total_bytes).unwrap());
+    }
+    let with_preview = options.preview.unwrap_or(imageproc.settings().responsive_previews);
+    drop(imageproc);
+    if with_preview {
+        let preview = imageproc::preview_data_uri(&source_path)
+            .map_err(|e| Error::chain(format!("`{}`: {}", fn_name, path), e))?;
+        picture.insert("preview".to_string(), to_value(preview).unwrap());
+    }
+    Ok(picture)
 }
 
 #[derive(Debug)]
-pub struct GetSection {
-    base_path: PathBuf,
-    library: Arc<RwLock<Library>>,
+pub struct ImageSetCss {
+    imageproc: Arc<Mutex<imageproc::Processor>>,
 }
-impl GetSection {
-    pub fn new(base_path: PathBuf, library: Arc<RwLock<Library>>) -> Self {
-        Self { base_path: base_path.join("content"), library }
+impl ImageSetCss {
+    pub fn new(imageproc: Arc<Mutex<imageproc::Processor>>) -> Self {
+        Self { imageproc }
     }
 }
-impl TeraFn for GetSection {
+
+/// The pixel densities `image_set_css` generates when not given any
+const DEFAULT_DENSITIES: &[f64] = &[1.0, 2.0];
+
+impl TeraFn for ImageSetCss {
     fn call(&self, args: &HashMap<String, Value>) -> Result<Value> {
         let path = required_arg!(
             String,
             args.get("path"),
-            "`get_section` requires a `path` argument with a string value"
+            "`image_set_css` requires a `path` argument with a string value"
         );
+        let width = required_arg!(
+            imageproc::Dimension,
+            args.get("width"),
+            "`image_set_css` requires a `width` argument with a non-negative integer or a named size"
+        );
+        let height = optional_arg!(
+            imageproc::Dimension,
+            args.get("height"),
+            "`image_set_css`: `height` must be a non-negative integer or a named size"
+        );
+        let op = optional_arg!(String, args.get("op"), "`image_set_css`: `op` must be a string")
+            .unwrap_or_else(|| if height.is_some() { DEFAULT_OP } else { "fit_width" }.to_string());
+        let densities = optional_arg!(
+            Vec<f64>,
+            args.get("densities"),
+            "`image_set_css`: `densities` must be an array of numbers"
+        )
+        .unwrap_or_else(|| DEFAULT_DENSITIES.to_vec());
+        let formats = optional_arg!(
+            Vec<String>,
+            args.get("formats"),
+            "`image_set_css`: `formats` must be an array of strings"
+        )
+        .unwrap_or_else(|| vec![DEFAULT_FMT.to_string()]);
+        let quality =
+            optional_arg!(u8, args.get("quality"), "`image_set_css`: `quality` must be a number");
+        if let Some(q) = quality {
+            if q == 0 || q > 100 {
+                return Err("`image_set_css`: `quality` must be in range 1-100".to_string().into());
+            }
+        }
+        if densities.is_empty() || densities.iter().any(|d| d.is_nan() || *d <= 0.0) {
+            return Err("`image_set_css`: `densities` must be positive numbers".into());
+        }
+        if formats.is_empty() {
+            return Err("`image_set_css`: `formats` cannot be empty".into());
+        }
 
-        let metadata_only = args
-            .get("metadata_only")
-            .map_or(false, |c| from_value::<bool>(c.clone()).unwrap_or(false));
-
-        let full_path = self.base_path.join(&path);
-        let library = self.library.read().unwrap();
+        let mut imageproc = self.imageproc.lock().unwrap();
+        if !imageproc.source_exists(&path) {
+            return Err(format!("`image_set_css`: Cannot find path: {}", path).into());
+        }
+        let quality = quality.unwrap_or_else(|| imageproc.default_quality(&path));
+        let source_path = imageproc.source_path(&path);
+        // The densities replace the `pixel_ratio` setting
+        let settings = ImageProcessing { pixel_ratio: 1, ..imageproc.settings().clone() };
+        let resolve = |d: &imageproc::Dimension| {
+            d.resolve(&settings).map_err(|e| format!("`image_set_css`: {}", e))
+        };
+        let width = resolve(&width)?;
+        let height = height.as_ref().map(resolve).transpose()?;
 
-        match library.get_section(&full_path) {
-            Some(s) => {
-                if metadata_only {
-                    Ok(to_value(s.to_serialized_basic(&library)).unwrap())
-                } else {
-                    Ok(to_value(s.to_serialized(&library)).unwrap())
+        let mut fallback = None;
+        let mut candidates = Vec::new();
+        for format in &formats {
+            for density in &densities {
+                let scale =
+                    |px: u32| imageproc::Dimension::Pixels((px as f64 * density).round() as u32);
+                let resize_args = imageproc::ResizeArgs {
+                    op: op.clone(),
+                    width: Some(scale(width)),
+                    height: height.map(scale),
+                    format: format.clone(),
+                    quality,
+                    crop: None,
+                    corner_radius: None,
+                    circle: false,
+                    focus: None,
+                    anchor: None,
+                    respect_exif: true,
+                    alpha_quality: None,
+                    strip_metadata: None,
+                    percent: None,
+                    output_dir: None,
+                    first_frame_only: false,
+                    color_profile: None,
+                };
+                let imageop = imageproc::ImageOp::from_args(
+                    path.clone(),
+                    &source_path,
+                    &resize_args,
+                    &settings,
+                )
+                .map_err(|e| format!("`image_set_css`: {}", e))?;
+                let mime_type = imageop.format().mime_type();
+                let response = imageproc
+                    .insert(imageop)
+                    .map_err(|e| Error::chain(format!("`image_set_css`: {}", path), e))?;
+                // Browsers without `image-set` get the image of the first format and density
+                if fallback.is_none() {
+                    fallback = Some(response.url.clone());
                 }
+                // The type is only needed for the browser to pick between formats
+                candidates.push(if formats.len() > 1 {
+                    format!("url(\"{}\") type(\"{}\") {}x", response.url, mime_type, density)
+                } else {
+                    format!("url(\"{}\") {}x", response.url, density)
+                });
             }
-            None => Err(format!("Section `{}` not found.", path).into()),
         }
+
+        let css = format!(
+            "background-image: url(\"{}\"); background-image: image-set({});",
+            fallback.unwrap(),
+            candidates.join(", ")
+        );
+        Ok(to_value(css).unwrap())
     }
 }
 
 #[derive(Debug)]
-pub struct GetTaxonomy {
-    library: Arc<RwLock<Library>>,
-    taxonomies: HashMap<String, Taxonomy>,
-    default_lang: String,
+pub struct GetImagePreload {
+    imageproc: Arc<Mutex<imageproc::Processor>>,
 }
-impl GetTaxonomy {
-    pub fn new(
-        default_lang: &str,
-        all_taxonomies: Vec<Taxonomy>,
-        library: Arc<RwLock<Library>>,
-    ) -> Self {
-        let mut taxonomies = HashMap::new();
-        for taxo in all_taxonomies {
-            taxonomies.insert(format!("{}-{}", taxo.kind.name, taxo.kind.lang), taxo);
+impl GetImagePreload {
+    pub fn new(imageproc: Arc<Mutex<imageproc::Processor>>) -> Self {
+        Self { imageproc }
+    }
+}
+
+impl TeraFn for GetImagePreload {
+    fn call(&self, args: &HashMap<String, Value>) -> Result<Value> {
+        let path =
+            optional_arg!(String, args.get("path"), "`get_image_preload`: `path` must be a string");
+        let sizes = optional_arg!(
+            String,
+            args.get("sizes"),
+            "`get_image_preload`: `sizes` must be a string"
+        );
+        let path = match (path, args.get("set")) {
+            (Some(path), None) => path,
+            (None, Some(set)) => return preload_set(set, sizes.as_deref()).map(Value::String),
+            (Some(_), Some(_)) => {
+                return Err("`get_image_preload`: `path` and `set` cannot be combined".into())
+            }
+            (None, None) => return Err(
+                "`get_image_preload` requires a `path` argument with a string value or a `set` argument with the result of `resize_image_srcset` or of `resize_image` with `art_direction`"
+                    .into(),
+            ),
+        };
+        if sizes.is_some() {
+            return Err("`get_image_preload`: `sizes` can only be used with `set`, the one of `markdown_images` is used for `path`".into());
+        }
+        // The same images as the `<img>` of `markdown_images`, which are only processed once
+        let processed = rendering::ProcessedImage::from_source(path.clone(), "", &self.imageproc)
+            .map_err(|e| Error::chain(format!("`get_image_preload`: {}", path), e))?;
+        match processed {
+            Some(processed) => Ok(to_value(processed.to_preload_html()).unwrap()),
+            None => Err(format!(
+                "`get_image_preload`: {} is not processed: `markdown_images` needs to be set in \
+                 `[image_processing]` and the image to be one it can process",
+                path
+            )
+            .into()),
+        }
+    }
+}
+
+/// The `<link>`s preloading a set of images made by the other functions: the `srcset` of
+/// `resize_image_srcset`, with the `sizes` of its `<img>`, or one per `<source>` of a
+/// `<picture>` of `resize_image` with `art_direction`, each with its `media`
+fn preload_set(set: &Value, sizes: Option<&str>) -> Result<String> {
+    let url = |image: &Value| image.get("url").and_then(Value::as_str).map(str::to_string);
+    if let (Some(srcset), Some(images)) =
+        (set.get("srcset").and_then(Value::as_str), set.get("images").and_then(Value::as_array))
+    {
+        // For the browsers that don't know `imagesrcset`
+        let href = images.first().and_then(url).unwrap_or_default();
+        let mut html = format!(
+            "<link rel=\"preload\" as=\"image\" href=\"{}\" imagesrcset=\"{}\"",
+            href, srcset
+        );
+        if let Some(sizes) = sizes {
+            html.push_str(&format!(" imagesizes=\"{}\"", sizes));
+        }
+        html.push_str(" />");
+        return Ok(html);
+    }
+    if sizes.is_some() {
+        return Err(
+            "`get_image_preload`: `sizes` can only be used with the `set` of `resize_image_srcset`"
+                .into(),
+        );
+    }
+
+    let (sources, fallback) = match (
+        set.get("sources").and_then(Value::as_array),
+        set.get("fallback").and_then(url),
+    ) {
+        (Some(sources), Some(fallback)) => (sources, fallback),
+        _ => return Err(
+            "`get_image_preload`: `set` needs to be the result of `resize_image_srcset` or of `resize_image` with `art_direction`"
+                .into(),
+        ),
+    };
+    let mut links = Vec::with_capacity(sources.len() + 1);
+    let mut medias = Vec::with_capacity(sources.len());
+    for source in sources {
+        match (url(source), source.get("media").and_then(Value::as_str)) {
+            (Some(url), Some(media)) => {
+                links.push(format!(
+                    "<link rel=\"preload\" as=\"image\" href=\"{}\" media=\"{}\" />",
+                    url, media
+                ));
+                medias.push(if media.starts_with('(') && media.ends_with(')') {
+                    media.to_string()
+                } else {
+                    format!("({})", media)
+                });
+            }
+            _ => {
+                return Err(
+                    "`get_image_preload`: every `sources` of `set` needs a `url` and a `media`"
+                        .into(),
+                )
+            }
+        }
+    }
+    // Only preloaded where none of the sources is picked, as the `<img>` of the `<picture>`
+    let fallback_media = match medias.len() {
+        0 => String::new(),
+        1 => format!(" media=\"not {}\"", medias[0]),
+        _ => format!(" media=\"not ({})\"", medias.join(" or ")),
+    };
+    links.push(format!(
+        "<link rel=\"preload\" as=\"image\" href=\"{}\"{} />",
+        fallback, fallback_media
+    ));
+    Ok(links.join("\n"))
+}
+
+#[derive(Debug)]
+pub struct CountImageVariants {
+    imageproc: Arc<Mutex<imageproc::Processor>>,
+}
+impl CountImageVariants {
+    pub fn new(imageproc: Arc<Mutex<imageproc::Processor>>) -> Self {
+        Self { imageproc }
+    }
+}
+
+impl TeraFn for CountImageVariants {
+    fn call(&self, args: &HashMap<String, Value>) -> Result<Value> {
+        let path = optional_arg!(
+            String,
+            args.get("path"),
+            "`count_image_variants`: `path` must be a string"
+        );
+        let page = match path.or_else(imageproc::current_page) {
+            Some(page) => page,
+            None => {
+                return Err("`count_image_variants` requires a `path` argument outside of the \
+                            rendering of a page or section"
+                    .into())
+            }
+        };
+        Ok(to_value(self.imageproc.lock().unwrap().page_variants(&page)).unwrap())
+    }
+}
+
+/// Where the image at `path` is for the functions that only read it. Paths starting with `/` are
+/// relative to the root of the site like URLs are: they are looked up in `static` and then in
+/// `content`, or in the root itself for the ones starting with `/static/` or `/content/`.
+/// The other paths are relative to `content`. Paths going up with `..` are refused.
+/// Either way, the images found in none of these are looked up in each of the `asset_dirs`.
+/// `None` if it's in none of them. `fn_name` is the function errors are about
+fn find_image_to_read(
+    fn_name: &str,
+    content_path: &Path,
+    asset_dirs: &[String],
+    path: &str,
+) -> Result<Option<PathBuf>> {
+    let relative = path.trim_start_matches('/');
+    if Path::new(relative).is_absolute()
+        || Path::new(relative).components().any(|c| c == Component::ParentDir)
+    {
+        return Err(
+            format!("`{}`: `path` can't go outside of the site, got `{}`", fn_name, path).into()
+        );
+    }
+
+    // The site root is where `content` is
+    let root = content_path.parent().unwrap_or(content_path);
+    let mut candidates = Vec::new();
+    if relative.len() == path.len() {
+        candidates.push(content_path.join(relative));
+    } else {
+        if relative.starts_with("static/") || relative.starts_with("content/") {
+            candidates.push(root.join(relative));
+        }
+        candidates.push(root.join("static").join(relative));
+        candidates.push(content_path.join(relative));
+    }
+    candidates.extend(asset_dirs.iter().map(|dir| root.join(dir.trim_matches('/')).join(relative)));
+    Ok(candidates.into_iter().find(|candidate| candidate.exists()))
+}
+
+/// What `get_image_metadata` already read about an image
+#[derive(Debug)]
+struct CachedImageMeta {
+    /// The modification time of the image when it was read
+    mtime: Option<SystemTime>,
+    meta: imageproc::ImageMeta,
+    /// Only guessed once asked for with `detect_screenshot`, as it reads the image again
+    is_screenshot: Option<bool>,
+    /// Only read once asked for with `compute_color`, along with the metadata if it's the
+    /// first time the image is read. Never set for SVGs
+    colors: Option<imageproc::ImageColors>,
+}
+
+#[derive(Debug)]
+pub struct GetImageMeta {
+    content_path: PathBuf,
+    /// What was already read about the images, by path, so each is only read once per build
+    /// unless it changes.
+    /// Behind a read-write lock so concurrent renders reading the same images don't block each other
+    cache: RwLock<HashMap<PathBuf, CachedImageMeta>>,
+    skip_corrupt_images: bool,
+    max_pixels: u64,
+    fetch_settings: remote_images::FetchSettings,
+    asset_dirs: Vec<String>,
+}
+
+impl GetImageMeta {
+    pub fn new(content_path: PathBuf, config: &Config) -> Self {
+        Self {
+            content_path,
+            cache: RwLock::new(HashMap::new()),
+            skip_corrupt_images: config.image_processing.skip_corrupt_images,
+            max_pixels: config.image_processing.max_pixels,
+            fetch_settings: remote_images::FetchSettings::new(&config.image_processing),
+            asset_dirs: config.image_processing.asset_dirs.clone(),
+        }
+    }
+}
+
+/// The arguments of `get_image_metadata` applying to each of the images it reads
+#[derive(Clone, Copy)]
+struct ImageMetaOptions {
+    detect_screenshot: bool,
+    compute_color: bool,
+    palette: Option<usize>,
+    allow_missing: bool,
+}
+
+impl TeraFn for GetImageMeta {
+    fn call(&self, args: &HashMap<String, Value>) -> Result<Value> {
+        let path = optional_arg!(
+            String,
+            args.get("path"),
+            "`get_image_metadata`: `path` must be a string"
+        );
+        let paths = optional_arg!(
+            Vec<String>,
+            args.get("paths"),
+            "`get_image_metadata`: `paths` must be an array of strings"
+        );
+        let allow_missing = optional_arg!(
+            bool,
+            args.get("allow_missing"),
+            "`get_image_metadata`: `allow_missing` must be a boolean (true or false)"
+        )
+        .unwrap_or(false);
+        let detect_screenshot = optional_arg!(
+            bool,
+            args.get("detect_screenshot"),
+            "`get_image_metadata`: `detect_screenshot` must be a boolean (true or false)"
+        )
+        .unwrap_or(false);
+        let compute_color = optional_arg!(
+            bool,
+            args.get("compute_color"),
+            "`get_image_metadata`: `compute_color` must be a boolean (true or false)"
+        )
+        .unwrap_or(false);
+        let palette = optional_arg!(
+            usize,
+            args.get("palette"),
+            "`get_image_metadata`: `palette` must be a non-negative integer"
+        );
+        match palette {
+            Some(_) if !compute_color => {
+                return Err(
+                    "`get_image_metadata`: `palette` can only be used with `compute_color=true`"
+                        .into(),
+                )
+            }
+            Some(n) if n > imageproc::MAX_PALETTE_SIZE => {
+                return Err(format!(
+                    "`get_image_metadata`: `palette` can have at most {} colors",
+                    imageproc::MAX_PALETTE_SIZE
+                )
+                .into())
+            }
+            _ => (),
+        }
+
+        let options = ImageMetaOptions { detect_screenshot, compute_color, palette, allow_missing };
+        match (path, paths) {
+            (Some(path), None) => self.read(&path, &options),
+            // One object or `null` per path, in the same order. The images that aren't
+            // cached yet are decoded in parallel
+            (None, Some(paths)) => paths
+                .par_iter()
+                .map(|path| self.read(path, &options))
+                .collect::<Result<_>>()
+                .map(Value::Array),
+            (Some(_), Some(_)) => {
+                Err("`get_image_metadata`: `path` and `paths` cannot be combined".into())
+            }
+            (None, None) => Err(
+                "`get_image_metadata` requires a `path` argument with a string value or a `paths` argument with an array of strings"
+                    .into(),
+            ),
+        }
+    }
+}
+
+impl GetImageMeta {
+    /// The metadata of the image at that `path`, `null` if it's skipped or, with `allow_missing`,
+    /// missing
+    fn read(&self, path: &str, options: &ImageMetaOptions) -> Result<Value> {
+        let ImageMetaOptions { detect_screenshot, compute_color, palette, allow_missing } =
+            *options;
+        let src_path = if remote_images::is_remote(path) {
+            self.content_path.join(remote_images::fetch_remote_image(
+                "get_image_metadata",
+                &self.content_path,
+                path,
+                &self.fetch_settings,
+            )?)
+        } else {
+            match find_image_to_read(
+                "get_image_metadata",
+                &self.content_path,
+                &self.asset_dirs,
+                path,
+            )? {
+                Some(found) => found,
+                None if allow_missing => return Ok(Value::Null),
+                None => {
+                    return Err(format!("`get_image_metadata`: Cannot find path: {}", path).into())
+                }
+            }
+        };
+        let mtime = get_file_time(&src_path);
+        let cached = match self.cache.read().unwrap().get(&src_path) {
+            Some(cached) if mtime.is_some() && cached.mtime == mtime => Some((
+                to_value(&cached.meta).map_err(Error::from)?,
+                cached.is_screenshot,
+                cached.colors.clone(),
+            )),
+            _ => None,
+        };
+        let (mut value, is_screenshot, colors) = match cached {
+            Some(cached) => cached,
+            None => {
+                // Not a corrupt image, so it errors even with `skip_corrupt_images`
+                imageproc::check_pixel_limit(&src_path, self.max_pixels)
+                    .map_err(|e| Error::chain(format!("`get_image_metadata`: {}", path), e))?;
+                let (meta, colors) =
+                    match imageproc::read_image_metadata_with_colors(&src_path, compute_color) {
+                        Ok(read) => read,
+                        Err(e) if self.skip_corrupt_images => {
+                            println!(
+                                "Warning: `get_image_metadata`: skipping the corrupt image {}: {}",
+                                path, e
+                            );
+                            return Ok(Value::Null);
+                        }
+                        Err(e) => {
+                            return Err(Error::chain(format!("`get_image_metadata`: {}", path), e))
+                        }
+                    };
+                let value = to_value(&meta).map_err(Error::from)?;
+                let cached =
+                    CachedImageMeta { mtime, meta, is_screenshot: None, colors: colors.clone() };
+                self.cache.write().unwrap().insert(src_path.clone(), cached);
+                (value, None, colors)
+            }
+        };
+
+        if detect_screenshot {
+            let is_screenshot = match is_screenshot {
+                Some(is_screenshot) => is_screenshot,
+                None => {
+                    let is_screenshot = imageproc::is_likely_screenshot(&src_path)
+                        .map_err(|e| Error::chain(format!("`get_image_metadata`: {}", path), e))?;
+                    if let Some(cached) = self.cache.write().unwrap().get_mut(&src_path) {
+                        cached.is_screenshot = Some(is_screenshot);
+                    }
+                    is_screenshot
+                }
+            };
+            if let Value::Object(ref mut map) = value {
+                map.insert("is_screenshot".to_string(), Value::Bool(is_screenshot));
+            }
+        }
+
+        if compute_color {
+            let colors = match colors {
+                Some(colors) => Some(colors),
+                None => {
+                    let colors = imageproc::read_image_colors(&src_path)
+                        .map_err(|e| Error::chain(format!("`get_image_metadata`: {}", path), e))?;
+                    if let Some(cached) = self.cache.write().unwrap().get_mut(&src_path) {
+                        cached.colors = colors.clone();
+                    }
+                    colors
+                }
+            };
+            // SVGs have no pixels to read the colors of
+            if let (Some(colors), Value::Object(map)) = (colors, &mut value) {
+                map.insert("average_color".to_string(), Value::String(colors.average));
+                if let Some(n) = palette {
+                    let palette = colors.palette.into_iter().take(n).map(Value::String).collect();
+                    map.insert("palette".to_string(), Value::Array(palette));
+                }
+            }
+        }
+        Ok(value)
+    }
+}
+
+/// Used in place of the date of images without one in their EXIF data, so they sort first
+const UNKNOWN_IMAGE_DATE: &str = "0000-00-00T00:00:00";
+
+#[derive(Debug)]
+pub struct GetImageSortKey {
+    content_path: PathBuf,
+}
+
+impl GetImageSortKey {
+    pub fn new(content_path: PathBuf) -> Self {
+        Self { content_path }
+    }
+}
+
+impl TeraFn for GetImageSortKey {
+    fn call(&self, args: &HashMap<String, Value>) -> Result<Value> {
+        let path = required_arg!(
+            String,
+            args.get("path"),
+            "`get_image_sort_key` requires a `path` argument with a string value"
+        );
+        let src_path = self.content_path.join(&path);
+        if !src_path.exists() {
+            return Err(format!("`get_image_sort_key`: Cannot find path: {}", path).into());
+        }
+        let date = imageproc::read_image_date(&src_path)
+            .map_err(|e| Error::chain(format!("`get_image_sort_key`: {}", path), e))?;
+        // The path breaks ties between images taken at the same time, always with forward
+        // slashes so the order is the same on every platform
+        let key = format!(
+            "{} {}",
+            date.as_deref().unwrap_or(UNKNOWN_IMAGE_DATE),
+            path.replace('\\', "/")
+        );
+        Ok(to_value(key).unwrap())
+    }
+}
+
+#[derive(Debug)]
+pub struct ImageGradient {
+    content_path: PathBuf,
+    default_colors: u32,
+}
+
+impl ImageGradient {
+    pub fn new(content_path: PathBuf, config: &Config) -> Self {
+        Self { content_path, default_colors: config.image_processing.gradient_colors }
+    }
+}
+
+impl TeraFn for ImageGradient {
+    fn call(&self, args: &HashMap<String, Value>) -> Result<Value> {
+        let path = required_arg!(
+            String,
+            args.get("path"),
+            "`image_gradient` requires a `path` argument with a string value"
+        );
+        let colors = optional_arg!(
+            u32,
+            args.get("colors"),
+            "`image_gradient`: `colors` must be a positive integer"
+        )
+        .unwrap_or(self.default_colors);
+        if colors < 2 {
+            return Err("`image_gradient`: `colors` needs to be at least 2".into());
+        }
+
+        let src_path = self.content_path.join(&path);
+        if !src_path.exists() {
+            return Err(format!("`image_gradient`: Cannot find path: {}", path).into());
+        }
+        let gradient = imageproc::gradient_css(&src_path, colors)
+            .map_err(|e| Error::chain(format!("`image_gradient`: {}", path), e))?;
+        Ok(to_value(gradient.unwrap_or_default()).unwrap())
+    }
+}
+
+/// The biggest dimension of the placeholders of `get_image_placeholder` by default, in pixels
+const DEFAULT_PLACEHOLDER_SIZE: u32 = 20;
+/// The JPEG quality of the placeholders of `get_image_placeholder` by default
+const DEFAULT_PLACEHOLDER_QUALITY: u8 = 40;
+
+/// The source, size, blur and quality of a placeholder of `get_image_placeholder`
+type PlaceholderKey = (PathBuf, u32, Option<u32>, u8);
+
+#[derive(Debug)]
+pub struct GetImagePlaceholder {
+    content_path: PathBuf,
+    asset_dirs: Vec<String>,
+    /// The placeholders already made, along with the modification time of their source when
+    /// they were made, so each is only made once per build unless its source changes
+    cache: RwLock<HashMap<PlaceholderKey, (Option<SystemTime>, String)>>,
+}
+
+impl GetImagePlaceholder {
+    pub fn new(content_path: PathBuf, config: &Config) -> Self {
+        Self {
+            content_path,
+            asset_dirs: config.image_processing.asset_dirs.clone(),
+            cache: RwLock::new(HashMap::new()),
+        }
+    }
+}
+
+impl TeraFn for GetImagePlaceholder {
+    fn call(&self, args: &HashMap<String, Value>) -> Result<Value> {
+        let path = required_arg!(
+            String,
+            args.get("path"),
+            "`get_image_placeholder` requires a `path` argument with a string value"
+        );
+        let size = optional_arg!(
+            u32,
+            args.get("size"),
+            "`get_image_placeholder`: `size` must be a positive integer"
+        )
+        .unwrap_or(DEFAULT_PLACEHOLDER_SIZE);
+        if size == 0 {
+            return Err("`get_image_placeholder`: `size` needs to be at least 1".into());
+        }
+        let blur = optional_arg!(
+            f32,
+            args.get("blur"),
+            "`get_image_placeholder`: `blur` must be a number"
+        );
+        if blur.filter(|b| *b <= 0.0).is_some() {
+            return Err("`get_image_placeholder`: `blur` needs to be greater than 0".into());
+        }
+        let quality = optional_arg!(
+            u8,
+            args.get("quality"),
+            "`get_image_placeholder`: `quality` must be a number between 1 and 100"
+        )
+        .unwrap_or(DEFAULT_PLACEHOLDER_QUALITY);
+        if quality == 0 || quality > 100 {
+            return Err(
+                "`get_image_placeholder`: `quality` must be a number between 1 and 100".into()
+            );
+        }
+
+        let src_path = match find_image_to_read(
+            "get_image_placeholder",
+            &self.content_path,
+            &self.asset_dirs,
+            path.trim_start_matches("@/"),
+        )? {
+            Some(found) => found,
+            None => {
+                return Err(format!("`get_image_placeholder`: Cannot find path: {}", path).into())
+            }
+        };
+        let mtime = get_file_time(&src_path);
+        let key = (src_path, size, blur.map(f32::to_bits), quality);
+        if let Some((made_at, placeholder)) = self.cache.read().unwrap().get(&key) {
+            if *made_at == mtime {
+                return Ok(to_value(placeholder).unwrap());
+            }
+        }
+
+        let placeholder = imageproc::placeholder_data_uri(&key.0, size, blur, quality)
+            .map_err(|e| Error::chain(format!("`get_image_placeholder`: {}", path), e))?
+            .unwrap_or_default();
+        let value = to_value(&placeholder).unwrap();
+        self.cache.write().unwrap().insert(key, (mtime, placeholder));
+        Ok(value)
+    }
+}
+
+/// The smallest image most social platforms accept for their cards
+const SOCIAL_CARD_MIN_SIZE: (u32, u32) = (200, 200);
+/// The size Open Graph and Twitter recommend for large cards
+const SOCIAL_CARD_RECOMMENDED_SIZE: (u32, u32) = (1200, 630);
+
+#[derive(Debug)]
+pub struct CheckSocialCard {
+    content_path: PathBuf,
+}
+
+impl CheckSocialCard {
+    pub fn new(content_path: PathBuf) -> Self {
+        Self { content_path }
+    }
+}
+
+impl TeraFn for CheckSocialCard {
+    fn call(&self, args: &HashMap<String, Value>) -> Result<Value> {
+        let path = required_arg!(
+            String,
+            args.get("path"),
+            "`check_social_card` requires a `path` argument with a string value"
+        );
+        let min_width = optional_arg!(
+            u32,
+            args.get("min_width"),
+            "`check_social_card`: `min_width` must be a positive integer"
+        )
+        .unwrap_or(SOCIAL_CARD_MIN_SIZE.0);
+        let min_height = optional_arg!(
+            u32,
+            args.get("min_height"),
+            "`check_social_card`: `min_height` must be a positive integer"
+        )
+        .unwrap_or(SOCIAL_CARD_MIN_SIZE.1);
+
+        let src_path = self.content_path.join(&path);
+        if !src_path.exists() {
+            return Err(format!("`check_social_card`: Cannot find path: {}", path).into());
+        }
+        let meta = imageproc::read_image_metadata(&src_path)
+            .map_err(|e| Error::chain(format!("`check_social_card`: {}", path), e))?;
+
+        let (rec_width, rec_height) = SOCIAL_CARD_RECOMMENDED_SIZE;
+        let recommended = meta.width >= rec_width && meta.height >= rec_height;
+        let (valid, reason) = if meta.width < min_width || meta.height < min_height {
+            (
+                false,
+                format!(
+                    "{}x{} is smaller than the minimum of {}x{}",
+                    meta.width, meta.height, min_width, min_height
+                ),
+            )
+        } else if !recommended {
+            (
+                true,
+                format!(
+                    "{}x{} is accepted but smaller than the recommended {}x{}",
+                    meta.width, meta.height, rec_width, rec_height
+                ),
+            )
+        } else {
+            (true, String::new())
+        };
+
+        let mut result = Map::new();
+        result.insert("valid".to_string(), Value::Bool(valid));
+        result.insert("recommended".to_string(), Value::Bool(recommended));
+        result.insert("reason".to_string(), to_value(reason).unwrap());
+        Ok(Value::Object(result))
+    }
+}
+
+#[derive(Debug)]
+pub struct GetMimeType {
+    content_path: PathBuf,
+}
+
+impl GetMimeType {
+    pub fn new(content_path: PathBuf) -> Self {
+        Self { content_path }
+    }
+}
+
+impl TeraFn for GetMimeType {
+    fn call(&self, args: &HashMap<String, Value>) -> Result<Value> {
+        let path = required_arg!(
+            String,
+            args.get("path"),
+            "`get_mime_type` requires a `path` argument with a string value"
+        );
+        let src_path = self.content_path.join(&path);
+        if !src_path.exists() {
+            return Err(format!("`get_mime_type`: Cannot find path: {}", path).into());
+        }
+        let mime = imageproc::detect_mime_type(&src_path)
+            .map_err(|e| Error::chain(format!("`get_mime_type`: {}", path), e))?;
+        Ok(to_value(mime).unwrap())
+    }
+}
+
+#[derive(Debug)]
+pub struct GetAlt {
+    /// The file mapping image paths to their alt text, if one is configured
+    data_path: Option<PathBuf>,
+    /// How to make an alt text for images without one, if enabled
+    fallback: Option<AltFallback>,
+    /// The parsed alt text file along with its modification time, so we only re-read
+    /// it when it changes
+    alts: RwLock<Option<(Option<SystemTime>, HashMap<String, String>)>>,
+    /// Where the images without alt text are reported, for `strict_alt`
+    imageproc: Arc<Mutex<imageproc::Processor>>,
+}
+
+impl GetAlt {
+    pub fn new(
+        base_path: &Path,
+        config: &Config,
+        imageproc: Arc<Mutex<imageproc::Processor>>,
+    ) -> Self {
+        let data_path = config.image_processing.alt_text_file.as_ref().map(|f| base_path.join(f));
+        let fallback = config.image_processing.alt_fallback.clone();
+        Self { data_path, fallback, alts: RwLock::new(None), imageproc }
+    }
+
+    /// The alt text made out of the filename, with a warning as it's unlikely to be a good one
+    fn fallback_alt(&self, path: &str) -> Option<Value> {
+        let alt = self.fallback.as_ref()?.alt_from_filename(path);
+        println!(
+            "Warning: `get_alt`: no alt text for `{}`, using `{}` made from its filename",
+            path, alt
+        );
+        Some(to_value(alt).unwrap())
+    }
+}
+
+fn normalize_image_path(path: &str) -> &str {
+    path.trim_start_matches("@/").trim_start_matches('/')
+}
+
+fn parse_alt_text_file(path: &Path) -> Result<HashMap<String, String>> {
+    let content = read_file(path)
+        .map_err(|e| Error::chain(format!("Failed to read {}", path.display()), e))?;
+    let alts: HashMap<String, String> = match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => toml::from_str(&content)
+            .map_err(|e| Error::chain(format!("Failed to parse {}", path.display()), e))?,
+        Some("json") => serde_json::from_str(&content)
+            .map_err(|e| Error::chain(format!("Failed to parse {}", path.display()), e))?,
+        _ => {
+            return Err(format!(
+                "Alt text file {} needs to be a `.toml` or a `.json` file",
+                path.display()
+            )
+            .into())
+        }
+    };
+
+    Ok(alts.into_iter().map(|(k, v)| (normalize_image_path(&k).to_string(), v)).collect())
+}
+
+impl TeraFn for GetAlt {
+    fn call(&self, args: &HashMap<String, Value>) -> Result<Value> {
+        let path = required_arg!(
+            String,
+            args.get("path"),
+            "`get_alt` requires a `path` argument with a string value"
+        );
+        let data_path = match self.data_path {
+            Some(ref p) => p,
+            None if self.fallback.is_some() => return Ok(self.fallback_alt(&path).unwrap()),
+            None => {
+                return Err(
+                    "`get_alt`: no `alt_text_file` is set in the [image_processing] section of the config"
+                        .into(),
+                )
+            }
+        };
+
+        let mtime = get_file_time(data_path);
+        let is_stale = match *self.alts.read().unwrap() {
+            Some((cached_mtime, _)) => cached_mtime != mtime,
+            None => true,
+        };
+        if is_stale {
+            let parsed =
+                parse_alt_text_file(data_path).map_err(|e| Error::chain("`get_alt`", e))?;
+            *self.alts.write().unwrap() = Some((mtime, parsed));
+        }
+
+        let alts = self.alts.read().unwrap();
+        let (_, alts) = alts.as_ref().unwrap();
+        match alts.get(normalize_image_path(&path)) {
+            Some(alt) => Ok(to_value(alt).unwrap()),
+            None if self.fallback.is_some() => Ok(self.fallback_alt(&path).unwrap()),
+            None => {
+                self.imageproc.lock().unwrap().report_missing_alt(
+                    &path,
+                    &format!("`get_alt`, it isn't in {}", data_path.display()),
+                );
+                Ok(to_value("").unwrap())
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct GetImageSidecar {
+    content_path: PathBuf,
+    suffix: String,
+}
+
+impl GetImageSidecar {
+    pub fn new(content_path: PathBuf, config: &Config) -> Self {
+        Self { content_path, suffix: config.image_processing.sidecar_suffix.clone() }
+    }
+}
+
+impl TeraFn for GetImageSidecar {
+    fn call(&self, args: &HashMap<String, Value>) -> Result<Value> {
+        let path = required_arg!(
+            String,
+            args.get("path"),
+            "`get_image_sidecar` requires a `path` argument with a string value"
+        );
+        let sidecar_path =
+            self.content_path.join(format!("{}{}", normalize_image_path(&path), self.suffix));
+        if !sidecar_path.exists() {
+            return Ok(Value::Null);
+        }
+
+        let content = read_file(&sidecar_path).map_err(|e| {
+            Error::chain(
+                format!("`get_image_sidecar`: Failed to read {}", sidecar_path.display()),
+                e,
+            )
+        })?;
+        let error = format!("`get_image_sidecar`: Failed to parse {}", sidecar_path.display());
+        // The suffix is validated with the config to be either TOML or JSON
+        if self.suffix.ends_with(".toml") {
+            let sidecar: toml::Value =
+                toml::from_str(&content).map_err(|e| Error::chain(error, e))?;
+            to_value(sidecar).map_err(|e| e.into())
+        } else {
+            serde_json::from_str(&content).map_err(|e| Error::chain(error, e))
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ImageOcr {
+    content_path: PathBuf,
+    command: Option<Vec<String>>,
+    /// The text of the images already read, so the command runs once per image and build
+    cache: RwLock<HashMap<PathBuf, String>>,
+}
+
+impl ImageOcr {
+    pub fn new(content_path: PathBuf, config: &Config) -> Self {
+        Self {
+            content_path,
+            command: config.image_processing.ocr_command.clone(),
+            cache: RwLock::new(HashMap::new()),
+        }
+    }
+}
+
+/// Runs the OCR command on the image at that path and returns what it printed
+fn run_ocr(command: &[String], path: &Path) -> result::Result<String, String> {
+    let path = path.to_string_lossy();
+    let mut args: Vec<_> = command[1..].iter().map(|arg| arg.replace("{path}", &path)).collect();
+    if !command[1..].iter().any(|arg| arg.contains("{path}")) {
+        args.push(path.to_string());
+    }
+
+    let output = Command::new(&command[0])
+        .args(&args)
+        .output()
+        .map_err(|e| format!("Failed to run `{}`: {}", command[0], e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "`{}` exited with {}: {}",
+            command[0],
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+impl TeraFn for ImageOcr {
+    fn call(&self, args: &HashMap<String, Value>) -> Result<Value> {
+        let path = required_arg!(
+            String,
+            args.get("path"),
+            "`image_ocr` requires a `path` argument with a string value"
+        );
+        let command = match self.command {
+            Some(ref command) => command,
+            None => return Ok(to_value("").unwrap()),
+        };
+        let src_path = self.content_path.join(normalize_image_path(&path));
+        if !src_path.exists() {
+            return Err(format!("`image_ocr`: Cannot find path: {}", path).into());
+        }
+        if let Some(text) = self.cache.read().unwrap().get(&src_path) {
+            return Ok(to_value(text).unwrap());
+        }
+
+        // OCR is best effort, failing to read the text of an image doesn't fail the build
+        let text = run_ocr(command, &src_path).unwrap_or_else(|e| {
+            println!("Warning: `image_ocr`: Failed to read the text of {}: {}", path, e);
+            String::new()
+        });
+        let value = to_value(&text).unwrap();
+        self.cache.write().unwrap().insert(src_path, text);
+        Ok(value)
+    }
+}
+
+#[derive(Debug)]
+pub struct GetTaxonomyUrl {
+    taxonomies: HashMap<String, HashMap<String, String>>,
+    default_lang: String,
+    slugify: SlugifyStrategy,
+}
+
+impl GetTaxonomyUrl {
+    pub fn new(default_lang: &str, all_taxonomies: &[Taxonomy], slugify: SlugifyStrategy) -> Self {
+        let mut taxonomies = HashMap::new();
+        for taxo in all_taxonomies {
+            let mut items = HashMap::new();
+            for item in &taxo.items {
+                items.insert(slugify_paths(&item.name.clone(), slugify), item.permalink.clone());
+            }
+            taxonomies.insert(format!("{}-{}", taxo.kind.name, taxo.kind.lang), items);
+        }
+        Self { taxonomies, default_lang: default_lang.to_string(), slugify }
+    }
+}
+impl TeraFn for GetTaxonomyUrl {
+    fn call(&self, args: &HashMap<String, Value>) -> Result<Value> {
+        let kind = required_arg!(
+            String,
+            args.get("kind"),
+            "`get_taxonomy_url` requires a `kind` argument with a string value"
+        );
+        let name = required_arg!(
+            String,
+            args.get("name"),
+            "`get_taxonomy_url` requires a `name` argument with a string value"
+        );
+        let lang =
+            optional_arg!(String, args.get("lang"), "`get_taxonomy`: `lang` must be a string")
+                .unwrap_or_else(|| self.default_lang.clone());
+
+        let container = match self.taxonomies.get(&format!("{}-{}", kind, lang)) {
+            Some(c) => c,
+            None => {
+                return Err(format!(
+                    "`get_taxonomy_url` received an unknown taxonomy as kind: {}",
+                    kind
+                )
+                .into());
+            }
+        };
+
+        if let Some(permalink) = container.get(&slugify_paths(&name, self.slugify)) {
+            return Ok(to_value(permalink).unwrap());
+        }
+
+        Err(format!("`get_taxonomy_url`: couldn't find `{}` in `{}` taxonomy", name, kind).into())
+    }
+}
+
+#[derive(Debug)]
+pub struct GetPage {
+    base_path: PathBuf,
+    library: Arc<RwLock<Library>>,
+}
+impl GetPage {
+    pub fn new(base_path: PathBuf, library: Arc<RwLock<Library>>) -> Self {
+        Self { base_path: base_path.join("content"), library }
+    }
+}
+impl TeraFn for GetPage {
+    fn call(&self, args: &HashMap<String, Value>) -> Result<Value> {
+        let path = required_arg!(
+            String,
+            args.get("path"),
+            "`get_page` requires a `path` argument with a string value"
+        );
+        let full_path = self.base_path.join(&path);
+        let library = self.library.read().unwrap();
+        match library.get_page(&full_path) {
+            Some(p) => Ok(to_value(p.to_serialized(&library)).unwrap()),
+            None => Err(format!("Page `{}` not found.", path).into()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct GetSection {
+    base_path: PathBuf,
+    library: Arc<RwLock<Library>>,
+}
+impl GetSection {
+    pub fn new(base_path: PathBuf, library: Arc<RwLock<Library>>) -> Self {
+        Self { base_path: base_path.join("content"), library }
+    }
+}
+impl TeraFn for GetSection {
+    fn call(&self, args: &HashMap<String, Value>) -> Result<Value> {
+        let path = required_arg!(
+            String,
+            args.get("path"),
+            "`get_section` requires a `path` argument with a string value"
+        );
+
+        let metadata_only = args
+            .get("metadata_only")
+            .map_or(false, |c| from_value::<bool>(c.clone()).unwrap_or(false));
+
+        let full_path = self.base_path.join(&path);
+        let library = self.library.read().unwrap();
+
+        match library.get_section(&full_path) {
+            Some(s) => {
+                if metadata_only {
+                    Ok(to_value(s.to_serialized_basic(&library)).unwrap())
+                } else {
+                    Ok(to_value(s.to_serialized(&library)).unwrap())
+                }
+            }
+            None => Err(format!("Section `{}` not found.", path).into()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct GetTaxonomy {
+    library: Arc<RwLock<Library>>,
+    taxonomies: HashMap<String, Taxonomy>,
+    default_lang: String,
+}
+impl GetTaxonomy {
+    pub fn new(
+        default_lang: &str,
+        all_taxonomies: Vec<Taxonomy>,
+        library: Arc<RwLock<Library>>,
+    ) -> Self {
+        let mut taxonomies = HashMap::new();
+        for taxo in all_taxonomies {
+            taxonomies.insert(format!("{}-{}", taxo.kind.name, taxo.kind.lang), taxo);
+        }
+        Self { taxonomies, library, default_lang: default_lang.to_string() }
+    }
+}
+impl TeraFn for GetTaxonomy {
+    fn call(&self, args: &HashMap<String, Value>) -> Result<Value> {
+        let kind = required_arg!(
+            String,
+            args.get("kind"),
+            "`get_taxonomy` requires a `kind` argument with a string value"
+        );
+
+        let lang =
+            optional_arg!(String, args.get("lang"), "`get_taxonomy`: `lang` must be a string")
+                .unwrap_or_else(|| self.default_lang.clone());
+
+        match self.taxonomies.get(&format!("{}-{}", kind, lang)) {
+            Some(t) => Ok(to_value(t.to_serialized(&self.library.read().unwrap())).unwrap()),
+            None => {
+                Err(format!("`get_taxonomy` received an unknown taxonomy as kind: {}", kind).into())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::remote_images::{fetch_remote_image, FetchSettings};
+    use super::{
+        CheckSocialCard, CountImageVariants, GetAlt, GetFileHash, GetImageMeta,
+        GetImagePlaceholder, GetImagePreload, GetImageSidecar, GetImageSortKey, GetImages,
+        GetMimeType, GetTaxonomy, GetTaxonomyUrl, GetUrl, ImageGradient, ImageOcr, ImageSetCss,
+        ResizeGallery, ResizeImage, ResizeImageSrcset, Trans,
+    };
+
+    use std::collections::HashMap;
+    use std::env::temp_dir;
+    use std::fs::remove_dir_all;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex, RwLock};
+
+    use image::{
+        ColorType, DynamicImage, GenericImageView, ImageFormat, ImageOutputFormat, Rgb, RgbImage,
+        Rgba, RgbaImage,
+    };
+    use lazy_static::lazy_static;
+    use sha2::{Digest, Sha384};
+
+    use tera::{to_value, Function, Value};
+
+    use config::{
+        ArtDirectionVariant, Config, EncoderChangePolicy, ForbidUpscale, MarkdownImages,
+        ResizeRounding, Taxonomy as TaxonomyConfig,
+    };
+    use library::{Library, Taxonomy, TaxonomyItem};
+    use utils::fs::{create_directory, create_file};
+    use utils::slugs::SlugifyStrategy;
+
+    struct TestContext {
+        static_path: PathBuf,
+    }
+    impl TestContext {
+        fn setup() -> Self {
+            let dir = temp_dir().join("static");
+            create_directory(&dir).expect("Could not create test directory");
+            create_file(&dir.join("app.css"), "// Hello world!")
+                .expect("Could not create test content (app.css)");
+            Self { static_path: dir }
+        }
+    }
+    impl Drop for TestContext {
+        fn drop(&mut self) {
+            remove_dir_all(&self.static_path).expect("Could not free test directory");
+        }
+    }
+
+    lazy_static! {
+        static ref TEST_CONTEXT: TestContext = TestContext::setup();
+    }
+
+    #[test]
+    fn can_add_cachebust_to_url() {
+        let config = Config::default();
+        let static_fn = GetUrl::new(config, HashMap::new(), vec![TEST_CONTEXT.static_path.clone()]);
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("app.css").unwrap());
+        args.insert("cachebust".to_string(), to_value(true).unwrap());
+        assert_eq!(static_fn.call(&args).unwrap(), "http://a-website.com/app.css?h=572e691dc68c3fcd653ae463261bdb38f35dc6f01715d9ce68799319dd158840");
+    }
+
+    #[test]
+    fn can_add_trailing_slashes() {
+        let config = Config::default();
+        let static_fn = GetUrl::new(config, HashMap::new(), vec![TEST_CONTEXT.static_path.clone()]);
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("app.css").unwrap());
+        args.insert("trailing_slash".to_string(), to_value(true).unwrap());
+        assert_eq!(static_fn.call(&args).unwrap(), "http://a-website.com/app.css/");
+    }
+
+    #[test]
+    fn can_add_slashes_and_cachebust() {
+        let config = Config::default();
+        let static_fn = GetUrl::new(config, HashMap::new(), vec![TEST_CONTEXT.static_path.clone()]);
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("app.css").unwrap());
+        args.insert("trailing_slash".to_string(), to_value(true).unwrap());
+        args.insert("cachebust".to_string(), to_value(true).unwrap());
+        assert_eq!(static_fn.call(&args).unwrap(), "http://a-website.com/app.css/?h=572e691dc68c3fcd653ae463261bdb38f35dc6f01715d9ce68799319dd158840");
+    }
+
+    #[test]
+    fn can_link_to_some_static_file() {
+        let config = Config::default();
+        let static_fn = GetUrl::new(config, HashMap::new(), vec![TEST_CONTEXT.static_path.clone()]);
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("app.css").unwrap());
+        assert_eq!(static_fn.call(&args).unwrap(), "http://a-website.com/app.css");
+    }
+
+    #[test]
+    fn can_get_taxonomy() {
+        let mut config = Config::default();
+        config.slugify.taxonomies = SlugifyStrategy::On;
+        let taxo_config = TaxonomyConfig {
+            name: "tags".to_string(),
+            lang: config.default_language.clone(),
+            ..TaxonomyConfig::default()
+        };
+        let taxo_config_fr = TaxonomyConfig {
+            name: "tags".to_string(),
+            lang: "fr".to_string(),
+            ..TaxonomyConfig::default()
+        };
+        let library = Arc::new(RwLock::new(Library::new(0, 0, false)));
+        let tag = TaxonomyItem::new(
+            "Programming",
+            &taxo_config,
+            "tags",
+            &config,
+            vec![],
+            &library.read().unwrap(),
+        );
+        let tag_fr = TaxonomyItem::new(
+            "Programmation",
+            &taxo_config_fr,
+            "tags",
+            &config,
+            vec![],
+            &library.read().unwrap(),
+        );
+        let tags = Taxonomy { kind: taxo_config, slug: "tags".to_string(), items: vec![tag] };
+        let tags_fr =
+            Taxonomy { kind: taxo_config_fr, slug: "tags".to_string(), items: vec![tag_fr] };
+
+        let taxonomies = vec![tags.clone(), tags_fr.clone()];
+        let static_fn =
+            GetTaxonomy::new(&config.default_language, taxonomies.clone(), library.clone());
+        // can find it correctly
+        let mut args = HashMap::new();
+        args.insert("kind".to_string(), to_value("tags").unwrap());
+        let res = static_fn.call(&args).unwrap();
+        let res_obj = res.as_object().unwrap();
+        assert_eq!(res_obj["kind"], to_value(tags.kind).unwrap());
+        assert_eq!(res_obj["items"].clone().as_array().unwrap().len(), 1);
+        assert_eq!(
+            res_obj["items"].clone().as_array().unwrap()[0].clone().as_object().unwrap()["name"],
+            Value::String("Programming".to_string())
+        );
+        assert_eq!(
+            res_obj["items"].clone().as_array().unwrap()[0].clone().as_object().unwrap()["slug"],
+            Value::String("programming".to_string())
+        );
+        assert_eq!(
+            res_obj["items"].clone().as_array().unwrap()[0].clone().as_object().unwrap()
+                ["permalink"],
+            Value::String("http://a-website.com/tags/programming/".to_string())
+        );
+        assert_eq!(
+            res_obj["items"].clone().as_array().unwrap()[0].clone().as_object().unwrap()["pages"],
+            Value::Array(vec![])
+        );
+        // Works with other languages as well
+        let mut args = HashMap::new();
+        args.insert("kind".to_string(), to_value("tags").unwrap());
+        args.insert("lang".to_string(), to_value("fr").unwrap());
+        let res = static_fn.call(&args).unwrap();
+        let res_obj = res.as_object().unwrap();
+        assert_eq!(res_obj["kind"], to_value(tags_fr.kind).unwrap());
+        assert_eq!(res_obj["items"].clone().as_array().unwrap().len(), 1);
+        assert_eq!(
+            res_obj["items"].clone().as_array().unwrap()[0].clone().as_object().unwrap()["name"],
+            Value::String("Programmation".to_string())
+        );
+
+        // and errors if it can't find it
+        let mut args = HashMap::new();
+        args.insert("kind".to_string(), to_value("something-else").unwrap());
+        assert!(static_fn.call(&args).is_err());
+    }
+
+    #[test]
+    fn can_get_taxonomy_url() {
+        let mut config = Config::default();
+        config.slugify.taxonomies = SlugifyStrategy::On;
+        let taxo_config = TaxonomyConfig {
+            name: "tags".to_string(),
+            lang: config.default_language.clone(),
+            ..TaxonomyConfig::default()
+        };
+        let taxo_config_fr = TaxonomyConfig {
+            name: "tags".to_string(),
+            lang: "fr".to_string(),
+            ..TaxonomyConfig::default()
+        };
+        let library = Library::new(0, 0, false);
+        let tag = TaxonomyItem::new("Programming", &taxo_config, "tags", &config, vec![], &library);
+        let tag_fr =
+            TaxonomyItem::new("Programmation", &taxo_config_fr, "tags", &config, vec![], &library);
+        let tags = Taxonomy { kind: taxo_config, slug: "tags".to_string(), items: vec![tag] };
+        let tags_fr =
+            Taxonomy { kind: taxo_config_fr, slug: "tags".to_string(), items: vec![tag_fr] };
+
+        let taxonomies = vec![tags.clone(), tags_fr.clone()];
+        let static_fn =
+            GetTaxonomyUrl::new(&config.default_language, &taxonomies, config.slugify.taxonomies);
+
+        // can find it correctly
+        let mut args = HashMap::new();
+        args.insert("kind".to_string(), to_value("tags").unwrap());
+        args.insert("name".to_string(), to_value("Programming").unwrap());
+        assert_eq!(
+            static_fn.call(&args).unwrap(),
+            to_value("http://a-website.com/tags/programming/").unwrap()
+        );
+
+        // can find it correctly with inconsistent capitalisation
+        let mut args = HashMap::new();
+        args.insert("kind".to_string(), to_value("tags").unwrap());
+        args.insert("name".to_string(), to_value("programming").unwrap());
+        assert_eq!(
+            static_fn.call(&args).unwrap(),
+            to_value("http://a-website.com/tags/programming/").unwrap()
+        );
+
+        // works with other languages
+        let mut args = HashMap::new();
+        args.insert("kind".to_string(), to_value("tags").unwrap());
+        args.insert("name".to_string(), to_value("Programmation").unwrap());
+        args.insert("lang".to_string(), to_value("fr").unwrap());
+        assert_eq!(
+            static_fn.call(&args).unwrap(),
+            to_value("http://a-website.com/fr/tags/programmation/").unwrap()
+        );
+
+        // and errors if it can't find it
+        let mut args = HashMap::new();
+        args.insert("kind".to_string(), to_value("tags").unwrap());
+        args.insert("name".to_string(), to_value("random").unwrap());
+        assert!(static_fn.call(&args).is_err());
+    }
+
+    const TRANS_CONFIG: &str = r#"
+base_url = "https://remplace-par-ton-url.fr"
+default_language = "fr"
+languages = [
+    { code = "en" },
+]
+
+[translations]
+[translations.fr]
+title = "Un titre"
+
+[translations.en]
+title = "A title"
+        "#;
+
+    #[test]
+    fn can_translate_a_string() {
+        let config = Config::parse(TRANS_CONFIG).unwrap();
+        let static_fn = Trans::new(config);
+        let mut args = HashMap::new();
+
+        args.insert("key".to_string(), to_value("title").unwrap());
+        assert_eq!(static_fn.call(&args).unwrap(), "Un titre");
+
+        args.insert("lang".to_string(), to_value("en").unwrap());
+        assert_eq!(static_fn.call(&args).unwrap(), "A title");
+
+        args.insert("lang".to_string(), to_value("fr").unwrap());
+        assert_eq!(static_fn.call(&args).unwrap(), "Un titre");
+    }
+
+    #[test]
+    fn error_on_absent_translation_lang() {
+        let mut args = HashMap::new();
+        args.insert("lang".to_string(), to_value("absent").unwrap());
+        args.insert("key".to_string(), to_value("title").unwrap());
+
+        let config = Config::parse(TRANS_CONFIG).unwrap();
+        let error = Trans::new(config).call(&args).unwrap_err();
+        assert_eq!("Failed to retrieve term translation", format!("{}", error));
+    }
+
+    #[test]
+    fn error_on_absent_translation_key() {
+        let mut args = HashMap::new();
+        args.insert("lang".to_string(), to_value("en").unwrap());
+        args.insert("key".to_string(), to_value("absent").unwrap());
+
+        let config = Config::parse(TRANS_CONFIG).unwrap();
+        let error = Trans::new(config).call(&args).unwrap_err();
+        assert_eq!("Failed to retrieve term translation", format!("{}", error));
+    }
+
+    #[test]
+    fn error_when_language_not_available() {
+        let config = Config::parse(TRANS_CONFIG).unwrap();
+        let static_fn = GetUrl::new(config, HashMap::new(), vec![TEST_CONTEXT.static_path.clone()]);
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("@/a_section/a_page.md").unwrap());
+        args.insert("lang".to_string(), to_value("it").unwrap());
+        let err = static_fn.call(&args).unwrap_err();
+        assert_eq!(
+            "`it` is not an authorized language (check config.languages).",
+            format!("{}", err)
+        );
+    }
+
+    #[test]
+    fn can_get_url_with_default_language() {
+        let config = Config::parse(TRANS_CONFIG).unwrap();
+        let mut permalinks = HashMap::new();
+        permalinks.insert(
+            "a_section/a_page.md".to_string(),
+            "https://remplace-par-ton-url.fr/a_section/a_page/".to_string(),
+        );
+        permalinks.insert(
+            "a_section/a_page.en.md".to_string(),
+            "https://remplace-par-ton-url.fr/en/a_section/a_page/".to_string(),
+        );
+        let static_fn = GetUrl::new(config, permalinks, vec![TEST_CONTEXT.static_path.clone()]);
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("@/a_section/a_page.md").unwrap());
+        args.insert("lang".to_string(), to_value("fr").unwrap());
+        assert_eq!(
+            static_fn.call(&args).unwrap(),
+            "https://remplace-par-ton-url.fr/a_section/a_page/"
+        );
+    }
+
+    #[test]
+    fn can_get_url_with_other_language() {
+        let config = Config::parse(TRANS_CONFIG).unwrap();
+        let mut permalinks = HashMap::new();
+        permalinks.insert(
+            "a_section/a_page.md".to_string(),
+            "https://remplace-par-ton-url.fr/a_section/a_page/".to_string(),
+        );
+        permalinks.insert(
+            "a_section/a_page.en.md".to_string(),
+            "https://remplace-par-ton-url.fr/en/a_section/a_page/".to_string(),
+        );
+        let static_fn = GetUrl::new(config, permalinks, vec![TEST_CONTEXT.static_path.clone()]);
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("@/a_section/a_page.md").unwrap());
+        args.insert("lang".to_string(), to_value("en").unwrap());
+        assert_eq!(
+            static_fn.call(&args).unwrap(),
+            "https://remplace-par-ton-url.fr/en/a_section/a_page/"
+        );
+    }
+
+    #[test]
+    fn can_get_file_hash_sha256() {
+        let static_fn = GetFileHash::new(vec![TEST_CONTEXT.static_path.clone()]);
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("app.css").unwrap());
+        args.insert("sha_type".to_string(), to_value(256).unwrap());
+        assert_eq!(
+            static_fn.call(&args).unwrap(),
+            "572e691dc68c3fcd653ae463261bdb38f35dc6f01715d9ce68799319dd158840"
+        );
+    }
+
+    #[test]
+    fn can_get_file_hash_sha384() {
+        let static_fn = GetFileHash::new(vec![TEST_CONTEXT.static_path.clone()]);
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("app.css").unwrap());
+        assert_eq!(static_fn.call(&args).unwrap(), "141c09bd28899773b772bbe064d8b718fa1d6f2852b7eafd5ed6689d26b74883b79e2e814cd69d5b52ab476aa284c414");
+    }
+
+    #[test]
+    fn can_get_file_hash_sha512() {
+        let static_fn = GetFileHash::new(vec![TEST_CONTEXT.static_path.clone()]);
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("app.css").unwrap());
+        args.insert("sha_type".to_string(), to_value(512).unwrap());
+        assert_eq!(static_fn.call(&args).unwrap(), "379dfab35123b9159d9e4e92dc90e2be44cf3c2f7f09b2e2df80a1b219b461de3556c93e1a9ceb3008e999e2d6a54b4f1d65ee9be9be63fa45ec88931623372f");
+    }
+
+    #[test]
+    fn error_when_file_not_found_for_hash() {
+        let static_fn = GetFileHash::new(vec![TEST_CONTEXT.static_path.clone()]);
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("doesnt-exist").unwrap());
+        assert_eq!(
+            format!(
+                "file `doesnt-exist` not found; searched in {}",
+                TEST_CONTEXT.static_path.to_str().unwrap()
+            ),
+            format!("{}", static_fn.call(&args).unwrap_err())
+        );
+    }
+
+    #[test]
+    fn can_detect_mime_type_from_content() {
+        let path = TEST_CONTEXT.static_path.join("mislabeled.jpg");
+        RgbImage::new(4, 4).save_with_format(&path, ImageFormat::Png).unwrap();
+        let static_fn = GetMimeType::new(TEST_CONTEXT.static_path.clone());
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("mislabeled.jpg").unwrap());
+        assert_eq!(static_fn.call(&args).unwrap(), "image/png");
+    }
+
+    #[test]
+    fn can_compute_the_colors_of_images() {
+        let path = TEST_CONTEXT.static_path.join("two-colors.png");
+        // Three quarters red, a quarter blue
+        RgbImage::from_fn(64, 32, |x, _| if x < 48 { Rgb([255, 0, 0]) } else { Rgb([0, 0, 255]) })
+            .save(&path)
+            .unwrap();
+        let static_fn = GetImageMeta::new(TEST_CONTEXT.static_path.clone(), &Config::default());
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("two-colors.png").unwrap());
+        // Not computed unless asked for, the metadata being cached without them
+        assert!(static_fn.call(&args).unwrap().get("average_color").is_none());
+
+        args.insert("compute_color".to_string(), to_value(true).unwrap());
+        let data = static_fn.call(&args).unwrap();
+        assert_eq!(data["average_color"], "#bf0040");
+        assert!(data.get("palette").is_none());
+        args.insert("palette".to_string(), to_value(1).unwrap());
+        assert_eq!(static_fn.call(&args).unwrap()["palette"], to_value(vec!["#ff0000"]).unwrap());
+        args.insert("palette".to_string(), to_value(4).unwrap());
+        let palette = static_fn.call(&args).unwrap()["palette"].clone();
+        assert_eq!(palette, to_value(vec!["#ff0000", "#0000ff"]).unwrap());
+
+        args.insert("palette".to_string(), to_value(17).unwrap());
+        assert!(static_fn.call(&args).is_err());
+        args.insert("compute_color".to_string(), to_value(false).unwrap());
+        args.insert("palette".to_string(), to_value(2).unwrap());
+        assert!(static_fn.call(&args).is_err());
+
+        let svg = TEST_CONTEXT.static_path.join("colorless.svg");
+        std::fs::write(&svg, r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"/>"#)
+            .unwrap();
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("colorless.svg").unwrap());
+        args.insert("compute_color".to_string(), to_value(true).unwrap());
+        assert!(static_fn.call(&args).unwrap().get("average_color").is_none());
+    }
+
+    #[test]
+    fn can_read_the_metadata_of_several_images_at_once() {
+        let base_path = TEST_CONTEXT.static_path.join("batch-metadata-site");
+        create_directory(&base_path.join("content")).unwrap();
+        RgbImage::new(30, 20).save(base_path.join("content").join("a.png")).unwrap();
+        RgbImage::new(40, 10).save(base_path.join("content").join("b.png")).unwrap();
+        let static_fn = GetImageMeta::new(base_path.join("content"), &Config::default());
+        let mut args = HashMap::new();
+        args.insert("paths".to_string(), to_value(vec!["b.png", "missing.png", "a.png"]).unwrap());
+        let err = format!("{}", static_fn.call(&args).unwrap_err());
+        assert_eq!(err, "`get_image_metadata`: Cannot find path: missing.png");
+
+        args.insert("allow_missing".to_string(), to_value(true).unwrap());
+        let data = static_fn.call(&args).unwrap();
+        let widths: Vec<_> =
+            data.as_array().unwrap().iter().map(|meta| meta.get("width")).collect();
+        assert_eq!(widths, vec![Some(&to_value(40).unwrap()), None, Some(&to_value(30).unwrap())]);
+        assert_eq!(data[1], Value::Null);
+
+        args.insert("path".to_string(), to_value("a.png").unwrap());
+        assert!(static_fn.call(&args).is_err());
+    }
+
+    #[test]
+    fn names_heic_in_the_errors_of_heic_sources() {
+        let base_path = TEST_CONTEXT.static_path.join("heic-site");
+        create_directory(&base_path.join("content")).unwrap();
+        // Only the `ftyp` box, which is enough to know what it is but not to decode it
+        let ftyp = b"\x00\x00\x00\x18ftypheic\x00\x00\x00\x00mif1heic";
+        std::fs::write(base_path.join("content").join("iphone.heic"), &ftyp[..]).unwrap();
+
+        let static_fn = GetMimeType::new(base_path.join("content"));
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("iphone.heic").unwrap());
+        assert_eq!(static_fn.call(&args).unwrap(), "image/heic");
+
+        // Without the `heic` feature the error says it's missing, with it libheif fails on
+        // the truncated file
+        let imageproc =
+            Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &Config::default())));
+        let static_fn = ResizeImage::new(imageproc);
+        args.insert("width".to_string(), to_value(20).unwrap());
+        args.insert("op".to_string(), to_value("fit_width").unwrap());
+        let err = format!("{:?}", static_fn.call(&args).unwrap_err());
+        assert!(err.contains("HEIC"), "{}", err);
+
+        let static_fn = GetImageMeta::new(base_path.join("content"), &Config::default());
+        let err = format!("{:?}", static_fn.call(&args).unwrap_err());
+        assert!(err.contains("HEIC"), "{}", err);
+    }
+
+    #[test]
+    fn error_when_file_not_found_for_mime_type() {
+        let static_fn = GetMimeType::new(TEST_CONTEXT.static_path.clone());
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("doesnt-exist.png").unwrap());
+        assert!(static_fn.call(&args).is_err());
+    }
+
+    #[test]
+    fn can_get_image_metadata_dimensions_in_the_right_order() {
+        RgbImage::new(300, 380)
+            .save(TEST_CONTEXT.static_path.join("portrait-300x380.png"))
+            .unwrap();
+        std::fs::write(
+            TEST_CONTEXT.static_path.join("portrait-300x380.svg"),
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="300" height="380"></svg>"#,
+        )
+        .unwrap();
+
+        let static_fn = GetImageMeta::new(TEST_CONTEXT.static_path.clone(), &Config::default());
+        let mut args = HashMap::new();
+        for path in &["portrait-300x380.png", "portrait-300x380.svg"] {
+            args.insert("path".to_string(), to_value(path).unwrap());
+            let data = static_fn.call(&args).unwrap();
+            assert_eq!(data["width"], to_value(300).unwrap(), "{}", path);
+            assert_eq!(data["height"], to_value(380).unwrap(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn can_get_image_metadata_of_svgs_with_units() {
+        let svgs = [
+            ("svg-px.svg", r#"width="200px" height="100px""#, Some((200, 100))),
+            ("svg-pt.svg", r#"width="72pt" height="36pt""#, Some((96, 48))),
+            ("svg-mm.svg", r#"width='25.4mm' height='50.8mm'"#, Some((96, 192))),
+            (
+                "svg-percent.svg",
+                r#"width="100%" height="100%" viewBox="0 0 640 480""#,
+                Some((640, 480)),
+            ),
+            ("svg-width-only.svg", r#"width="320px" viewBox="0 0 640 480""#, Some((320, 240))),
+            (
+                "svg-stroke.svg",
+                r#"stroke-width="3" viewBox="0 0 30 10" height="5em""#,
+                Some((30, 10)),
+            ),
+            ("svg-view-box.svg", r#"viewBox="0 0 64 32""#, Some((64, 32))),
+            ("svg-nothing.svg", r#"width="100%" height="50%""#, None),
+        ];
+        let static_fn = GetImageMeta::new(TEST_CONTEXT.static_path.clone(), &Config::default());
+        for (name, attributes, expected) in &svgs {
+            std::fs::write(
+                TEST_CONTEXT.static_path.join(name),
+                format!(r#"<svg xmlns="http://www.w3.org/2000/svg" {}></svg>"#, attributes),
+            )
+            .unwrap();
+            let mut args = HashMap::new();
+            args.insert("path".to_string(), to_value(name).unwrap());
+            match expected {
+                Some((width, height)) => {
+                    let data = static_fn.call(&args).unwrap();
+                    assert_eq!(data["width"], to_value(width).unwrap(), "{}", name);
+                    assert_eq!(data["height"], to_value(height).unwrap(), "{}", name);
+                }
+                None => {
+                    let err = format!("{:?}", static_fn.call(&args).unwrap_err());
+                    assert!(err.contains("in absolute units"), "{}", name);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn can_get_image_metadata_format_and_alpha() {
+        RgbaImage::new(4, 4).save(TEST_CONTEXT.static_path.join("alpha.png")).unwrap();
+        RgbImage::new(4, 4).save(TEST_CONTEXT.static_path.join("no-alpha.png")).unwrap();
+        RgbImage::new(4, 4).save(TEST_CONTEXT.static_path.join("format-photo.jpg")).unwrap();
+        std::fs::write(
+            TEST_CONTEXT.static_path.join("drawing.svg"),
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="4" height="4"></svg>"#,
+        )
+        .unwrap();
+
+        let static_fn = GetImageMeta::new(TEST_CONTEXT.static_path.clone(), &Config::default());
+        let mut args = HashMap::new();
+        for (path, format, has_alpha) in &[
+            ("alpha.png", "png", true),
+            ("no-alpha.png", "png", false),
+            ("format-photo.jpg", "jpg", false),
+            ("drawing.svg", "svg", true),
+        ] {
+            args.insert("path".to_string(), to_value(path).unwrap());
+            let data = static_fn.call(&args).unwrap();
+            assert_eq!(data["format"], to_value(format).unwrap(), "{}", path);
+            assert_eq!(data["has_alpha"], Value::Bool(*has_alpha), "{}", path);
+        }
+    }
+
+    #[test]
+    fn can_get_image_metadata_after_exif_orientation() {
+        let mut jpeg = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(40, 20))
+            .write_to(&mut jpeg, ImageOutputFormat::Jpeg(90))
+            .unwrap();
+        // A TIFF with a single Orientation entry asking for a quarter turn clockwise
+        let tiff = b"II\x2a\x00\x08\x00\x00\x00\
+            \x01\x00\x12\x01\x03\x00\x01\x00\x00\x00\x06\x00\x00\x00\
+            \x00\x00\x00\x00";
+        let mut app1 = vec![0xFF, 0xE1];
+        app1.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+        app1.extend_from_slice(b"Exif\0\0");
+        app1.extend_from_slice(tiff);
+        let mut rotated = jpeg[..2].to_vec();
+        rotated.extend(app1);
+        rotated.extend_from_slice(&jpeg[2..]);
+        std::fs::write(TEST_CONTEXT.static_path.join("rotated.jpg"), rotated).unwrap();
+        RgbImage::new(40, 20).save(TEST_CONTEXT.static_path.join("unrotated.png")).unwrap();
+
+        let static_fn = GetImageMeta::new(TEST_CONTEXT.static_path.clone(), &Config::default());
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("rotated.jpg").unwrap());
+        let data = static_fn.call(&args).unwrap();
+        assert_eq!(data["orientation"], to_value(6).unwrap());
+        assert_eq!(
+            (&data["width"], &data["height"]),
+            (&to_value(20).unwrap(), &to_value(40).unwrap())
+        );
+
+        args.insert("path".to_string(), to_value("unrotated.png").unwrap());
+        let data = static_fn.call(&args).unwrap();
+        assert_eq!(data["orientation"], to_value(1).unwrap());
+        assert_eq!(
+            (&data["width"], &data["height"]),
+            (&to_value(40).unwrap(), &to_value(20).unwrap())
+        );
+    }
+
+    #[test]
+    fn can_tell_if_image_is_opaque() {
+        let mut img = RgbaImage::from_pixel(4, 4, Rgba([255, 0, 0, 255]));
+        img.save(TEST_CONTEXT.static_path.join("opaque.png")).unwrap();
+        img.put_pixel(3, 3, Rgba([255, 0, 0, 128]));
+        img.save(TEST_CONTEXT.static_path.join("translucent.png")).unwrap();
+
+        let static_fn = GetImageMeta::new(TEST_CONTEXT.static_path.clone(), &Config::default());
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("opaque.png").unwrap());
+        let data = static_fn.call(&args).unwrap();
+        assert_eq!(data["is_opaque"], Value::Bool(true));
+
+        args.insert("path".to_string(), to_value("translucent.png").unwrap());
+        let data = static_fn.call(&args).unwrap();
+        assert_eq!(data["is_opaque"], Value::Bool(false));
+    }
+
+    #[test]
+    fn can_read_software_tag() {
+        let mut jpeg = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(4, 4))
+            .write_to(&mut jpeg, ImageOutputFormat::Jpeg(90))
+            .unwrap();
+        // A little-endian TIFF with a single Software entry pointing to the string after the IFD
+        let mut tiff = b"II\x2a\x00\x08\x00\x00\x00\
+            \x01\x00\x31\x01\x02\x00\x0a\x00\x00\x00\x1a\x00\x00\x00\
+            \x00\x00\x00\x00"
+            .to_vec();
+        tiff.extend_from_slice(b"Zola test\0");
+        let mut app1 = vec![0xFF, 0xE1];
+        app1.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+        app1.extend_from_slice(b"Exif\0\0");
+        app1.extend_from_slice(&tiff);
+        // The APP1 segment goes right after the start of image marker
+        let mut tagged = jpeg[..2].to_vec();
+        tagged.extend(app1);
+        tagged.extend_from_slice(&jpeg[2..]);
+        std::fs::write(TEST_CONTEXT.static_path.join("tagged.jpg"), tagged).unwrap();
+        RgbImage::new(4, 4).save(TEST_CONTEXT.static_path.join("untagged.png")).unwrap();
+
+        let static_fn = GetImageMeta::new(TEST_CONTEXT.static_path.clone(), &Config::default());
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("tagged.jpg").unwrap());
+        let data = static_fn.call(&args).unwrap();
+        assert_eq!(data["software"], to_value("Zola test").unwrap());
+        assert_eq!(data["creator"], Value::Null);
+
+        args.insert("path".to_string(), to_value("untagged.png").unwrap());
+        let data = static_fn.call(&args).unwrap();
+        assert_eq!(data["software"], Value::Null);
+        assert_eq!(data["samples_per_pixel"], Value::Null);
+    }
+
+    #[test]
+    fn caches_image_metadata_until_the_image_changes() {
+        let path = TEST_CONTEXT.static_path.join("edited.png");
+        let set_mtime = |seconds| {
+            filetime::set_file_mtime(&path, filetime::FileTime::from_unix_time(seconds, 0)).unwrap()
+        };
+        RgbImage::new(4, 4).save(&path).unwrap();
+        set_mtime(4_000_000_000);
+
+        let static_fn = GetImageMeta::new(TEST_CONTEXT.static_path.clone(), &Config::default());
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("edited.png").unwrap());
+        args.insert("detect_screenshot".to_string(), to_value(true).unwrap());
+        assert_eq!(static_fn.call(&args).unwrap()["width"], to_value(4).unwrap());
+
+        // Not read again while its modification time is the same...
+        RgbImage::new(1920, 1080).save(&path).unwrap();
+        set_mtime(4_000_000_000);
+        let data = static_fn.call(&args).unwrap();
+        assert_eq!(data["width"], to_value(4).unwrap());
+        assert_eq!(data["is_screenshot"], to_value(false).unwrap());
+        // ...and read again once it changes
+        set_mtime(4_000_000_001);
+        let data = static_fn.call(&args).unwrap();
+        assert_eq!(data["width"], to_value(1920).unwrap());
+        assert_eq!(data["is_screenshot"], to_value(true).unwrap());
+    }
+
+    #[test]
+    fn can_guess_if_image_is_screenshot() {
+        let dir = TEST_CONTEXT.static_path.join("screenshots");
+        create_directory(&dir).unwrap();
+        RgbImage::new(1920, 1080).save(dir.join("desktop.png")).unwrap();
+        RgbImage::new(1170, 2532).save(dir.join("phone.png")).unwrap();
+        RgbImage::new(1000, 700).save(dir.join("diagram.png")).unwrap();
+        RgbImage::new(1920, 1080).save(dir.join("photo.jpg")).unwrap();
+        let mut jpeg = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(4, 4))
+            .write_to(&mut jpeg, ImageOutputFormat::Jpeg(90))
+            .unwrap();
+        // A TIFF with a single Software entry, like in `can_read_software_tag`
+        let mut tiff = b"II\x2a\x00\x08\x00\x00\x00\
+            \x01\x00\x31\x01\x02\x00\x0a\x00\x00\x00\x1a\x00\x00\x00\
+            \x00\x00\x00\x00"
+            .to_vec();
+        tiff.extend_from_slice(b"Greenshot\0");
+        let mut app1 = vec![0xFF, 0xE1];
+        app1.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+        app1.extend_from_slice(b"Exif\0\0");
+        app1.extend_from_slice(&tiff);
+        let mut tagged = jpeg[..2].to_vec();
+        tagged.extend(app1);
+        tagged.extend_from_slice(&jpeg[2..]);
+        std::fs::write(dir.join("capture.jpg"), tagged).unwrap();
+
+        let static_fn = GetImageMeta::new(TEST_CONTEXT.static_path.clone(), &Config::default());
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("screenshots/desktop.png").unwrap());
+        assert!(static_fn.call(&args).unwrap().get("is_screenshot").is_none());
+
+        args.insert("detect_screenshot".to_string(), to_value(true).unwrap());
+        for (name, expected) in &[
+            ("desktop.png", true),
+            ("phone.png", true),
+            ("capture.jpg", true),
+            ("diagram.png", false),
+            ("photo.jpg", false),
+        ] {
+            args.insert("path".to_string(), to_value(format!("screenshots/{}", name)).unwrap());
+            assert_eq!(static_fn.call(&args).unwrap()["is_screenshot"], *expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn can_read_pixel_aspect_ratio() {
+        let mut jpeg = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(4, 4))
+            .write_to(&mut jpeg, ImageOutputFormat::Jpeg(90))
+            .unwrap();
+        // A JFIF header with no unit and a density of 2 pixels horizontally for 3 vertically
+        let app0 = b"\xFF\xE0\x00\x10JFIF\x00\x01\x02\x00\x00\x02\x00\x03\x00\x00";
+        let mut anamorphic = jpeg[..2].to_vec();
+        anamorphic.extend_from_slice(app0);
+        anamorphic.extend_from_slice(&jpeg[2..]);
+        std::fs::write(TEST_CONTEXT.static_path.join("anamorphic.jpg"), anamorphic).unwrap();
+        RgbImage::new(4, 4).save(TEST_CONTEXT.static_path.join("square-pixels.png")).unwrap();
+
+        let static_fn = GetImageMeta::new(TEST_CONTEXT.static_path.clone(), &Config::default());
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("anamorphic.jpg").unwrap());
+        assert_eq!(static_fn.call(&args).unwrap()["pixel_aspect_ratio"], to_value(1.5).unwrap());
+        args.insert("path".to_string(), to_value("square-pixels.png").unwrap());
+        assert_eq!(static_fn.call(&args).unwrap()["pixel_aspect_ratio"], to_value(1.0).unwrap());
+    }
+
+    #[test]
+    fn can_get_image_metadata_of_root_relative_paths() {
+        let base_path = TEST_CONTEXT.static_path.join("root-relative-site");
+        create_directory(&base_path.join("content").join("blog")).unwrap();
+        create_directory(&base_path.join("static")).unwrap();
+        RgbImage::new(5, 3).save(base_path.join("static").join("gutenberg.png")).unwrap();
+        RgbImage::new(2, 7).save(base_path.join("content").join("blog").join("cover.png")).unwrap();
+
+        let static_fn = GetImageMeta::new(base_path.join("content"), &Config::default());
+        let width = |path: &str| {
+            let mut args = HashMap::new();
+            args.insert("path".to_string(), to_value(path).unwrap());
+            static_fn.call(&args).map(|meta| meta["width"].clone())
+        };
+        // Like the URL the static file is served at, or its path from the site root
+        assert_eq!(width("/gutenberg.png").unwrap(), to_value(5).unwrap());
+        assert_eq!(width("/static/gutenberg.png").unwrap(), to_value(5).unwrap());
+        assert_eq!(width("/blog/cover.png").unwrap(), to_value(2).unwrap());
+        assert_eq!(width("/content/blog/cover.png").unwrap(), to_value(2).unwrap());
+        assert_eq!(width("blog/cover.png").unwrap(), to_value(2).unwrap());
+        // Without the slash, the path stays relative to `content`
+        assert!(width("gutenberg.png").is_err());
+
+        for path in &["../static/gutenberg.png", "/../root-relative-site/static/gutenberg.png"] {
+            let err = format!("{:?}", width(path).unwrap_err());
+            assert!(err.contains("can't go outside of the site"), "{}", path);
+        }
+    }
+
+    #[test]
+    fn can_get_image_metadata_file_times() {
+        let path = TEST_CONTEXT.static_path.join("dated.png");
+        RgbImage::new(4, 4).save(&path).unwrap();
+        filetime::set_file_mtime(&path, filetime::FileTime::from_unix_time(1_600_000_000, 0))
+            .unwrap();
+
+        let static_fn = GetImageMeta::new(TEST_CONTEXT.static_path.clone(), &Config::default());
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("dated.png").unwrap());
+        let data = static_fn.call(&args).unwrap();
+        assert_eq!(data["mtime"], to_value("2020-09-13T12:26:40Z").unwrap());
+        assert!(data["ctime"].is_null() || data["ctime"].is_string());
+    }
+
+    #[test]
+    fn can_read_tiff_channels() {
+        RgbImage::new(4, 4)
+            .save_with_format(TEST_CONTEXT.static_path.join("channels.tiff"), ImageFormat::Tiff)
+            .unwrap();
+
+        let static_fn = GetImageMeta::new(TEST_CONTEXT.static_path.clone(), &Config::default());
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("channels.tiff").unwrap());
+        let data = static_fn.call(&args).unwrap();
+        assert_eq!(data["samples_per_pixel"], to_value(3).unwrap());
+        assert_eq!(data["photometric_interpretation"], to_value("rgb").unwrap());
+    }
+
+    #[test]
+    fn can_read_animation_frames() {
+        // A 1x1 GIF with a 2 colors palette, each frame is preceded by a graphic control
+        // extension with its delay in hundredths of a second
+        let mut gif = b"GIF89a\x01\x00\x01\x00\x80\x00\x00\x00\x00\x00\xff\xff\xff".to_vec();
+        for delay in &[10u8, 25] {
+            gif.extend_from_slice(&[0x21, 0xF9, 0x04, 0x00, *delay, 0x00, 0x00, 0x00]);
+            gif.extend_from_slice(b"\x2c\x00\x00\x00\x00\x01\x00\x01\x00\x00\x02\x02\x44\x01\x00");
+        }
+        gif.push(0x3B);
+        std::fs::write(TEST_CONTEXT.static_path.join("animated.gif"), gif).unwrap();
+
+        let static_fn = GetImageMeta::new(TEST_CONTEXT.static_path.clone(), &Config::default());
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("animated.gif").unwrap());
+        let data = static_fn.call(&args).unwrap();
+        assert_eq!(data["animation"]["frames"], to_value(2).unwrap());
+        assert_eq!(data["animation"]["delays"], to_value([100, 250]).unwrap());
+        assert_eq!(data["animation"]["duration_ms"], to_value(350).unwrap());
+
+        RgbImage::new(4, 4)
+            .save_with_format(TEST_CONTEXT.static_path.join("still.png"), ImageFormat::Png)
+            .unwrap();
+        args.insert("path".to_string(), to_value("still.png").unwrap());
+        let data = static_fn.call(&args).unwrap();
+        assert_eq!(data["animation"], Value::Null);
+        assert_eq!(data["interlaced"], to_value(false).unwrap());
+
+        args.insert("path".to_string(), to_value("animated.gif").unwrap());
+        assert_eq!(static_fn.call(&args).unwrap()["interlaced"], Value::Null);
+    }
+
+    #[test]
+    fn can_get_image_sort_key() {
+        let mut jpeg = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(4, 4))
+            .write_to(&mut jpeg, ImageOutputFormat::Jpeg(90))
+            .unwrap();
+        // A big-endian TIFF with a single DateTime entry pointing to the string after the IFD
+        let mut tiff = b"MM\x00\x2a\x00\x00\x00\x08\
+            \x00\x01\x01\x32\x00\x02\x00\x00\x00\x14\x00\x00\x00\x1a\
+            \x00\x00\x00\x00"
+            .to_vec();
+        tiff.extend_from_slice(b"2021:03:14 15:09:26\0");
+        let mut app1 = vec![0xFF, 0xE1];
+        app1.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+        app1.extend_from_slice(b"Exif\0\0");
+        app1.extend_from_slice(&tiff);
+        let mut dated = jpeg[..2].to_vec();
+        dated.extend(app1);
+        dated.extend_from_slice(&jpeg[2..]);
+        std::fs::write(TEST_CONTEXT.static_path.join("dated.jpg"), dated).unwrap();
+        std::fs::write(TEST_CONTEXT.static_path.join("undated.jpg"), jpeg).unwrap();
+
+        let static_fn = GetImageSortKey::new(TEST_CONTEXT.static_path.clone());
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("dated.jpg").unwrap());
+        assert_eq!(
+            static_fn.call(&args).unwrap(),
+            to_value("2021-03-14T15:09:26 dated.jpg").unwrap()
+        );
+
+        args.insert("path".to_string(), to_value("undated.jpg").unwrap());
+        assert_eq!(
+            static_fn.call(&args).unwrap(),
+            to_value("0000-00-00T00:00:00 undated.jpg").unwrap()
+        );
+    }
+
+    #[test]
+    fn can_apply_exif_crop_and_orientation() {
+        let base_path = TEST_CONTEXT.static_path.join("exif-transform-site");
+        create_directory(&base_path.join("content")).unwrap();
+        // Red on the left 10 pixels, cropped away by the EXIF data
+        let img =
+            RgbImage::from_fn(
+                40,
+                20,
+                |x, _| {
+                    if x < 10 {
+                        Rgb([255, 0, 0])
+                    } else {
+                        Rgb([0, 0, 255])
+                    }
+                },
+            );
+        let mut jpeg = Vec::new();
+        DynamicImage::ImageRgb8(img).write_to(&mut jpeg, ImageOutputFormat::Jpeg(90)).unwrap();
+        // A little-endian TIFF with the Orientation (rotated a quarter turn clockwise),
+        // DefaultCropOrigin (10, 0) and DefaultCropSize (30, 20) entries
+        let tiff = b"II\x2a\x00\x08\x00\x00\x00\x03\x00\
+            \x12\x01\x03\x00\x01\x00\x00\x00\x06\x00\x00\x00\
+            \x1f\xc6\x03\x00\x02\x00\x00\x00\x0a\x00\x00\x00\
+            \x20\xc6\x03\x00\x02\x00\x00\x00\x1e\x00\x14\x00\
+            \x00\x00\x00\x00";
+        let mut app1 = vec![0xFF, 0xE1];
+        app1.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+        app1.extend_from_slice(b"Exif\0\0");
+        app1.extend_from_slice(tiff);
+        let mut tagged = jpeg[..2].to_vec();
+        tagged.extend(app1);
+        tagged.extend_from_slice(&jpeg[2..]);
+        std::fs::write(base_path.join("content").join("raw-export.jpg"), tagged).unwrap();
+
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("raw-export.jpg").unwrap());
+        args.insert("width".to_string(), to_value(10).unwrap());
+        args.insert("op".to_string(), to_value("fit_width").unwrap());
+
+        // Only the orientation is applied by default, and nothing with `respect_exif=false`
+        let imageproc =
+            Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &Config::default())));
+        let static_fn = ResizeImage::new(imageproc.clone());
+        let turned = static_fn.call(&args).unwrap();
+        assert_eq!(turned["intrinsic_height"], to_value(20).unwrap());
+        args.insert("respect_exif".to_string(), to_value(false).unwrap());
+        let as_stored = static_fn.call(&args).unwrap();
+        assert_eq!(as_stored["intrinsic_height"], to_value(5).unwrap());
+        assert_ne!(turned["static_path"], as_stored["static_path"]);
+        imageproc.lock().unwrap().do_process().unwrap();
+        let output =
+            image::open(base_path.join(turned["static_path"].as_str().unwrap())).unwrap().to_rgb8();
+        assert_eq!(output.dimensions(), (10, 20));
+        // The red left side of the source is now at the top
+        assert!(output.get_pixel(5, 1).0[0] > 192);
+        args.remove("respect_exif");
+
+        let mut config = Config::default();
+        config.image_processing.apply_exif_transforms = true;
+        let imageproc = Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config)));
+        let response = ResizeImage::new(imageproc.clone()).call(&args).unwrap();
+        assert_eq!(response["intrinsic_height"], to_value(15).unwrap());
+        imageproc.lock().unwrap().do_process().unwrap();
+
+        let output = image::open(base_path.join(response["static_path"].as_str().unwrap()))
+            .unwrap()
+            .to_rgb8();
+        assert_eq!(output.dimensions(), (10, 15));
+        let center = output.get_pixel(5, 7).0;
+        assert!(center[0] < 64 && center[2] > 192);
+    }
+
+    #[test]
+    fn can_crop_with_percentages() {
+        let base_path = TEST_CONTEXT.static_path.join("crop-site");
+        create_directory(&base_path.join("content")).unwrap();
+        RgbImage::new(200, 100).save(base_path.join("content").join("wide.png")).unwrap();
+
+        let imageproc =
+            Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &Config::default())));
+        let static_fn = ResizeImage::new(imageproc.clone());
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("wide.png").unwrap());
+        args.insert("op".to_string(), to_value("crop").unwrap());
+        args.insert("crop".to_string(), to_value([0, 0, 50, 50]).unwrap());
+        args.insert("crop_unit".to_string(), to_value("percent").unwrap());
+        let response = static_fn.call(&args).unwrap();
+        imageproc.lock().unwrap().do_process().unwrap();
+
+        let output = base_path.join(response["static_path"].as_str().unwrap());
+        assert_eq!(image::image_dimensions(output).unwrap(), (100, 50));
+
+        args.insert("crop".to_string(), to_value([60, 0, 50, 50]).unwrap());
+        assert!(static_fn.call(&args).is_err());
+
+        // The partly covered pixels are kept: the bottom right half of 199x99 is 100x50 and
+        // still ends at the edge of the source
+        RgbImage::new(199, 99).save(base_path.join("content").join("odd.png")).unwrap();
+        args.insert("path".to_string(), to_value("odd.png").unwrap());
+        args.insert("crop".to_string(), to_value([50, 50, 50, 50]).unwrap());
+        let response = static_fn.call(&args).unwrap();
+        assert_eq!(response["width"], to_value(100).unwrap());
+        assert_eq!(response["height"], to_value(50).unwrap());
+        imageproc.lock().unwrap().do_process().unwrap();
+        let output = base_path.join(response["static_path"].as_str().unwrap());
+        assert_eq!(image::image_dimensions(output).unwrap(), (100, 50));
+    }
+
+    #[test]
+    fn can_distinguish_intrinsic_and_display_dimensions() {
+        let base_path = TEST_CONTEXT.static_path.join("dpr-site");
+        create_directory(&base_path.join("content")).unwrap();
+        RgbImage::new(300, 380).save(base_path.join("content").join("tall.png")).unwrap();
+
+        let mut config = Config::default();
+        config.image_processing.pixel_ratio = 2;
+        let imageproc = Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config)));
+        let static_fn = ResizeImage::new(imageproc.clone());
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("tall.png").unwrap());
+        args.insert("op".to_string(), to_value("fit_width").unwrap());
+        args.insert("width".to_string(), to_value(100).unwrap());
+        let response = static_fn.call(&args).unwrap();
+        assert_eq!(response["intrinsic_width"], to_value(200).unwrap());
+        assert_eq!(response["intrinsic_height"], to_value(253).unwrap());
+        assert_eq!(response["display_width"], to_value(100).unwrap());
+        assert_eq!(response["display_height"], to_value(127).unwrap());
+        assert_eq!(response["width"], to_value(100).unwrap());
+        assert_eq!(response["height"], to_value(127).unwrap());
+
+        imageproc.lock().unwrap().do_process().unwrap();
+        let output = base_path.join(response["static_path"].as_str().unwrap());
+        assert_eq!(image::image_dimensions(output).unwrap(), (200, 253));
+    }
+
+    #[test]
+    fn can_preload_markdown_images() {
+        let base_path = TEST_CONTEXT.static_path.join("preload-site");
+        create_directory(&base_path.join("content")).unwrap();
+        RgbImage::new(100, 50).save(base_path.join("content").join("hero.png")).unwrap();
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("hero.png").unwrap());
+
+        let mut config = Config::default();
+        let imageproc = Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config)));
+        let err = GetImagePreload::new(imageproc).call(&args).unwrap_err();
+        assert!(err.to_string().contains("`markdown_images` needs to be set"));
+
+        config.image_processing.markdown_images =
+            Some(MarkdownImages { widths: vec![40, 80], ..MarkdownImages::default() });
+        let imageproc = Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config)));
+        let link = GetImagePreload::new(imageproc.clone()).call(&args).unwrap();
+        let link = link.as_str().unwrap();
+        assert!(link.starts_with(r#"<link rel="preload" as="image" href=""#));
+        assert!(link.contains(" 40w, "));
+        assert!(link.ends_with(r#" 80w" imagesizes="(max-width: 80px) 100vw, 80px" />"#));
+        assert_eq!(imageproc.lock().unwrap().num_img_ops(), 2);
+    }
+
+    #[test]
+    fn can_preload_the_sets_of_the_other_functions() {
+        let base_path = TEST_CONTEXT.static_path.join("preload-set-site");
+        create_directory(&base_path.join("content")).unwrap();
+        RgbImage::new(200, 100).save(base_path.join("content").join("hero.png")).unwrap();
+        let mut config = Config::default();
+        config.image_processing.art_direction.insert(
+            "hero".to_string(),
+            vec![
+                ArtDirectionVariant {
+                    media: Some("(max-width: 600px)".to_string()),
+                    ratio: "1:1".to_string(),
+                    width: 50,
+                },
+                ArtDirectionVariant { media: None, ratio: "2:1".to_string(), width: 100 },
+            ],
+        );
+        let imageproc = Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config)));
+        let preload = GetImagePreload::new(imageproc.clone());
+
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("hero.png").unwrap());
+        args.insert("widths".to_string(), to_value([50, 100]).unwrap());
+        let srcset = ResizeImageSrcset::new(imageproc.clone()).call(&args).unwrap();
+        let mut args = HashMap::new();
+        args.insert("set".to_string(), srcset.clone());
+        args.insert("sizes".to_string(), to_value("50vw").unwrap());
+        let link = preload.call(&args).unwrap();
+        assert_eq!(
+            link,
+            to_value(format!(
+                r#"<link rel="preload" as="image" href="{}" imagesrcset="{}" imagesizes="50vw" />"#,
+                srcset["images"][0]["url"].as_str().unwrap(),
+                srcset["srcset"].as_str().unwrap()
+            ))
+            .unwrap()
+        );
+
+        let mut resize_args = HashMap::new();
+        resize_args.insert("path".to_string(), to_value("hero.png").unwrap());
+        resize_args.insert("art_direction".to_string(), to_value("hero").unwrap());
+        let picture = ResizeImage::new(imageproc).call(&resize_args).unwrap();
+        args.insert("set".to_string(), picture.clone());
+        assert!(preload.call(&args).is_err());
+        args.remove("sizes");
+        let links = preload.call(&args).unwrap();
+        let links: Vec<_> = links.as_str().unwrap().lines().collect();
+        assert_eq!(
+            links,
+            vec![
+                format!(
+                    r#"<link rel="preload" as="image" href="{}" media="(max-width: 600px)" />"#,
+                    picture["sources"][0]["url"].as_str().unwrap()
+                ),
+                format!(
+                    r#"<link rel="preload" as="image" href="{}" media="not (max-width: 600px)" />"#,
+                    picture["fallback"]["url"].as_str().unwrap()
+                ),
+            ]
+        );
+
+        args.insert("set".to_string(), to_value("hero.png").unwrap());
+        assert!(preload.call(&args).is_err());
+        args.insert("path".to_string(), to_value("hero.png").unwrap());
+        assert!(preload.call(&args).is_err());
+    }
+
+    #[test]
+    fn can_count_image_variants_of_a_page() {
+        let base_path = TEST_CONTEXT.static_path.join("variants-site");
+        create_directory(&base_path.join("content")).unwrap();
+        RgbImage::new(100, 50).save(base_path.join("content").join("hero.png")).unwrap();
+        let imageproc =
+            Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &Config::default())));
+        let resize = ResizeImage::new(imageproc.clone());
+        let count = CountImageVariants::new(imageproc);
+        let resize_args = |width: u32| {
+            let mut args = HashMap::new();
+            args.insert("path".to_string(), to_value("hero.png").unwrap());
+            args.insert("width".to_string(), to_value(width).unwrap());
+            args.insert("op".to_string(), to_value("scale").unwrap());
+            args
+        };
+
+        assert!(count.call(&HashMap::new()).is_err());
+        imageproc::render_for_page("posts/a.md", || {
+            for width in &[20, 40, 20] {
+                resize.call(&resize_args(*width)).unwrap();
+            }
+            assert_eq!(count.call(&HashMap::new()).unwrap(), to_value(2).unwrap());
+        });
+        // The repeated call is still counted for the other page
+        imageproc::render_for_page("posts/b.md", || {
+            resize.call(&resize_args(20)).unwrap();
+            assert_eq!(count.call(&HashMap::new()).unwrap(), to_value(1).unwrap());
+        });
+
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("posts/a.md").unwrap());
+        assert_eq!(count.call(&args).unwrap(), to_value(2).unwrap());
+        args.insert("path".to_string(), to_value("posts/c.md").unwrap());
+        assert_eq!(count.call(&args).unwrap(), to_value(0).unwrap());
+    }
+
+    #[test]
+    fn can_check_social_card_dimensions() {
+        RgbImage::new(1200, 630).save(TEST_CONTEXT.static_path.join("card.png")).unwrap();
+        RgbImage::new(600, 315).save(TEST_CONTEXT.static_path.join("small-card.png")).unwrap();
+        RgbImage::new(150, 150).save(TEST_CONTEXT.static_path.join("tiny-card.png")).unwrap();
+
+        let static_fn = CheckSocialCard::new(TEST_CONTEXT.static_path.clone());
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("card.png").unwrap());
+        let res = static_fn.call(&args).unwrap();
+        assert_eq!(res["valid"], Value::Bool(true));
+        assert_eq!(res["recommended"], Value::Bool(true));
+        assert_eq!(res["reason"], to_value("").unwrap());
+
+        args.insert("path".to_string(), to_value("small-card.png").unwrap());
+        let res = static_fn.call(&args).unwrap();
+        assert_eq!(res["valid"], Value::Bool(true));
+        assert_eq!(res["recommended"], Value::Bool(false));
+
+        args.insert("path".to_string(), to_value("tiny-card.png").unwrap());
+        let res = static_fn.call(&args).unwrap();
+        assert_eq!(res["valid"], Value::Bool(false));
+        assert_eq!(
+            res["reason"],
+            to_value("150x150 is smaller than the minimum of 200x200").unwrap()
+        );
+    }
+
+    #[test]
+    fn reproducible_builds_give_identical_images() {
+        let base_path = TEST_CONTEXT.static_path.join("reproducible-site");
+        create_directory(&base_path.join("content")).unwrap();
+        let gradient = RgbImage::from_fn(64, 48, |x, y| Rgb([(x * 4) as u8, (y * 5) as u8, 128]));
+        gradient.save(base_path.join("content").join("photo.jpg")).unwrap();
+        gradient.save(base_path.join("content").join("logo.png")).unwrap();
+        let mut config = Config::default();
+        config.image_processing.reproducible = true;
+
+        let build = |sizes: &[u32]| {
+            let imageproc = Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config)));
+            let static_fn = ResizeImage::new(imageproc.clone());
+            for source in &["photo.jpg", "logo.png"] {
+                for size in sizes {
+                    let mut args = HashMap::new();
+                    args.insert("path".to_string(), to_value(source).unwrap());
+                    args.insert("width".to_string(), to_value(size).unwrap());
+                    args.insert("op".to_string(), to_value("fit_width").unwrap());
+                    static_fn.call(&args).unwrap();
+                }
+            }
+            imageproc.lock().unwrap().do_process().unwrap();
+
+            let processed = base_path.join("static").join("processed_images");
+            let mut outputs = std::collections::BTreeMap::new();
+            for entry in std::fs::read_dir(&processed).unwrap() {
+                let path = entry.unwrap().path();
+                outputs.insert(path.file_name().unwrap().to_owned(), std::fs::read(&path).unwrap());
+            }
+            remove_dir_all(&processed).unwrap();
+            outputs
+        };
+
+        // Asking for the images in another order doesn't change anything
+        let first = build(&[16, 32, 48]);
+        let second = build(&[48, 16, 32]);
+        assert_eq!(first.len(), 7);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn can_make_image_set_css() {
+        let base_path = TEST_CONTEXT.static_path.join("image-set-site");
+        create_directory(&base_path.join("content")).unwrap();
+        RgbImage::new(400, 200).save(base_path.join("content").join("hero.png")).unwrap();
+
+        let imageproc =
+            Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &Config::default())));
+        let static_fn = ImageSetCss::new(imageproc.clone());
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("hero.png").unwrap());
+        args.insert("width".to_string(), to_value(100).unwrap());
+        let css = static_fn.call(&args).unwrap();
+        let css = css.as_str().unwrap();
+        assert!(css.starts_with("background-image: url(\""));
+        assert!(css.contains("; background-image: image-set(url(\""));
+        assert!(css.contains("\") 1x, url(\""));
+        assert!(css.ends_with("\") 2x);"));
+        assert!(!css.contains("type("));
+
+        args.insert("formats".to_string(), to_value(["png", "jpg"]).unwrap());
+        args.insert("densities".to_string(), to_value([1]).unwrap());
+        let css = static_fn.call(&args).unwrap();
+        let css = css.as_str().unwrap();
+        assert!(css.contains("type(\"image/png\") 1x, url(\""));
+        assert!(css.ends_with("type(\"image/jpeg\") 1x);"));
+
+        imageproc.lock().unwrap().do_process().unwrap();
+        let processed = base_path.join("static").join("processed_images");
+        let mut widths: Vec<_> = std::fs::read_dir(&processed)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| p.extension().is_some())
+            .map(|p| image::image_dimensions(p).unwrap().0)
+            .collect();
+        widths.sort_unstable();
+        assert_eq!(widths, vec![100, 100, 200]);
+    }
+
+    #[test]
+    fn can_fall_back_to_original_images() {
+        let base_path = TEST_CONTEXT.static_path.join("fallback-site");
+        create_directory(&base_path.join("content").join("photos")).unwrap();
+        create_file(&base_path.join("content").join("photos").join("broken.jpg"), "not a jpeg")
+            .unwrap();
+        let mut config = Config::default();
+        config.image_processing.fallback_to_original = true;
+
+        let imageproc = Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config)));
+        let static_fn = ResizeImage::new(imageproc.clone());
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("photos/broken.jpg").unwrap());
+        args.insert("width".to_string(), to_value(100).unwrap());
+        args.insert("op".to_string(), to_value("fit_width").unwrap());
+        let response = static_fn.call(&args).unwrap();
+        assert_eq!(
+            response["static_path"],
+            to_value("static/processed_images/originals/photos/broken.jpg").unwrap()
+        );
+        assert_eq!(response["intrinsic_width"], to_value(0).unwrap());
+        assert!(base_path.join("static/processed_images/originals/photos/broken.jpg").exists());
+
+        let imageproc =
+            Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &Config::default())));
+        assert!(ResizeImage::new(imageproc).call(&args).is_err());
+
+        // Copied inside of `originals` even though it's outside of `content`
+        create_directory(&base_path.join("assets").join("icons")).unwrap();
+        create_file(&base_path.join("assets").join("icons").join("broken.jpg"), "not a jpeg")
+            .unwrap();
+        config.image_processing.asset_dirs = vec!["assets".to_string()];
+        let imageproc = Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config)));
+        args.insert("path".to_string(), to_value("icons/broken.jpg").unwrap());
+        let response = ResizeImage::new(imageproc).call(&args).unwrap();
+        assert_eq!(
+            response["static_path"],
+            to_value("static/processed_images/originals/_root/assets/icons/broken.jpg").unwrap()
+        );
+        assert!(base_path
+            .join("static/processed_images/originals/_root/assets/icons/broken.jpg")
+            .exists());
+        assert!(!base_path.join("static/processed_images/assets").exists());
+
+        // Truncated, it only fails once processed, after its URL was given out
+        let mut png = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(64, 64))
+            .write_to(&mut png, ImageOutputFormat::Png)
+            .unwrap();
+        png.truncate(png.len() / 2);
+        std::fs::write(base_path.join("content").join("photos").join("truncated.png"), png)
+            .unwrap();
+        let imageproc = Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config)));
+        args.insert("path".to_string(), to_value("photos/truncated.png").unwrap());
+        let response = ResizeImage::new(imageproc.clone()).call(&args).unwrap();
+        imageproc.lock().unwrap().do_process().unwrap();
+        let original = base_path.join("static/processed_images/originals/photos/truncated.png");
+        assert!(original.exists());
+        let target = base_path.join(response["static_path"].as_str().unwrap());
+        assert_eq!(std::fs::read(target).unwrap(), std::fs::read(original).unwrap());
+    }
+
+    #[test]
+    fn can_skip_corrupt_images() {
+        let base_path = TEST_CONTEXT.static_path.join("corrupt-site");
+        create_directory(&base_path.join("content")).unwrap();
+        let mut png = Vec::new();
+        let noise = RgbImage::from_fn(64, 64, |x, y| Rgb([(x * 7) as u8, (y * 13) as u8, 0]));
+        DynamicImage::ImageRgb8(noise).write_to(&mut png, ImageOutputFormat::Png).unwrap();
+        // The header is still there so only decoding the pixels fails
+        png.truncate(png.len() / 2);
+        std::fs::write(base_path.join("content").join("truncated.png"), png).unwrap();
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("truncated.png").unwrap());
+        args.insert("width".to_string(), to_value(10).unwrap());
+        args.insert("op".to_string(), to_value("fit_width").unwrap());
+
+        let mut config = Config::default();
+        config.image_processing.skip_corrupt_images = true;
+        let imageproc = Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config)));
+        assert_eq!(ResizeImage::new(imageproc.clone()).call(&args).unwrap(), Value::Null);
+        assert_eq!(imageproc.lock().unwrap().num_img_ops(), 0);
+        let static_fn = GetImageMeta::new(base_path.join("content"), &config);
+        assert_eq!(static_fn.call(&args).unwrap(), Value::Null);
+
+        let config = Config::default();
+        let static_fn = GetImageMeta::new(base_path.join("content"), &config);
+        assert!(static_fn.call(&args).is_err());
+    }
+
+    #[test]
+    fn refuses_images_over_pixel_limit() {
+        let base_path = TEST_CONTEXT.static_path.join("pixel-limit-site");
+        create_directory(&base_path.join("content")).unwrap();
+        RgbImage::new(100, 50).save(base_path.join("content").join("big.png")).unwrap();
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("big.png").unwrap());
+        args.insert("width".to_string(), to_value(10).unwrap());
+        args.insert("op".to_string(), to_value("fit_width").unwrap());
+
+        let mut config = Config::default();
+        config.image_processing.max_pixels = 5000;
+        let imageproc = Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config)));
+        assert!(ResizeImage::new(imageproc.clone()).call(&args).is_ok());
+        assert!(GetImageMeta::new(base_path.join("content"), &config).call(&args).is_ok());
+
+        config.image_processing.max_pixels = 4999;
+        config.image_processing.skip_corrupt_images = true;
+        let imageproc = Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config)));
+        let err = ResizeImage::new(imageproc.clone()).call(&args).unwrap_err();
+        assert!(format!("{:?}", err).contains("image exceeds safe pixel limit"));
+        assert_eq!(imageproc.lock().unwrap().num_img_ops(), 0);
+        let err = GetImageMeta::new(base_path.join("content"), &config).call(&args).unwrap_err();
+        assert!(format!("{:?}", err).contains("image exceeds safe pixel limit"));
+    }
+
+    #[test]
+    fn can_resize_embedded_thumbnails() {
+        let base_path = TEST_CONTEXT.static_path.join("thumbnail-site");
+        create_directory(&base_path.join("content")).unwrap();
+        let encode = |img: RgbImage| {
+            let mut jpeg = Vec::new();
+            DynamicImage::ImageRgb8(img).write_to(&mut jpeg, ImageOutputFormat::Jpeg(95)).unwrap();
+            jpeg
+        };
+        let photo = encode(RgbImage::from_pixel(400, 200, Rgb([255, 0, 0])));
+        // A green thumbnail, so it's clear which one was resized
+        let thumbnail = encode(RgbImage::from_pixel(100, 50, Rgb([0, 255, 0])));
+        // An empty first IFD pointing to a second one with the offset and length of the thumbnail
+        let mut tiff = b"II\x2a\x00\x08\x00\x00\x00\x00\x00\x0e\x00\x00\x00\x02\x00".to_vec();
+        for (tag, value) in &[(0x0201u16, 44u32), (0x0202, thumbnail.len() as u32)] {
+            tiff.extend_from_slice(&tag.to_le_bytes());
+            tiff.extend_from_slice(&[4, 0, 1, 0, 0, 0]);
+            tiff.extend_from_slice(&value.to_le_bytes());
         }
-        Self { taxonomies, library, default_lang: default_lang.to_string() }
+        tiff.extend_from_slice(&[0; 4]);
+        tiff.extend_from_slice(&thumbnail);
+        let mut app1 = vec![0xFF, 0xE1];
+        app1.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+        app1.extend_from_slice(b"Exif\0\0");
+        app1.extend_from_slice(&tiff);
+        let mut jpeg = photo[..2].to_vec();
+        jpeg.extend(app1);
+        jpeg.extend_from_slice(&photo[2..]);
+        std::fs::write(base_path.join("content").join("photo.jpg"), jpeg).unwrap();
+
+        let resize = |config: &Config, width: u32| {
+            let imageproc = Arc::new(Mutex::new(imageproc::Processor::new(&base_path, config)));
+            let mut args = HashMap::new();
+            args.insert("path".to_string(), to_value("photo.jpg").unwrap());
+            args.insert("width".to_string(), to_value(width).unwrap());
+            args.insert("op".to_string(), to_value("fit_width").unwrap());
+            let response = ResizeImage::new(imageproc.clone()).call(&args).unwrap();
+            imageproc.lock().unwrap().do_process().unwrap();
+            let output = base_path.join(response["static_path"].as_str().unwrap());
+            let img = image::open(output).unwrap().to_rgb8();
+            assert_eq!(img.dimensions(), (width, width / 2));
+            img.get_pixel(width / 2, width / 4).0
+        };
+        let is_green = |[r, g, _]: [u8; 3]| g > 200 && r < 50;
+
+        let mut config = Config::default();
+        assert!(!is_green(resize(&config, 80)));
+        config.image_processing.use_embedded_thumbnails = true;
+        assert!(is_green(resize(&config, 80)));
+        // Too small for that width
+        assert!(!is_green(resize(&config, 200)));
+    }
+
+    #[test]
+    fn ignores_alpha_quality_without_separate_alpha() {
+        let base_path = TEST_CONTEXT.static_path.join("alpha-quality-site");
+        create_directory(&base_path.join("content")).unwrap();
+        RgbaImage::new(40, 40).save(base_path.join("content").join("shadow.png")).unwrap();
+
+        let imageproc =
+            Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &Config::default())));
+        let static_fn = ResizeImage::new(imageproc);
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("shadow.png").unwrap());
+        args.insert("width".to_string(), to_value(20).unwrap());
+        args.insert("op".to_string(), to_value("fit_width").unwrap());
+        let url = static_fn.call(&args).unwrap()["url"].clone();
+        // PNG is lossless so the image stays the same
+        args.insert("alpha_quality".to_string(), to_value(90).unwrap());
+        assert_eq!(static_fn.call(&args).unwrap()["url"], url);
+
+        args.insert("alpha_quality".to_string(), to_value(0).unwrap());
+        assert!(static_fn.call(&args).is_err());
+    }
+
+    #[test]
+    fn can_keep_metadata_of_source() {
+        let base_path = TEST_CONTEXT.static_path.join("strip-metadata-site");
+        create_directory(&base_path.join("content")).unwrap();
+        let mut jpeg = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(40, 20))
+            .write_to(&mut jpeg, ImageOutputFormat::Jpeg(90))
+            .unwrap();
+        // A TIFF with an Orientation entry asking for a quarter turn clockwise and a Software
+        // entry pointing to the string after the IFD
+        let mut tiff = b"II\x2a\x00\x08\x00\x00\x00\x02\x00\
+            \x12\x01\x03\x00\x01\x00\x00\x00\x06\x00\x00\x00\
+            \x31\x01\x02\x00\x0a\x00\x00\x00\x26\x00\x00\x00\
+            \x00\x00\x00\x00"
+            .to_vec();
+        tiff.extend_from_slice(b"Zola test\0");
+        let mut app1 = vec![0xFF, 0xE1];
+        app1.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+        app1.extend_from_slice(b"Exif\0\0");
+        app1.extend_from_slice(&tiff);
+        let mut tagged = jpeg[..2].to_vec();
+        tagged.extend(app1);
+        tagged.extend_from_slice(&jpeg[2..]);
+        std::fs::write(base_path.join("content").join("phone.jpg"), tagged).unwrap();
+
+        let config = Config::default();
+        let imageproc = Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config)));
+        let static_fn = ResizeImage::new(imageproc.clone());
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("phone.jpg").unwrap());
+        args.insert("width".to_string(), to_value(10).unwrap());
+        args.insert("op".to_string(), to_value("fit_width").unwrap());
+        let stripped = static_fn.call(&args).unwrap();
+        args.insert("strip_metadata".to_string(), to_value(false).unwrap());
+        let kept = static_fn.call(&args).unwrap();
+        args.insert("format".to_string(), to_value("png").unwrap());
+        let kept_png = static_fn.call(&args).unwrap();
+        assert_ne!(stripped["url"], kept["url"]);
+        imageproc.lock().unwrap().do_process().unwrap();
+
+        let meta = GetImageMeta::new(base_path.clone(), &config);
+        let read = |response: &Value| {
+            let mut args = HashMap::new();
+            args.insert("path".to_string(), response["static_path"].clone());
+            meta.call(&args).unwrap()
+        };
+        assert_eq!(read(&stripped)["software"], Value::Null);
+        for kept in &[kept, kept_png] {
+            let data = read(kept);
+            assert_eq!(data["software"], to_value("Zola test").unwrap());
+            // Already turned, so it isn't turned again
+            assert_eq!(data["orientation"], to_value(1).unwrap());
+            assert_eq!(data["width"], to_value(10).unwrap());
+        }
+    }
+
+    #[test]
+    fn can_keep_or_apply_the_color_profile_of_source() {
+        let base_path = TEST_CONTEXT.static_path.join("color-profile-site");
+        create_directory(&base_path.join("content")).unwrap();
+        // A profile with the primaries of sRGB but linear curves, so a mid gray gets lighter
+        // once converted to sRGB
+        let mut profile = vec![0; 128];
+        profile[16..20].copy_from_slice(b"RGB ");
+        profile.extend_from_slice(&6u32.to_be_bytes());
+        let primaries = [
+            (b"rXYZ", [0.4361, 0.2225, 0.0139]),
+            (b"gXYZ", [0.3851, 0.7169, 0.0971]),
+            (b"bXYZ", [0.1431, 0.0606, 0.7141]),
+        ];
+        let mut data = Vec::new();
+        let data_start = 132 + 6 * 12;
+        for (signature, xyz) in &primaries {
+            profile.extend_from_slice(*signature);
+            profile.extend_from_slice(&((data_start + data.len()) as u32).to_be_bytes());
+            profile.extend_from_slice(&20u32.to_be_bytes());
+            data.extend_from_slice(b"XYZ \0\0\0\0");
+            for v in xyz {
+                data.extend_from_slice(&((v * 65536.0) as i32).to_be_bytes());
+            }
+        }
+        for signature in &[b"rTRC", b"gTRC", b"bTRC"] {
+            profile.extend_from_slice(*signature);
+            profile.extend_from_slice(&((data_start + data.len()) as u32).to_be_bytes());
+            profile.extend_from_slice(&12u32.to_be_bytes());
+            data.extend_from_slice(b"curv\0\0\0\0\0\0\0\0");
+        }
+        profile.extend(data);
+        let mut jpeg = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(20, 20, Rgb([128, 128, 128])))
+            .write_to(&mut jpeg, ImageOutputFormat::Jpeg(95))
+            .unwrap();
+        let mut app2 = vec![0xFF, 0xE2];
+        app2.extend_from_slice(&((profile.len() + 16) as u16).to_be_bytes());
+        app2.extend_from_slice(b"ICC_PROFILE\0\x01\x01");
+        app2.extend_from_slice(&profile);
+        let mut tagged = jpeg[..2].to_vec();
+        tagged.extend(app2.clone());
+        tagged.extend_from_slice(&jpeg[2..]);
+        std::fs::write(base_path.join("content").join("wide.jpg"), tagged).unwrap();
+        // The same profile claiming to be for CMYK images
+        let cmyk_start = app2.windows(4).position(|w| w == b"RGB ").unwrap();
+        app2[cmyk_start..cmyk_start + 4].copy_from_slice(b"CMYK");
+        let mut cmyk = jpeg[..2].to_vec();
+        cmyk.extend(app2);
+        cmyk.extend_from_slice(&jpeg[2..]);
+        std::fs::write(base_path.join("content").join("print.jpg"), cmyk).unwrap();
+        std::fs::write(base_path.join("content").join("plain.jpg"), jpeg).unwrap();
+
+        let config = Config::default();
+        let imageproc = Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config)));
+        let static_fn = ResizeImage::new(imageproc.clone());
+        let resize_to = |path: &str, color_profile: Option<&str>, format: &str| {
+            let mut args = HashMap::new();
+            args.insert("path".to_string(), to_value(path).unwrap());
+            args.insert("width".to_string(), to_value(10).unwrap());
+            args.insert("op".to_string(), to_value("fit_width").unwrap());
+            args.insert("format".to_string(), to_value(format).unwrap());
+            if let Some(color_profile) = color_profile {
+                args.insert("color_profile".to_string(), to_value(color_profile).unwrap());
+            }
+            static_fn.call(&args)
+        };
+        let resize =
+            |path: &str, color_profile: Option<&str>| resize_to(path, color_profile, "auto");
+        let stripped = resize("wide.jpg", None).unwrap();
+        let preserved = resize("wide.jpg", Some("preserve")).unwrap();
+        let converted = resize("wide.jpg", Some("srgb")).unwrap();
+        assert_eq!(resize("wide.jpg", Some("strip")).unwrap()["url"], stripped["url"]);
+        assert_ne!(preserved["url"], stripped["url"]);
+        assert_ne!(converted["url"], stripped["url"]);
+        assert_ne!(converted["url"], preserved["url"]);
+        // Nothing to keep or convert
+        assert_eq!(
+            resize("plain.jpg", Some("preserve")).unwrap()["url"],
+            resize("plain.jpg", None).unwrap()["url"]
+        );
+        // A CMYK profile can't describe the RGB pixels the JPEG is decoded to
+        assert_eq!(
+            resize("print.jpg", Some("preserve")).unwrap()["url"],
+            resize("print.jpg", None).unwrap()["url"]
+        );
+        assert!(resize("wide.jpg", Some("p3")).is_err());
+        let preserved_png = resize_to("wide.jpg", Some("preserve"), "png").unwrap();
+        imageproc.lock().unwrap().do_process().unwrap();
+
+        let output = |response: &Value| base_path.join(response["static_path"].as_str().unwrap());
+        let has_profile = |response: &Value| {
+            std::fs::read(output(response)).unwrap().windows(11).any(|w| w == b"ICC_PROFILE")
+        };
+        assert!(!has_profile(&stripped));
+        assert!(has_profile(&preserved));
+        assert!(!has_profile(&converted));
+        let gray = |response: &Value| {
+            image::open(output(response)).unwrap().to_rgb8().get_pixel(5, 5).0[0]
+        };
+        assert!((gray(&stripped) as i32 - 128).abs() < 8);
+        // 128 is 50% linear light, which is 188 in sRGB
+        assert!((gray(&converted) as i32 - 188).abs() < 8);
+
+        // The gray pixels of an auto PNG stay RGB to keep its RGB profile
+        std::fs::copy(output(&preserved_png), base_path.join("content").join("wide.png")).unwrap();
+        let auto_png = resize("wide.png", Some("preserve")).unwrap();
+        imageproc.lock().unwrap().do_process().unwrap();
+        let png = std::fs::read(output(&auto_png)).unwrap();
+        assert!(png.windows(4).any(|w| w == b"iCCP"));
+        assert_eq!(image::load_from_memory(&png).unwrap().color(), ColorType::Rgb8);
+    }
+
+    #[test]
+    fn can_only_keep_the_channels_an_auto_png_uses() {
+        let base_path = TEST_CONTEXT.static_path.join("narrow-png-site");
+        create_directory(&base_path.join("content")).unwrap();
+        RgbaImage::from_pixel(20, 20, Rgba([255, 0, 0, 255]))
+            .save(base_path.join("content").join("opaque.png"))
+            .unwrap();
+
+        let imageproc =
+            Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &Config::default())));
+        let static_fn = ResizeImage::new(imageproc.clone());
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("opaque.png").unwrap());
+        args.insert("width".to_string(), to_value(10).unwrap());
+        args.insert("op".to_string(), to_value("fit_width").unwrap());
+        let auto = static_fn.call(&args).unwrap();
+        assert_eq!(auto["format"], to_value("png").unwrap());
+        args.insert("format".to_string(), to_value("png").unwrap());
+        let png = static_fn.call(&args).unwrap();
+        assert_ne!(auto["url"], png["url"]);
+        imageproc.lock().unwrap().do_process().unwrap();
+
+        let color = |response: &Value| {
+            image::open(base_path.join(response["static_path"].as_str().unwrap())).unwrap().color()
+        };
+        assert_eq!(color(&auto), ColorType::Rgb8);
+        assert_eq!(color(&png), ColorType::Rgba8);
+    }
+
+    #[test]
+    fn can_dedup_identical_sources() {
+        let base_path = TEST_CONTEXT.static_path.join("dedup-site");
+        for section in &["a", "b"] {
+            create_directory(&base_path.join("content").join(section)).unwrap();
+            RgbImage::from_pixel(40, 20, Rgb([0, 0, 255]))
+                .save(base_path.join("content").join(section).join("hero.png"))
+                .unwrap();
+        }
+        let resize = |config: &Config| {
+            let imageproc = Arc::new(Mutex::new(imageproc::Processor::new(&base_path, config)));
+            let static_fn = ResizeImage::new(imageproc.clone());
+            let mut args = HashMap::new();
+            args.insert("width".to_string(), to_value(10).unwrap());
+            args.insert("op".to_string(), to_value("fit_width").unwrap());
+            let urls: Vec<_> = ["a/hero.png", "b/hero.png"]
+                .iter()
+                .map(|path| {
+                    args.insert("path".to_string(), to_value(path).unwrap());
+                    static_fn.call(&args).unwrap()["url"].clone()
+                })
+                .collect();
+            let num_img_ops = imageproc.lock().unwrap().num_img_ops();
+            (urls, num_img_ops)
+        };
+
+        let mut config = Config::default();
+        let (urls, num_img_ops) = resize(&config);
+        assert_ne!(urls[0], urls[1]);
+        assert_eq!(num_img_ops, 2);
+
+        config.image_processing.dedup_identical_sources = true;
+        let (urls, num_img_ops) = resize(&config);
+        assert_eq!(urls[0], urls[1]);
+        assert_eq!(num_img_ops, 1);
+        // The same in every build
+        assert_eq!(resize(&config).0, urls);
+    }
+
+    #[test]
+    fn can_process_images_on_a_set_number_of_threads() {
+        let base_path = TEST_CONTEXT.static_path.join("threads-site");
+        create_directory(&base_path.join("content")).unwrap();
+        RgbImage::new(64, 64).save(base_path.join("content").join("square.png")).unwrap();
+        let mut config = Config::default();
+        config.image_processing.threads = Some(2);
+
+        let imageproc = Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config)));
+        let static_fn = ResizeImage::new(imageproc.clone());
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("square.png").unwrap());
+        args.insert("op".to_string(), to_value("fit_width").unwrap());
+        let outputs: Vec<_> = (1..=5)
+            .map(|width| {
+                args.insert("width".to_string(), to_value(width * 8).unwrap());
+                let response = static_fn.call(&args).unwrap();
+                base_path.join(response["static_path"].as_str().unwrap())
+            })
+            .collect();
+        imageproc.lock().unwrap().do_process().unwrap();
+        for (width, output) in (1..=5).zip(outputs) {
+            assert_eq!(image::image_dimensions(output).unwrap(), (width * 8, width * 8));
+        }
+    }
+
+    #[test]
+    fn can_count_image_cache_hits() {
+        let base_path = TEST_CONTEXT.static_path.join("cache-stats-site");
+        create_directory(&base_path.join("content")).unwrap();
+        RgbImage::new(64, 64).save(base_path.join("content").join("square.png")).unwrap();
+        let build = |widths: &[u32]| {
+            let imageproc =
+                Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &Config::default())));
+            let static_fn = ResizeImage::new(imageproc.clone());
+            for width in widths {
+                let mut args = HashMap::new();
+                args.insert("path".to_string(), to_value("square.png").unwrap());
+                args.insert("op".to_string(), to_value("fit_width").unwrap());
+                args.insert("width".to_string(), to_value(width).unwrap());
+                static_fn.call(&args).unwrap();
+            }
+            let mut imageproc = imageproc.lock().unwrap();
+            imageproc.do_process().unwrap();
+            let stats = imageproc.processing_stats();
+            (stats.cache_hits, stats.processed, stats.hit_percentage())
+        };
+
+        assert_eq!(build(&[8, 16]), (0, 2, 0));
+        assert_eq!(build(&[8, 16, 32]), (2, 1, 66));
+    }
+
+    #[test]
+    fn can_sum_up_the_processing_of_images() {
+        let base_path = TEST_CONTEXT.static_path.join("processing-stats-site");
+        create_directory(&base_path.join("content")).unwrap();
+        let _ = remove_dir_all(base_path.join("static"));
+        RgbImage::new(64, 64).save(base_path.join("content").join("square.png")).unwrap();
+        std::fs::write(base_path.join("content").join("broken.png"), b"not a png").unwrap();
+        let mut config = Config::default();
+        config.image_processing.fallback_to_original = true;
+        let build = |paths: &[&str]| {
+            let imageproc = Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config)));
+            let static_fn = ResizeImage::new(imageproc.clone());
+            for path in paths {
+                let mut args = HashMap::new();
+                args.insert("path".to_string(), to_value(path).unwrap());
+                args.insert("op".to_string(), to_value("fit_width").unwrap());
+                args.insert("width".to_string(), to_value(16).unwrap());
+                static_fn.call(&args).unwrap();
+            }
+            let mut imageproc = imageproc.lock().unwrap();
+            imageproc.do_process().unwrap();
+            imageproc.processing_stats()
+        };
+
+        let stats = build(&["square.png", "broken.png"]);
+        assert_eq!((stats.processed, stats.cache_hits, stats.skipped), (1, 0, 1));
+        // The copied original isn't up to date, it couldn't be processed
+        assert_eq!(stats.hit_percentage(), 0);
+        let written = std::fs::read_dir(base_path.join("static").join("processed_images"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.is_file())
+            .unwrap();
+        assert_eq!(stats.bytes_written, std::fs::metadata(written).unwrap().len());
+        let stats = build(&["square.png"]);
+        assert_eq!((stats.processed, stats.cache_hits, stats.skipped), (0, 1, 0));
+        assert_eq!(stats.bytes_written, 0);
+        assert_eq!(stats.hit_percentage(), 100);
+    }
+
+    #[test]
+    fn can_match_on_the_errors_of_image_ops() {
+        use imageproc::{ImageOp, ImageOpError, ResizeArgs};
+
+        let path = TEST_CONTEXT.static_path.join("matchable-errors.png");
+        RgbImage::new(40, 20).save(&path).unwrap();
+        let settings = Config::default().image_processing;
+        let from_args = |op: &str, format: &str, quality: u8| {
+            let args = ResizeArgs {
+                op: op.to_string(),
+                width: Some(imageproc::Dimension::Pixels(20)),
+                height: None,
+                format: format.to_string(),
+                quality,
+                crop: None,
+                corner_radius: None,
+                circle: false,
+                focus: None,
+                anchor: None,
+                respect_exif: true,
+                alpha_quality: None,
+                strip_metadata: None,
+                percent: None,
+                output_dir: None,
+                first_frame_only: false,
+                color_profile: None,
+            };
+            ImageOp::from_args("matchable-errors.png".to_string(), &path, &args, &settings)
+        };
+
+        assert!(from_args("fit_width", "png", 75).is_ok());
+        match from_args("stretch", "png", 75) {
+            Err(ImageOpError::UnknownOp(op)) => assert_eq!(op, "stretch"),
+            other => panic!("expected an unknown op, got {:?}", other),
+        }
+        match from_args("fit_width", "tiff", 75) {
+            Err(ImageOpError::UnknownFormat(format)) => assert_eq!(format, "tiff"),
+            other => panic!("expected an unknown format, got {:?}", other),
+        }
+        match from_args("scale", "png", 75) {
+            Err(e @ ImageOpError::MissingDimensions { .. }) => {
+                assert_eq!(e.to_string(), "op=\"scale\" requires a `width` and `height` argument")
+            }
+            other => panic!("expected missing dimensions, got {:?}", other),
+        }
+        assert!(matches!(from_args("fit_width", "png", 0), Err(ImageOpError::InvalidQuality(0))));
+
+        // The causes of the errors they wrap are kept, also once converted
+        use std::error::Error as StdError;
+        let cause = std::io::Error::new(std::io::ErrorKind::Other, "disk on fire");
+        let e = ImageOpError::Source(errors::Error::chain("Failed to read the image", cause));
+        assert_eq!(e.to_string(), "Failed to read the image");
+        assert_eq!(e.source().unwrap().to_string(), "disk on fire");
+        let e = errors::Error::from(e);
+        assert_eq!(e.source().unwrap().to_string(), "disk on fire");
+    }
+
+    #[test]
+    fn lists_the_valid_ops_of_resize_image() {
+        let static_fn = ResizeImage::new(Arc::new(Mutex::new(imageproc::Processor::new(
+            &TEST_CONTEXT.static_path,
+            &Config::default(),
+        ))));
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("doesnt-exist.png").unwrap());
+        args.insert("width".to_string(), to_value(100).unwrap());
+        args.insert("height".to_string(), to_value(100).unwrap());
+        args.insert("op".to_string(), to_value("cover").unwrap());
+        // Reported before the missing file
+        let err = format!("{}", static_fn.call(&args).unwrap_err());
+        assert_eq!(
+            err,
+            "`resize_image`: Invalid op: \"cover\", it needs to be one of: fill, fit, fit_width, fit_height, scale, crop, convert"
+        );
+    }
+
+    #[test]
+    fn refuses_dimensions_above_max_image_dimension() {
+        let base_path = TEST_CONTEXT.static_path.join("max-image-dimension-site");
+        create_directory(&base_path.join("content")).unwrap();
+        RgbImage::new(10, 200).save(base_path.join("content").join("narrow.png")).unwrap();
+        let static_fn = ResizeImage::new(Arc::new(Mutex::new(imageproc::Processor::new(
+            &base_path,
+            &Config::default(),
+        ))));
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("narrow.png").unwrap());
+        args.insert("width".to_string(), to_value(60000).unwrap());
+        args.insert("height".to_string(), to_value(100).unwrap());
+        let err = format!("{}", static_fn.call(&args).unwrap_err());
+        assert_eq!(
+            err,
+            "`resize_image`: The width of the image made of narrow.png would be 60000px, above the 20000px of `max_image_dimension`"
+        );
+
+        // The computed dimension is checked too
+        args.remove("height");
+        args.insert("width".to_string(), to_value(1500).unwrap());
+        args.insert("op".to_string(), to_value("fit_width").unwrap());
+        let err = format!("{}", static_fn.call(&args).unwrap_err());
+        assert!(
+            err.contains("The height of the image made of narrow.png would be 30000px"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn names_the_dimensions_missing_from_resize_image() {
+        let base_path = TEST_CONTEXT.static_path.join("missing-dimensions-site");
+        create_directory(&base_path.join("content")).unwrap();
+        RgbImage::new(30, 20).save(base_path.join("content").join("small.png")).unwrap();
+        let static_fn = ResizeImage::new(Arc::new(Mutex::new(imageproc::Processor::new(
+            &base_path,
+            &Config::default(),
+        ))));
+        let error = |extra: &[(&str, Value)]| {
+            let mut args = HashMap::new();
+            args.insert("path".to_string(), to_value("small.png").unwrap());
+            for (name, value) in extra {
+                args.insert(name.to_string(), value.clone());
+            }
+            format!("{:?}", static_fn.call(&args).unwrap_err())
+        };
+        let width = ("width", to_value(10).unwrap());
+        let height = ("height", to_value(10).unwrap());
+
+        // `fill` is the default op
+        assert!(
+            error(&[]).contains("op=\\\"fill\\\" needs a `width` and a `height`, none were given")
+        );
+        for op in &["fill", "fit", "scale"] {
+            let op = ("op", to_value(op).unwrap());
+            assert!(
+                error(&[op.clone()]).contains("needs a `width` and a `height`, none were given")
+            );
+            assert!(error(&[op.clone(), height.clone()]).contains("needs a `width` along with"));
+            assert!(error(&[op.clone(), width.clone()]).contains("needs a `height` along with"));
+        }
+        let fit_width = ("op", to_value("fit_width").unwrap());
+        assert!(
+            error(&[fit_width, height.clone()]).contains("op=\\\"fit_width\\\" needs a `width`")
+        );
+        let fit_height = ("op", to_value("fit_height").unwrap());
+        assert!(
+            error(&[fit_height, width.clone()]).contains("op=\\\"fit_height\\\" needs a `height`")
+        );
+
+        // Only changing the format doesn't take any
+        let convert = ("op", to_value("convert").unwrap());
+        assert!(error(&[convert, width]).contains("keeps the size of the source"));
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("small.png").unwrap());
+        args.insert("op".to_string(), to_value("convert").unwrap());
+        args.insert("format".to_string(), to_value("jpg").unwrap());
+        let converted = static_fn.call(&args).unwrap();
+        assert_eq!(
+            (&converted["width"], &converted["height"]),
+            (&to_value(30).unwrap(), &to_value(20).unwrap())
+        );
+        assert!(converted["url"].as_str().unwrap().ends_with(".jpg"));
+    }
+
+    #[test]
+    fn can_resize_animated_gifs() {
+        use image::codecs::gif::GifEncoder;
+        use image::{Delay, Frame};
+
+        let base_path = TEST_CONTEXT.static_path.join("animated-site");
+        create_directory(&base_path.join("content")).unwrap();
+        let frames = [Rgba([255, 0, 0, 255]), Rgba([0, 255, 0, 255]), Rgba([0, 0, 255, 255])]
+            .iter()
+            .zip(&[100, 200, 300])
+            .map(|(color, delay)| {
+                Frame::from_parts(
+                    RgbaImage::from_pixel(40, 20, *color),
+                    0,
+                    0,
+                    Delay::from_numer_denom_ms(*delay, 1),
+                )
+            });
+        let mut gif = Vec::new();
+        GifEncoder::new(&mut gif).encode_frames(frames).unwrap();
+        // Loops forever, right after the header as the GIF has no global color table
+        let netscape = b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00";
+        gif.splice(13..13, netscape.iter().copied());
+        std::fs::write(base_path.join("content").join("loop.gif"), &gif).unwrap();
+
+        let imageproc =
+            Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &Config::default())));
+        let static_fn = ResizeImage::new(imageproc.clone());
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("loop.gif").unwrap());
+        args.insert("width".to_string(), to_value(20).unwrap());
+        args.insert("op".to_string(), to_value("fit_width").unwrap());
+        let animated = static_fn.call(&args).unwrap();
+        imageproc.lock().unwrap().do_process().unwrap();
+
+        let output = base_path.join(animated["static_path"].as_str().unwrap());
+        assert_eq!(output.extension().unwrap(), "gif");
+        assert_eq!(image::image_dimensions(&output).unwrap(), (20, 10));
+        let animation = imageproc::read_animation(&output).unwrap().unwrap();
+        assert_eq!(animation.delays, vec![100, 200, 300]);
+        assert!(std::fs::read(&output).unwrap().windows(11).any(|w| w == b"NETSCAPE2.0"));
+
+        // There is no encoder for animated WebP
+        args.insert("format".to_string(), to_value("webp").unwrap());
+        let error = format!("{:?}", static_fn.call(&args).unwrap_err());
+        assert!(error
+            .contains("loop.gif is animated and this build of Zola can't encode animated WebP"));
+
+        // Static formats would lose the animation...
+        args.insert("format".to_string(), to_value("jpg").unwrap());
+        let error = format!("{:?}", static_fn.call(&args).unwrap_err());
+        assert!(error.contains("loop.gif is animated and JPG images can't be"));
+        // ...unless it's asked for
+        args.insert("first_frame_only".to_string(), to_value(true).unwrap());
+        let first_frame = static_fn.call(&args).unwrap();
+        assert!(first_frame["url"].as_str().unwrap().ends_with(".jpg"));
+        args.insert("format".to_string(), to_value("gif").unwrap());
+        let static_gif = static_fn.call(&args).unwrap();
+        assert_ne!(static_gif["url"], animated["url"]);
+    }
+
+    #[test]
+    fn can_make_srcset_of_widths() {
+        let base_path = TEST_CONTEXT.static_path.join("srcset-site");
+        create_directory(&base_path.join("content")).unwrap();
+        RgbImage::new(300, 200).save(base_path.join("content").join("wide.png")).unwrap();
+        let static_fn = ResizeImageSrcset::new(Arc::new(Mutex::new(imageproc::Processor::new(
+            &base_path,
+            &Config::default(),
+        ))));
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("wide.png").unwrap());
+        args.insert("widths".to_string(), to_value(vec![100, 200, 200]).unwrap());
+        args.insert("format".to_string(), to_value("jpg").unwrap());
+        let srcset = static_fn.call(&args).unwrap();
+
+        let images = srcset["images"].as_array().unwrap();
+        assert_eq!(images.len(), 2);
+        assert_eq!(images[0]["height"], to_value(66).unwrap());
+        assert!(images[1]["url"].as_str().unwrap().ends_with(".jpg"));
+        assert_eq!(
+            srcset["srcset"],
+            to_value(format!(
+                "{} 100w, {} 200w",
+                images[0]["url"].as_str().unwrap(),
+                images[1]["url"].as_str().unwrap()
+            ))
+            .unwrap()
+        );
+
+        args.insert("width".to_string(), to_value(100).unwrap());
+        assert!(static_fn.call(&args).is_err());
+        args.remove("width");
+        args.insert("widths".to_string(), to_value(Vec::<u32>::new()).unwrap());
+        assert!(static_fn.call(&args).is_err());
+    }
+
+    #[test]
+    fn can_compress_pngs_with_quality() {
+        let base_path = TEST_CONTEXT.static_path.join("png-compression-site");
+        create_directory(&base_path.join("content")).unwrap();
+        RgbImage::from_fn(128, 128, |x, y| {
+            Rgb([(x * 2) as u8, (y * 2) as u8, ((x * y) % 251) as u8])
+        })
+        .save(base_path.join("content").join("gradient.png"))
+        .unwrap();
+        let imageproc =
+            Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &Config::default())));
+        let static_fn = ResizeImage::new(imageproc.clone());
+        let resize = |quality: Option<u8>| {
+            let mut args = HashMap::new();
+            args.insert("path".to_string(), to_value("gradient.png").unwrap());
+            args.insert("op".to_string(), to_value("scale").unwrap());
+            args.insert("width".to_string(), to_value(100).unwrap());
+            args.insert("height".to_string(), to_value(100).unwrap());
+            if let Some(quality) = quality {
+                args.insert("quality".to_string(), to_value(quality).unwrap());
+            }
+            static_fn.call(&args).unwrap()["static_path"].as_str().unwrap().to_string()
+        };
+        let default = resize(None);
+        let fast = resize(Some(10));
+        let best = resize(Some(100));
+        // The default quality is the default compression
+        assert_eq!(resize(Some(60)), default);
+        assert_ne!(fast, default);
+        assert_ne!(best, default);
+        imageproc.lock().unwrap().do_process().unwrap();
+
+        let read = |path: &str| std::fs::read(base_path.join(path)).unwrap();
+        assert!(read(&best).len() <= read(&fast).len());
+        // Lossless whatever the compression
+        let pixels = |path: &str| image::load_from_memory(&read(path)).unwrap().to_rgb8();
+        assert_eq!(pixels(&best), pixels(&fast));
+    }
+
+    #[test]
+    fn can_scale_by_percent() {
+        let base_path = TEST_CONTEXT.static_path.join("percent-site");
+        create_directory(&base_path.join("content")).unwrap();
+        RgbImage::new(101, 40).save(base_path.join("content").join("wide.png")).unwrap();
+        let imageproc =
+            Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &Config::default())));
+        let static_fn = ResizeImage::new(imageproc);
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("wide.png").unwrap());
+        args.insert("op".to_string(), to_value("scale").unwrap());
+        args.insert("percent".to_string(), to_value(50).unwrap());
+        let half = static_fn.call(&args).unwrap();
+        assert_eq!(
+            (&half["width"], &half["height"]),
+            (&to_value(50).unwrap(), &to_value(20).unwrap())
+        );
+
+        // The same size as with the dimensions, but another file
+        args.remove("percent");
+        args.insert("width".to_string(), to_value(50).unwrap());
+        args.insert("height".to_string(), to_value(20).unwrap());
+        assert_ne!(static_fn.call(&args).unwrap()["url"], half["url"]);
+
+        args.insert("percent".to_string(), to_value(50).unwrap());
+        let err = static_fn.call(&args).unwrap_err();
+        assert!(format!("{:?}", err).contains("cannot be combined with `width` or `height`"));
+        args.remove("width");
+        args.remove("height");
+        args.insert("op".to_string(), to_value("fill").unwrap());
+        let err = static_fn.call(&args).unwrap_err();
+        assert!(format!("{:?}", err).contains("can only be used with op=\\\"scale\\\""));
+        args.insert("percent".to_string(), to_value(0).unwrap());
+        assert!(static_fn.call(&args).is_err());
+    }
+
+    #[test]
+    fn can_cap_dimensions_by_format() {
+        let base_path = TEST_CONTEXT.static_path.join("max-dimensions-site");
+        create_directory(&base_path.join("content")).unwrap();
+        RgbImage::new(400, 200).save(base_path.join("content").join("wide.png")).unwrap();
+        let mut config = Config::default();
+        config.image_processing.max_dimensions.insert("png".to_string(), 100);
+
+        let imageproc = Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config)));
+        let static_fn = ResizeImage::new(imageproc);
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("wide.png").unwrap());
+        args.insert("width".to_string(), to_value(300).unwrap());
+        args.insert("op".to_string(), to_value("fit_width").unwrap());
+        let response = static_fn.call(&args).unwrap();
+        assert_eq!(response["intrinsic_width"], to_value(100).unwrap());
+        assert_eq!(response["intrinsic_height"], to_value(50).unwrap());
+
+        // Only PNGs are capped
+        args.insert("format".to_string(), to_value("jpg").unwrap());
+        let response = static_fn.call(&args).unwrap();
+        assert_eq!(response["intrinsic_width"], to_value(300).unwrap());
+    }
+
+    #[test]
+    fn errors_on_webp_lossless_without_its_encoder() {
+        let base_path = TEST_CONTEXT.static_path.join("webp-lossless-site");
+        create_directory(&base_path.join("content")).unwrap();
+        RgbImage::new(40, 20).save(base_path.join("content").join("screenshot.png")).unwrap();
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("screenshot.png").unwrap());
+        args.insert("width".to_string(), to_value(20).unwrap());
+        args.insert("op".to_string(), to_value("fit_width").unwrap());
+        args.insert("format".to_string(), to_value("webp-lossless").unwrap());
+
+        let static_fn = ResizeImage::new(Arc::new(Mutex::new(imageproc::Processor::new(
+            &base_path,
+            &Config::default(),
+        ))));
+        let error = format!("{:?}", static_fn.call(&args).unwrap_err());
+        assert!(error.contains("needs the lossless WebP encoder, which is not compiled"));
+
+        let mut config = Config::default();
+        config.image_processing.fallback_unsupported_formats = true;
+        let static_fn =
+            ResizeImage::new(Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config))));
+        let response = static_fn.call(&args).unwrap();
+        assert!(response["url"].as_str().unwrap().ends_with(".png"));
+    }
+
+    #[test]
+    fn can_forbid_upscaling() {
+        let base_path = TEST_CONTEXT.static_path.join("forbid-upscale-site");
+        create_directory(&base_path.join("content")).unwrap();
+        RgbImage::new(300, 380).save(base_path.join("content").join("tall.png")).unwrap();
+        let mut config = Config::default();
+        config.image_processing.forbid_upscale = ForbidUpscale::Error;
+
+        let static_fn =
+            ResizeImage::new(Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config))));
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("tall.png").unwrap());
+        args.insert("width".to_string(), to_value(200).unwrap());
+        args.insert("height".to_string(), to_value(400).unwrap());
+        let err = static_fn.call(&args).unwrap_err().to_string();
+        assert!(err.contains("tall.png would be upscaled from 300x380 to 200x400"));
+        // `fit` never upscales, keeping the dimensions of sources smaller than the box
+        args.insert("op".to_string(), to_value("fit").unwrap());
+        let response = static_fn.call(&args).unwrap();
+        assert_eq!(
+            (&response["width"], &response["height"]),
+            (&to_value(300).unwrap(), &to_value(380).unwrap())
+        );
+
+        config.image_processing.forbid_upscale = ForbidUpscale::Clamp;
+        let static_fn =
+            ResizeImage::new(Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config))));
+        args.insert("op".to_string(), to_value("fill").unwrap());
+        let response = static_fn.call(&args).unwrap();
+        assert_eq!(response["intrinsic_width"], to_value(190).unwrap());
+        assert_eq!(response["intrinsic_height"], to_value(380).unwrap());
+        args.insert("op".to_string(), to_value("fit_width").unwrap());
+        args.insert("width".to_string(), to_value(600).unwrap());
+        let response = static_fn.call(&args).unwrap();
+        assert_eq!(response["intrinsic_width"], to_value(300).unwrap());
+        assert_eq!(response["intrinsic_height"], to_value(380).unwrap());
+    }
+
+    #[test]
+    fn can_add_previews_to_responsive_sets() {
+        let base_path = TEST_CONTEXT.static_path.join("preview-site");
+        create_directory(&base_path.join("content")).unwrap();
+        RgbImage::from_pixel(80, 40, Rgb([255, 0, 0]))
+            .save(base_path.join("content").join("hero.png"))
+            .unwrap();
+        let mut config = Config::default();
+        config.image_processing.art_direction.insert(
+            "hero".to_string(),
+            vec![ArtDirectionVariant { media: None, ratio: "2:1".to_string(), width: 20 }],
+        );
+
+        let static_fn =
+            ResizeImage::new(Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config))));
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("hero.png").unwrap());
+        args.insert("art_direction".to_string(), to_value("hero").unwrap());
+        assert!(static_fn.call(&args).unwrap().get("preview").is_none());
+
+        args.insert("preview".to_string(), to_value(true).unwrap());
+        let picture = static_fn.call(&args).unwrap();
+        let preview = picture["preview"].as_str().unwrap();
+        let png = base64::decode(&preview["data:image/png;base64,".len()..]).unwrap();
+        let preview = image::load_from_memory(&png).unwrap();
+        assert_eq!(preview.dimensions(), (16, 8));
+
+        // On by default with `responsive_previews`...
+        config.image_processing.responsive_previews = true;
+        let static_fn =
+            ResizeImage::new(Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config))));
+        args.remove("preview");
+        assert!(static_fn.call(&args).unwrap()["preview"].is_string());
+        // ...but not for single images
+        args.remove("art_direction");
+        args.insert("width".to_string(), to_value(10).unwrap());
+        args.insert("height".to_string(), to_value(10).unwrap());
+        assert!(static_fn.call(&args).unwrap().get("preview").is_none());
+        args.insert("preview".to_string(), to_value(true).unwrap());
+        assert!(static_fn.call(&args).is_err());
+        args.insert("preview".to_string(), to_value(false).unwrap());
+        assert!(static_fn.call(&args).unwrap().get("preview").is_none());
     }
-}
-impl TeraFn for GetTaxonomy {
-    fn call(&self, args: &HashMap<String, Value>) -> Result<Value> {
-        let kind = required_arg!(
-            String,
-            args.get("kind"),
-            "`get_taxonomy` requires a `kind` argument with a string value"
+
+    #[test]
+    fn can_make_every_crop_of_an_art_direction_rule() {
+        let base_path = TEST_CONTEXT.static_path.join("art-direction-site");
+        create_directory(&base_path.join("content")).unwrap();
+        RgbImage::new(200, 120).save(base_path.join("content").join("hero.png")).unwrap();
+        let mut config = Config::default();
+        config.image_processing.art_direction.insert(
+            "hero".to_string(),
+            vec![
+                ArtDirectionVariant {
+                    media: Some("(max-width: 600px)".to_string()),
+                    ratio: "1:1".to_string(),
+                    width: 30,
+                },
+                ArtDirectionVariant { media: None, ratio: "16:9".to_string(), width: 64 },
+            ],
         );
+        config.image_processing.art_direction.insert("banner".to_string(), vec![]);
 
-        let lang =
-            optional_arg!(String, args.get("lang"), "`get_taxonomy`: `lang` must be a string")
-                .unwrap_or_else(|| self.default_lang.clone());
+        let static_fn =
+            ResizeImage::new(Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config))));
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("hero.png").unwrap());
+        args.insert("art_direction".to_string(), to_value("hero").unwrap());
+        let picture = static_fn.call(&args).unwrap();
+        let sources = picture["sources"].as_array().unwrap();
+        assert_eq!(sources.len(), 1);
+        assert_eq!(sources[0]["media"], to_value("(max-width: 600px)").unwrap());
+        assert_eq!(sources[0]["width"], to_value(30).unwrap());
+        assert_eq!(sources[0]["height"], to_value(30).unwrap());
+        assert!(picture["fallback"].get("media").is_none());
+        assert_eq!(picture["fallback"]["width"], to_value(64).unwrap());
+        assert_eq!(picture["fallback"]["height"], to_value(36).unwrap());
 
-        match self.taxonomies.get(&format!("{}-{}", kind, lang)) {
-            Some(t) => Ok(to_value(t.to_serialized(&self.library.read().unwrap())).unwrap()),
-            None => {
-                Err(format!("`get_taxonomy` received an unknown taxonomy as kind: {}", kind).into())
+        args.insert("art_direction".to_string(), to_value("footer").unwrap());
+        let error = format!("{:?}", static_fn.call(&args).unwrap_err());
+        assert!(error
+            .contains("Unknown art direction rule set `footer`, available ones are: banner, hero"));
+
+        // The rule gives the dimensions
+        args.insert("art_direction".to_string(), to_value("hero").unwrap());
+        args.insert("width".to_string(), to_value(100).unwrap());
+        assert!(static_fn.call(&args).is_err());
+    }
+
+    #[test]
+    fn can_round_fit_dimensions_with_resize_rounding() {
+        let base_path = TEST_CONTEXT.static_path.join("rounding-site");
+        create_directory(&base_path.join("content")).unwrap();
+        RgbImage::new(100, 37).save(base_path.join("content").join("strip.png")).unwrap();
+
+        let mut urls = Vec::new();
+        for (rounding, heights) in &[
+            (ResizeRounding::Floor, (11, 18)),
+            (ResizeRounding::Round, (11, 19)),
+            (ResizeRounding::Ceil, (12, 19)),
+            (ResizeRounding::Even, (12, 18)),
+        ] {
+            let mut config = Config::default();
+            config.image_processing.resize_rounding = *rounding;
+            let static_fn = ResizeImage::new(Arc::new(Mutex::new(imageproc::Processor::new(
+                &base_path, &config,
+            ))));
+            let mut args = HashMap::new();
+            args.insert("path".to_string(), to_value("strip.png").unwrap());
+            args.insert("op".to_string(), to_value("fit_width").unwrap());
+            for (width, height) in &[(30, heights.0), (50, heights.1)] {
+                args.insert("width".to_string(), to_value(width).unwrap());
+                let response = static_fn.call(&args).unwrap();
+                assert_eq!(
+                    response["intrinsic_height"],
+                    to_value(height).unwrap(),
+                    "{:?}",
+                    rounding
+                );
+                urls.push(response["url"].as_str().unwrap().to_string());
             }
         }
+        // Each rounding gets its own files
+        urls.sort();
+        urls.dedup();
+        assert_eq!(urls.len(), 8);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::{GetFileHash, GetTaxonomy, GetTaxonomyUrl, GetUrl, Trans};
+    #[test]
+    fn can_resize_gallery_from_glob() {
+        let base_path = TEST_CONTEXT.static_path.join("gallery-site");
+        let gallery = base_path.join("content").join("gallery");
+        create_directory(&gallery).unwrap();
+        for name in &["b.png", "a.png", "c.jpg"] {
+            RgbImage::new(40, 40).save(gallery.join(name)).unwrap();
+        }
+        create_file(&gallery.join("notes.png"), "not an image").unwrap();
+        create_file(&gallery.join("index.md"), "+++\n+++\n").unwrap();
+        let mut config = Config::default();
+        config.image_processing.art_direction.insert(
+            "thumb".to_string(),
+            vec![ArtDirectionVariant { media: None, ratio: "1:1".to_string(), width: 10 }],
+        );
 
-    use std::collections::HashMap;
-    use std::env::temp_dir;
-    use std::fs::remove_dir_all;
-    use std::path::PathBuf;
-    use std::sync::{Arc, RwLock};
+        let static_fn = ResizeGallery::new(
+            base_path.join("content"),
+            Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config))),
+        );
+        let mut args = HashMap::new();
+        args.insert("glob".to_string(), to_value("gallery/*").unwrap());
+        args.insert("preset".to_string(), to_value("thumb").unwrap());
+        let images = static_fn.call(&args).unwrap();
+        let images = images.as_array().unwrap();
+        let paths: Vec<_> = images.iter().map(|i| i["path"].as_str().unwrap()).collect();
+        assert_eq!(paths, vec!["gallery/a.png", "gallery/b.png", "gallery/c.jpg"]);
+        for image in images {
+            assert_eq!(image["fallback"]["width"], to_value(10).unwrap());
+            assert!(image["fallback"]["url"].as_str().unwrap().contains("/processed_images/"));
+        }
 
-    use lazy_static::lazy_static;
+        args.insert("glob".to_string(), to_value("../*.png").unwrap());
+        assert!(static_fn.call(&args).is_err());
+        args.insert("glob".to_string(), to_value("gallery/*.png").unwrap());
+        args.insert("preset".to_string(), to_value("unknown").unwrap());
+        assert!(static_fn.call(&args).is_err());
+    }
 
-    use tera::{to_value, Function, Value};
+    #[test]
+    fn can_get_images_from_glob() {
+        let base_path = TEST_CONTEXT.static_path.join("get-images-site");
+        let gallery = base_path.join("content").join("gallery");
+        create_directory(&gallery.join("nested")).unwrap();
+        for name in &["b.jpg", "a.jpg", "c.png", "nested/d.jpg"] {
+            RgbImage::new(4, 4).save(gallery.join(name)).unwrap();
+        }
 
-    use config::{Config, Taxonomy as TaxonomyConfig};
-    use library::{Library, Taxonomy, TaxonomyItem};
-    use utils::fs::{create_directory, create_file};
-    use utils::slugs::SlugifyStrategy;
+        let static_fn = GetImages::new(base_path.join("content"));
+        let mut args = HashMap::new();
+        args.insert("glob".to_string(), to_value("gallery/**/*.jpg").unwrap());
+        assert_eq!(
+            static_fn.call(&args).unwrap(),
+            to_value(vec!["gallery/a.jpg", "gallery/b.jpg", "gallery/nested/d.jpg"]).unwrap()
+        );
+        args.insert("glob".to_string(), to_value("gallery/*.webp").unwrap());
+        assert_eq!(static_fn.call(&args).unwrap(), to_value(Vec::<String>::new()).unwrap());
 
-    struct TestContext {
-        static_path: PathBuf,
-    }
-    impl TestContext {
-        fn setup() -> Self {
-            let dir = temp_dir().join("static");
-            create_directory(&dir).expect("Could not create test directory");
-            create_file(&dir.join("app.css"), "// Hello world!")
-                .expect("Could not create test content (app.css)");
-            Self { static_path: dir }
+        for pattern in &["/gallery/*.jpg", "../*.jpg"] {
+            args.insert("glob".to_string(), to_value(pattern).unwrap());
+            assert!(static_fn.call(&args).is_err(), "{}", pattern);
         }
     }
-    impl Drop for TestContext {
-        fn drop(&mut self) {
-            remove_dir_all(&self.static_path).expect("Could not free test directory");
+
+    #[test]
+    fn can_add_svg_blur_placeholder() {
+        let base_path = TEST_CONTEXT.static_path.join("svg-blur-site");
+        create_directory(&base_path.join("content")).unwrap();
+        RgbImage::from_pixel(80, 40, Rgb([0, 255, 0]))
+            .save(base_path.join("content").join("hero.png"))
+            .unwrap();
+
+        let static_fn = ResizeImage::new(Arc::new(Mutex::new(imageproc::Processor::new(
+            &base_path,
+            &Config::default(),
+        ))));
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("hero.png").unwrap());
+        args.insert("width".to_string(), to_value(40).unwrap());
+        args.insert("op".to_string(), to_value("fit_width").unwrap());
+        assert!(static_fn.call(&args).unwrap().get("placeholder").is_none());
+
+        args.insert("placeholder".to_string(), to_value("svg-blur").unwrap());
+        let response = static_fn.call(&args).unwrap();
+        let svg = response["placeholder"].as_str().unwrap();
+        assert!(
+            svg.starts_with(r#"<svg xmlns="http://www.w3.org/2000/svg" width="40" height="20""#)
+        );
+        assert!(svg.contains("<feGaussianBlur"));
+        assert!(svg.contains(r#"href="data:image/png;base64,"#));
+
+        args.insert("placeholder".to_string(), to_value("blurhash").unwrap());
+        assert!(static_fn.call(&args).is_err());
+    }
+
+    #[test]
+    fn can_add_integrity_to_responsive_sets() {
+        let base_path = TEST_CONTEXT.static_path.join("integrity-site");
+        create_directory(&base_path.join("content")).unwrap();
+        RgbImage::from_pixel(80, 40, Rgb([0, 0, 255]))
+            .save(base_path.join("content").join("hero.png"))
+            .unwrap();
+        let mut config = Config::default();
+        config.image_processing.art_direction.insert(
+            "hero".to_string(),
+            vec![
+                ArtDirectionVariant {
+                    media: Some("(max-width: 600px)".to_string()),
+                    ratio: "1:1".to_string(),
+                    width: 10,
+                },
+                ArtDirectionVariant { media: None, ratio: "2:1".to_string(), width: 20 },
+            ],
+        );
+
+        let imageproc = Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config)));
+        let static_fn = ResizeImage::new(imageproc.clone());
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("hero.png").unwrap());
+        args.insert("art_direction".to_string(), to_value("hero").unwrap());
+        assert!(static_fn.call(&args).unwrap()["fallback"].get("integrity").is_none());
+
+        args.insert("integrity".to_string(), to_value(true).unwrap());
+        let picture = static_fn.call(&args).unwrap();
+        let mut variants = picture["sources"].as_array().unwrap().clone();
+        variants.push(picture["fallback"].clone());
+        for variant in variants {
+            let written = base_path.join(variant["static_path"].as_str().unwrap());
+            let mut hasher = Sha384::new();
+            hasher.update(std::fs::read(written).unwrap());
+            let expected = format!("sha384-{}", base64::encode(hasher.finalize()));
+            assert_eq!(variant["integrity"], to_value(expected).unwrap());
         }
+        // The same arguments give the same digests, asking the processor again
+        assert_eq!(ResizeImage::new(imageproc.clone()).call(&args).unwrap(), picture);
+
+        args.remove("art_direction");
+        args.insert("width".to_string(), to_value(10).unwrap());
+        args.insert("height".to_string(), to_value(10).unwrap());
+        assert!(static_fn.call(&args).is_err());
     }
 
-    lazy_static! {
-        static ref TEST_CONTEXT: TestContext = TestContext::setup();
+    #[test]
+    fn can_use_another_url_prefix_than_the_processed_dir() {
+        let base_path = TEST_CONTEXT.static_path.join("url-prefix-site");
+        create_directory(&base_path.join("content")).unwrap();
+        RgbImage::new(20, 20).save(base_path.join("content").join("square.png")).unwrap();
+        let mut config = Config::default();
+        config.image_processing.url_prefix = Some("img".to_string());
+
+        let imageproc = Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config)));
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("square.png").unwrap());
+        args.insert("width".to_string(), to_value(10).unwrap());
+        args.insert("height".to_string(), to_value(10).unwrap());
+        let response = ResizeImage::new(imageproc.clone()).call(&args).unwrap();
+        let url = response["url"].as_str().unwrap();
+        let static_path = response["static_path"].as_str().unwrap();
+        assert!(url.starts_with(&format!("{}/img/", config.base_url)));
+        assert!(static_path.starts_with("static/processed_images/"));
+        assert_eq!(url.rsplit('/').next(), static_path.rsplit('/').next());
+        imageproc.lock().unwrap().do_process().unwrap();
+        assert!(base_path.join(static_path).exists());
     }
 
     #[test]
-    fn can_add_cachebust_to_url() {
+    fn can_write_images_to_their_own_output_dir() {
+        let base_path = TEST_CONTEXT.static_path.join("output-dir-site");
+        create_directory(&base_path.join("content")).unwrap();
+        RgbImage::new(20, 20).save(base_path.join("content").join("square.png")).unwrap();
         let config = Config::default();
-        let static_fn = GetUrl::new(config, HashMap::new(), vec![TEST_CONTEXT.static_path.clone()]);
+
+        let imageproc = Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config)));
+        let static_fn = ResizeImage::new(imageproc.clone());
         let mut args = HashMap::new();
-        args.insert("path".to_string(), to_value("app.css").unwrap());
-        args.insert("cachebust".to_string(), to_value(true).unwrap());
-        assert_eq!(static_fn.call(&args).unwrap(), "http://a-website.com/app.css?h=572e691dc68c3fcd653ae463261bdb38f35dc6f01715d9ce68799319dd158840");
+        args.insert("path".to_string(), to_value("square.png").unwrap());
+        args.insert("width".to_string(), to_value(10).unwrap());
+        args.insert("height".to_string(), to_value(10).unwrap());
+        let default = static_fn.call(&args).unwrap();
+        args.insert("output_dir".to_string(), to_value("/blog/my-post/").unwrap());
+        let response = static_fn.call(&args).unwrap();
+        let url = response["url"].as_str().unwrap();
+        let static_path = response["static_path"].as_str().unwrap();
+        assert!(url.starts_with(&format!("{}/blog/my-post/processed_images/", config.base_url)));
+        assert!(static_path.starts_with("static/blog/my-post/processed_images/"));
+        assert_eq!(url.rsplit('/').next(), static_path.rsplit('/').next());
+        assert_ne!(default["url"], response["url"]);
+        // A file of the site with the same name next to the subdirectory Zola owns
+        let filename = static_path.rsplit('/').next().unwrap();
+        let user_file = base_path.join("static").join("blog").join("my-post").join(filename);
+        create_directory(user_file.parent().unwrap()).unwrap();
+        std::fs::write(&user_file, b"mine").unwrap();
+        {
+            let mut imageproc = imageproc.lock().unwrap();
+            imageproc.prune().unwrap();
+            imageproc.do_process().unwrap();
+        }
+        assert!(base_path.join(static_path).exists());
+        assert!(base_path.join(default["static_path"].as_str().unwrap()).exists());
+        assert_eq!(std::fs::read(&user_file).unwrap(), b"mine");
+
+        // The images of a directory no operation uses anymore, eg of a deleted page, are pruned
+        let imageproc = imageproc::Processor::new(&base_path, &config);
+        imageproc.prune().unwrap();
+        assert!(!base_path.join("static/blog/my-post/processed_images").exists());
+        assert_eq!(std::fs::read(&user_file).unwrap(), b"mine");
+
+        for dir in &["../outside", "blog/../../outside", ""] {
+            args.insert("output_dir".to_string(), to_value(dir).unwrap());
+            assert!(static_fn.call(&args).is_err(), "{}", dir);
+        }
     }
 
     #[test]
-    fn can_add_trailing_slashes() {
-        let config = Config::default();
-        let static_fn = GetUrl::new(config, HashMap::new(), vec![TEST_CONTEXT.static_path.clone()]);
+    fn can_clean_processed_images_not_produced_by_the_build() {
+        let base_path = TEST_CONTEXT.static_path.join("clean-processed-site");
+        let _ = remove_dir_all(base_path.join("static"));
+        create_directory(&base_path.join("content")).unwrap();
+        RgbImage::new(64, 64).save(base_path.join("content").join("square.png")).unwrap();
+        let processed_path = base_path.join("static").join("processed_images");
+        create_directory(&processed_path.join("nested")).unwrap();
+        std::fs::write(processed_path.join("nested").join("kept.png"), b"").unwrap();
+        // Put there by hand
+        std::fs::write(processed_path.join("logo.png"), b"").unwrap();
+        std::fs::write(base_path.join("static").join("user.png"), b"").unwrap();
         let mut args = HashMap::new();
-        args.insert("path".to_string(), to_value("app.css").unwrap());
-        args.insert("trailing_slash".to_string(), to_value(true).unwrap());
-        assert_eq!(static_fn.call(&args).unwrap(), "http://a-website.com/app.css/");
+        args.insert("path".to_string(), to_value("square.png").unwrap());
+        args.insert("width".to_string(), to_value(16).unwrap());
+        args.insert("op".to_string(), to_value("fit_width").unwrap());
+
+        // Not a directory Zola processed images in yet
+        let imageproc = imageproc::Processor::new(&base_path, &Config::default());
+        assert!(imageproc.clean_processed_images().is_err());
+
+        // A previous build with another `filename_template`, which `prune` doesn't recognize
+        let mut config = Config::default();
+        config.image_processing.filename_template = "{stem}-{width}x{height}.{ext}".to_string();
+        let imageproc = Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config)));
+        let old_template = ResizeImage::new(imageproc.clone()).call(&args).unwrap();
+        imageproc.lock().unwrap().do_process().unwrap();
+        let old_template = base_path.join(old_template["static_path"].as_str().unwrap());
+        assert!(old_template.exists());
+
+        let imageproc =
+            Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &Config::default())));
+        let static_fn = ResizeImage::new(imageproc.clone());
+        let produced = static_fn.call(&args).unwrap();
+        args.insert("output_dir".to_string(), to_value("elsewhere").unwrap());
+        let elsewhere = static_fn.call(&args).unwrap();
+
+        let mut imageproc = imageproc.lock().unwrap();
+        imageproc.prune().unwrap();
+        imageproc.do_process().unwrap();
+        assert!(old_template.exists());
+        assert_eq!(imageproc.clean_processed_images().unwrap(), 1);
+
+        assert!(!old_template.exists());
+        assert!(base_path.join(produced["static_path"].as_str().unwrap()).exists());
+        assert!(base_path.join(elsewhere["static_path"].as_str().unwrap()).exists());
+        assert!(processed_path.join("nested").join("kept.png").exists());
+        assert!(processed_path.join("logo.png").exists());
+        assert!(base_path.join("static").join("user.png").exists());
     }
 
     #[test]
-    fn can_add_slashes_and_cachebust() {
-        let config = Config::default();
-        let static_fn = GetUrl::new(config, HashMap::new(), vec![TEST_CONTEXT.static_path.clone()]);
+    fn can_plan_images_without_writing_them() {
+        let base_path = TEST_CONTEXT.static_path.join("dry-run-site");
+        create_directory(&base_path.join("content")).unwrap();
+        RgbImage::new(64, 32).save(base_path.join("content").join("wide.png")).unwrap();
+        let stale_path = base_path.join("static").join("processed_images");
+        create_directory(&stale_path).unwrap();
+        std::fs::write(stale_path.join("stale.png"), b"").unwrap();
+
+        let resize = |imageproc: &Arc<Mutex<imageproc::Processor>>| {
+            let static_fn = ResizeImage::new(imageproc.clone());
+            let mut args = HashMap::new();
+            args.insert("path".to_string(), to_value("wide.png").unwrap());
+            args.insert("width".to_string(), to_value(16).unwrap());
+            args.insert("op".to_string(), to_value("fit_width").unwrap());
+            static_fn.call(&args).unwrap()
+        };
+        let mut processor = imageproc::Processor::new(&base_path, &Config::default());
+        processor.enable_dry_run();
+        let dry_run = Arc::new(Mutex::new(processor));
+        let planned = resize(&dry_run);
+        let mut dry_run = dry_run.lock().unwrap();
+        dry_run.prune().unwrap();
+        dry_run.do_process().unwrap();
+        assert_eq!(dry_run.clean_processed_images().unwrap(), 0);
+        assert!(!base_path.join(planned["static_path"].as_str().unwrap()).exists());
+        assert!(stale_path.join("stale.png").exists());
+
+        let plan = dry_run.planned_images().unwrap();
+        assert_eq!(plan.len(), 1);
+        assert_eq!(plan[0].url, planned["url"].as_str().unwrap());
+        assert_eq!((plan[0].width, plan[0].height), (16, 8));
+        assert!(!plan[0].up_to_date);
+        let json: Value = serde_json::from_str(&dry_run.planned_images_json().unwrap()).unwrap();
+        assert_eq!(json[0]["format"], "png");
+
+        // A real build gives out the same URL
+        let real = Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &Config::default())));
+        let produced = resize(&real);
+        assert_eq!(produced["url"], planned["url"]);
+        real.lock().unwrap().do_process().unwrap();
+        assert!(dry_run.planned_images().unwrap()[0].up_to_date);
+    }
+
+    #[test]
+    fn can_find_images_in_asset_dirs() {
+        let base_path = TEST_CONTEXT.static_path.join("asset-dirs-site");
+        let _ = remove_dir_all(base_path.join("content"));
+        create_directory(&base_path.join("content")).unwrap();
+        create_directory(&base_path.join("assets").join("logos")).unwrap();
+        create_directory(&base_path.join("shared").join("logos")).unwrap();
+        RgbImage::new(30, 20)
+            .save(base_path.join("assets").join("logos").join("zola.png"))
+            .unwrap();
+        RgbImage::new(40, 20)
+            .save(base_path.join("shared").join("logos").join("zola.png"))
+            .unwrap();
+        let mut config = Config::default();
+        config.image_processing.asset_dirs = vec!["assets".to_string(), "shared".to_string()];
         let mut args = HashMap::new();
-        args.insert("path".to_string(), to_value("app.css").unwrap());
-        args.insert("trailing_slash".to_string(), to_value(true).unwrap());
-        args.insert("cachebust".to_string(), to_value(true).unwrap());
-        assert_eq!(static_fn.call(&args).unwrap(), "http://a-website.com/app.css/?h=572e691dc68c3fcd653ae463261bdb38f35dc6f01715d9ce68799319dd158840");
+        args.insert("path".to_string(), to_value("logos/zola.png").unwrap());
+
+        // Not looked up unless configured
+        let static_fn = GetImageMeta::new(base_path.join("content"), &Config::default());
+        assert!(static_fn.call(&args).is_err());
+        // The first of the dirs wins
+        let static_fn = GetImageMeta::new(base_path.join("content"), &config);
+        assert_eq!(static_fn.call(&args).unwrap()["width"], to_value(30).unwrap());
+        args.insert("path".to_string(), to_value("/logos/zola.png").unwrap());
+        assert_eq!(static_fn.call(&args).unwrap()["width"], to_value(30).unwrap());
+
+        let static_fn =
+            ResizeImage::new(Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config))));
+        args.insert("path".to_string(), to_value("logos/zola.png").unwrap());
+        args.insert("op".to_string(), to_value("convert").unwrap());
+        assert_eq!(static_fn.call(&args).unwrap()["width"], to_value(30).unwrap());
+        // `content` comes first
+        create_directory(&base_path.join("content").join("logos")).unwrap();
+        RgbImage::new(50, 20)
+            .save(base_path.join("content").join("logos").join("zola.png"))
+            .unwrap();
+        let static_fn =
+            ResizeImage::new(Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config))));
+        assert_eq!(static_fn.call(&args).unwrap()["width"], to_value(50).unwrap());
     }
 
     #[test]
-    fn can_link_to_some_static_file() {
-        let config = Config::default();
-        let static_fn = GetUrl::new(config, HashMap::new(), vec![TEST_CONTEXT.static_path.clone()]);
+    fn can_give_the_file_size_of_resized_images() {
+        let base_path = TEST_CONTEXT.static_path.join("file-size-site");
+        create_directory(&base_path.join("content")).unwrap();
+        let _ = remove_dir_all(base_path.join("static"));
+        RgbImage::new(64, 32).save(base_path.join("content").join("hero.png")).unwrap();
+        let resize = |compute_weight: bool| {
+            let static_fn = ResizeImage::new(Arc::new(Mutex::new(imageproc::Processor::new(
+                &base_path,
+                &Config::default(),
+            ))));
+            let mut args = HashMap::new();
+            args.insert("path".to_string(), to_value("hero.png").unwrap());
+            args.insert("width".to_string(), to_value(16).unwrap());
+            args.insert("op".to_string(), to_value("fit_width").unwrap());
+            args.insert("compute_weight".to_string(), to_value(compute_weight).unwrap());
+            static_fn.call(&args).unwrap()
+        };
+
+        // Only written at the end of the build
+        assert_eq!(resize(false)["file_size"], Value::Null);
+        let processed = resize(true);
+        let written = base_path.join(processed["static_path"].as_str().unwrap());
+        let size = std::fs::metadata(&written).unwrap().len();
+        assert_eq!(processed["file_size"], to_value(size).unwrap());
+        // Not looked up without `compute_weight`, even when up to date
+        assert_eq!(resize(false)["file_size"], Value::Null);
+        // Read from the file of the previous build
+        assert_eq!(resize(true)["file_size"], to_value(size).unwrap());
+    }
+
+    #[test]
+    fn doesnt_process_again_the_images_processed_right_away() {
+        let base_path = TEST_CONTEXT.static_path.join("processed-now-site");
+        create_directory(&base_path.join("content")).unwrap();
+        let _ = remove_dir_all(base_path.join("static"));
+        RgbImage::new(64, 32).save(base_path.join("content").join("hero.png")).unwrap();
+        let imageproc =
+            Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &Config::default())));
         let mut args = HashMap::new();
-        args.insert("path".to_string(), to_value("app.css").unwrap());
-        assert_eq!(static_fn.call(&args).unwrap(), "http://a-website.com/app.css");
+        args.insert("path".to_string(), to_value("hero.png").unwrap());
+        args.insert("width".to_string(), to_value(16).unwrap());
+        args.insert("op".to_string(), to_value("fit_width").unwrap());
+        args.insert("compute_weight".to_string(), to_value(true).unwrap());
+        let processed = ResizeImage::new(imageproc.clone()).call(&args).unwrap();
+        let written = base_path.join(processed["static_path"].as_str().unwrap());
+
+        // Not encoded again even though the file isn't up to date anymore
+        std::fs::write(&written, b"changed").unwrap();
+        let mut imageproc = imageproc.lock().unwrap();
+        imageproc.do_process().unwrap();
+        assert_eq!(std::fs::read(&written).unwrap(), b"changed");
+        let stats = imageproc.processing_stats();
+        assert_eq!((stats.processed, stats.cache_hits), (1, 0));
     }
 
     #[test]
-    fn can_get_taxonomy() {
+    fn can_report_the_timings_of_the_processed_images() {
+        let base_path = TEST_CONTEXT.static_path.join("timings-site");
+        create_directory(&base_path.join("content")).unwrap();
+        let _ = remove_dir_all(base_path.join("static"));
+        RgbImage::new(64, 32).save(base_path.join("content").join("hero.png")).unwrap();
         let mut config = Config::default();
-        config.slugify.taxonomies = SlugifyStrategy::On;
-        let taxo_config = TaxonomyConfig {
-            name: "tags".to_string(),
-            lang: config.default_language.clone(),
-            ..TaxonomyConfig::default()
+        config.image_processing.processing_log = Some("image-processing.json".to_string());
+        let process = |config: &Config| {
+            let imageproc = Arc::new(Mutex::new(imageproc::Processor::new(&base_path, config)));
+            let mut args = HashMap::new();
+            args.insert("path".to_string(), to_value("hero.png").unwrap());
+            args.insert("width".to_string(), to_value(16).unwrap());
+            args.insert("op".to_string(), to_value("fit_width").unwrap());
+            ResizeImage::new(imageproc.clone()).call(&args).unwrap();
+            imageproc.lock().unwrap().do_process().unwrap();
+            let log = std::fs::read_to_string(base_path.join("image-processing.json")).unwrap();
+            serde_json::from_str::<Vec<Value>>(&log).unwrap().remove(0)
         };
-        let taxo_config_fr = TaxonomyConfig {
-            name: "tags".to_string(),
-            lang: "fr".to_string(),
-            ..TaxonomyConfig::default()
+
+        // Off by default
+        let log = process(&config);
+        assert!(log.get("timings").is_none());
+
+        config.image_processing.report_timings = true;
+        let _ = remove_dir_all(base_path.join("static"));
+        let log = process(&config);
+        for step in &["decode_ms", "resize_ms", "encode_ms"] {
+            assert!(log["timings"][step].as_f64().unwrap() >= 0.0);
+        }
+        // Nothing to time once the image is up to date
+        let log = process(&config);
+        assert_eq!(log["cache_hit"], Value::Bool(true));
+        assert!(log.get("timings").is_none());
+    }
+
+    #[test]
+    fn can_copy_processed_images_to_extra_output_dirs() {
+        let base_path = TEST_CONTEXT.static_path.join("extra-output-dirs-site");
+        create_directory(&base_path.join("content")).unwrap();
+        let _ = remove_dir_all(base_path.join("mirror"));
+        RgbImage::new(64, 32).save(base_path.join("content").join("hero.png")).unwrap();
+        // A file where a directory is expected, so copying to it fails
+        std::fs::write(base_path.join("blocked"), b"").unwrap();
+        let mut config = Config::default();
+        config.image_processing.extra_output_dirs =
+            vec!["mirror".to_string(), "blocked".to_string()];
+
+        let imageproc = Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config)));
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("hero.png").unwrap());
+        args.insert("width".to_string(), to_value(16).unwrap());
+        args.insert("op".to_string(), to_value("fit_width").unwrap());
+        let response = ResizeImage::new(imageproc.clone()).call(&args).unwrap();
+        // Only warns about the directory it can't copy to
+        imageproc.lock().unwrap().do_process().unwrap();
+
+        let written = base_path.join(response["static_path"].as_str().unwrap());
+        let copy = base_path.join("mirror").join(written.file_name().unwrap());
+        assert_eq!(std::fs::read(&copy).unwrap(), std::fs::read(&written).unwrap());
+        // URLs keep pointing to the primary output
+        assert!(!response["url"].as_str().unwrap().contains("mirror"));
+    }
+
+    #[test]
+    fn regenerates_images_made_by_other_encoders() {
+        let base_path = TEST_CONTEXT.static_path.join("encoder-version-site");
+        create_directory(&base_path.join("content")).unwrap();
+        let _ = remove_dir_all(base_path.join("static"));
+        RgbImage::new(64, 32).save(base_path.join("content").join("hero.png")).unwrap();
+        let version_file =
+            base_path.join("static").join("processed_images").join(".encoder-version");
+        let process = |config: &Config| {
+            let imageproc = Arc::new(Mutex::new(imageproc::Processor::new(&base_path, config)));
+            let mut args = HashMap::new();
+            args.insert("path".to_string(), to_value("hero.png").unwrap());
+            args.insert("width".to_string(), to_value(16).unwrap());
+            args.insert("op".to_string(), to_value("fit_width").unwrap());
+            ResizeImage::new(imageproc.clone()).call(&args).unwrap();
+            let mut imageproc = imageproc.lock().unwrap();
+            imageproc.do_process().unwrap();
+            let stats = imageproc.processing_stats();
+            (stats.processed, stats.cache_hits)
         };
-        let library = Arc::new(RwLock::new(Library::new(0, 0, false)));
-        let tag = TaxonomyItem::new(
-            "Programming",
-            &taxo_config,
-            "tags",
-            &config,
-            vec![],
-            &library.read().unwrap(),
-        );
-        let tag_fr = TaxonomyItem::new(
-            "Programmation",
-            &taxo_config_fr,
-            "tags",
-            &config,
-            vec![],
-            &library.read().unwrap(),
-        );
-        let tags = Taxonomy { kind: taxo_config, slug: "tags".to_string(), items: vec![tag] };
-        let tags_fr =
-            Taxonomy { kind: taxo_config_fr, slug: "tags".to_string(), items: vec![tag_fr] };
 
-        let taxonomies = vec![tags.clone(), tags_fr.clone()];
+        let mut config = Config::default();
+        assert_eq!(process(&config), (1, 0));
+        assert_eq!(std::fs::read_to_string(&version_file).unwrap(), imageproc::ENCODER_VERSION);
+        assert_eq!(process(&config), (0, 1));
+
+        // Up to date but made by another version
+        std::fs::write(&version_file, "image-0.22").unwrap();
+        assert_eq!(process(&config), (1, 0));
+        assert_eq!(std::fs::read_to_string(&version_file).unwrap(), imageproc::ENCODER_VERSION);
+
+        // Only a warning with that policy, the version is still updated
+        config.image_processing.on_encoder_change = EncoderChangePolicy::Warn;
+        std::fs::write(&version_file, "image-0.22").unwrap();
+        assert_eq!(process(&config), (0, 1));
+        assert_eq!(std::fs::read_to_string(&version_file).unwrap(), imageproc::ENCODER_VERSION);
+    }
+
+    #[test]
+    fn can_write_a_manifest_of_the_processed_images() {
+        let base_path = TEST_CONTEXT.static_path.join("manifest-site");
+        create_directory(&base_path.join("content")).unwrap();
+        RgbImage::new(64, 32).save(base_path.join("content").join("b.png")).unwrap();
+        RgbImage::new(64, 32).save(base_path.join("content").join("a.png")).unwrap();
+        let mut config = Config::default();
+        config.image_processing.write_manifest = true;
+
+        let build = || {
+            let imageproc = Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config)));
+            let static_fn = ResizeImage::new(imageproc.clone());
+            let mut urls = Vec::new();
+            for (path, width) in &[("b.png", 16), ("a.png", 32), ("a.png", 16)] {
+                let mut args = HashMap::new();
+                args.insert("path".to_string(), to_value(path).unwrap());
+                args.insert("width".to_string(), to_value(width).unwrap());
+                args.insert("op".to_string(), to_value("fit_width").unwrap());
+                urls.push(static_fn.call(&args).unwrap()["url"].as_str().unwrap().to_string());
+            }
+            imageproc.lock().unwrap().do_process().unwrap();
+            let manifest_path = base_path.join("static/processed_images/manifest.json");
+            (urls, std::fs::read_to_string(manifest_path).unwrap())
+        };
+        let (urls, manifest) = build();
+
+        let entries: Value = serde_json::from_str(&manifest).unwrap();
+        let entries = entries.as_array().unwrap();
+        assert_eq!(entries.len(), 3);
+        let sources: Vec<_> = entries.iter().map(|e| e["source"].as_str().unwrap()).collect();
+        assert_eq!(sources, vec!["a.png", "a.png", "b.png"]);
+        assert_eq!(entries[2]["url"], urls[0]);
+        assert_eq!(entries[2]["op"], "fit_width");
+        assert_eq!(entries[2]["width"], 16);
+        assert_eq!(entries[2]["format"], "png");
+        let url_of_a = |width: u32| {
+            entries.iter().find(|e| e["source"] == "a.png" && e["width"] == width).unwrap()["url"]
+                .clone()
+        };
+        assert_eq!(url_of_a(32), urls[1]);
+        assert_eq!(url_of_a(16), urls[2]);
+
+        // The same on every build, whatever the order of the calls
+        assert_eq!(build().1, manifest);
+    }
+
+    #[test]
+    fn lengthens_the_stem_of_images_with_the_same_filename() {
+        let base_path = TEST_CONTEXT.static_path.join("stem-collision-site");
+        for (dir, color) in &[("red", Rgb([255, 0, 0])), ("blue", Rgb([0, 0, 255]))] {
+            create_directory(&base_path.join("content").join(dir)).unwrap();
+            RgbImage::from_pixel(40, 20, *color)
+                .save(base_path.join("content").join(dir).join("cover.png"))
+                .unwrap();
+        }
+        let mut config = Config::default();
+        config.image_processing.filename_template = "{stem}-{width}x{height}.{ext}".to_string();
+
+        let imageproc = Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config)));
+        let static_fn = ResizeImage::new(imageproc.clone());
+        let mut args = HashMap::new();
+        args.insert("width".to_string(), to_value(10).unwrap());
+        args.insert("height".to_string(), to_value(10).unwrap());
+        args.insert("path".to_string(), to_value("red/cover.png").unwrap());
+        let red = static_fn.call(&args).unwrap();
+        args.insert("path".to_string(), to_value("blue/cover.png").unwrap());
+        let blue = static_fn.call(&args).unwrap();
+        // Told apart by the hash of their directory, whatever the order they're processed in
+        let red_path = red["static_path"].as_str().unwrap();
+        let blue_path = blue["static_path"].as_str().unwrap();
+        assert_ne!(red_path, blue_path);
+        for path in &[red_path, blue_path] {
+            assert!(path.starts_with("static/processed_images/cover-"));
+            assert!(path.ends_with("-10x10.png"));
+            assert_eq!(path.len(), "static/processed_images/cover-01234567-10x10.png".len());
+        }
+        imageproc.lock().unwrap().do_process().unwrap();
+        let pixel = |response: &Value| {
+            image::open(base_path.join(response["static_path"].as_str().unwrap()))
+                .unwrap()
+                .to_rgb8()
+                .get_pixel(5, 5)
+                .0
+        };
+        assert_eq!(pixel(&red), [255, 0, 0]);
+        assert_eq!(pixel(&blue), [0, 0, 255]);
+
+        // Sources with the same stem in the same directory still need a longer stem
+        RgbImage::from_pixel(40, 20, Rgb([0, 255, 0]))
+            .save(base_path.join("content").join("red").join("cover.jpg"))
+            .unwrap();
+        args.insert("path".to_string(), to_value("red/cover.jpg").unwrap());
+        args.insert("format".to_string(), to_value("png").unwrap());
+        let green = static_fn.call(&args).unwrap();
+        let green_path = green["static_path"].as_str().unwrap();
+        assert!(green_path.starts_with(&red_path[..red_path.len() - "-10x10.png".len()]));
+        assert!(green_path.ends_with("-10x10.png"));
+        assert_eq!(green_path.len(), red_path.len() + 9);
+        // The same image keeps its lengthened stem, asking the processor again
+        assert_eq!(ResizeImage::new(imageproc.clone()).call(&args).unwrap(), green);
+        imageproc.lock().unwrap().do_process().unwrap();
+        assert_eq!(pixel(&green), [0, 255, 0]);
+
+        // The next build keeps the same filenames even when rendered in the other order
         let static_fn =
-            GetTaxonomy::new(&config.default_language, taxonomies.clone(), library.clone());
-        // can find it correctly
+            ResizeImage::new(Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config))));
+        assert_eq!(static_fn.call(&args).unwrap()["static_path"], green["static_path"]);
+        args.insert("path".to_string(), to_value("red/cover.png").unwrap());
+        args.remove("format");
+        assert_eq!(static_fn.call(&args).unwrap()["static_path"], red["static_path"]);
+
+        // Without a stem or a hash in the filename, lengthening the stem can't help
+        config.image_processing.filename_template = "{width}x{height}.{ext}".to_string();
+        let static_fn =
+            ResizeImage::new(Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config))));
+        args.insert("path".to_string(), to_value("red/cover.png").unwrap());
+        static_fn.call(&args).unwrap();
+        args.insert("path".to_string(), to_value("blue/cover.png").unwrap());
+        assert!(static_fn.call(&args).is_err());
+    }
+
+    #[test]
+    fn regenerates_images_made_by_another_operation_with_the_same_filename() {
+        let base_path = TEST_CONTEXT.static_path.join("filename-owner-site");
+        create_directory(&base_path.join("content")).unwrap();
+        RgbImage::from_fn(40, 20, |x, y| Rgb([(x * 6) as u8, (y * 12) as u8, 0]))
+            .save(base_path.join("content").join("photo.png"))
+            .unwrap();
+        let mut config = Config::default();
+        config.image_processing.filename_template = "{stem}.{ext}".to_string();
+
         let mut args = HashMap::new();
-        args.insert("kind".to_string(), to_value("tags").unwrap());
-        let res = static_fn.call(&args).unwrap();
-        let res_obj = res.as_object().unwrap();
-        assert_eq!(res_obj["kind"], to_value(tags.kind).unwrap());
-        assert_eq!(res_obj["items"].clone().as_array().unwrap().len(), 1);
-        assert_eq!(
-            res_obj["items"].clone().as_array().unwrap()[0].clone().as_object().unwrap()["name"],
-            Value::String("Programming".to_string())
-        );
-        assert_eq!(
-            res_obj["items"].clone().as_array().unwrap()[0].clone().as_object().unwrap()["slug"],
-            Value::String("programming".to_string())
-        );
-        assert_eq!(
-            res_obj["items"].clone().as_array().unwrap()[0].clone().as_object().unwrap()
-                ["permalink"],
-            Value::String("http://a-website.com/tags/programming/".to_string())
-        );
-        assert_eq!(
-            res_obj["items"].clone().as_array().unwrap()[0].clone().as_object().unwrap()["pages"],
-            Value::Array(vec![])
-        );
-        // Works with other languages as well
+        args.insert("path".to_string(), to_value("photo.png").unwrap());
+        args.insert("width".to_string(), to_value(20).unwrap());
+        args.insert("height".to_string(), to_value(10).unwrap());
+        args.insert("format".to_string(), to_value("jpg").unwrap());
+        let mut sizes = Vec::new();
+        for quality in &[100, 10] {
+            let imageproc = Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config)));
+            args.insert("quality".to_string(), to_value(quality).unwrap());
+            let response = ResizeImage::new(imageproc.clone()).call(&args).unwrap();
+            assert_eq!(
+                response["static_path"],
+                to_value("static/processed_images/photo.jpg").unwrap()
+            );
+            imageproc.lock().unwrap().do_process().unwrap();
+            sizes.push(
+                std::fs::metadata(base_path.join("static/processed_images/photo.jpg"))
+                    .unwrap()
+                    .len(),
+            );
+        }
+        // Same name and dimensions, but the second build encoded it again with its quality
+        assert!(sizes[1] < sizes[0]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn can_hardlink_identical_processed_images() {
+        use std::os::unix::fs::MetadataExt;
+
+        let base_path = TEST_CONTEXT.static_path.join("hardlink-site");
+        for dir in &["first", "second"] {
+            create_directory(&base_path.join("content").join(dir)).unwrap();
+            RgbImage::from_pixel(40, 20, Rgb([0, 128, 0]))
+                .save(base_path.join("content").join(dir).join("copy.png"))
+                .unwrap();
+        }
+        RgbImage::new(40, 20).save(base_path.join("content").join("other.png")).unwrap();
+        let mut config = Config::default();
+        config.image_processing.hardlink_identical = true;
+
+        let imageproc = Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config)));
+        let static_fn = ResizeImage::new(imageproc.clone());
         let mut args = HashMap::new();
-        args.insert("kind".to_string(), to_value("tags").unwrap());
-        args.insert("lang".to_string(), to_value("fr").unwrap());
-        let res = static_fn.call(&args).unwrap();
-        let res_obj = res.as_object().unwrap();
-        assert_eq!(res_obj["kind"], to_value(tags_fr.kind).unwrap());
-        assert_eq!(res_obj["items"].clone().as_array().unwrap().len(), 1);
-        assert_eq!(
-            res_obj["items"].clone().as_array().unwrap()[0].clone().as_object().unwrap()["name"],
-            Value::String("Programmation".to_string())
-        );
+        args.insert("width".to_string(), to_value(10).unwrap());
+        args.insert("height".to_string(), to_value(10).unwrap());
+        let mut outputs = Vec::new();
+        for path in &["first/copy.png", "second/copy.png", "other.png"] {
+            args.insert("path".to_string(), to_value(path).unwrap());
+            let response = static_fn.call(&args).unwrap();
+            outputs.push(base_path.join(response["static_path"].as_str().unwrap()));
+        }
+        imageproc.lock().unwrap().do_process().unwrap();
 
-        // and errors if it can't find it
+        let inodes: Vec<_> =
+            outputs.iter().map(|path| std::fs::metadata(path).unwrap().ino()).collect();
+        assert_eq!(inodes[0], inodes[1]);
+        assert_ne!(inodes[0], inodes[2]);
+    }
+
+    #[test]
+    fn answers_repeated_resize_image_calls_without_the_processor() {
+        let base_path = TEST_CONTEXT.static_path.join("repeated-site");
+        create_directory(&base_path.join("content")).unwrap();
+        RgbImage::new(40, 20).save(base_path.join("content").join("logo.png")).unwrap();
+
+        let imageproc =
+            Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &Config::default())));
+        let static_fn = ResizeImage::new(imageproc.clone());
         let mut args = HashMap::new();
-        args.insert("kind".to_string(), to_value("something-else").unwrap());
-        assert!(static_fn.call(&args).is_err());
+        args.insert("path".to_string(), to_value("logo.png").unwrap());
+        args.insert("width".to_string(), to_value(10).unwrap());
+        args.insert("height".to_string(), to_value(10).unwrap());
+        let logo = static_fn.call(&args).unwrap();
+
+        // Poisons the processor lock, any call taking it would now fail
+        let poisoner = imageproc.clone();
+        std::thread::spawn(move || {
+            let _guard = poisoner.lock().unwrap();
+            panic!("poisoning the processor lock");
+        })
+        .join()
+        .unwrap_err();
+        assert_eq!(static_fn.call(&args).unwrap(), logo);
+        args.insert("width".to_string(), to_value(20).unwrap());
+        assert!(std::panic::catch_unwind(|| static_fn.call(&args)).is_err());
     }
 
     #[test]
-    fn can_get_taxonomy_url() {
+    fn can_write_cache_headers() {
+        let base_path = TEST_CONTEXT.static_path.join("cache-headers-site");
+        create_directory(&base_path.join("content")).unwrap();
+        create_directory(&base_path.join("static")).unwrap();
+        RgbImage::new(40, 20).save(base_path.join("content").join("wide.png")).unwrap();
+        let headers_path = base_path.join("static").join("_headers");
+        create_file(&headers_path, "/*\n  X-Frame-Options: DENY\n").unwrap();
         let mut config = Config::default();
-        config.slugify.taxonomies = SlugifyStrategy::On;
-        let taxo_config = TaxonomyConfig {
-            name: "tags".to_string(),
-            lang: config.default_language.clone(),
-            ..TaxonomyConfig::default()
+        config.image_processing.cache_headers_file = Some("static/_headers".to_string());
+
+        for _ in 0..2 {
+            let imageproc = Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config)));
+            let mut args = HashMap::new();
+            args.insert("path".to_string(), to_value("wide.png").unwrap());
+            args.insert("width".to_string(), to_value(10).unwrap());
+            args.insert("height".to_string(), to_value(10).unwrap());
+            let response = ResizeImage::new(imageproc.clone()).call(&args).unwrap();
+            imageproc.lock().unwrap().do_process().unwrap();
+
+            let headers = std::fs::read_to_string(&headers_path).unwrap();
+            let filename = response["static_path"].as_str().unwrap().replace("static/", "/");
+            assert!(headers.starts_with("/*\n  X-Frame-Options: DENY\n"));
+            assert!(headers.contains(&format!(
+                "{}\n  Cache-Control: public, max-age=31536000, immutable\n",
+                filename
+            )));
+            // The block of the first build is replaced rather than appended to
+            assert_eq!(headers.matches("Cache-Control").count(), 1);
+        }
+    }
+
+    #[test]
+    fn can_fill_around_the_busiest_third() {
+        let base_path = TEST_CONTEXT.static_path.join("focus-site");
+        create_directory(&base_path.join("content")).unwrap();
+        // Flat on the left, a checkerboard on the right third
+        RgbImage::from_fn(300, 100, |x, y| {
+            if x < 200 {
+                Rgb([128, 128, 128])
+            } else if (x / 25 + y / 25) % 2 == 0 {
+                Rgb([0, 0, 0])
+            } else {
+                Rgb([255, 255, 255])
+            }
+        })
+        .save(base_path.join("content").join("subject.png"))
+        .unwrap();
+
+        let imageproc =
+            Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &Config::default())));
+        let static_fn = ResizeImage::new(imageproc.clone());
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("subject.png").unwrap());
+        args.insert("width".to_string(), to_value(100).unwrap());
+        args.insert("height".to_string(), to_value(100).unwrap());
+        let centered = static_fn.call(&args).unwrap();
+        args.insert("focus".to_string(), to_value("thirds").unwrap());
+        let focused = static_fn.call(&args).unwrap();
+        imageproc.lock().unwrap().do_process().unwrap();
+
+        let right_edge = |response: &Value| {
+            let path = base_path.join(response["static_path"].as_str().unwrap());
+            image::open(path).unwrap().to_rgb8().get_pixel(99, 50).0[0]
         };
-        let taxo_config_fr = TaxonomyConfig {
-            name: "tags".to_string(),
-            lang: "fr".to_string(),
-            ..TaxonomyConfig::default()
+        assert!((i32::from(right_edge(&centered)) - 128).abs() <= 2);
+        let edge = right_edge(&focused);
+        assert!(edge < 64 || edge > 192);
+
+        args.insert("op".to_string(), to_value("fit_width").unwrap());
+        assert!(static_fn.call(&args).is_err());
+    }
+
+    #[test]
+    fn can_fill_from_an_anchor() {
+        let base_path = TEST_CONTEXT.static_path.join("anchor-site");
+        create_directory(&base_path.join("content")).unwrap();
+        // A portrait with a red head on a black body
+        RgbImage::from_fn(100, 300, |_, y| if y < 100 { Rgb([255, 0, 0]) } else { Rgb([0, 0, 0]) })
+            .save(base_path.join("content").join("portrait.png"))
+            .unwrap();
+
+        let imageproc =
+            Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &Config::default())));
+        let static_fn = ResizeImage::new(imageproc.clone());
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("portrait.png").unwrap());
+        args.insert("width".to_string(), to_value(100).unwrap());
+        args.insert("height".to_string(), to_value(100).unwrap());
+        let centered = static_fn.call(&args).unwrap();
+        args.insert("anchor".to_string(), to_value("center").unwrap());
+        assert_eq!(static_fn.call(&args).unwrap(), centered);
+        args.insert("anchor".to_string(), to_value("top").unwrap());
+        let top = static_fn.call(&args).unwrap();
+        assert_ne!(top["url"], centered["url"]);
+        imageproc.lock().unwrap().do_process().unwrap();
+
+        let middle = |response: &Value| {
+            let path = base_path.join(response["static_path"].as_str().unwrap());
+            image::open(path).unwrap().to_rgb8().get_pixel(50, 50).0[0]
         };
-        let library = Library::new(0, 0, false);
-        let tag = TaxonomyItem::new("Programming", &taxo_config, "tags", &config, vec![], &library);
-        let tag_fr =
-            TaxonomyItem::new("Programmation", &taxo_config_fr, "tags", &config, vec![], &library);
-        let tags = Taxonomy { kind: taxo_config, slug: "tags".to_string(), items: vec![tag] };
-        let tags_fr =
-            Taxonomy { kind: taxo_config_fr, slug: "tags".to_string(), items: vec![tag_fr] };
+        assert_eq!(middle(&centered), 0);
+        assert_eq!(middle(&top), 255);
 
-        let taxonomies = vec![tags.clone(), tags_fr.clone()];
-        let static_fn =
-            GetTaxonomyUrl::new(&config.default_language, &taxonomies, config.slugify.taxonomies);
+        // Ignored by the operations that don't crop, but still checked
+        args.insert("op".to_string(), to_value("fit_width").unwrap());
+        assert!(static_fn.call(&args).is_ok());
+        args.insert("anchor".to_string(), to_value("up").unwrap());
+        let err = static_fn.call(&args).unwrap_err().to_string();
+        assert!(err.contains("Invalid anchor: up"));
+    }
 
-        // can find it correctly
+    #[test]
+    fn can_diff_processed_images() {
+        let base_path = TEST_CONTEXT.static_path.join("diff-site");
+        create_directory(&base_path.join("content")).unwrap();
+        RgbImage::new(40, 20).save(base_path.join("content").join("wide.png")).unwrap();
+
+        let imageproc =
+            Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &Config::default())));
         let mut args = HashMap::new();
-        args.insert("kind".to_string(), to_value("tags").unwrap());
-        args.insert("name".to_string(), to_value("Programming").unwrap());
-        assert_eq!(
-            static_fn.call(&args).unwrap(),
-            to_value("http://a-website.com/tags/programming/").unwrap()
-        );
+        args.insert("path".to_string(), to_value("wide.png").unwrap());
+        args.insert("width".to_string(), to_value(10).unwrap());
+        args.insert("height".to_string(), to_value(10).unwrap());
+        let processed = ResizeImage::new(imageproc.clone()).call(&args).unwrap();
+        let processed = processed["url"].as_str().unwrap().to_string();
+        let missing = "http://a-website.com/processed_images/0123456789abcdef00.png".to_string();
+        let expected = vec![processed.clone(), missing.clone()];
 
-        // can find it correctly with inconsistent capitalisation
+        // Only the images written count, not the ones requested
+        let mut imageproc = imageproc.lock().unwrap();
+        let mut sorted = expected.clone();
+        sorted.sort();
+        assert_eq!(imageproc.diff_produced_urls(&expected).missing, sorted);
+        imageproc.do_process().unwrap();
+        let diff = imageproc.diff_produced_urls(&expected);
+        assert_eq!(diff.missing, vec![missing]);
+        assert!(diff.extra.is_empty());
+        assert_eq!(imageproc.diff_produced_urls(&[]).extra, vec![processed]);
+    }
+
+    #[test]
+    fn can_make_circular_thumbnails() {
+        let base_path = TEST_CONTEXT.static_path.join("circle-site");
+        create_directory(&base_path.join("content")).unwrap();
+        RgbImage::from_pixel(20, 20, Rgb([255, 0, 0]))
+            .save(base_path.join("content").join("avatar.png"))
+            .unwrap();
+        RgbImage::new(20, 20).save(base_path.join("content").join("avatar.jpg")).unwrap();
+
+        let imageproc =
+            Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &Config::default())));
+        let static_fn = ResizeImage::new(imageproc.clone());
         let mut args = HashMap::new();
-        args.insert("kind".to_string(), to_value("tags").unwrap());
-        args.insert("name".to_string(), to_value("programming").unwrap());
+        args.insert("path".to_string(), to_value("avatar.png").unwrap());
+        args.insert("width".to_string(), to_value(10).unwrap());
+        args.insert("height".to_string(), to_value(10).unwrap());
+        args.insert("circle".to_string(), to_value(true).unwrap());
+        let response = static_fn.call(&args).unwrap();
+        imageproc.lock().unwrap().do_process().unwrap();
+
+        let output = image::open(base_path.join(response["static_path"].as_str().unwrap()))
+            .unwrap()
+            .to_rgba8();
+        assert_eq!(output.get_pixel(0, 0).0[3], 0);
+        assert_eq!(output.get_pixel(5, 5).0, [255, 0, 0, 255]);
+
+        // JPEGs can't be transparent
+        args.insert("path".to_string(), to_value("avatar.jpg").unwrap());
+        assert!(static_fn.call(&args).is_err());
+    }
+
+    #[test]
+    fn can_make_gradient_placeholder() {
+        RgbImage::from_pixel(8, 2, Rgb([255, 0, 0]))
+            .save(TEST_CONTEXT.static_path.join("red.png"))
+            .unwrap();
+        RgbImage::from_pixel(2, 8, Rgb([0, 0, 255]))
+            .save(TEST_CONTEXT.static_path.join("blue.png"))
+            .unwrap();
+
+        let static_fn = ImageGradient::new(TEST_CONTEXT.static_path.clone(), &Config::default());
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("red.png").unwrap());
         assert_eq!(
             static_fn.call(&args).unwrap(),
-            to_value("http://a-website.com/tags/programming/").unwrap()
+            to_value("linear-gradient(to right, #ff0000, #ff0000, #ff0000, #ff0000)").unwrap()
         );
 
-        // works with other languages
-        let mut args = HashMap::new();
-        args.insert("kind".to_string(), to_value("tags").unwrap());
-        args.insert("name".to_string(), to_value("Programmation").unwrap());
-        args.insert("lang".to_string(), to_value("fr").unwrap());
+        args.insert("path".to_string(), to_value("blue.png").unwrap());
+        args.insert("colors".to_string(), to_value(2).unwrap());
         assert_eq!(
             static_fn.call(&args).unwrap(),
-            to_value("http://a-website.com/fr/tags/programmation/").unwrap()
+            to_value("linear-gradient(to bottom, #0000ff, #0000ff)").unwrap()
         );
+    }
 
-        // and errors if it can't find it
+    #[test]
+    fn can_make_image_placeholder() {
+        RgbImage::from_pixel(100, 50, Rgb([0, 255, 0]))
+            .save(TEST_CONTEXT.static_path.join("green.png"))
+            .unwrap();
+
+        let static_fn =
+            GetImagePlaceholder::new(TEST_CONTEXT.static_path.clone(), &Config::default());
         let mut args = HashMap::new();
-        args.insert("kind".to_string(), to_value("tags").unwrap());
-        args.insert("name".to_string(), to_value("random").unwrap());
-        assert!(static_fn.call(&args).is_err());
-    }
+        args.insert("path".to_string(), to_value("green.png").unwrap());
+        let placeholder = static_fn.call(&args).unwrap();
+        let placeholder = placeholder.as_str().unwrap();
+        let jpg = base64::decode(&placeholder["data:image/jpeg;base64,".len()..]).unwrap();
+        let img = image::load_from_memory(&jpg).unwrap();
+        assert_eq!(img.dimensions(), (20, 10));
+        assert!(img.to_rgb8().get_pixel(10, 5).0[1] > 200);
 
-    const TRANS_CONFIG: &str = r#"
-base_url = "https://remplace-par-ton-url.fr"
-default_language = "fr"
-languages = [
-    { code = "en" },
-]
+        args.insert("size".to_string(), to_value(8).unwrap());
+        args.insert("blur".to_string(), to_value(1.5).unwrap());
+        let blurred = static_fn.call(&args).unwrap();
+        let jpg =
+            base64::decode(&blurred.as_str().unwrap()["data:image/jpeg;base64,".len()..]).unwrap();
+        assert_eq!(image::load_from_memory(&jpg).unwrap().dimensions(), (8, 4));
 
-[translations]
-[translations.fr]
-title = "Un titre"
+        args.insert("blur".to_string(), to_value(0).unwrap());
+        assert!(static_fn.call(&args).is_err());
 
-[translations.en]
-title = "A title"
-        "#;
+        // Found in `static` as with `get_image_metadata`
+        let base_path = TEST_CONTEXT.static_path.join("placeholder-site");
+        create_directory(&base_path.join("content")).unwrap();
+        create_directory(&base_path.join("static").join("img")).unwrap();
+        RgbImage::from_pixel(40, 40, Rgb([0, 0, 255]))
+            .save(base_path.join("static").join("img").join("blue.png"))
+            .unwrap();
+        let static_fn = GetImagePlaceholder::new(base_path.join("content"), &Config::default());
+        let mut args = HashMap::new();
+        for path in &["/img/blue.png", "/static/img/blue.png"] {
+            args.insert("path".to_string(), to_value(path).unwrap());
+            assert!(static_fn
+                .call(&args)
+                .unwrap()
+                .as_str()
+                .unwrap()
+                .starts_with("data:image/jpeg"));
+        }
+        args.insert("path".to_string(), to_value("img/blue.png").unwrap());
+        assert!(static_fn.call(&args).is_err());
+    }
+
+    fn get_alt_fn(config: &Config) -> GetAlt {
+        let imageproc = imageproc::Processor::new(&TEST_CONTEXT.static_path, config);
+        GetAlt::new(&TEST_CONTEXT.static_path, config, Arc::new(Mutex::new(imageproc)))
+    }
 
     #[test]
-    fn can_translate_a_string() {
-        let config = Config::parse(TRANS_CONFIG).unwrap();
-        let static_fn = Trans::new(config);
-        let mut args = HashMap::new();
+    fn can_get_alt_text() {
+        create_file(
+            &TEST_CONTEXT.static_path.join("alts.toml"),
+            r#""gallery/beach.jpg" = "A sunset on the beach""#,
+        )
+        .unwrap();
+        let mut config = Config::default();
+        config.image_processing.alt_text_file = Some("alts.toml".to_string());
+        let static_fn = get_alt_fn(&config);
 
-        args.insert("key".to_string(), to_value("title").unwrap());
-        assert_eq!(static_fn.call(&args).unwrap(), "Un titre");
+        let mut args = HashMap::new();
+        args.insert("path".to_string(), to_value("gallery/beach.jpg").unwrap());
+        assert_eq!(static_fn.call(&args).unwrap(), "A sunset on the beach");
 
-        args.insert("lang".to_string(), to_value("en").unwrap());
-        assert_eq!(static_fn.call(&args).unwrap(), "A title");
+        args.insert("path".to_string(), to_value("@/gallery/beach.jpg").unwrap());
+        assert_eq!(static_fn.call(&args).unwrap(), "A sunset on the beach");
 
-        args.insert("lang".to_string(), to_value("fr").unwrap());
-        assert_eq!(static_fn.call(&args).unwrap(), "Un titre");
+        args.insert("path".to_string(), to_value("gallery/unknown.jpg").unwrap());
+        assert_eq!(static_fn.call(&args).unwrap(), "");
     }
 
     #[test]
-    fn error_on_absent_translation_lang() {
-        let mut args = HashMap::new();
-        args.insert("lang".to_string(), to_value("absent").unwrap());
-        args.insert("key".to_string(), to_value("title").unwrap());
+    fn can_report_missing_alt_text_together_with_strict_alt() {
+        create_file(
+            &TEST_CONTEXT.static_path.join("strict-alts.toml"),
+            r#""gallery/beach.jpg" = "A sunset on the beach""#,
+        )
+        .unwrap();
+        let mut config = Config::default();
+        config.image_processing.alt_text_file = Some("strict-alts.toml".to_string());
+        config.image_processing.strict_alt = true;
+        let imageproc =
+            Arc::new(Mutex::new(imageproc::Processor::new(&TEST_CONTEXT.static_path, &config)));
+        let static_fn = GetAlt::new(&TEST_CONTEXT.static_path, &config, imageproc.clone());
 
-        let config = Config::parse(TRANS_CONFIG).unwrap();
-        let error = Trans::new(config).call(&args).unwrap_err();
-        assert_eq!("Failed to retrieve term translation", format!("{}", error));
+        let mut args = HashMap::new();
+        for path in &["gallery/beach.jpg", "gallery/forest.jpg", "gallery/lake.jpg"] {
+            args.insert("path".to_string(), to_value(path).unwrap());
+            static_fn.call(&args).unwrap();
+        }
+        let err = imageproc.lock().unwrap().check_missing_alts().unwrap_err().to_string();
+        assert!(err.starts_with("2 image(s) have no alt text"));
+        assert!(err.contains("`gallery/forest.jpg`"));
+        assert!(err.contains("`gallery/lake.jpg`"));
+        assert!(!err.contains("beach"));
+        // Only reported once
+        assert!(imageproc.lock().unwrap().check_missing_alts().is_ok());
     }
 
     #[test]
-    fn error_on_absent_translation_key() {
+    fn error_when_no_alt_text_file_configured() {
+        let static_fn = get_alt_fn(&Config::default());
         let mut args = HashMap::new();
-        args.insert("lang".to_string(), to_value("en").unwrap());
-        args.insert("key".to_string(), to_value("absent").unwrap());
-
-        let config = Config::parse(TRANS_CONFIG).unwrap();
-        let error = Trans::new(config).call(&args).unwrap_err();
-        assert_eq!("Failed to retrieve term translation", format!("{}", error));
+        args.insert("path".to_string(), to_value("gallery/beach.jpg").unwrap());
+        assert!(static_fn.call(&args).is_err());
     }
 
     #[test]
-    fn error_when_language_not_available() {
-        let config = Config::parse(TRANS_CONFIG).unwrap();
-        let static_fn = GetUrl::new(config, HashMap::new(), vec![TEST_CONTEXT.static_path.clone()]);
+    fn can_get_image_sidecar() {
+        let dir = TEST_CONTEXT.static_path.join("sidecars");
+        create_directory(&dir).unwrap();
+        create_file(
+            &dir.join("beach.jpg.meta.toml"),
+            "alt = \"A sunset on the beach\"\ncredit = \"Jane Doe\"",
+        )
+        .unwrap();
+
+        let static_fn = GetImageSidecar::new(TEST_CONTEXT.static_path.clone(), &Config::default());
         let mut args = HashMap::new();
-        args.insert("path".to_string(), to_value("@/a_section/a_page.md").unwrap());
-        args.insert("lang".to_string(), to_value("it").unwrap());
-        let err = static_fn.call(&args).unwrap_err();
-        assert_eq!(
-            "`it` is not an authorized language (check config.languages).",
-            format!("{}", err)
-        );
+        args.insert("path".to_string(), to_value("sidecars/beach.jpg").unwrap());
+        let sidecar = static_fn.call(&args).unwrap();
+        assert_eq!(sidecar["alt"], to_value("A sunset on the beach").unwrap());
+        assert_eq!(sidecar["credit"], to_value("Jane Doe").unwrap());
+
+        args.insert("path".to_string(), to_value("sidecars/forest.jpg").unwrap());
+        assert_eq!(static_fn.call(&args).unwrap(), Value::Null);
     }
 
+    // `cat` stands in for the OCR tool, printing the "text" of the image
+    #[cfg(not(windows))]
     #[test]
-    fn can_get_url_with_default_language() {
-        let config = Config::parse(TRANS_CONFIG).unwrap();
-        let mut permalinks = HashMap::new();
-        permalinks.insert(
-            "a_section/a_page.md".to_string(),
-            "https://remplace-par-ton-url.fr/a_section/a_page/".to_string(),
-        );
-        permalinks.insert(
-            "a_section/a_page.en.md".to_string(),
-            "https://remplace-par-ton-url.fr/en/a_section/a_page/".to_string(),
-        );
-        let static_fn = GetUrl::new(config, permalinks, vec![TEST_CONTEXT.static_path.clone()]);
+    fn can_read_text_of_images_with_ocr_command() {
+        let dir = TEST_CONTEXT.static_path.join("ocr");
+        create_directory(&dir).unwrap();
+        create_file(&dir.join("scan.png"), "  Chapter 1\n").unwrap();
         let mut args = HashMap::new();
-        args.insert("path".to_string(), to_value("@/a_section/a_page.md").unwrap());
-        args.insert("lang".to_string(), to_value("fr").unwrap());
-        assert_eq!(
-            static_fn.call(&args).unwrap(),
-            "https://remplace-par-ton-url.fr/a_section/a_page/"
-        );
+        args.insert("path".to_string(), to_value("ocr/scan.png").unwrap());
+
+        // No OCR without a command
+        let static_fn = ImageOcr::new(TEST_CONTEXT.static_path.clone(), &Config::default());
+        assert_eq!(static_fn.call(&args).unwrap(), "");
+
+        let mut config = Config::default();
+        config.image_processing.ocr_command = Some(vec!["cat".to_string()]);
+        let static_fn = ImageOcr::new(TEST_CONTEXT.static_path.clone(), &config);
+        assert_eq!(static_fn.call(&args).unwrap(), "Chapter 1");
+        // Read once per build
+        create_file(&dir.join("scan.png"), "Chapter 2").unwrap();
+        assert_eq!(static_fn.call(&args).unwrap(), "Chapter 1");
+
+        // Failures of the command are only warnings
+        config.image_processing.ocr_command =
+            Some(vec!["cat".to_string(), "{path}.missing".to_string()]);
+        let static_fn = ImageOcr::new(TEST_CONTEXT.static_path.clone(), &config);
+        assert_eq!(static_fn.call(&args).unwrap(), "");
+
+        args.insert("path".to_string(), to_value("ocr/missing.png").unwrap());
+        assert!(static_fn.call(&args).is_err());
     }
 
     #[test]
-    fn can_get_url_with_other_language() {
-        let config = Config::parse(TRANS_CONFIG).unwrap();
-        let mut permalinks = HashMap::new();
-        permalinks.insert(
-            "a_section/a_page.md".to_string(),
-            "https://remplace-par-ton-url.fr/a_section/a_page/".to_string(),
-        );
-        permalinks.insert(
-            "a_section/a_page.en.md".to_string(),
-            "https://remplace-par-ton-url.fr/en/a_section/a_page/".to_string(),
-        );
-        let static_fn = GetUrl::new(config, permalinks, vec![TEST_CONTEXT.static_path.clone()]);
+    fn can_make_alt_text_from_filenames() {
+        let mut config = Config::default();
+        config.image_processing.alt_fallback = Some(Default::default());
+        let static_fn = get_alt_fn(&config);
+
         let mut args = HashMap::new();
-        args.insert("path".to_string(), to_value("@/a_section/a_page.md").unwrap());
-        args.insert("lang".to_string(), to_value("en").unwrap());
-        assert_eq!(
-            static_fn.call(&args).unwrap(),
-            "https://remplace-par-ton-url.fr/en/a_section/a_page/"
-        );
+        args.insert("path".to_string(), to_value("gallery/beach-sunset.jpg").unwrap());
+        assert_eq!(static_fn.call(&args).unwrap(), "beach sunset");
+
+        // Explicit alt texts always win
+        create_file(
+            &TEST_CONTEXT.static_path.join("fallback-alts.toml"),
+            r#""gallery/beach-sunset.jpg" = "A sunset on the beach""#,
+        )
+        .unwrap();
+        config.image_processing.alt_text_file = Some("fallback-alts.toml".to_string());
+        let static_fn = get_alt_fn(&config);
+        assert_eq!(static_fn.call(&args).unwrap(), "A sunset on the beach");
+        args.insert("path".to_string(), to_value("gallery/forest_path.jpg").unwrap());
+        assert_eq!(static_fn.call(&args).unwrap(), "forest path");
     }
 
     #[test]
-    fn can_get_file_hash_sha256() {
-        let static_fn = GetFileHash::new(vec![TEST_CONTEXT.static_path.clone()]);
+    fn can_use_remote_images_once_allowed() {
+        let base_path = TEST_CONTEXT.static_path.join("remote-images-site");
+        create_directory(&base_path.join("content")).unwrap();
+        // Downloaded again on every test run
+        let _ = remove_dir_all(base_path.join(".remote-images"));
+        let mut png = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(40, 20))
+            .write_to(&mut png, ImageOutputFormat::Png)
+            .unwrap();
+        // No extension in the URL, it comes from the content type
+        let m = mockito::mock("GET", "/images/ohw2ieM4")
+            .with_header("content-type", "image/png")
+            .with_body(&png)
+            .expect(1)
+            .create();
+        let url = format!("{}/images/ohw2ieM4", mockito::server_url());
         let mut args = HashMap::new();
-        args.insert("path".to_string(), to_value("app.css").unwrap());
-        args.insert("sha_type".to_string(), to_value(256).unwrap());
-        assert_eq!(
-            static_fn.call(&args).unwrap(),
-            "572e691dc68c3fcd653ae463261bdb38f35dc6f01715d9ce68799319dd158840"
-        );
+        args.insert("path".to_string(), to_value(&url).unwrap());
+
+        let static_fn = GetImageMeta::new(base_path.join("content"), &Config::default());
+        let err = format!("{}", static_fn.call(&args).unwrap_err());
+        assert!(err.contains("`allow_remote_images = true`"), "{}", err);
+
+        let mut config = Config::default();
+        config.image_processing.allow_remote_images = true;
+        let static_fn =
+            ResizeImage::new(Arc::new(Mutex::new(imageproc::Processor::new(&base_path, &config))));
+        args.insert("width".to_string(), to_value(10).unwrap());
+        args.insert("op".to_string(), to_value("fit_width").unwrap());
+        let data = static_fn.call(&args).unwrap();
+        assert_eq!(data["height"], to_value(5).unwrap());
+
+        // Read from the downloaded copy
+        args.remove("width");
+        args.remove("op");
+        let static_fn = GetImageMeta::new(base_path.join("content"), &config);
+        let data = static_fn.call(&args).unwrap();
+        assert_eq!(data["width"], to_value(40).unwrap());
+        m.assert();
+        let downloaded: Vec<_> = std::fs::read_dir(base_path.join(".remote-images"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert_eq!(downloaded.len(), 1);
+        assert!(downloaded[0].ends_with(".png"), "{:?}", downloaded);
     }
 
     #[test]
-    fn can_get_file_hash_sha384() {
-        let static_fn = GetFileHash::new(vec![TEST_CONTEXT.static_path.clone()]);
-        let mut args = HashMap::new();
-        args.insert("path".to_string(), to_value("app.css").unwrap());
-        assert_eq!(static_fn.call(&args).unwrap(), "141c09bd28899773b772bbe064d8b718fa1d6f2852b7eafd5ed6689d26b74883b79e2e814cd69d5b52ab476aa284c414");
+    fn can_download_the_same_remote_image_from_several_threads() {
+        let base_path = TEST_CONTEXT.static_path.join("remote-images-threads-site");
+        create_directory(&base_path.join("content")).unwrap();
+        let _ = remove_dir_all(base_path.join(".remote-images"));
+        let mut png = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(4, 4))
+            .write_to(&mut png, ImageOutputFormat::Png)
+            .unwrap();
+        // Downloaded once, the other threads waiting for it
+        let m = mockito::mock("GET", "/images/threads.png").with_body(&png).expect(1).create();
+        let url = format!("{}/images/threads.png", mockito::server_url());
+
+        let content_path = base_path.join("content");
+        let mut config = Config::default();
+        config.image_processing.allow_remote_images = true;
+        config.image_processing.max_concurrent_fetches = 2;
+        let settings = FetchSettings::new(&config.image_processing);
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let (content_path, url, settings) =
+                    (content_path.clone(), url.clone(), settings.clone());
+                std::thread::spawn(move || {
+                    fetch_remote_image("resize_image", &content_path, &url, &settings)
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap().unwrap();
+        }
+        m.assert();
+        // Only the image is left, none of the temporary files
+        let downloaded: Vec<_> = std::fs::read_dir(base_path.join(".remote-images"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert_eq!(downloaded.len(), 1, "{:?}", downloaded);
+        assert!(downloaded[0].ends_with(".png"), "{:?}", downloaded);
     }
 
     #[test]
-    fn can_get_file_hash_sha512() {
-        let static_fn = GetFileHash::new(vec![TEST_CONTEXT.static_path.clone()]);
-        let mut args = HashMap::new();
-        args.insert("path".to_string(), to_value("app.css").unwrap());
-        args.insert("sha_type".to_string(), to_value(512).unwrap());
-        assert_eq!(static_fn.call(&args).unwrap(), "379dfab35123b9159d9e4e92dc90e2be44cf3c2f7f09b2e2df80a1b219b461de3556c93e1a9ceb3008e999e2d6a54b4f1d65ee9be9be63fa45ec88931623372f");
+    fn can_retry_remote_images_failing_with_server_errors() {
+        let base_path = TEST_CONTEXT.static_path.join("remote-images-retries-site");
+        create_directory(&base_path.join("content")).unwrap();
+        let _ = remove_dir_all(base_path.join(".remote-images"));
+        let mut config = Config::default();
+        config.image_processing.allow_remote_images = true;
+        config.image_processing.fetch_retries = 2;
+        let settings = FetchSettings::new(&config.image_processing);
+        let fetch = |path: &str| {
+            let url = format!("{}{}", mockito::server_url(), path);
+            fetch_remote_image("resize_image", &base_path.join("content"), &url, &settings)
+        };
+
+        let m = mockito::mock("GET", "/images/unavailable.png").with_status(503).expect(3).create();
+        let err = format!("{:?}", fetch("/images/unavailable.png").unwrap_err());
+        assert!(err.contains("unavailable.png after 3 tries"), "{}", err);
+        m.assert();
+        // Not found won't change by trying again
+        let m = mockito::mock("GET", "/images/missing.png").with_status(404).expect(1).create();
+        let err = format!("{:?}", fetch("/images/missing.png").unwrap_err());
+        assert!(!err.contains("tries"), "{}", err);
+        m.assert();
     }
 
     #[test]
-    fn error_when_file_not_found_for_hash() {
-        let static_fn = GetFileHash::new(vec![TEST_CONTEXT.static_path.clone()]);
-        let mut args = HashMap::new();
-        args.insert("path".to_string(), to_value("doesnt-exist").unwrap());
-        assert_eq!(
-            format!(
-                "file `doesnt-exist` not found; searched in {}",
-                TEST_CONTEXT.static_path.to_str().unwrap()
-            ),
-            format!("{}", static_fn.call(&args).unwrap_err())
-        );
+    fn can_limit_the_size_of_remote_images() {
+        let base_path = TEST_CONTEXT.static_path.join("remote-images-size-site");
+        create_directory(&base_path.join("content")).unwrap();
+        let _ = remove_dir_all(base_path.join(".remote-images"));
+        let mut png = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(16, 16))
+            .write_to(&mut png, ImageOutputFormat::Png)
+            .unwrap();
+        let _m = mockito::mock("GET", "/images/big.png").with_body(&png).create();
+        let url = format!("{}/images/big.png", mockito::server_url());
+        let mut config = Config::default();
+        config.image_processing.allow_remote_images = true;
+        config.image_processing.max_remote_image_bytes = png.len() as u64 - 1;
+        let content_path = base_path.join("content");
+
+        let settings = FetchSettings::new(&config.image_processing);
+        let err = fetch_remote_image("resize_image", &content_path, &url, &settings).unwrap_err();
+        let cause = std::error::Error::source(&err).unwrap().to_string();
+        assert!(cause.contains("`max_remote_image_bytes`"), "{}", cause);
+        assert!(!base_path.join(".remote-images").join("big.png").exists());
+
+        config.image_processing.max_remote_image_bytes = png.len() as u64;
+        let settings = FetchSettings::new(&config.image_processing);
+        assert!(fetch_remote_image("resize_image", &content_path, &url, &settings).is_ok());
     }
 }
@@ -0,0 +1,328 @@
+//! Downloads the remote images given to `resize_image` and `get_image_metadata` when
+//! `allow_remote_images` is set. Each URL is only downloaded once: the images are kept in
+//! `REMOTE_IMAGES_DIR`, at the root of the site, named after the hash of their URL.
+//! Downloads are limited to `max_concurrent_fetches` at a time and retried `fetch_retries`
+//! times when they fail in a way that can be transient, so a build doesn't hammer a server.
+//! The pages asking for a URL being downloaded wait for it rather than downloading it again.
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use reqwest::blocking::Client;
+use reqwest::header;
+use sha2::{Digest, Sha256};
+use tera::{Error, Result};
+
+use config::ImageProcessing;
+
+/// Where the remote images are downloaded to, relative to the root of the site
+pub const REMOTE_IMAGES_DIR: &str = ".remote-images";
+
+/// How long the first retry of a download waits, the next ones waiting twice as long each time
+const RETRY_BACKOFF: Duration = Duration::from_millis(250);
+
+/// Numbers the downloads of this process, so each is written to its own temporary file
+static DOWNLOADS: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static! {
+    static ref CLIENT: Client = Client::builder()
+        .user_agent(concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")))
+        .build()
+        .expect("reqwest client build");
+    /// How many downloads are running, shared by all the pages rendered in parallel
+    static ref RUNNING: (Mutex<usize>, Condvar) = (Mutex::new(0), Condvar::new());
+    /// The downloads running, by hash of their URL, held while downloading
+    static ref IN_FLIGHT: Mutex<HashMap<String, Arc<Mutex<()>>>> = Mutex::new(HashMap::new());
+}
+
+/// What the `[image_processing]` section of the config says about remote images
+#[derive(Debug, Clone)]
+pub struct FetchSettings {
+    allowed: bool,
+    max_concurrent: usize,
+    timeout: Duration,
+    retries: u32,
+    max_bytes: u64,
+}
+
+impl FetchSettings {
+    pub fn new(settings: &ImageProcessing) -> Self {
+        Self {
+            allowed: settings.allow_remote_images,
+            max_concurrent: settings.max_concurrent_fetches,
+            timeout: Duration::from_secs(settings.fetch_timeout),
+            retries: settings.fetch_retries,
+            max_bytes: settings.max_remote_image_bytes,
+        }
+    }
+}
+
+/// One of the `max_concurrent_fetches` downloads that can run at the same time, the slot
+/// being given back once it's dropped
+struct FetchPermit;
+
+impl FetchPermit {
+    fn acquire(max_concurrent: usize) -> FetchPermit {
+        let (running, slot_freed) = &*RUNNING;
+        let mut running = running.lock().unwrap();
+        while *running >= max_concurrent {
+            running = slot_freed.wait(running).unwrap();
+        }
+        *running += 1;
+        FetchPermit
+    }
+}
+
+impl Drop for FetchPermit {
+    fn drop(&mut self) {
+        let (running, slot_freed) = &*RUNNING;
+        *running.lock().unwrap() -= 1;
+        slot_freed.notify_all();
+    }
+}
+
+/// Whether that `path` argument is the URL of a remote image rather than a local file
+pub fn is_remote(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+/// Downloads the image at that URL, unless it already was, and returns where it is relative to
+/// `content_path`, so it can be used as any other source and its processed images get the same
+/// names on every machine.
+/// `fn_name` is the function errors are about
+pub fn fetch_remote_image(
+    fn_name: &str,
+    content_path: &Path,
+    url: &str,
+    settings: &FetchSettings,
+) -> Result<String> {
+    if !settings.allowed {
+        return Err(format!(
+            "`{}`: {} is a remote image, set `allow_remote_images = true` in the `[image_processing]` section of the config to download it",
+            fn_name, url
+        )
+        .into());
+    }
+
+    let root = content_path.parent().unwrap_or(content_path);
+    let dir = root.join(REMOTE_IMAGES_DIR);
+    let hash = format!("{:x}", Sha256::digest(url.as_bytes()))[..16].to_string();
+    if let Some(filename) = find_downloaded(&dir, &hash) {
+        return Ok(format!("../{}/{}", REMOTE_IMAGES_DIR, filename));
+    }
+
+    // Only one download per URL at a time, the others find it downloaded once it's done
+    let in_flight = IN_FLIGHT.lock().unwrap().entry(hash.clone()).or_default().clone();
+    let _downloading = in_flight.lock().unwrap();
+    let filename = match find_downloaded(&dir, &hash) {
+        Some(filename) => filename,
+        None => {
+            let downloaded = download(fn_name, url, &dir, &hash, settings);
+            IN_FLIGHT.lock().unwrap().remove(&hash);
+            downloaded?
+        }
+    };
+    Ok(format!("../{}/{}", REMOTE_IMAGES_DIR, filename))
+}
+
+/// The name of the file already downloaded for that hash, whatever its extension
+fn find_downloaded(dir: &Path, hash: &str) -> Option<String> {
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|path| path.file_stem() == Some(OsStr::new(hash)))
+        .and_then(|path| path.file_name().and_then(OsStr::to_str).map(str::to_string))
+}
+
+/// What a successful request for a remote image got
+struct Fetched {
+    /// The path of the URL it ended at, after the redirections
+    path: String,
+    content_type: Option<String>,
+    bytes: Vec<u8>,
+}
+
+/// Why a remote image couldn't be fetched
+#[derive(Debug)]
+enum FetchError {
+    Request(reqwest::Error),
+    /// Reading the body failed, eg when it timed out
+    Body(io::Error),
+    /// Bigger than `max_remote_image_bytes`, which it holds
+    TooBig(u64),
+}
+
+impl From<reqwest::Error> for FetchError {
+    fn from(e: reqwest::Error) -> Self {
+        FetchError::Request(e)
+    }
+}
+
+impl std::fmt::Display for FetchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FetchError::Request(e) => e.fmt(f),
+            FetchError::Body(e) => e.fmt(f),
+            FetchError::TooBig(max_bytes) => {
+                write!(f, "it's bigger than `max_remote_image_bytes`, {} bytes", max_bytes)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FetchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FetchError::Request(e) => Some(e),
+            FetchError::Body(e) => Some(e),
+            FetchError::TooBig(_) => None,
+        }
+    }
+}
+
+/// Downloads the image at that URL, as long as it's at most `max_bytes` bytes
+fn fetch(url: &str, timeout: Duration, max_bytes: u64) -> std::result::Result<Fetched, FetchError> {
+    let response = CLIENT.get(url).timeout(timeout).send()?.error_for_status()?;
+    // Refused before downloading it when the server tells its size
+    if matches!(response.content_length(), Some(len) if len > max_bytes) {
+        return Err(FetchError::TooBig(max_bytes));
+    }
+    let path = response.url().path().to_string();
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.split(';').next().unwrap_or("").trim().to_lowercase());
+    // A byte more than allowed is enough to know it's too big
+    let mut bytes = Vec::new();
+    response.take(max_bytes + 1).read_to_end(&mut bytes).map_err(FetchError::Body)?;
+    if bytes.len() as u64 > max_bytes {
+        return Err(FetchError::TooBig(max_bytes));
+    }
+    Ok(Fetched { path, content_type, bytes })
+}
+
+/// Whether trying again later could succeed
+fn is_transient(e: &FetchError) -> bool {
+    match e {
+        FetchError::Request(e) => {
+            e.is_timeout()
+                || e.is_connect()
+                || matches!(e.status(), Some(status) if status.is_server_error())
+        }
+        FetchError::Body(_) => true,
+        FetchError::TooBig(_) => false,
+    }
+}
+
+fn download(
+    fn_name: &str,
+    url: &str,
+    dir: &Path,
+    hash: &str,
+    settings: &FetchSettings,
+) -> Result<String> {
+    let mut retries = 0;
+    let Fetched { path, content_type, bytes } = loop {
+        // The slot is given back while waiting to retry
+        let fetched = {
+            let _permit = FetchPermit::acquire(settings.max_concurrent);
+            fetch(url, settings.timeout, settings.max_bytes)
+        };
+        match fetched {
+            Ok(fetched) => break fetched,
+            Err(e) if retries < settings.retries && is_transient(&e) => {
+                thread::sleep(RETRY_BACKOFF * 2u32.pow(retries));
+                retries += 1;
+            }
+            Err(e) => {
+                let tries = match retries {
+                    0 => String::new(),
+                    retries => format!(" after {} tries", retries + 1),
+                };
+                return Err(Error::chain(
+                    format!("`{}`: Failed to download {}{}", fn_name, url, tries),
+                    e,
+                ));
+            }
+        }
+    };
+
+    // The extension is what tells the format of the source, so it comes from the URL and
+    // otherwise from the content type
+    let extension = url_extension(&path)
+        .or_else(|| content_type.as_deref().and_then(mime_extension).map(str::to_string));
+    let extension = match extension {
+        Some(extension) => extension,
+        None => {
+            return Err(format!(
+                "`{}`: {} doesn't look like an image, its content type is {}",
+                fn_name,
+                url,
+                content_type.as_deref().unwrap_or("unknown")
+            )
+            .into())
+        }
+    };
+
+    let filename = format!("{}.{}", hash, extension);
+    let target = dir.join(&filename);
+    let save_failed = |e: std::io::Error| {
+        Error::chain(format!("`{}`: Failed to save {} to {}", fn_name, url, target.display()), e)
+    };
+    // Written next to where it goes and then moved, so an interrupted download never leaves a
+    // truncated image behind. Each download has its own temporary file as pages rendered in
+    // parallel can download the same URL at the same time
+    let partial = dir.join(format!(
+        "{}.{}-{}.part",
+        filename,
+        std::process::id(),
+        DOWNLOADS.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::create_dir_all(dir).and_then(|_| fs::write(&partial, &bytes)).map_err(save_failed)?;
+    if let Err(e) = fs::rename(&partial, &target) {
+        let _ = fs::remove_file(&partial);
+        // Another download of the same URL got there first
+        if !target.exists() {
+            return Err(save_failed(e));
+        }
+    }
+    Ok(filename)
+}
+
+/// The extension of the last segment of the path of the URL, if it's one of an image
+fn url_extension(path: &str) -> Option<String> {
+    let extension = Path::new(path).extension().and_then(OsStr::to_str)?.to_lowercase();
+    let is_image = matches!(
+        extension.as_str(),
+        "jpg" | "jpeg" | "png" | "gif" | "webp" | "bmp" | "tif" | "tiff" | "heic" | "heif"
+    );
+    if is_image {
+        Some(extension)
+    } else {
+        None
+    }
+}
+
+fn mime_extension(content_type: &str) -> Option<&'static str> {
+    match content_type {
+        "image/jpeg" | "image/jpg" => Some("jpg"),
+        "image/png" => Some("png"),
+        "image/gif" => Some("gif"),
+        "image/webp" => Some("webp"),
+        "image/bmp" => Some("bmp"),
+        "image/tiff" => Some("tif"),
+        "image/heic" => Some("heic"),
+        "image/heif" => Some("heif"),
+        _ => None,
+    }
+}
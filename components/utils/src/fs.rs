@@ -18,8 +18,13 @@ pub fn is_path_in_directory(parent: &Path, path: &Path) -> Result<bool> {
     Ok(canonical_path.starts_with(canonical_parent))
 }
 
-/// Create a file with the content given
+/// Create a file with the content given, along with its parent directories if they don't exist
 pub fn create_file(path: &Path, content: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent).map_err(|e| {
+            Error::chain(format!("Failed to create directory {}", parent.display()), e)
+        })?;
+    }
     let mut file = File::create(&path)
         .map_err(|e| Error::chain(format!("Failed to create file {}", path.display()), e))?;
     file.write_all(content.as_bytes())?;
@@ -219,7 +224,7 @@ mod tests {
 
     use tempfile::{tempdir, tempdir_in};
 
-    use super::{copy_file, find_related_assets};
+    use super::{copy_file, create_file, find_related_assets};
 
     #[test]
     fn can_find_related_assets() {
@@ -237,6 +242,14 @@ mod tests {
         assert_eq!(assets.iter().filter(|p| p.file_name().unwrap() == "fail.png").count(), 1);
     }
 
+    #[test]
+    fn can_create_file_in_missing_directories() {
+        let tmp_dir = tempdir().expect("create temp dir");
+        let path = tmp_dir.path().join("reports").join("images").join("log.json");
+        create_file(&path, "[]").unwrap();
+        assert_eq!(read_to_string(&path).unwrap(), "[]");
+    }
+
     #[test]
     fn test_copy_file_timestamp_preserved() {
         let base_path = PathBuf::from_str(env!("CARGO_MANIFEST_DIR")).unwrap();
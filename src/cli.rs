@@ -50,6 +50,22 @@ pub fn build_cli() -> App<'static, 'static> {
                         .long("drafts")
                         .takes_value(false)
                         .help("Include drafts when loading the site"),
+                    Arg::with_name("strict_alt")
+                        .long("strict-alt")
+                        .takes_value(false)
+                        .help("Fail the build if images are left without alt text"),
+                    Arg::with_name("clean_processed_images")
+                        .long("clean-processed-images")
+                        .takes_value(false)
+                        .help("Remove the processed images the build didn't produce"),
+                    Arg::with_name("image_dry_run")
+                        .long("image-dry-run")
+                        .takes_value(false)
+                        .help("Only report the processed images the build would write, without writing them"),
+                    Arg::with_name("expected_images")
+                        .long("expected-images")
+                        .takes_value(true)
+                        .help("Fail the build if images listed in that JSON file of URLs weren't processed"),
                 ]),
             SubCommand::with_name("serve")
                 .about("Serve the site. Rebuild and reload on change automatically")
@@ -1,6 +1,7 @@
+use std::fs;
 use std::path::Path;
 
-use errors::Result;
+use errors::{Error, Result};
 use site::Site;
 
 use crate::console;
@@ -11,6 +12,10 @@ pub fn build(
     base_url: Option<&str>,
     output_dir: Option<&Path>,
     include_drafts: bool,
+    strict_alt: bool,
+    clean_processed_images: bool,
+    image_dry_run: bool,
+    expected_images: Option<&Path>,
 ) -> Result<()> {
     let mut site = Site::new(root_dir, config_file)?;
     if let Some(output_dir) = output_dir {
@@ -22,8 +27,35 @@ pub fn build(
     if include_drafts {
         site.include_drafts();
     }
+    if strict_alt {
+        site.enable_strict_alt();
+    }
+    if clean_processed_images {
+        site.enable_clean_processed_images();
+    }
+    if image_dry_run {
+        site.enable_image_dry_run();
+    }
     site.load()?;
     console::notify_site_size(&site);
     console::warn_about_ignored_pages(&site);
-    site.build()
+    site.build()?;
+    if image_dry_run {
+        console::report_image_plan(&site)?;
+    } else {
+        console::report_image_processing(&site);
+        if let Some(path) = expected_images {
+            let content = fs::read_to_string(path).map_err(|e| {
+                Error::chain(format!("Failed to read the expected images {}", path.display()), e)
+            })?;
+            let expected: Vec<String> = serde_json::from_str(&content).map_err(|e| {
+                Error::chain(
+                    format!("{} needs to be a JSON array of image URLs", path.display()),
+                    e,
+                )
+            })?;
+            console::report_image_diff(&site, &expected)?;
+        }
+    }
+    Ok(())
 }
@@ -232,6 +232,7 @@ fn create_new_site(
     console::notify_site_size(&site);
     console::warn_about_ignored_pages(&site);
     site.build()?;
+    console::report_image_processing(&site);
     Ok((site, address))
 }
 
@@ -8,7 +8,7 @@ use std::time::Instant;
 use chrono::Duration;
 use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 
-use errors::Error;
+use errors::{Error, Result};
 use site::Site;
 
 lazy_static! {
@@ -60,6 +60,62 @@ pub fn notify_site_size(site: &Site) {
     );
 }
 
+/// Display in the console what processing the images did: how many were encoded, how many were
+/// already up to date from a previous build and how many were skipped, only if there were any
+pub fn report_image_processing(site: &Site) {
+    let stats = site.image_processing_stats();
+    if stats.processed + stats.cache_hits + stats.skipped == 0 {
+        return;
+    }
+    println!(
+        "-> Processed {} images ({:.1} MB written) in {}ms, {} up to date ({}% cache hit), {} skipped",
+        stats.processed,
+        stats.bytes_written as f64 / 1_000_000.0,
+        stats.duration.as_millis(),
+        stats.cache_hits,
+        stats.hit_percentage(),
+        stats.skipped
+    );
+}
+
+/// Display in the console how many processed images a dry run planned and how much work encoding
+/// the ones that aren't up to date would be, only if there were processed images
+pub fn report_image_plan(site: &Site) -> Result<()> {
+    let plan = site.planned_images()?;
+    if plan.is_empty() {
+        return Ok(());
+    }
+    let to_encode: Vec<_> = plan.iter().filter(|img| !img.up_to_date).collect();
+    let pixels: u64 =
+        to_encode.iter().map(|img| u64::from(img.width) * u64::from(img.height)).sum();
+    let source_bytes: u64 = to_encode.iter().map(|img| img.input_bytes).sum();
+    println!(
+        "-> image dry run: {} processed images, {} to encode ({:.1} megapixels from {:.1} MB of sources), {} up to date",
+        plan.len(),
+        to_encode.len(),
+        pixels as f64 / 1_000_000.0,
+        source_bytes as f64 / 1_000_000.0,
+        plan.len() - to_encode.len()
+    );
+    Ok(())
+}
+
+/// Lists the images expected by `--expected-images` that the build didn't write, which fails
+/// it, and the ones it wrote that weren't expected
+pub fn report_image_diff(site: &Site, expected: &[String]) -> Result<()> {
+    let diff = site.diff_processed_images(expected);
+    for url in &diff.extra {
+        println!("Processed image not in the expected ones: {}", url);
+    }
+    if diff.missing.is_empty() {
+        return Ok(());
+    }
+    for url in &diff.missing {
+        error(&format!("Expected image not processed: {}", url));
+    }
+    Err(format!("{} expected image(s) were not processed", diff.missing.len()).into())
+}
+
 /// Display in the console only the number of pages/sections in the site
 pub fn check_site_summary(site: &Site) {
     let library = site.library.read().unwrap();
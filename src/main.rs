@@ -44,6 +44,10 @@ fn main() {
                 matches.value_of("base_url"),
                 output_dir,
                 matches.is_present("drafts"),
+                matches.is_present("strict_alt"),
+                matches.is_present("clean_processed_images"),
+                matches.is_present("image_dry_run"),
+                matches.value_of("expected_images").map(Path::new),
             ) {
                 Ok(()) => console::report_elapsed_time(start),
                 Err(e) => {